@@ -1,12 +1,9 @@
 use std::env;
-use std::ops::ControlFlow;
 use std::process;
 use std::time::Instant;
 
-use fxfsp::{
-    parse_superblock, IoEngine, MaybeInstrumented, detect_disk_profile_for_path,
-    InodeInfo, FileExtentsInfo, DirEntryInfo,
-};
+use fxfsp::prelude::*;
+use fxfsp::{DirStats, InstrumentationConfig, KindCounts, MaybeInstrumented, NamePolicy, detect_disk_profile_for_path};
 
 fn mode_string(mode: u16) -> String {
     let file_type = match mode & 0o170000 {
@@ -37,6 +34,8 @@ struct Args {
     max_ag: Option<u32>,
     merge_gap_kb: usize,
     max_merged_kb: usize,
+    top_dirs: usize,
+    names: NamePolicy,
 }
 
 fn parse_args() -> Args {
@@ -45,6 +44,8 @@ fn parse_args() -> Args {
     let mut max_ag = None;
     let mut merge_gap_kb = 256;
     let mut max_merged_kb = 2048;
+    let mut top_dirs = 10;
+    let mut names = NamePolicy::EscapeNonUtf8;
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
@@ -60,12 +61,27 @@ fn parse_args() -> Args {
                 i += 1;
                 max_merged_kb = args.get(i).and_then(|s| s.parse().ok()).unwrap_or(max_merged_kb);
             }
+            "--top-dirs" => {
+                i += 1;
+                top_dirs = args.get(i).and_then(|s| s.parse().ok()).unwrap_or(top_dirs);
+            }
+            "--names" => {
+                i += 1;
+                names = match args.get(i).map(String::as_str) {
+                    Some("lossy") => NamePolicy::Utf8Lossy,
+                    Some("escape") => NamePolicy::EscapeNonUtf8,
+                    _ => {
+                        eprintln!("--names takes 'lossy' or 'escape'");
+                        process::exit(1);
+                    }
+                };
+            }
             _ if !args[i].starts_with('-') && path.is_none() => {
                 path = Some(args[i].clone());
             }
             _ => {
                 eprintln!("Unknown argument: {}", args[i]);
-                eprintln!("Usage: sample [--max-ag N] [--merge-gap KB] [--max-merged KB] <device-or-image>");
+                eprintln!("Usage: sample [--max-ag N] [--merge-gap KB] [--max-merged KB] [--top-dirs N] [--names lossy|escape] <device-or-image>");
                 process::exit(1);
             }
         }
@@ -74,11 +90,11 @@ fn parse_args() -> Args {
     let path = match path {
         Some(p) => p,
         None => {
-            eprintln!("Usage: sample [--max-ag N] [--merge-gap KB] [--max-merged KB] <device-or-image>");
+            eprintln!("Usage: sample [--max-ag N] [--merge-gap KB] [--max-merged KB] [--top-dirs N] [--names lossy|escape] <device-or-image>");
             process::exit(1);
         }
     };
-    Args { path, max_ag, merge_gap_kb, max_merged_kb }
+    Args { path, max_ag, merge_gap_kb, max_merged_kb, top_dirs, names }
 }
 
 fn main() {
@@ -88,7 +104,7 @@ fn main() {
     eprintln!("{}", profile);
     eprintln!("merge_gap={} KB  max_merged={} KB", args.merge_gap_kb, args.max_merged_kb);
 
-    let engine = IoEngine::open(
+    let mut engine = IoEngine::open(
         &args.path,
         args.merge_gap_kb * 1024,
         args.max_merged_kb * 1024,
@@ -96,25 +112,27 @@ fn main() {
         eprintln!("Failed to open {}: {e}", args.path);
         process::exit(1);
     });
+    engine.set_stripe_width(profile.optimal_io_bytes);
 
-    let reader = MaybeInstrumented::from_env(engine).unwrap_or_else(|e| {
+    let reader = MaybeInstrumented::new(engine, &InstrumentationConfig::from_env()).unwrap_or_else(|e| {
         eprintln!("Failed to set up I/O reader: {e}", );
         process::exit(1);
     });
 
     let max_ag = args.max_ag;
+    let names = args.names;
 
     let start = Instant::now();
     let mut inode_count: u64 = 0;
     let mut dir_entry_count: u64 = 0;
-    let mut dir_count: u64 = 0;
-    let mut file_count: u64 = 0;
+    let mut kinds = KindCounts::default();
+    let mut dir_stats = DirStats::new();
 
     let result = (|| {
         let (sb, mut scanner) = parse_superblock(reader)?;
         println!(
-            "Superblock: block_size={} ag_count={} ag_blocks={} inode_size={} root_ino={}",
-            sb.block_size, sb.ag_count, sb.ag_blocks, sb.inode_size, sb.root_ino
+            "Superblock: block_size={} ag_count={} ag_blocks={} inode_size={} root_ino={} sunit={} swidth={}",
+            sb.block_size, sb.ag_count, sb.ag_blocks, sb.inode_size, sb.root_ino, sb.stripe_unit, sb.stripe_width
         );
 
         while let Some(ag_result) = scanner.next_ag() {
@@ -128,12 +146,9 @@ fn main() {
             // Phase 1: Scan inodes
             let phase2 = ag.scan_inodes(|inode: &InodeInfo| {
                 inode_count += 1;
-                match inode.mode & 0o170000 {
-                    0o040000 => dir_count += 1,
-                    0o100000 => file_count += 1,
-                    _ => {}
-                }
-                if inode_count % 1000 == 0 {
+                dir_stats.add_inode(inode);
+                kinds.add_inode(inode);
+                if inode_count.is_multiple_of(1000) {
                     println!(
                         "[inode #{:>9}] ag={:<4} ino={:<12} {} uid={:<5} gid={:<5} nlink={:<4} size={:<12} blocks={:<8} mtime={}",
                         inode_count, inode.ag_number, inode.ino, mode_string(inode.mode),
@@ -152,8 +167,11 @@ fn main() {
             // Phase 2: Directory entries
             phase3.scan_dir_entries(|de: &DirEntryInfo| {
                 dir_entry_count += 1;
-                if dir_entry_count % 1000 == 0 {
-                    let name_str = String::from_utf8_lossy(de.name);
+                dir_stats.add_dir_entry(de);
+                if dir_entry_count.is_multiple_of(1000) {
+                    // Both text policies yield valid UTF-8, so this never replaces.
+                    let name = names.encode(de.name);
+                    let name_str = String::from_utf8_lossy(&name);
                     let ft = match de.file_type {
                         1 => "REG",
                         2 => "DIR",
@@ -183,9 +201,16 @@ fn main() {
             println!();
             println!("=== Scan complete ===");
             println!("  Inodes:      {}", inode_count);
-            println!("    Files:     {}", file_count);
-            println!("    Dirs:      {}", dir_count);
-            println!("    Other:     {}", inode_count - file_count - dir_count);
+            println!("    Files:     {}", kinds.files);
+            println!("    Dirs:      {}", kinds.dirs);
+            println!("    Symlinks:  {}", kinds.symlinks);
+            println!("    Devices:   {}", kinds.char_devices + kinds.block_devices);
+            println!("    FIFOs:     {}", kinds.fifos);
+            println!("    Sockets:   {}", kinds.sockets);
+            println!("    Internal:  {}", kinds.internal);
+            println!("    Setuid:    {}", kinds.setuid);
+            println!("    Setgid:    {}", kinds.setgid);
+            println!("    World-wr:  {}", kinds.world_writable);
             println!("  Dir entries: {}", dir_entry_count);
             println!("  Elapsed:     {:.3}s", elapsed.as_secs_f64());
             if elapsed.as_secs_f64() > 0.0 {
@@ -195,6 +220,14 @@ fn main() {
                     dir_entry_count as f64 / elapsed.as_secs_f64()
                 );
             }
+            let top = dir_stats.top(args.top_dirs);
+            if !top.is_empty() {
+                println!();
+                println!("=== Largest directories ===");
+                for dir in top {
+                    println!("  ino={:<12} entries={:<10} child_bytes={}", dir.ino, dir.entries, dir.child_bytes);
+                }
+            }
         }
         Err(e) => {
             eprintln!("Scan failed: {e}");
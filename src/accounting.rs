@@ -0,0 +1,133 @@
+//! Superblock counter reconciliation.
+//!
+//! [`SpaceAccounting`] compares the superblock summary counters with the
+//! per-AG headers and with what the scan itself saw. Drift means the
+//! counters are stale: expected after an unclean shutdown on lazy-count
+//! filesystems (mount or `xfs_repair` recomputes them), suspicious otherwise.
+//!
+//! Each AG's headers go in through [`add_ag`](SpaceAccounting::add_ag),
+//! alongside the inodes the scan delivers.
+
+use crate::staged::{AgfInfo, AgiInfo, InodeInfo, SuperblockInfo};
+
+/// A counter that [`SpaceAccounting`] checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum AccountingCounter {
+    /// `sb_icount` against the sum of `agi_count`.
+    InodeCount,
+    /// `sb_ifree` against the sum of `agi_freecount`.
+    FreeInodes,
+    /// `sb_fdblocks` against the sum of `agf_freeblks + agf_flcount +
+    /// agf_btreeblks`, which is how the kernel recomputes it.
+    FreeBlocks,
+    /// In-use inodes per the AGIs (`agi_count - agi_freecount`) against the
+    /// inodes the scan reported. Only meaningful when
+    /// [`ScanOptions`](crate::ScanOptions) skip nothing.
+    InUseInodes,
+}
+
+/// A counter whose recorded value differs from the observed one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Discrepancy {
+    pub counter: AccountingCounter,
+    pub recorded: u64,
+    pub observed: u64,
+}
+
+impl Discrepancy {
+    /// `observed - recorded`.
+    pub fn delta(&self) -> i128 {
+        self.observed as i128 - self.recorded as i128
+    }
+}
+
+/// Result of [`SpaceAccounting::finish`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccountingReport {
+    pub discrepancies: Vec<Discrepancy>,
+    /// `sb_dblocks - sb_fdblocks`.
+    pub used_blocks: u64,
+    /// Blocks claimed by the scanned inodes (sum of `di_nblocks`). The rest
+    /// of `used_blocks` is the log, AG headers and btrees.
+    pub file_blocks: u64,
+}
+
+/// Collects AG headers and inode events and reconciles the superblock
+/// counters.
+#[derive(Debug)]
+pub struct SpaceAccounting {
+    ag_count: u32,
+    data_blocks: u64,
+    sb_inode_count: u64,
+    sb_free_inodes: u64,
+    sb_free_blocks: u64,
+    ags_seen: u32,
+    agi_count: u64,
+    agi_free_count: u64,
+    agf_free_blocks: u64,
+    scanned_inodes: u64,
+    file_blocks: u64,
+}
+
+impl SpaceAccounting {
+    pub fn new(sb: &SuperblockInfo) -> Self {
+        Self {
+            ag_count: sb.ag_count,
+            data_blocks: sb.data_blocks,
+            sb_inode_count: sb.inode_count,
+            sb_free_inodes: sb.free_inodes,
+            sb_free_blocks: sb.free_blocks,
+            ags_seen: 0,
+            agi_count: 0,
+            agi_free_count: 0,
+            agf_free_blocks: 0,
+            scanned_inodes: 0,
+            file_blocks: 0,
+        }
+    }
+
+    /// Record one AG's headers. Every AG must be added for the superblock
+    /// comparisons to be made.
+    pub fn add_ag(&mut self, agi: &AgiInfo, agf: &AgfInfo) {
+        self.ags_seen += 1;
+        self.agi_count += agi.count as u64;
+        self.agi_free_count += agi.free_count as u64;
+        self.agf_free_blocks += agf.free_blocks as u64 + agf.fl_count as u64 + agf.btree_blocks as u64;
+    }
+
+    /// Record an inode reported by the scan.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        self.scanned_inodes += 1;
+        self.file_blocks += inode.nblocks;
+    }
+
+    /// Finish and report every counter that does not match.
+    pub fn finish(self) -> AccountingReport {
+        let mut checks = vec![(
+            AccountingCounter::InUseInodes,
+            self.agi_count.saturating_sub(self.agi_free_count),
+            self.scanned_inodes,
+        )];
+        if self.ags_seen == self.ag_count {
+            checks.extend([
+                (AccountingCounter::InodeCount, self.sb_inode_count, self.agi_count),
+                (AccountingCounter::FreeInodes, self.sb_free_inodes, self.agi_free_count),
+                (AccountingCounter::FreeBlocks, self.sb_free_blocks, self.agf_free_blocks),
+            ]);
+        }
+        let discrepancies = checks
+            .into_iter()
+            .filter(|&(_, recorded, observed)| recorded != observed)
+            .map(|(counter, recorded, observed)| Discrepancy { counter, recorded, observed })
+            .collect();
+        AccountingReport {
+            discrepancies,
+            used_blocks: self.data_blocks.saturating_sub(self.sb_free_blocks),
+            file_blocks: self.file_blocks,
+        }
+    }
+}
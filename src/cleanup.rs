@@ -0,0 +1,155 @@
+//! Age-based cleanup candidates (tmpwatch-style).
+//!
+//! [`CleanupCollector`] is fed the events of a scan and lists the regular
+//! files matching [`CleanupCriteria`], with their paths, so cleanups of
+//! volumes too slow to `find` through the kernel can be planned offline.
+
+use std::collections::HashMap;
+
+use crate::name::escape_name;
+use crate::staged::{InodeInfo, SuperblockInfo};
+use crate::tree::{DirTree, EntryKind, is_under};
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::inode::{S_IFMT, S_IFREG};
+
+/// Which files are cleanup candidates. Every set predicate must hold.
+#[derive(Debug, Clone, Default)]
+pub struct CleanupCriteria {
+    /// Last modified before this time (seconds since the epoch).
+    pub mtime_before: Option<i64>,
+    /// Last accessed before this time (seconds since the epoch). Only
+    /// meaningful on filesystems not mounted `noatime`.
+    pub atime_before: Option<i64>,
+    /// At least this many bytes (logical size).
+    pub min_size: u64,
+    /// Only under one of these absolute paths (raw bytes, e.g. `b"/var/tmp"`).
+    /// Empty means anywhere.
+    pub under: Vec<Vec<u8>>,
+}
+
+impl CleanupCriteria {
+    fn matches_inode(&self, inode: &InodeInfo) -> bool {
+        inode.size >= self.min_size
+            && self.mtime_before.is_none_or(|t| (inode.mtime_sec as i64) < t)
+            && self.atime_before.is_none_or(|t| (inode.atime_sec as i64) < t)
+    }
+
+    fn matches_path(&self, path: &[u8]) -> bool {
+        self.under.is_empty() || self.under.iter().any(|prefix| is_under(path, prefix))
+    }
+}
+
+/// A file matching the criteria.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CleanupCandidate {
+    pub ino: u64,
+    /// Path of the first link seen that is under
+    /// [`CleanupCriteria::under`], escaped with [`escape_name`].
+    pub path: String,
+    pub size: u64,
+    /// Number of links; space is only freed once every link is removed.
+    pub nlink: u32,
+    pub mtime_sec: i64,
+    pub atime_sec: i64,
+}
+
+/// Fields kept for a matching inode.
+#[derive(Debug)]
+struct Matched {
+    size: u64,
+    nlink: u32,
+    mtime_sec: i64,
+    atime_sec: i64,
+}
+
+/// Collects scan events into a list of [`CleanupCandidate`]s.
+#[derive(Debug)]
+pub struct CleanupCollector {
+    criteria: CleanupCriteria,
+    tree: DirTree,
+    /// Every regular file seen, with its fields if it matches.
+    files: HashMap<u64, Option<Matched>>,
+    /// Links of each possibly matching file: child → [(parent, name)].
+    links: HashMap<u64, Vec<(u64, Vec<u8>)>>,
+}
+
+impl CleanupCollector {
+    pub fn new(sb: &SuperblockInfo, criteria: CleanupCriteria) -> Self {
+        Self {
+            criteria,
+            tree: DirTree::new(sb.root_ino),
+            files: HashMap::new(),
+            links: HashMap::new(),
+        }
+    }
+
+    /// Record an inode.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        self.tree.add_inode(inode);
+        if inode.mode & S_IFMT != S_IFREG {
+            return;
+        }
+        let matched = self.criteria.matches_inode(inode).then_some(Matched {
+            size: inode.size,
+            nlink: inode.nlink,
+            mtime_sec: inode.mtime_sec as i64,
+            atime_sec: inode.atime_sec as i64,
+        });
+        if matched.is_none() {
+            self.links.remove(&inode.ino);
+        }
+        self.files.insert(inode.ino, matched);
+    }
+
+    /// Record a directory entry.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) {
+        if self.tree.add_dir_entry(de) == EntryKind::Other {
+            self.add_link(de.parent_ino, de.child_ino, de.name);
+        }
+    }
+
+    fn add_link(&mut self, parent: u64, child: u64, name: &[u8]) {
+        // Files already known not to match need no name.
+        if matches!(self.files.get(&child), Some(None)) {
+            return;
+        }
+        self.links.entry(child).or_default().push((parent, name.to_vec()));
+    }
+
+    /// Finish and return the candidates, sorted by path.
+    pub fn finish(mut self) -> Vec<CleanupCandidate> {
+        for (parent, child, name) in self.tree.resolve_untyped() {
+            self.add_link(parent, child, &name);
+        }
+
+        let mut dir_paths: HashMap<u64, Option<Vec<u8>>> = HashMap::new();
+        let mut candidates = Vec::new();
+        for (ino, links) in &self.links {
+            let Some(Some(m)) = self.files.get(ino) else {
+                continue;
+            };
+            let path = links.iter().find_map(|(parent, name)| {
+                let mut path = dir_paths.entry(*parent).or_insert_with(|| self.tree.path(*parent)).clone()?;
+                if path != b"/" {
+                    path.push(b'/');
+                }
+                path.extend_from_slice(name);
+                self.criteria.matches_path(&path).then_some(path)
+            });
+            let Some(path) = path else {
+                continue;
+            };
+            candidates.push(CleanupCandidate {
+                ino: *ino,
+                path: escape_name(&path).into_owned(),
+                size: m.size,
+                nlink: m.nlink,
+                mtime_sec: m.mtime_sec,
+                atime_sec: m.atime_sec,
+            });
+        }
+        candidates.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        candidates
+    }
+}
@@ -0,0 +1,73 @@
+//! Time sources for scan metadata and instrumentation.
+//!
+//! Everything in fxfsp that reads the time does so through a [`Clock`], so
+//! tests and replay harnesses can substitute a [`FixedClock`] and get
+//! byte-identical output. fxfsp uses no randomness.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of wall-clock and monotonic time.
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Wall-clock time, for record headers such as
+    /// [`ScanMeta::started_at`](crate::ScanMeta::started_at).
+    fn now(&self) -> SystemTime;
+
+    /// Monotonic time since an arbitrary fixed origin, for intervals.
+    fn monotonic(&self) -> Duration;
+}
+
+/// The real clock: `SystemTime::now` and an `Instant` taken at creation.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self { origin: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// A deterministic clock for tests.
+///
+/// `now` always returns the same wall time; `monotonic` advances by `step`
+/// on every call, starting at zero.
+#[derive(Debug)]
+pub struct FixedClock {
+    wall: SystemTime,
+    step: Duration,
+    ticks: AtomicU64,
+}
+
+impl FixedClock {
+    pub fn new(wall: SystemTime, step: Duration) -> Self {
+        Self {
+            wall,
+            step,
+            ticks: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.wall
+    }
+
+    fn monotonic(&self) -> Duration {
+        let tick = self.ticks.fetch_add(1, Ordering::Relaxed);
+        self.step.saturating_mul(tick.min(u32::MAX as u64) as u32)
+    }
+}
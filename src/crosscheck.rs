@@ -0,0 +1,123 @@
+//! Cross-file consistency checks over a completed scan.
+//!
+//! [`CrossCheck`] is fed the events of a scan and reports what no single
+//! inode can show on its own: an inode number reported twice (corrupt inobt)
+//! and physical blocks claimed by more than one extent (crosslinked files).
+//!
+//! Directory blocks are not reported as events and are not checked.
+
+use std::collections::HashMap;
+
+use crate::staged::{FileExtentsInfo, FileId, InodeInfo, SuperblockInfo};
+use crate::xfs::extent::Extent;
+use crate::xfs::superblock::XFS_SB_FEAT_RO_COMPAT_REFLINK;
+
+/// A conflict between two owners found by [`CrossCheck`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// The same inode number was reported twice.
+    DuplicateInode { first: FileId, second: FileId },
+    /// Two extents claim overlapping physical blocks. `ag_block` and
+    /// `block_count` describe the overlap.
+    CrosslinkedBlocks {
+        ag_number: u32,
+        ag_block: u32,
+        block_count: u64,
+        first: FileId,
+        second: FileId,
+    },
+}
+
+/// A physical block range and the file that claims it.
+struct Claim {
+    ag_number: u32,
+    ag_block: u32,
+    block_count: u64,
+    owner: FileId,
+}
+
+/// Collects scan events and checks them for duplicates and crosslinks.
+pub struct CrossCheck {
+    /// Reflinked filesystems share blocks legitimately.
+    check_blocks: bool,
+    inodes: HashMap<u64, FileId>,
+    conflicts: Vec<Conflict>,
+    claims: Vec<Claim>,
+}
+
+impl CrossCheck {
+    /// Create a checker for the filesystem described by `sb`.
+    ///
+    /// On reflink filesystems, shared blocks are legitimate, so only
+    /// duplicate inodes are checked.
+    pub fn new(sb: &SuperblockInfo) -> Self {
+        Self {
+            check_blocks: sb.features_ro_compat & XFS_SB_FEAT_RO_COMPAT_REFLINK == 0,
+            inodes: HashMap::new(),
+            conflicts: Vec::new(),
+            claims: Vec::new(),
+        }
+    }
+
+    /// Record an inode and its inline data and attr extents.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        let id = inode.file_id();
+        if let Some(&first) = self.inodes.get(&inode.ino) {
+            self.conflicts.push(Conflict::DuplicateInode { first, second: id });
+        } else {
+            self.inodes.insert(inode.ino, id);
+        }
+        for extents in [&inode.extents, &inode.attr_extents].into_iter().flatten() {
+            self.add_claims(id, extents);
+        }
+    }
+
+    /// Record the extents of a btree-format file.
+    pub fn add_file_extents(&mut self, info: &FileExtentsInfo) {
+        self.add_claims(info.file_id(), &info.extents);
+    }
+
+    fn add_claims(&mut self, owner: FileId, extents: &[Extent]) {
+        if !self.check_blocks {
+            return;
+        }
+        self.claims.extend(extents.iter().filter(|e| e.block_count > 0).map(|e| Claim {
+            ag_number: e.ag_number,
+            ag_block: e.ag_block,
+            block_count: e.block_count,
+            owner,
+        }));
+    }
+
+    /// Finish the check and return every conflict found.
+    ///
+    /// Each overlapping extent is reported against the earlier extent that
+    /// reaches furthest past its start, so a block claimed `n` times yields
+    /// `n - 1` conflicts.
+    pub fn finish(mut self) -> Vec<Conflict> {
+        self.claims.sort_unstable_by_key(|c| (c.ag_number, c.ag_block));
+
+        // The claim reaching furthest in the current AG: (ag, end, owner).
+        let mut reach: Option<(u32, u64, FileId)> = None;
+        for claim in &self.claims {
+            let start = claim.ag_block as u64;
+            let end = start + claim.block_count;
+            match reach {
+                Some((ag, reach_end, first)) if ag == claim.ag_number && start < reach_end => {
+                    self.conflicts.push(Conflict::CrosslinkedBlocks {
+                        ag_number: ag,
+                        ag_block: claim.ag_block,
+                        block_count: end.min(reach_end) - start,
+                        first,
+                        second: claim.owner,
+                    });
+                    if end > reach_end {
+                        reach = Some((ag, end, claim.owner));
+                    }
+                }
+                _ => reach = Some((claim.ag_number, end, claim.owner)),
+            }
+        }
+        self.conflicts
+    }
+}
@@ -0,0 +1,249 @@
+//! Decode any single metadata block, `xfs_db`-style.
+//!
+//! [`decode_block`] turns one block into a [`DecodedBlock`]: owned, `Debug`
+//! and (with the `serde` feature) serializable, so an inspector can print or
+//! ship it as JSON without knowing which parser applies. The block kind is
+//! taken from the caller or detected from its magic.
+
+use alloc::vec::Vec;
+use core::ops::ControlFlow;
+
+use zerocopy::FromBytes;
+
+use crate::error::FxfspError;
+use crate::xfs::ag::{AgfInfo, AgiInfo};
+use crate::xfs::bmbt::{BmbtBlock, parse_bmbt_block};
+use crate::xfs::btree::{InobtBlock, parse_inobt_block};
+use crate::xfs::dir::block::{DirBlockKind, parse_dir_data_block_staged};
+use crate::xfs::extent::Extent;
+use crate::xfs::inode::{InodeInfo, V4_CORE_SIZE, XfsDinodeV3, parse_inode_core};
+use crate::xfs::superblock::{FormatVersion, FsContext};
+use crate::xfs::symlink::XfsDsymlinkHdr;
+
+/// Header magics, as `xfs_db` uses them to guess a block's type.
+const XFS_SB_MAGIC: u32 = 0x58465342; // XFSB
+const XFS_AGF_MAGIC: u32 = 0x58414746; // XAGF
+const XFS_AGI_MAGIC: u32 = 0x58414749; // XAGI
+const XFS_AGFL_MAGIC: u32 = 0x5841464c; // XAFL
+const XFS_IBT_MAGICS: [u32; 2] = [0x49414254, 0x49414233]; // IABT, IAB3
+const XFS_BMAP_MAGICS: [u32; 2] = [0x424d4150, 0x424d4133]; // BMAP, BMA3
+/// Directory data, block-format and free-index blocks, V4 and V5.
+const XFS_DIR_MAGICS: [u32; 6] = [0x58443244, 0x58443242, 0x58443246, 0x58444433, 0x58444233, 0x58444633];
+/// Directory leaf and da-node magics (16-bit, at offset 8), V4 and V5.
+const XFS_DA_MAGICS: [u16; 6] = [0xd2f1, 0xd2ff, 0xfebe, 0x3df1, 0x3dff, 0x3ebe];
+const XFS_SYMLINK_MAGIC: u32 = 0x58534c4d; // XSLM
+const XFS_DINODE_MAGIC: u16 = 0x494e; // IN
+
+/// Which parser [`decode_block`] applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum BlockKind {
+    Superblock,
+    Agf,
+    Agi,
+    /// AG free list. V4 AGFLs have no magic and are never detected.
+    Agfl,
+    /// One inode, or a block of them.
+    Inode,
+    Inobt,
+    Bmbt,
+    /// Any directory block: data, block-format, leaf, node or free index.
+    Dir,
+    /// A remote symlink block. V4 ones have no magic and are never detected.
+    Symlink,
+}
+
+impl BlockKind {
+    /// Guess the kind from the block's magic, or `None` if it has none this
+    /// module knows.
+    pub fn detect(buf: &[u8]) -> Option<Self> {
+        let be32 = |at: usize| buf.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+        let be16 = |at: usize| buf.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+
+        let magic = be32(0)?;
+        let kind = match magic {
+            XFS_SB_MAGIC => Self::Superblock,
+            XFS_AGF_MAGIC => Self::Agf,
+            XFS_AGI_MAGIC => Self::Agi,
+            XFS_AGFL_MAGIC => Self::Agfl,
+            XFS_SYMLINK_MAGIC => Self::Symlink,
+            m if XFS_IBT_MAGICS.contains(&m) => Self::Inobt,
+            m if XFS_BMAP_MAGICS.contains(&m) => Self::Bmbt,
+            m if XFS_DIR_MAGICS.contains(&m) => Self::Dir,
+            _ if be16(0) == Some(XFS_DINODE_MAGIC) => Self::Inode,
+            _ if be16(8).is_some_and(|m| XFS_DA_MAGICS.contains(&m)) => Self::Dir,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// One inobt record, decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InobtRecord {
+    /// First AG inode number of the chunk.
+    pub start_ino: u32,
+    /// Sparse chunk hole mask, one bit per four inodes.
+    pub holemask: u16,
+    pub count: u8,
+    pub free_count: u8,
+    /// Free inode bitmap, one bit per inode.
+    pub free: u64,
+}
+
+/// One directory entry, decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DecodedDirEntry {
+    /// Byte offset of the entry within the block.
+    pub offset: u32,
+    pub ino: u64,
+    pub name: Vec<u8>,
+    pub file_type: u8,
+}
+
+/// A metadata block as [`decode_block`] read it.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum DecodedBlock {
+    Superblock(FsContext),
+    Agf(AgfInfo),
+    Agi(AgiInfo),
+    /// Every slot of the free list, active or not; the AGF says which are.
+    Agfl { slots: Vec<u32> },
+    /// Each inode-sized slot of the block, `None` where there is no valid
+    /// inode. Inode numbers come from `di_ino` on V5 and are 0 on V4.
+    Inodes(Vec<Option<InodeInfo>>),
+    InobtLeaf(Vec<InobtRecord>),
+    InobtNode { level: u16, children: Vec<u32> },
+    BmbtLeaf(Vec<Extent>),
+    BmbtNode { level: u16, children: Vec<u64> },
+    /// A directory block. Only data blocks have `entries`.
+    Dir { kind: DirBlockKind, entries: Vec<DecodedDirEntry> },
+    /// A remote symlink block: the target bytes it holds, starting at
+    /// `offset` within the target. V4 blocks are raw, so `offset` is 0 and
+    /// the bytes run to the end of the block.
+    Symlink { offset: u32, bytes: Vec<u8> },
+    /// No kind was given and the magic matched none.
+    Unknown { magic: u32 },
+}
+
+/// Decode one metadata block of the filesystem described by `ctx`.
+///
+/// With `hint` as `None` the kind is detected from the magic, and a block
+/// that matches none decodes to [`DecodedBlock::Unknown`]. A wrong hint
+/// surfaces as the parser's [`FxfspError::BadMagic`]. Nothing beyond `buf`
+/// is read, and CRCs are not checked.
+pub fn decode_block(buf: &[u8], ctx: &FsContext, hint: Option<BlockKind>) -> Result<DecodedBlock, FxfspError> {
+    let Some(kind) = hint.or_else(|| BlockKind::detect(buf)) else {
+        let magic = buf.get(..4).map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+        return Ok(DecodedBlock::Unknown { magic });
+    };
+    // AG headers are checked against their AG; take it from the block.
+    let seqno = || {
+        buf.get(8..12)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or(FxfspError::Parse("buffer too small for AG header"))
+    };
+
+    Ok(match kind {
+        BlockKind::Superblock => DecodedBlock::Superblock(FsContext::from_superblock(buf)?),
+        BlockKind::Agf => DecodedBlock::Agf(AgfInfo::from_buf(buf, seqno()?)?),
+        BlockKind::Agi => DecodedBlock::Agi(AgiInfo::from_buf(buf, seqno()?, ctx.version)?),
+        BlockKind::Agfl => decode_agfl(buf, ctx)?,
+        BlockKind::Inode => decode_inodes(buf, ctx)?,
+        BlockKind::Inobt => match parse_inobt_block(buf, ctx)? {
+            InobtBlock::Leaf(recs) => DecodedBlock::InobtLeaf(
+                recs.iter()
+                    .map(|r| InobtRecord {
+                        start_ino: r.ir_startino.get(),
+                        holemask: r.ir_holemask.get(),
+                        count: r.ir_count,
+                        free_count: r.ir_freecount,
+                        free: r.ir_free.get(),
+                    })
+                    .collect(),
+            ),
+            InobtBlock::Node { level, children } => DecodedBlock::InobtNode { level, children },
+        },
+        BlockKind::Bmbt => match parse_bmbt_block(buf, ctx)? {
+            BmbtBlock::Leaf(extents) => DecodedBlock::BmbtLeaf(extents),
+            BmbtBlock::Node { level, children } => DecodedBlock::BmbtNode { level, children },
+        },
+        BlockKind::Dir => {
+            let mut entries = Vec::new();
+            let kind = parse_dir_data_block_staged(buf, 0, ctx, &mut |e| {
+                entries.push(DecodedDirEntry {
+                    offset: e.location.map_or(0, |loc| loc.offset),
+                    ino: e.child_ino,
+                    name: e.name.to_vec(),
+                    file_type: e.file_type,
+                });
+                ControlFlow::Continue(())
+            })?;
+            DecodedBlock::Dir { kind, entries }
+        }
+        BlockKind::Symlink => decode_symlink(buf, ctx)?,
+    })
+}
+
+fn decode_agfl(buf: &[u8], ctx: &FsContext) -> Result<DecodedBlock, FxfspError> {
+    let sector = buf
+        .get(..ctx.sect_size as usize)
+        .ok_or(FxfspError::Parse("buffer too small for AGFL"))?;
+    let slots = match ctx.version {
+        FormatVersion::V4 => sector,
+        FormatVersion::V5 => {
+            if sector.get(..4) != Some(&XFS_AGFL_MAGIC.to_be_bytes()[..]) {
+                return Err(FxfspError::BadMagic("AGFL"));
+            }
+            // magic, seqno, uuid, lsn, crc
+            sector.get(36..).unwrap_or_default()
+        }
+    };
+    let slots = slots
+        .chunks_exact(4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    Ok(DecodedBlock::Agfl { slots })
+}
+
+fn decode_inodes(buf: &[u8], ctx: &FsContext) -> Result<DecodedBlock, FxfspError> {
+    let inode_size = ctx.inode_size as usize;
+    if buf.len() < inode_size {
+        return Err(FxfspError::Parse("buffer too small for an inode"));
+    }
+    let is_v5 = ctx.version == FormatVersion::V5;
+    let inodes = buf
+        .chunks_exact(inode_size)
+        .map(|slot| {
+            let ino = slot
+                .get(V4_CORE_SIZE..)
+                .filter(|_| is_v5)
+                .and_then(|tail| XfsDinodeV3::ref_from_prefix(tail).ok())
+                .map_or(0, |(v3, _)| v3.di_ino.get());
+            parse_inode_core(slot, ino, is_v5, ctx.has_nrext64, ctx.inode_size).ok()
+        })
+        .collect();
+    Ok(DecodedBlock::Inodes(inodes))
+}
+
+fn decode_symlink(buf: &[u8], ctx: &FsContext) -> Result<DecodedBlock, FxfspError> {
+    match ctx.version {
+        FormatVersion::V4 => Ok(DecodedBlock::Symlink { offset: 0, bytes: buf.to_vec() }),
+        FormatVersion::V5 => {
+            let (hdr, rest) = XfsDsymlinkHdr::ref_from_prefix(buf)
+                .map_err(|_| FxfspError::Parse("buffer too small for symlink header"))?;
+            if hdr.sl_magic.get() != XFS_SYMLINK_MAGIC {
+                return Err(FxfspError::BadMagic("symlink block"));
+            }
+            let bytes = rest
+                .get(..hdr.sl_bytes.get() as usize)
+                .ok_or(FxfspError::Parse("symlink block bytes out of bounds"))?;
+            Ok(DecodedBlock::Symlink { offset: hdr.sl_offset.get(), bytes: bytes.to_vec() })
+        }
+    }
+}
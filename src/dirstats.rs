@@ -0,0 +1,73 @@
+//! Per-directory entry counts and child sizes.
+//!
+//! [`DirStats`] is fed the events of a scan and reports the directories with
+//! the most entries, answering "which directory has 40 million files" on a
+//! slow volume without a second pass.
+
+use std::collections::HashMap;
+
+use crate::staged::InodeInfo;
+use crate::xfs::dir::DirEntryInfo;
+
+/// Entry count and total child size of one directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirStat {
+    pub ino: u64,
+    /// Entries other than `.` and `..`.
+    pub entries: u64,
+    /// Sum of the logical sizes of the children. A hard-linked file counts
+    /// once in every directory that names it.
+    pub child_bytes: u64,
+}
+
+/// Collects scan events into per-directory statistics.
+#[derive(Debug, Default)]
+pub struct DirStats {
+    sizes: HashMap<u64, u64>,
+    dirs: HashMap<u64, DirStat>,
+    /// `(parent, child)` entries seen before the child's inode.
+    pending: Vec<(u64, u64)>,
+}
+
+impl DirStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an inode's size.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        self.sizes.insert(inode.ino, inode.size);
+    }
+
+    /// Record a directory entry.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) {
+        if de.is_dot() {
+            return;
+        }
+        let stat = self.dirs.entry(de.parent_ino).or_insert(DirStat {
+            ino: de.parent_ino,
+            entries: 0,
+            child_bytes: 0,
+        });
+        stat.entries += 1;
+        // Children in a later AG have not been reported yet.
+        match self.sizes.get(&de.child_ino) {
+            Some(&size) => stat.child_bytes += size,
+            None => self.pending.push((de.parent_ino, de.child_ino)),
+        }
+    }
+
+    /// Finish and return the `n` directories with the most entries, largest
+    /// first. Ties are broken by inode number.
+    pub fn top(mut self, n: usize) -> Vec<DirStat> {
+        for (parent, child) in self.pending.drain(..) {
+            if let (Some(stat), Some(&size)) = (self.dirs.get_mut(&parent), self.sizes.get(&child)) {
+                stat.child_bytes += size;
+            }
+        }
+        let mut dirs: Vec<DirStat> = self.dirs.into_values().collect();
+        dirs.sort_unstable_by(|a, b| b.entries.cmp(&a.entries).then(a.ino.cmp(&b.ino)));
+        dirs.truncate(n);
+        dirs
+    }
+}
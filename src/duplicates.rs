@@ -0,0 +1,86 @@
+//! Duplicate names within a directory.
+//!
+//! A healthy directory never holds a name twice; a corrupt one can, when
+//! two data blocks (or two slots of one) claim it. [`DuplicateNames`]
+//! reports each such name with both locations, so the blocks can be looked
+//! at with `xfs_db` before `xfs_repair` picks a winner.
+//!
+//! Directory entries are matched per AG: call
+//! [`end_ag`](DuplicateNames::end_ag) after each AG's directory phase.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use crate::xfs::dir::{DirEntryInfo, DirEntryLocation};
+
+/// One occurrence of a name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NameSlot {
+    pub location: DirEntryLocation,
+    pub child_ino: u64,
+}
+
+/// A name that a directory holds more than once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DuplicateName {
+    pub dir_ino: u64,
+    pub name: Vec<u8>,
+    /// The occurrence stored first (lowest block, then offset), whatever
+    /// order the scan delivered them in.
+    pub first: NameSlot,
+    pub second: NameSlot,
+}
+
+/// Collects directory entries and finds names stored twice in the same
+/// directory.
+///
+/// Every name of the AG being scanned is held until [`end_ag`](Self::end_ag);
+/// a directory's entries are all delivered by its own AG's directory phase,
+/// so that bounds memory by the largest AG rather than the filesystem.
+#[derive(Debug, Default)]
+pub struct DuplicateNames {
+    seen: HashMap<(u64, Vec<u8>), NameSlot>,
+    found: Vec<DuplicateName>,
+}
+
+impl DuplicateNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a directory entry. Entries without a location (the implied
+    /// `.` and `..` of shortform directories) are ignored.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) {
+        let Some(location) = de.location else { return };
+        let slot = NameSlot { location, child_ino: de.child_ino };
+        match self.seen.entry((de.parent_ino, de.name.to_vec())) {
+            Entry::Vacant(vacant) => {
+                vacant.insert(slot);
+            }
+            Entry::Occupied(mut occupied) => {
+                let other = *occupied.get();
+                let (first, second) = if other.location <= location { (other, slot) } else { (slot, other) };
+                // Keep the earliest occurrence for any further copies.
+                occupied.insert(first);
+                self.found.push(DuplicateName { dir_ino: de.parent_ino, name: de.name.to_vec(), first, second });
+            }
+        }
+    }
+
+    /// Forget the names of the AG just scanned.
+    pub fn end_ag(&mut self) {
+        self.seen = HashMap::new();
+    }
+
+    /// Finish and return every duplicate, sorted by directory, then by the
+    /// first occurrence's location. A name stored three times is reported
+    /// twice.
+    pub fn finish(mut self) -> Vec<DuplicateName> {
+        self.found.sort_by(|a, b| {
+            (a.dir_ino, a.first.location, a.second.location).cmp(&(b.dir_ino, b.first.location, b.second.location))
+        });
+        self.found
+    }
+}
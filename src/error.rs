@@ -2,8 +2,18 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum FxfspError {
+    #[cfg(feature = "std")]
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
+    #[cfg(feature = "sqlite")]
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "parquet")]
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[cfg(feature = "kafka")]
+    #[error("Kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
     #[error("Bad magic number in {0}")]
     BadMagic(&'static str),
     #[error("Parse error: {0}")]
@@ -13,4 +23,8 @@ pub enum FxfspError {
     /// Scan was stopped early by the callback (not a real error).
     #[error("scan stopped by callback")]
     Stopped,
+    /// [`ScanOptions::deadline`](crate::ScanOptions::deadline) passed
+    /// before the AG was opened.
+    #[error("scan deadline passed")]
+    DeadlineExceeded,
 }
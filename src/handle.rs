@@ -0,0 +1,169 @@
+//! Flow control for running scans.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Instant;
+
+use crate::reader::IoPhase;
+
+/// Pauses, resumes and throttles a scan from another thread.
+///
+/// Obtained from [`FsScanner::scan_handle`](crate::FsScanner::scan_handle).
+/// The scan checks the handle at each phase boundary (before opening an AG,
+/// walking a btree, or submitting a read batch) and blocks there while it
+/// is paused or has too many unconsumed events. The batch in flight always
+/// completes; progress is kept and nothing is re-read. Under an event limit,
+/// batches that deliver events are split to fit the events left before it,
+/// so it is overshot by at most one read's events.
+#[derive(Debug, Clone, Default)]
+pub struct ScanHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    paused: Mutex<bool>,
+    wake: Condvar,
+    /// Events delivered to callbacks but not yet acknowledged.
+    outstanding: AtomicU64,
+    /// Block while `outstanding` reaches this; 0 disables backpressure.
+    limit: u64,
+    /// [`ScanOptions::deadline`](crate::ScanOptions::deadline), from the
+    /// start of the scan.
+    deadline: Option<Instant>,
+    stops: Mutex<Vec<DeadlineStop>>,
+}
+
+/// Where a [`ScanOptions::deadline`](crate::ScanOptions::deadline) cut a
+/// scan short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineStop {
+    /// The AG being scanned, or about to be opened.
+    pub ag_number: u32,
+    /// The batch that was not submitted: [`IoPhase::Agi`] if the AG was
+    /// never opened.
+    pub phase: IoPhase,
+}
+
+impl ScanHandle {
+    /// A handle that applies backpressure once `limit` delivered events
+    /// are unacknowledged (see [`consumed`](Self::consumed)); 0 never does.
+    /// Past `deadline`, the scan stops at its next batch boundary.
+    pub(crate) fn new(limit: usize, deadline: Option<Instant>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limit: limit as u64,
+                deadline,
+                ..Inner::default()
+            }),
+        }
+    }
+
+    /// Ask the scan to stop at its next phase boundary.
+    pub fn pause(&self) {
+        *self.lock() = true;
+    }
+
+    /// Let a paused scan continue.
+    pub fn resume(&self) {
+        *self.lock() = false;
+        self.inner.wake.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.lock()
+    }
+
+    /// Acknowledge that `n` delivered events have been consumed (e.g. taken
+    /// off the channel the callbacks feed). Only meaningful with
+    /// [`ScanOptions::max_unconsumed_events`](crate::ScanOptions::max_unconsumed_events).
+    pub fn consumed(&self, n: usize) {
+        let n = n as u64;
+        let _ = self
+            .inner
+            .outstanding
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |v| Some(v.saturating_sub(n)));
+        // Notify under the lock so a scanner about to wait can't miss it.
+        let _guard = self.lock();
+        self.inner.wake.notify_all();
+    }
+
+    /// Events delivered but not yet acknowledged.
+    pub fn unconsumed(&self) -> usize {
+        self.inner.outstanding.load(Ordering::Acquire) as usize
+    }
+
+    /// Whether the scan's deadline has passed.
+    pub fn deadline_passed(&self) -> bool {
+        self.inner.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub(crate) fn has_deadline(&self) -> bool {
+        self.inner.deadline.is_some()
+    }
+
+    /// Where the deadline cut the scan short, one entry per AG, in the
+    /// order they were hit. For a sequential scan there is at most one:
+    /// every AG handed out before it was scanned in full, and the phases
+    /// of its AG before `phase` ran to the end.
+    pub fn deadline_stops(&self) -> Vec<DeadlineStop> {
+        self.inner.stops.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// At a phase or batch boundary of AG `agno`: whether the deadline has
+    /// passed, recording the first such boundary of the AG.
+    pub(crate) fn cut_short(&self, agno: u32, phase: IoPhase) -> bool {
+        if !self.deadline_passed() {
+            return false;
+        }
+        let mut stops = self.inner.stops.lock().unwrap_or_else(|e| e.into_inner());
+        if !stops.iter().any(|stop| stop.ag_number == agno) {
+            stops.push(DeadlineStop { ag_number: agno, phase });
+        }
+        true
+    }
+
+    /// Before opening AG `agno` of a sequential scan: whether the deadline
+    /// has passed, recording the stop unless an earlier AG was cut short.
+    pub(crate) fn stops_before(&self, agno: u32) -> bool {
+        if !self.deadline_passed() {
+            return false;
+        }
+        let mut stops = self.inner.stops.lock().unwrap_or_else(|e| e.into_inner());
+        if stops.is_empty() {
+            stops.push(DeadlineStop { ag_number: agno, phase: IoPhase::Agi });
+        }
+        true
+    }
+
+    /// Count one event delivered to a callback. Never blocks: it runs
+    /// inside read completion handling.
+    pub(crate) fn delivered(&self) {
+        if self.inner.limit > 0 {
+            self.inner.outstanding.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Events that can be delivered before the limit is reached; `None`
+    /// without a limit.
+    pub(crate) fn events_left(&self) -> Option<u64> {
+        (self.inner.limit > 0)
+            .then(|| self.inner.limit.saturating_sub(self.inner.outstanding.load(Ordering::Acquire)))
+    }
+
+    /// Block while paused or over the unconsumed-event limit.
+    pub(crate) fn wait_until_clear(&self) {
+        let mut paused = self.lock();
+        while *paused || self.over_limit() {
+            paused = self.inner.wake.wait(paused).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn over_limit(&self) -> bool {
+        self.inner.limit > 0 && self.inner.outstanding.load(Ordering::Acquire) >= self.inner.limit
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, bool> {
+        self.inner.paused.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
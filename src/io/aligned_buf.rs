@@ -1,11 +1,35 @@
-use aligned_vec::{AVec, ConstAlign};
+use core::ops::{Deref, DerefMut};
 
 /// Alignment required for O_DIRECT I/O (512 bytes covers all common block devices).
 pub const IO_ALIGN: usize = 512;
 
-pub type AlignedBuf = AVec<u8, ConstAlign<IO_ALIGN>>;
+/// A zeroed byte buffer whose start is aligned to [`IO_ALIGN`].
+///
+/// Over-allocates by `IO_ALIGN - 1` bytes and starts at the first aligned
+/// byte; the backing `Vec` is never resized, so the alignment holds.
+pub struct AlignedBuf {
+    raw: Vec<u8>,
+    start: usize,
+    len: usize,
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.raw[self.start..self.start + self.len]
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.raw[self.start..self.start + self.len]
+    }
+}
 
 /// Create a new aligned buffer of `size` bytes, zeroed.
 pub fn alloc_aligned(size: usize) -> AlignedBuf {
-    AVec::from_iter(IO_ALIGN, std::iter::repeat_n(0u8, size))
+    let raw = vec![0u8; size + IO_ALIGN - 1];
+    let start = raw.as_ptr().align_offset(IO_ALIGN);
+    AlignedBuf { raw, start, len: size }
 }
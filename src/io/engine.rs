@@ -3,12 +3,15 @@ use std::os::fd::RawFd;
 
 use crate::error::FxfspError;
 use crate::io::aligned_buf::{AlignedBuf, IO_ALIGN, alloc_aligned};
-use crate::io::platform::{configure_direct_io, direct_open_flags};
+use crate::io::platform::{configure_direct_io, direct_open_flags, pread_at, size_of_fd};
 
 /// Physical characteristics of the underlying block device.
 pub struct DiskProfile {
     pub is_rotational: bool,
     pub max_io_bytes: usize,
+    /// Preferred I/O size reported by the device (RAID stripe width on md
+    /// and most hardware arrays), 0 if unknown.
+    pub optimal_io_bytes: usize,
 }
 
 impl Default for DiskProfile {
@@ -16,6 +19,7 @@ impl Default for DiskProfile {
         Self {
             is_rotational: true,
             max_io_bytes: 1024 * 1024,
+            optimal_io_bytes: 0,
         }
     }
 }
@@ -24,8 +28,8 @@ impl std::fmt::Display for DiskProfile {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Disk: rotational={} max_io={}",
-            self.is_rotational, self.max_io_bytes
+            "Disk: rotational={} max_io={} optimal_io={}",
+            self.is_rotational, self.max_io_bytes, self.optimal_io_bytes
         )
     }
 }
@@ -73,9 +77,14 @@ fn detect_disk_profile(fd: RawFd) -> DiskProfile {
 
     let max_io_bytes = max_sectors_kb * 1024;
 
+    let optimal_io_bytes = read_queue_file("optimal_io_size")
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+
     DiskProfile {
         is_rotational,
         max_io_bytes,
+        optimal_io_bytes,
     }
 }
 
@@ -102,19 +111,25 @@ pub fn detect_disk_profile_for_path(path: &str) -> DiskProfile {
 }
 
 /// Default buffer size: 256 MiB (large for batch reads).
-const DEFAULT_BUF_SIZE: usize = 256 * 1024 * 1024;
+pub const DEFAULT_BUF_SIZE: usize = 256 * 1024 * 1024;
 
 /// Maximum number of I/O operations in flight at once for `read_batch`.
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
 const BATCH_QUEUE_DEPTH: usize = 128;
 
 /// A direct-I/O engine with a single reusable aligned buffer.
+///
+/// `IoEngine` is `Send` and `Sync`: it exclusively owns its file descriptor
+/// and buffers, and io_uring rings (with the `io-uring` feature) live only
+/// for the duration of a batch.
+/// Reads take `&mut self`, so concurrent scans need one engine per thread.
 pub struct IoEngine {
     fd: RawFd,
     buf: AlignedBuf,
     device_size: u64,
     merge_gap: usize,
     max_merged: usize,
+    stripe_width: usize,
 }
 
 impl IoEngine {
@@ -123,6 +138,18 @@ impl IoEngine {
     /// `merge_gap`: maximum gap (bytes) between two reads to coalesce them.
     /// `max_merged`: maximum size (bytes) of a single coalesced read.
     pub fn open(path: &str, merge_gap: usize, max_merged: usize) -> Result<Self, FxfspError> {
+        Self::open_with_buffer_size(path, merge_gap, max_merged, DEFAULT_BUF_SIZE)
+    }
+
+    /// Like [`open`](Self::open), with an explicit initial `read_at` buffer
+    /// size instead of [`DEFAULT_BUF_SIZE`]; the buffer grows on demand.
+    /// Services running many engines at once use this to bound memory.
+    pub fn open_with_buffer_size(
+        path: &str,
+        merge_gap: usize,
+        max_merged: usize,
+        buf_size: usize,
+    ) -> Result<Self, FxfspError> {
         let c_path =
             CString::new(path).map_err(|_| FxfspError::Parse("invalid path (contains NUL)"))?;
         let flags = direct_open_flags();
@@ -132,24 +159,36 @@ impl IoEngine {
         }
         configure_direct_io(fd)?;
 
-        // Get device/file size via lseek to end.
-        let size = unsafe { libc::lseek(fd, 0, libc::SEEK_END) };
-        if size < 0 {
-            unsafe {
-                libc::close(fd);
+        let size = match size_of_fd(fd) {
+            Ok(size) => size,
+            Err(e) => {
+                unsafe {
+                    libc::close(fd);
+                }
+                return Err(FxfspError::Io(e));
             }
-            return Err(FxfspError::Io(std::io::Error::last_os_error()));
-        }
+        };
 
         Ok(Self {
             fd,
-            buf: alloc_aligned(DEFAULT_BUF_SIZE),
-            device_size: size as u64,
+            buf: alloc_aligned(buf_size),
+            device_size: size,
             merge_gap,
             max_merged,
+            stripe_width: 0,
         })
     }
 
+    /// Align coalesced reads to a RAID stripe width (bytes, 0 to disable).
+    ///
+    /// Merged reads are capped at a whole number of stripes and never cross a
+    /// stripe boundary once they reach a full stripe, so each read maps onto
+    /// complete stripes instead of straddling two. Typically set from
+    /// `FsContext::stripe_width_bytes()` or `DiskProfile::optimal_io_bytes`.
+    pub fn set_stripe_width(&mut self, bytes: usize) {
+        self.stripe_width = bytes;
+    }
+
     /// Device/file size in bytes.
     pub fn device_size(&self) -> u64 {
         self.device_size
@@ -160,11 +199,7 @@ impl IoEngine {
     /// Returns a slice into the internal buffer (may be shorter than `len`
     /// if near end of device).
     pub fn read_at(&mut self, offset: u64, len: usize) -> Result<&[u8], FxfspError> {
-        // Clamp to device boundary.
-        let available = self.device_size.saturating_sub(offset) as usize;
-        let clamped = len.min(available);
-        // Round down to alignment.
-        let clamped = clamped & !(IO_ALIGN - 1);
+        let clamped = self.clamp_len(offset, len);
         if clamped == 0 {
             return Err(FxfspError::Io(std::io::Error::new(
                 std::io::ErrorKind::UnexpectedEof,
@@ -179,21 +214,12 @@ impl IoEngine {
 
         let mut total = 0usize;
         while total < clamped {
-            let ret = unsafe {
-                libc::pread(
-                    self.fd,
-                    self.buf[total..].as_mut_ptr() as *mut libc::c_void,
-                    clamped - total,
-                    (offset + total as u64) as libc::off_t,
-                )
-            };
-            if ret < 0 {
-                return Err(FxfspError::Io(std::io::Error::last_os_error()));
-            }
+            let ret = pread_at(self.fd, &mut self.buf[total..clamped], offset + total as u64)
+                .map_err(FxfspError::Io)?;
             if ret == 0 {
                 break; // EOF
             }
-            total += ret as usize;
+            total += ret;
         }
 
         if total == 0 {
@@ -206,9 +232,20 @@ impl IoEngine {
         Ok(&self.buf[..total])
     }
 
+    /// `len` clamped to the device end and rounded down to [`IO_ALIGN`].
+    ///
+    /// The distance to the device end is a `u64`; it is only narrowed when
+    /// it fits, so 32-bit targets don't truncate it.
+    fn clamp_len(&self, offset: u64, len: usize) -> usize {
+        let available = self.device_size.saturating_sub(offset);
+        let clamped = usize::try_from(available).map_or(len, |available| len.min(available));
+        clamped & !(IO_ALIGN - 1)
+    }
+
     /// Read with coalescing: merge sorted requests whose gaps fall within
     /// `merge_gap` into larger sequential reads, then submit the merged
-    /// reads through `read_batch` (io_uring on Linux).
+    /// reads through `read_batch` (io_uring on Linux with the `io-uring`
+    /// feature, pread otherwise).
     ///
     /// `requests` **must** be sorted by offset (ascending).
     pub fn coalesced_read_batch<T: Copy, F>(
@@ -224,7 +261,12 @@ impl IoEngine {
         }
 
         let merge_gap = self.merge_gap;
-        let max_merged = self.max_merged;
+        let stripe = self.stripe_width as u64;
+        let max_merged = if stripe > 0 {
+            (self.max_merged / self.stripe_width).max(1) * self.stripe_width
+        } else {
+            self.max_merged
+        };
 
         // ---- Build merged groups ----
         struct MergedGroup {
@@ -243,8 +285,11 @@ impl IoEngine {
             let flush = if i < requests.len() {
                 let gap = requests[i].0.saturating_sub(g_end);
                 let new_end = requests[i].0 + requests[i].1 as u64;
-                let new_len = (new_end - g_start) as usize;
-                gap > merge_gap as u64 || new_len > max_merged
+                let new_len = new_end - g_start;
+                let crosses_stripe = stripe > 0
+                    && g_end - g_start >= stripe
+                    && requests[i].0 / stripe != (g_end - 1) / stripe;
+                gap > merge_gap as u64 || new_len > max_merged as u64 || crosses_stripe
             } else {
                 true
             };
@@ -278,8 +323,7 @@ impl IoEngine {
 
         self.read_batch(&merged_requests, |buf, gi| {
             let g = &groups[gi];
-            for j in g.sub_start..g.sub_end {
-                let (offset, len, tag) = requests[j];
+            for &(offset, len, tag) in &requests[g.sub_start..g.sub_end] {
                 let rel = (offset - g.offset) as usize;
                 let end = (rel + len).min(buf.len());
                 if rel < buf.len() {
@@ -318,7 +362,7 @@ impl crate::reader::IoReader for IoEngine {
 
 // ---- Batch read: io_uring on Linux, pread fallback elsewhere ----
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
 impl IoEngine {
     /// Batch-read multiple (offset, len) pairs, calling `on_complete` for each.
     ///
@@ -375,13 +419,13 @@ impl IoEngine {
                     let (offset, len, tag) = requests[next_req];
                     next_req += 1;
 
-                    let available = self.device_size.saturating_sub(offset) as usize;
-                    let clamped = len.min(available) & !(IO_ALIGN - 1);
+                    // A single io_uring read is at most u32::MAX bytes; short
+                    // reads are reported like those at the device end.
+                    let clamped = self.clamp_len(offset, len.min(u32::MAX as usize));
                     if clamped == 0 {
                         continue;
                     }
 
-
                     let slot = free_slots.pop().unwrap();
                     slot_tags[slot] = Some(tag);
                     slot_lens[slot] = clamped;
@@ -397,8 +441,7 @@ impl IoEngine {
 
                     unsafe {
                         sq.push(&sqe).map_err(|_| {
-                            FxfspError::Io(std::io::Error::new(
-                                std::io::ErrorKind::Other,
+                            FxfspError::Io(std::io::Error::other(
                                 "io_uring submission queue full",
                             ))
                         })?;
@@ -450,12 +493,13 @@ impl IoEngine {
     }
 }
 
-#[cfg(not(target_os = "linux"))]
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
 impl IoEngine {
     /// Batch-read multiple (offset, len) pairs, calling `on_complete` for each.
     ///
-    /// Fallback implementation using sequential pread() calls.  Same API as
-    /// the Linux io_uring version so all callers are platform-agnostic.
+    /// Fallback implementation using sequential pread() calls, for other
+    /// platforms and builds without the `io-uring` feature. Same API as the
+    /// io_uring version so all callers are platform-agnostic.
     fn read_batch<T: Copy, F>(
         &mut self,
         requests: &[(u64, usize, T)],
@@ -473,30 +517,19 @@ impl IoEngine {
         let mut buf = alloc_aligned(aligned_max);
 
         for &(offset, len, tag) in requests {
-            let available = self.device_size.saturating_sub(offset) as usize;
-            let clamped = len.min(available) & !(IO_ALIGN - 1);
+            let clamped = self.clamp_len(offset, len);
             if clamped == 0 {
                 continue;
             }
 
-
             let mut total = 0usize;
             while total < clamped {
-                let ret = unsafe {
-                    libc::pread(
-                        self.fd,
-                        buf[total..].as_mut_ptr() as *mut libc::c_void,
-                        clamped - total,
-                        (offset + total as u64) as libc::off_t,
-                    )
-                };
-                if ret < 0 {
-                    return Err(FxfspError::Io(std::io::Error::last_os_error()));
-                }
+                let ret = pread_at(self.fd, &mut buf[total..clamped], offset + total as u64)
+                    .map_err(FxfspError::Io)?;
                 if ret == 0 {
                     break;
                 }
-                total += ret as usize;
+                total += ret;
             }
 
             if total > 0 {
@@ -1,4 +1,9 @@
+#[cfg(feature = "io")]
 pub mod aligned_buf;
+#[cfg(feature = "io")]
 pub mod engine;
+#[cfg(feature = "io")]
 pub mod platform;
 pub mod reader;
+#[cfg(feature = "io")]
+pub mod session;
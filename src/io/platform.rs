@@ -23,10 +23,47 @@ pub fn configure_direct_io(_fd: RawFd) -> std::io::Result<()> {
 /// Return platform-specific open flags for direct I/O.
 #[cfg(target_os = "linux")]
 pub fn direct_open_flags() -> libc::c_int {
-    libc::O_RDONLY | libc::O_DIRECT
+    // O_LARGEFILE is 0 on 64-bit targets; 32-bit ones need it to open
+    // images over 2 GiB.
+    libc::O_RDONLY | libc::O_DIRECT | libc::O_LARGEFILE
 }
 
 #[cfg(target_os = "macos")]
 pub fn direct_open_flags() -> libc::c_int {
     libc::O_RDONLY
 }
+
+/// `pread` into `buf` at a 64-bit offset, returning the bytes read.
+///
+/// `off_t` is 32 bits on 32-bit Linux without LFS, so the `64` variants are
+/// used there; macOS `off_t` is always 64 bits.
+#[cfg(target_os = "linux")]
+pub fn pread_at(fd: RawFd, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    let offset = libc::off64_t::try_from(offset).map_err(|_| overflow())?;
+    let ret = unsafe { libc::pread64(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), offset) };
+    usize::try_from(ret).map_err(|_| std::io::Error::last_os_error())
+}
+
+#[cfg(target_os = "macos")]
+pub fn pread_at(fd: RawFd, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    let offset = libc::off_t::try_from(offset).map_err(|_| overflow())?;
+    let ret = unsafe { libc::pread(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), offset) };
+    usize::try_from(ret).map_err(|_| std::io::Error::last_os_error())
+}
+
+/// Size of the file or device behind `fd`, by seeking to its end.
+#[cfg(target_os = "linux")]
+pub fn size_of_fd(fd: RawFd) -> std::io::Result<u64> {
+    let size = unsafe { libc::lseek64(fd, 0, libc::SEEK_END) };
+    u64::try_from(size).map_err(|_| std::io::Error::last_os_error())
+}
+
+#[cfg(target_os = "macos")]
+pub fn size_of_fd(fd: RawFd) -> std::io::Result<u64> {
+    let size = unsafe { libc::lseek(fd, 0, libc::SEEK_END) };
+    u64::try_from(size).map_err(|_| std::io::Error::last_os_error())
+}
+
+fn overflow() -> std::io::Error {
+    std::io::Error::from_raw_os_error(libc::EOVERFLOW)
+}
@@ -1,5 +1,9 @@
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+use crate::clock::{Clock, SystemClock};
 use crate::error::FxfspError;
 use crate::reader::{IoPhase, IoReader};
 
@@ -9,19 +13,49 @@ pub struct InstrumentedReader<R> {
     io_log: std::io::BufWriter<std::fs::File>,
     remaining: usize,
     batch: u64,
+    /// Set when each row carries a monotonic `elapsed_us` timestamp: the
+    /// clock and its reading when the reader was created.
+    started: Option<(Arc<dyn Clock>, Duration)>,
 }
 
 impl<R> InstrumentedReader<R> {
     /// Wrap `inner` with CSV logging to the given file path.
     pub fn new(inner: R, log_path: &str, limit: usize) -> Result<Self, FxfspError> {
+        Self::open(inner, Path::new(log_path), limit, None)
+    }
+
+    /// Like [`new`](Self::new), with an extra `elapsed_us` column: monotonic
+    /// microseconds since the reader was created.
+    pub fn with_timestamps(inner: R, log_path: &str, limit: usize) -> Result<Self, FxfspError> {
+        Self::with_clock(inner, log_path, limit, Arc::new(SystemClock::default()))
+    }
+
+    /// Like [`with_timestamps`](Self::with_timestamps), reading time from
+    /// `clock` (e.g. a [`FixedClock`](crate::clock::FixedClock) in tests).
+    pub fn with_clock(inner: R, log_path: &str, limit: usize, clock: Arc<dyn Clock>) -> Result<Self, FxfspError> {
+        let origin = clock.monotonic();
+        Self::open(inner, Path::new(log_path), limit, Some((clock, origin)))
+    }
+
+    fn open(
+        inner: R,
+        log_path: &Path,
+        limit: usize,
+        started: Option<(Arc<dyn Clock>, Duration)>,
+    ) -> Result<Self, FxfspError> {
         let f = std::fs::File::create(log_path).map_err(FxfspError::Io)?;
         let mut w = std::io::BufWriter::new(f);
-        writeln!(w, "batch,phase,offset,len").map_err(FxfspError::Io)?;
+        let header = match started {
+            Some(_) => "batch,phase,offset,len,elapsed_us",
+            None => "batch,phase,offset,len",
+        };
+        writeln!(w, "{header}").map_err(FxfspError::Io)?;
         Ok(Self {
             inner,
             io_log: w,
             remaining: limit,
             batch: 0,
+            started,
         })
     }
 
@@ -29,7 +63,18 @@ impl<R> InstrumentedReader<R> {
         if self.remaining == 0 {
             return;
         }
-        let _ = writeln!(self.io_log, "{},{},{},{}", self.batch, phase, offset, len);
+        let _ = match &self.started {
+            Some((clock, origin)) => writeln!(
+                self.io_log,
+                "{},{},{},{},{}",
+                self.batch,
+                phase,
+                offset,
+                len,
+                clock.monotonic().saturating_sub(*origin).as_micros()
+            ),
+            None => writeln!(self.io_log, "{},{},{},{}", self.batch, phase, offset, len),
+        };
         self.remaining -= 1;
     }
 }
@@ -58,30 +103,70 @@ impl<R: IoReader> IoReader for InstrumentedReader<R> {
     }
 }
 
+/// Explicit I/O instrumentation settings.
+///
+/// The library never reads the process environment on its own; CLIs that
+/// want the `FXFSP_IO_LOG` convention call [`from_env`](Self::from_env).
+#[derive(Debug, Clone, Default)]
+pub struct InstrumentationConfig {
+    /// CSV file to log reads to; `None` disables instrumentation.
+    pub io_log: Option<PathBuf>,
+    /// Maximum number of reads to log; `None` logs all of them.
+    pub limit: Option<usize>,
+    /// Add a monotonic `elapsed_us` column.
+    pub timestamps: bool,
+    /// Time source for timestamps; `None` uses the system clock.
+    pub clock: Option<Arc<dyn Clock>>,
+}
+
+impl InstrumentationConfig {
+    /// Build a config from environment variables.
+    ///
+    /// `FXFSP_IO_LOG` sets the log path, `FXFSP_IO_LOG_LIMIT` optionally caps
+    /// the number of logged operations.
+    pub fn from_env() -> Self {
+        Self {
+            io_log: std::env::var_os("FXFSP_IO_LOG").map(PathBuf::from),
+            limit: std::env::var("FXFSP_IO_LOG_LIMIT")
+                .ok()
+                .and_then(|s| s.parse::<usize>().ok()),
+            ..Self::default()
+        }
+    }
+}
+
 /// Runtime choice between a bare reader and an instrumented one.
 ///
 /// Avoids dynamic dispatch while allowing the decision to be made at runtime
-/// (e.g. based on environment variables).
+/// (e.g. from an [`InstrumentationConfig`]).
 pub enum MaybeInstrumented<R> {
     Bare(R),
     Instrumented(InstrumentedReader<R>),
 }
 
 impl<R> MaybeInstrumented<R> {
+    /// Wrap `inner` as `config` asks: with CSV logging if `config.io_log` is
+    /// set, bare otherwise.
+    pub fn new(inner: R, config: &InstrumentationConfig) -> Result<Self, FxfspError> {
+        let Some(path) = &config.io_log else {
+            return Ok(Self::Bare(inner));
+        };
+        let limit = config.limit.unwrap_or(usize::MAX);
+        let started = config.timestamps.then(|| {
+            let clock = config.clock.clone().unwrap_or_else(|| Arc::new(SystemClock::default()));
+            let origin = clock.monotonic();
+            (clock, origin)
+        });
+        Ok(Self::Instrumented(InstrumentedReader::open(inner, path, limit, started)?))
+    }
+
     /// Construct from environment variables.
     ///
     /// If `FXFSP_IO_LOG` is set, wraps `inner` with CSV logging.
     /// `FXFSP_IO_LOG_LIMIT` optionally caps the number of logged operations.
+    #[deprecated(note = "use `MaybeInstrumented::new(inner, &InstrumentationConfig::from_env())`")]
     pub fn from_env(inner: R) -> Result<Self, FxfspError> {
-        if let Ok(path) = std::env::var("FXFSP_IO_LOG") {
-            let limit = std::env::var("FXFSP_IO_LOG_LIMIT")
-                .ok()
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(usize::MAX);
-            Ok(Self::Instrumented(InstrumentedReader::new(inner, &path, limit)?))
-        } else {
-            Ok(Self::Bare(inner))
-        }
+        Self::new(inner, &InstrumentationConfig::from_env())
     }
 }
 
@@ -0,0 +1,62 @@
+//! Per-scan configuration owned by value.
+//!
+//! fxfsp keeps no global state: every tuning knob lives in a [`Session`],
+//! and each engine owns its file descriptor and buffers. A server can hold
+//! one `Session` per device (or per tenant) and run their scans
+//! concurrently with different tunings and logging.
+
+use crate::error::FxfspError;
+use crate::io::engine::{DEFAULT_BUF_SIZE, IoEngine, detect_disk_profile_for_path};
+use crate::io::reader::{InstrumentationConfig, MaybeInstrumented};
+use crate::staged::{FsScanner, ScanOptions, SuperblockInfo, parse_superblock_with_options};
+
+/// Configuration for scanning one device.
+#[derive(Debug, Clone)]
+pub struct Session {
+    /// Maximum gap (bytes) between two reads to coalesce them.
+    pub merge_gap: usize,
+    /// Maximum size (bytes) of a single coalesced read.
+    pub max_merged: usize,
+    /// Initial `read_at` buffer size per engine.
+    pub buffer_size: usize,
+    /// Stripe width (bytes) to align coalesced reads to. `None` uses the
+    /// device's optimal I/O size; `Some(0)` disables alignment.
+    pub stripe_width: Option<usize>,
+    /// I/O logging; give concurrent sessions distinct log paths.
+    pub instrumentation: InstrumentationConfig,
+    pub scan: ScanOptions,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self {
+            merge_gap: 256 * 1024,
+            max_merged: 2 * 1024 * 1024,
+            buffer_size: DEFAULT_BUF_SIZE,
+            stripe_width: None,
+            instrumentation: InstrumentationConfig::default(),
+            scan: ScanOptions::default(),
+        }
+    }
+}
+
+impl Session {
+    /// Open an engine for `path` configured by this session.
+    pub fn open_engine(&self, path: &str) -> Result<MaybeInstrumented<IoEngine>, FxfspError> {
+        let mut engine =
+            IoEngine::open_with_buffer_size(path, self.merge_gap, self.max_merged, self.buffer_size)?;
+        let stripe_width = self
+            .stripe_width
+            .unwrap_or_else(|| detect_disk_profile_for_path(path).optimal_io_bytes);
+        engine.set_stripe_width(stripe_width);
+        MaybeInstrumented::new(engine, &self.instrumentation)
+    }
+
+    /// Open `path` and parse its superblock, ready to scan.
+    pub fn open(
+        &self,
+        path: &str,
+    ) -> Result<(SuperblockInfo, FsScanner<MaybeInstrumented<IoEngine>>), FxfspError> {
+        parse_superblock_with_options(self.open_engine(path)?, self.scan.clone())
+    }
+}
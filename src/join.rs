@@ -0,0 +1,233 @@
+//! Inodes joined with their directory entries as the scan goes.
+//!
+//! Collecting every inode and every entry and joining the two maps at the
+//! end holds the whole filesystem in memory. [`FileJoin`] instead releases a
+//! [`FileRecord`] as soon as its inode, its extents and all of its links
+//! (`nlink` of them; one for a directory) have been seen, keeping only the
+//! records still waiting for something, plus the name and parent of every
+//! directory to build paths from.
+//!
+//! Call [`end_ag`](FileJoin::end_ag) once each AG's phases are done and take
+//! finished records from [`drain_ready`](FileJoin::drain_ready) as you go;
+//! [`finish`](FileJoin::finish) hands back the ones that never completed.
+
+use std::collections::HashMap;
+use std::vec::Drain;
+
+use crate::staged::{FileExtentsInfo, InodeInfo, SuperblockInfo};
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::extent::Extent;
+use crate::xfs::inode::{S_IFDIR, S_IFMT, S_IFREG};
+
+/// `XFS_DIR3_FT_DIR`.
+const FT_DIR: u8 = 2;
+
+fn is_dir(inode: &InodeInfo) -> bool {
+    inode.mode & S_IFMT == S_IFDIR
+}
+
+/// An inode with its paths and extents.
+#[derive(Debug, Clone)]
+pub struct FileRecord {
+    /// The inode. Its inline `extents` have been moved to
+    /// [`extents`](Self::extents).
+    pub stat: InodeInfo,
+    /// One path per link, each as its components below the root; the root
+    /// itself has a single empty path. A link whose parent directory never
+    /// reached the root is missing from records returned by
+    /// [`FileJoin::finish`].
+    pub paths: Vec<Vec<Vec<u8>>>,
+    /// Data fork extents of a regular file, inline or from
+    /// [`FileExtentsInfo`] events, in logical order. Empty otherwise.
+    pub extents: Vec<Extent>,
+}
+
+/// A record still missing its inode, extents, links or a parent path.
+#[derive(Debug, Default)]
+struct Pending {
+    stat: Option<InodeInfo>,
+    extents: Vec<Extent>,
+    /// `(parent, name)` of each entry naming the inode.
+    links: Vec<(u64, Vec<u8>)>,
+    /// No extent events can follow: not a btree-format file, or its AG has
+    /// ended.
+    extents_done: bool,
+}
+
+impl Pending {
+    fn expected_links(&self, root_ino: u64) -> Option<usize> {
+        let stat = self.stat.as_ref()?;
+        Some(match stat.ino {
+            ino if ino == root_ino => 0,
+            _ if is_dir(stat) => 1,
+            _ => stat.nlink as usize,
+        })
+    }
+}
+
+/// Joins inode, extent and directory entry events into [`FileRecord`]s.
+///
+/// Records become ready in whatever order their last piece arrives; take
+/// them with [`drain_ready`](Self::drain_ready) as often as convenient.
+/// Inodes with no links (deleted but still open) are never joined.
+#[derive(Debug)]
+pub struct FileJoin {
+    root_ino: u64,
+    /// Directory → (parent, name), first link wins.
+    dirs: HashMap<u64, (u64, Vec<u8>)>,
+    pending: HashMap<u64, Pending>,
+    /// Regular files with no inline extents, by AG, until the AG ends.
+    awaiting_extents: HashMap<u32, Vec<u64>>,
+    /// Directory not yet linked to its parent → records whose paths go
+    /// through it.
+    waiting_on_dir: HashMap<u64, Vec<u64>>,
+    ready: Vec<FileRecord>,
+}
+
+impl FileJoin {
+    pub fn new(sb: &SuperblockInfo) -> Self {
+        Self {
+            root_ino: sb.root_ino,
+            dirs: HashMap::new(),
+            pending: HashMap::new(),
+            awaiting_extents: HashMap::new(),
+            waiting_on_dir: HashMap::new(),
+            ready: Vec::new(),
+        }
+    }
+
+    /// Record an inode.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        if inode.nlink == 0 {
+            return;
+        }
+        let mut stat = inode.clone();
+        let inline = stat.extents.take();
+        let extents_done = inline.is_some() || stat.mode & S_IFMT != S_IFREG;
+        if !extents_done {
+            self.awaiting_extents.entry(stat.ag_number).or_default().push(stat.ino);
+        }
+        let ino = stat.ino;
+        let dir = is_dir(&stat);
+        let pending = self.pending.entry(ino).or_default();
+        pending.extents = inline.unwrap_or_default();
+        pending.extents_done = extents_done;
+        pending.stat = Some(stat);
+        // An untyped entry may have named this directory before its inode
+        // was seen.
+        if dir && let Some((parent, name)) = pending.links.first().cloned() {
+            self.link_dir(ino, parent, name);
+        }
+        self.try_complete(ino);
+    }
+
+    /// Record the extents of a btree-format file.
+    pub fn add_file_extents(&mut self, info: &FileExtentsInfo) {
+        if let Some(pending) = self.pending.get_mut(&info.ino) {
+            pending.extents.extend_from_slice(&info.extents);
+        }
+    }
+
+    /// Record a directory entry. `.` and `..` are ignored.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) {
+        if de.is_dot() {
+            return;
+        }
+        let pending = self.pending.entry(de.child_ino).or_default();
+        pending.links.push((de.parent_ino, de.name.to_vec()));
+        if de.file_type == FT_DIR || pending.stat.as_ref().is_some_and(is_dir) {
+            self.link_dir(de.child_ino, de.parent_ino, de.name.to_vec());
+        }
+        self.try_complete(de.child_ino);
+    }
+
+    /// Mark the end of an AG: the extents of its btree-format files are
+    /// now complete. Call once its extent phase is over; its directory
+    /// phase may come before or after.
+    pub fn end_ag(&mut self, ag_number: u32) {
+        for ino in self.awaiting_extents.remove(&ag_number).unwrap_or_default() {
+            if let Some(pending) = self.pending.get_mut(&ino) {
+                pending.extents_done = true;
+                self.try_complete(ino);
+            }
+        }
+    }
+
+    /// The records joined since the last call.
+    pub fn drain_ready(&mut self) -> Drain<'_, FileRecord> {
+        self.ready.drain(..)
+    }
+
+    /// Finish and return the records that never completed, sorted by inode
+    /// number: fewer entries than `nlink`, or a parent directory cut off
+    /// from the root. Ready records not yet drained come first. Entries
+    /// whose inode was never seen are dropped.
+    pub fn finish(mut self) -> Vec<FileRecord> {
+        let mut unmatched: Vec<FileRecord> = Vec::new();
+        for (_, pending) in std::mem::take(&mut self.pending) {
+            let Some(stat) = pending.stat else { continue };
+            let paths = pending.links.iter().filter_map(|(parent, name)| self.path(*parent, name).ok()).collect();
+            unmatched.push(FileRecord { stat, paths, extents: pending.extents });
+        }
+        unmatched.sort_unstable_by_key(|r| r.stat.ino);
+        let mut records = self.ready;
+        records.append(&mut unmatched);
+        records
+    }
+
+    /// Record where directory `dir` lives and retry the records waiting on
+    /// it.
+    fn link_dir(&mut self, dir: u64, parent: u64, name: Vec<u8>) {
+        if dir == self.root_ino || self.dirs.contains_key(&dir) {
+            return;
+        }
+        self.dirs.insert(dir, (parent, name));
+        for ino in self.waiting_on_dir.remove(&dir).unwrap_or_default() {
+            self.try_complete(ino);
+        }
+    }
+
+    /// Emit `ino` if everything about it is known.
+    fn try_complete(&mut self, ino: u64) {
+        let Some(pending) = self.pending.get(&ino) else { return };
+        if !pending.extents_done || pending.expected_links(self.root_ino) != Some(pending.links.len()) {
+            return;
+        }
+        let mut paths = Vec::with_capacity(pending.links.len().max(1));
+        if ino == self.root_ino {
+            paths.push(Vec::new());
+        }
+        for (parent, name) in &pending.links {
+            match self.path(*parent, name) {
+                Ok(path) => paths.push(path),
+                Err(Some(missing)) => {
+                    self.waiting_on_dir.entry(missing).or_default().push(ino);
+                    return;
+                }
+                // A loop: leave it for finish().
+                Err(None) => return,
+            }
+        }
+        let pending = self.pending.remove(&ino).expect("pending record vanished");
+        let stat = pending.stat.expect("completed record has no inode");
+        self.ready.push(FileRecord { stat, paths, extents: pending.extents });
+    }
+
+    /// Components of `name` in directory `parent`, from the root. `Err`
+    /// carries the first directory not yet linked to its parent, or `None`
+    /// for a loop.
+    fn path(&self, parent: u64, name: &[u8]) -> Result<Vec<Vec<u8>>, Option<u64>> {
+        let mut components = vec![name.to_vec()];
+        let mut cur = parent;
+        while cur != self.root_ino {
+            if components.len() > self.dirs.len() + 1 {
+                return Err(None);
+            }
+            let (up, dir_name) = self.dirs.get(&cur).ok_or(Some(cur))?;
+            components.push(dir_name.clone());
+            cur = *up;
+        }
+        components.reverse();
+        Ok(components)
+    }
+}
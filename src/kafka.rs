@@ -0,0 +1,294 @@
+//! Scan events published to a Kafka topic.
+//!
+//! [`KafkaSink`] turns events into JSON lines, one object per event, and
+//! publishes them [`batch_size`](KafkaSink::with_batch_size) at a time, so a
+//! fleet of scanning agents can feed a data lake directly. All messages of
+//! one sink carry the same key, keeping a scan's events in order on one
+//! partition, and an `fxfsp-schema` header with
+//! [`EVENT_SCHEMA_VERSION`](crate::EVENT_SCHEMA_VERSION).
+//!
+//! Each line has a `type`: `inode`, `extents` (with `ino` and `extents`),
+//! `dirent` or `file` (an inode's fields, `paths` and `extents`). Names and
+//! path components are escaped with [`escape_name`]; times are seconds since
+//! the epoch.
+
+use std::ops::ControlFlow;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rdkafka::ClientContext;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext};
+use serde::Serialize;
+
+use crate::error::FxfspError;
+use crate::join::FileRecord;
+use crate::name::escape_name;
+use crate::staged::{FileExtentsInfo, InodeInfo};
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::extent::Extent;
+
+/// Events per message unless set otherwise.
+const DEFAULT_BATCH_SIZE: usize = 1000;
+/// How long a publish may wait for room in the producer queue unless set
+/// otherwise.
+const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(60);
+/// Producer queue poll interval while waiting for room.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Keeps the first failed delivery.
+#[derive(Default)]
+struct DeliveryContext {
+    error: Mutex<Option<KafkaError>>,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _: ()) {
+        if let Err((e, _)) = result {
+            self.error.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(|| e.clone());
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WireExtent {
+    logical_offset: u64,
+    ag: u32,
+    ag_block: u32,
+    block_count: u64,
+    unwritten: bool,
+}
+
+impl From<&Extent> for WireExtent {
+    fn from(extent: &Extent) -> Self {
+        Self {
+            logical_offset: extent.logical_offset,
+            ag: extent.ag_number,
+            ag_block: extent.ag_block,
+            block_count: extent.block_count,
+            unwritten: extent.is_unwritten,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct WireInode {
+    ino: u64,
+    generation: u32,
+    ag: u32,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    projid: u32,
+    nlink: u32,
+    size: u64,
+    nblocks: u64,
+    atime: u32,
+    mtime: u32,
+    ctime: u32,
+    flags: u16,
+    flags2: u64,
+    rdev: Option<u32>,
+    internal: Option<String>,
+}
+
+impl From<&InodeInfo> for WireInode {
+    fn from(inode: &InodeInfo) -> Self {
+        Self {
+            ino: inode.ino,
+            generation: inode.generation,
+            ag: inode.ag_number,
+            mode: inode.mode,
+            uid: inode.uid,
+            gid: inode.gid,
+            projid: inode.projid,
+            nlink: inode.nlink,
+            size: inode.size,
+            nblocks: inode.nblocks,
+            atime: inode.atime_sec,
+            mtime: inode.mtime_sec,
+            ctime: inode.ctime_sec,
+            flags: inode.flags.bits(),
+            flags2: inode.flags2.bits(),
+            rdev: inode.rdev,
+            internal: inode.internal.map(|kind| format!("{kind:?}")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum WireEvent {
+    Inode(WireInode),
+    Extents { ino: u64, extents: Vec<WireExtent> },
+    Dirent { parent: u64, name: String, child: u64, file_type: u8 },
+    File {
+        #[serde(flatten)]
+        stat: WireInode,
+        paths: Vec<String>,
+        extents: Vec<WireExtent>,
+    },
+}
+
+/// Publishes scan events to a Kafka topic.
+///
+/// Publishing blocks while the producer's queue is full, up to
+/// [`with_max_wait`](Self::with_max_wait), so a slow broker slows the scan
+/// down instead of growing memory. Each `add_*` method returns
+/// [`ControlFlow::Break`] once a publish has failed, so it can be returned
+/// from a scan callback as is and stops the scan;
+/// [`finish`](Self::finish) then reports the error.
+pub struct KafkaSink {
+    producer: BaseProducer<DeliveryContext>,
+    topic: String,
+    key: String,
+    batch_size: usize,
+    max_wait: Duration,
+    /// JSON lines not yet published.
+    batch: Vec<u8>,
+    pending: usize,
+    error: Option<KafkaError>,
+}
+
+impl KafkaSink {
+    /// Publish to `topic` on the brokers `brokers` (`host:port`, comma
+    /// separated), keying every message with `key`, e.g. the host and
+    /// device scanned.
+    pub fn new(brokers: &str, topic: &str, key: &str) -> Result<Self, FxfspError> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", brokers).set("compression.codec", "lz4").set("linger.ms", "50");
+        Self::from_config(&config, topic, key)
+    }
+
+    /// Publish with a producer built from `config`, for TLS, SASL or
+    /// tuning.
+    pub fn from_config(config: &ClientConfig, topic: &str, key: &str) -> Result<Self, FxfspError> {
+        Ok(Self {
+            producer: config.create_with_context(DeliveryContext::default())?,
+            topic: topic.to_owned(),
+            key: key.to_owned(),
+            batch_size: DEFAULT_BATCH_SIZE,
+            max_wait: DEFAULT_MAX_WAIT,
+            batch: Vec::new(),
+            pending: 0,
+            error: None,
+        })
+    }
+
+    /// Put `events` events in each message instead of 1000.
+    pub fn with_batch_size(mut self, events: usize) -> Self {
+        self.batch_size = events.max(1);
+        self
+    }
+
+    /// Wait up to `wait` for room in the producer queue, and for the last
+    /// messages in [`finish`](Self::finish), instead of a minute.
+    pub fn with_max_wait(mut self, wait: Duration) -> Self {
+        self.max_wait = wait;
+        self
+    }
+
+    /// Publish an inode.
+    pub fn add_inode(&mut self, inode: &InodeInfo) -> ControlFlow<()> {
+        self.push(&WireEvent::Inode(inode.into()))
+    }
+
+    /// Publish the extents of a btree-format file.
+    pub fn add_file_extents(&mut self, info: &FileExtentsInfo) -> ControlFlow<()> {
+        self.push(&WireEvent::Extents { ino: info.ino, extents: info.extents.iter().map(Into::into).collect() })
+    }
+
+    /// Publish a directory entry.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) -> ControlFlow<()> {
+        self.push(&WireEvent::Dirent {
+            parent: de.parent_ino,
+            name: escape_name(de.name).into_owned(),
+            child: de.child_ino,
+            file_type: de.file_type,
+        })
+    }
+
+    /// Publish a joined record from [`FileJoin`](crate::FileJoin), its paths
+    /// absolute.
+    pub fn add_record(&mut self, record: &FileRecord) -> ControlFlow<()> {
+        let paths = record
+            .paths
+            .iter()
+            .map(|path| {
+                let mut joined = String::new();
+                for component in path {
+                    joined.push('/');
+                    joined.push_str(&escape_name(component));
+                }
+                if joined.is_empty() { "/".to_owned() } else { joined }
+            })
+            .collect();
+        self.push(&WireEvent::File {
+            stat: (&record.stat).into(),
+            paths,
+            extents: record.extents.iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Publish the last batch and wait for every message to be delivered,
+    /// or return the first error.
+    pub fn finish(mut self) -> Result<(), FxfspError> {
+        if self.error.is_none() && self.pending > 0 {
+            self.publish();
+        }
+        if let Some(e) = self.error.take() {
+            return Err(e.into());
+        }
+        self.producer.flush(self.max_wait)?;
+        match self.producer.context().error.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(e) => Err(e.into()),
+            None => Ok(()),
+        }
+    }
+
+    fn push(&mut self, event: &WireEvent) -> ControlFlow<()> {
+        if self.error.is_some() {
+            return ControlFlow::Break(());
+        }
+        serde_json::to_writer(&mut self.batch, event).expect("events serialize to JSON");
+        self.batch.push(b'\n');
+        self.pending += 1;
+        if self.pending >= self.batch_size {
+            self.publish();
+        }
+        if self.error.is_some() { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    }
+
+    /// Publish the batch, waiting for room in the queue, and record any
+    /// error, including a failed delivery of an earlier message.
+    fn publish(&mut self) {
+        let schema = crate::EVENT_SCHEMA_VERSION.to_string();
+        let headers = OwnedHeaders::new().insert(Header { key: "fxfsp-schema", value: Some(&schema) });
+        let mut record = BaseRecord::to(&self.topic).key(&self.key).payload(&self.batch).headers(headers);
+        let deadline = Instant::now() + self.max_wait;
+        let result = loop {
+            match self.producer.send(record) {
+                Ok(()) => break Ok(()),
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), unsent))
+                    if Instant::now() < deadline =>
+                {
+                    self.producer.poll(POLL_INTERVAL);
+                    record = unsent;
+                }
+                Err((e, _)) => break Err(e),
+            }
+        };
+        // Serve delivery reports.
+        self.producer.poll(Duration::ZERO);
+        self.batch.clear();
+        self.pending = 0;
+        let delivery = self.producer.context().error.lock().unwrap_or_else(|e| e.into_inner()).take();
+        self.error = result.err().or(delivery);
+    }
+}
@@ -1,29 +1,161 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod accounting;
+#[cfg(feature = "std")]
+pub mod cleanup;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod crosscheck;
+pub mod decode;
+#[cfg(feature = "std")]
+pub mod dirstats;
+#[cfg(feature = "std")]
+pub mod duplicates;
 pub mod error;
-#[cfg(feature = "io")]
+#[cfg(feature = "std")]
+pub mod handle;
+#[cfg(feature = "instrument")]
 pub mod io;
+#[cfg(feature = "std")]
+pub mod join;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod name;
+#[cfg(feature = "std")]
+pub mod orphans;
+pub mod packed;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod prelude;
 pub mod reader;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod rules;
+#[cfg(feature = "std")]
+pub mod security;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "std")]
 pub mod staged;
+#[cfg(feature = "std")]
+mod tree;
+#[cfg(feature = "std")]
+pub mod usage;
+pub mod warning;
 pub mod xfs;
 
+/// Version of the event schema (the fields of [`SuperblockInfo`], [`InodeInfo`],
+/// [`FileExtentsInfo`], [`DirEntryInfo`], [`SymlinkTargetInfo`], [`XattrInfo`],
+/// [`FreeSpaceExtent`], [`AgSummary`], [`RmapRecord`] and [`SharedExtent`]).
+///
+/// Bumped whenever an event type gains or changes a field, so consumers that
+/// persist events in their own binary formats can tag and migrate them. The
+/// event structs are `#[non_exhaustive]`; new fields are not a breaking change.
+pub const EVENT_SCHEMA_VERSION: u32 = 21;
+
+pub use decode::{BlockKind, DecodedBlock, DecodedDirEntry, InobtRecord, decode_block};
 pub use error::FxfspError;
-pub use reader::{IoPhase, IoReader};
+pub use name::{NamePolicy, escape_name, unescape_name};
+pub use packed::{PackedExtents, PackedIter};
+pub use reader::{CallbackReader, IoPhase, IoReader, SliceReader};
+pub use warning::{Provenance, ScanWarning, WarningCode};
+pub use xfs::ag::{AgfInfo, AgiInfo};
+pub use xfs::alloc::{FreeSpaceExtent, FreeSpaceOrder};
+pub use xfs::refcount::SharedExtent;
+pub use xfs::rmap::{RmapOwner, RmapRecord};
+pub use xfs::attr::XattrNamespace;
+pub use xfs::dir::{DirEntryInfo, DirEntryLocation};
 pub use xfs::extent::Extent;
-pub use xfs::superblock::FsContext;
+pub use xfs::inode::{InodeFlags, InodeFlags2, InternalInode};
+pub use xfs::log::{LogItem, LogSummary, LogTransaction};
+pub use xfs::superblock::{FeatureReport, FsContext, InodeLocation};
+
+#[cfg(feature = "std")]
+pub use accounting::{AccountingCounter, AccountingReport, Discrepancy, SpaceAccounting};
+#[cfg(feature = "std")]
+pub use cleanup::{CleanupCandidate, CleanupCollector, CleanupCriteria};
+#[cfg(feature = "std")]
+pub use clock::{Clock, FixedClock, SystemClock};
+#[cfg(feature = "std")]
+pub use crosscheck::{Conflict, CrossCheck};
+#[cfg(feature = "std")]
+pub use dirstats::{DirStat, DirStats};
+#[cfg(feature = "std")]
+pub use duplicates::{DuplicateName, DuplicateNames, NameSlot};
+#[cfg(feature = "std")]
+pub use handle::{DeadlineStop, ScanHandle};
+#[cfg(feature = "std")]
+pub use join::{FileJoin, FileRecord};
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaSink;
+#[cfg(feature = "std")]
+pub use orphans::{Orphan, OrphanCollector, OrphanReport};
+#[cfg(feature = "parquet")]
+pub use parquet::ParquetExporter;
+#[cfg(feature = "std")]
+pub use replay::{ReplayReader, ReplayReport};
+#[cfg(feature = "std")]
+pub use rules::{Predicate, Rule, RuleEngine, RuleMatch, RuleSink};
+#[cfg(feature = "std")]
+pub use security::{SecurityAudit, SecurityFinding, SecurityIssue};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteExporter;
+#[cfg(feature = "std")]
+pub use usage::{KindCounts, UsageBucket, UsageCollector, UsageReport};
 
 // Phased API exports
+#[cfg(feature = "std")]
 pub use staged::{
-    parse_superblock,
-    SuperblockInfo,
-    FsScanner,
-    AgScanner,
-    AgExtentPhase,
-    AgDirPhase,
-    InodeInfo,
-    FileExtentsInfo,
-    DirEntryInfo,
+    AgDirPhase, AgExtentPhase, AgScanner, AgSummary, CrcVerification, FileExtentsInfo, FileId, FsScanner,
+    GeometryMismatch, InodeInfo, ParallelScanner, ScanMeta, ScanOptions, SuperblockInfo, SymlinkTargetInfo,
+    XattrInfo, parse_superblock, parse_superblock_with_options,
 };
 
 #[cfg(feature = "io")]
 pub use io::engine::{DiskProfile, IoEngine, detect_disk_profile_for_path};
+#[cfg(feature = "instrument")]
+pub use io::reader::{InstrumentationConfig, InstrumentedReader, MaybeInstrumented};
 #[cfg(feature = "io")]
-pub use io::reader::MaybeInstrumented;
+pub use io::session::Session;
+
+// Compile-time thread-safety guarantees. Scanners can be moved between worker
+// threads whenever their reader can; `IoEngine` owns its fd and buffers, so it
+// is both `Send` and `Sync`.
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    const fn assert_sync<T: Sync>() {}
+
+    assert_send::<FsContext>();
+    assert_sync::<FsContext>();
+    assert_send::<DirEntryInfo<'static>>();
+    assert_send::<ScanWarning>();
+    assert_send::<FxfspError>();
+    assert_sync::<FxfspError>();
+
+    #[cfg(feature = "std")]
+    {
+        assert_send::<staged::InodeInfo>();
+        assert_send::<staged::FileExtentsInfo>();
+        assert_send::<ScanHandle>();
+        assert_sync::<ScanHandle>();
+    }
+
+    #[cfg(feature = "io")]
+    {
+        assert_send::<IoEngine>();
+        assert_sync::<IoEngine>();
+        assert_send::<MaybeInstrumented<IoEngine>>();
+        assert_send::<Session>();
+        assert_sync::<Session>();
+        assert_send::<FsScanner<IoEngine>>();
+        assert_send::<AgScanner<'static, IoEngine>>();
+        assert_send::<AgExtentPhase<'static, IoEngine>>();
+        assert_send::<AgDirPhase<'static, IoEngine>>();
+        assert_sync::<ParallelScanner<IoEngine, fn(u32) -> Result<IoEngine, FxfspError>>>();
+    }
+};
@@ -0,0 +1,92 @@
+//! Policies for presenting raw XFS names.
+//!
+//! XFS names are arbitrary bytes other than NUL and `/`. Consumers that need
+//! text pick a [`NamePolicy`] instead of calling `from_utf8_lossy` ad hoc, so
+//! the choice between fidelity and readability is explicit.
+//! [`NamePolicy::EscapeNonUtf8`] is both readable and reversible with
+//! [`unescape_name`].
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// How names are encoded for output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum NamePolicy {
+    /// Bytes as stored on disk, for binary formats.
+    Raw,
+    /// Invalid UTF-8 replaced with U+FFFD. Not reversible.
+    Utf8Lossy,
+    /// Invalid bytes written as `\xNN` and `\` as `\\`. Always valid UTF-8
+    /// and reversible with [`unescape_name`].
+    #[default]
+    EscapeNonUtf8,
+}
+
+impl NamePolicy {
+    /// Encode `name` under this policy. Borrows whenever the name needs no
+    /// change. Every policy but `Raw` yields valid UTF-8.
+    pub fn encode(self, name: &[u8]) -> Cow<'_, [u8]> {
+        match self {
+            Self::Raw => Cow::Borrowed(name),
+            Self::Utf8Lossy => match String::from_utf8_lossy(name) {
+                Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+                Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+            },
+            Self::EscapeNonUtf8 => match escape_name(name) {
+                Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+                Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+            },
+        }
+    }
+}
+
+/// Escape `name` as described for [`NamePolicy::EscapeNonUtf8`].
+pub fn escape_name(name: &[u8]) -> Cow<'_, str> {
+    match core::str::from_utf8(name) {
+        Ok(s) if !s.contains('\\') => return Cow::Borrowed(s),
+        _ => {}
+    }
+    let mut out = String::with_capacity(name.len() + 8);
+    for chunk in name.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            if c == '\\' {
+                out.push_str("\\\\");
+            } else {
+                out.push(c);
+            }
+        }
+        for b in chunk.invalid() {
+            let _ = write!(out, "\\x{b:02x}");
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Reverse [`escape_name`]. Returns `None` on a malformed escape.
+pub fn unescape_name(escaped: &str) -> Option<Vec<u8>> {
+    let bytes = escaped.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1)? {
+            b'\\' => {
+                out.push(b'\\');
+                i += 2;
+            }
+            b'x' => {
+                let hex = core::str::from_utf8(bytes.get(i + 2..i + 4)?).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 4;
+            }
+            _ => return None,
+        }
+    }
+    Some(out)
+}
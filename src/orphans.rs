@@ -0,0 +1,110 @@
+//! Space held by unlinked inodes.
+//!
+//! A file deleted while still open keeps its inode and blocks until the
+//! last descriptor closes: `nlink` is 0 and the inode sits on an AGI
+//! unlinked bucket chain. This is the usual cause of `df` reporting more
+//! used space than `du` can find. [`OrphanCollector`] combines both signals.
+//!
+//! Give it the inodes of every AG's unlinked chains
+//! ([`AgScanner::unlinked_inodes`](crate::AgScanner::unlinked_inodes)) as
+//! well as the inodes the scan delivers.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::staged::{InodeInfo, SuperblockInfo};
+
+/// An inode with no links.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Orphan {
+    pub ino: u64,
+    pub mode: u16,
+    /// Logical size in bytes.
+    pub size: u64,
+    /// Blocks held, data and attr forks (`di_nblocks`).
+    pub blocks: u64,
+    /// On an AGI unlinked chain. `false` means the inode is leaked: nothing
+    /// will free it short of `xfs_repair`.
+    pub on_unlinked_list: bool,
+}
+
+/// Unlinked inodes found by a scan.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrphanReport {
+    /// Sorted by inode number.
+    pub orphans: Vec<Orphan>,
+    /// Total blocks held by `orphans`.
+    pub held_blocks: u64,
+    /// `held_blocks` in bytes.
+    pub held_bytes: u64,
+    /// Inodes on an unlinked chain that still have links (e.g. an
+    /// `O_TMPFILE` being linked in). Not counted as held.
+    pub linked_on_list: Vec<u64>,
+}
+
+/// Collects unlinked chains and inode events into an [`OrphanReport`].
+#[derive(Debug)]
+pub struct OrphanCollector {
+    block_size: u64,
+    on_list: HashSet<u64>,
+    orphans: HashMap<u64, Orphan>,
+    linked: HashSet<u64>,
+}
+
+impl OrphanCollector {
+    pub fn new(sb: &SuperblockInfo) -> Self {
+        Self {
+            block_size: sb.block_size as u64,
+            on_list: HashSet::new(),
+            orphans: HashMap::new(),
+            linked: HashSet::new(),
+        }
+    }
+
+    /// Record the inodes on an AG's unlinked chains, from
+    /// [`AgScanner::unlinked_inodes`](crate::AgScanner::unlinked_inodes).
+    pub fn add_unlinked(&mut self, inodes: &[u64]) {
+        self.on_list.extend(inodes);
+    }
+
+    /// Record an inode.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        if inode.nlink > 0 {
+            self.linked.insert(inode.ino);
+            return;
+        }
+        self.orphans.insert(
+            inode.ino,
+            Orphan {
+                ino: inode.ino,
+                mode: inode.mode,
+                size: inode.size,
+                blocks: inode.nblocks,
+                on_unlinked_list: false,
+            },
+        );
+    }
+
+    /// Finish and build the report.
+    pub fn finish(self) -> OrphanReport {
+        let mut orphans: Vec<Orphan> = self
+            .orphans
+            .into_values()
+            .map(|mut o| {
+                o.on_unlinked_list = self.on_list.contains(&o.ino);
+                o
+            })
+            .collect();
+        orphans.sort_unstable_by_key(|o| o.ino);
+        let held_blocks = orphans.iter().map(|o| o.blocks).sum::<u64>();
+        let mut linked_on_list: Vec<u64> = self.on_list.intersection(&self.linked).copied().collect();
+        linked_on_list.sort_unstable();
+        OrphanReport {
+            orphans,
+            held_blocks,
+            held_bytes: held_blocks * self.block_size,
+            linked_on_list,
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Compact in-memory extent lists.
+//!
+//! An [`Extent`] takes 32 bytes; indexes holding hundreds of millions of them
+//! are dominated by that. [`PackedExtents`] stores each extent as varint
+//! deltas from the end of the previous one, which is a few bytes for the
+//! mostly contiguous, mostly ascending maps XFS produces.
+
+use alloc::vec::Vec;
+
+use crate::xfs::extent::Extent;
+use crate::xfs::superblock::FsContext;
+
+/// Extents between checkpoints. Lookups decode at most this many.
+const CHECKPOINT_INTERVAL: usize = 64;
+
+/// Decoder state before extent `i * CHECKPOINT_INTERVAL`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Checkpoint {
+    /// Offset into the encoded bytes.
+    pos: usize,
+    /// Logical offset of the first extent after the checkpoint.
+    first_logical: u64,
+    state: DeltaState,
+}
+
+/// End of the previous extent, which the next one is encoded against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct DeltaState {
+    logical_end: u64,
+    fsblock_end: u64,
+}
+
+/// A delta-encoded extent list.
+///
+/// Per extent: the zigzag gap from the previous logical end, the block
+/// count shifted left by one with the unwritten flag in bit 0, and the
+/// zigzag distance from the previous physical end, each a LEB128 varint.
+/// Every 64 extents the decoder state is saved so
+/// [`find`](Self::find) needn't decode from the start.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PackedExtents {
+    ag_blk_log: u8,
+    len: usize,
+    bytes: Vec<u8>,
+    checkpoints: Vec<Checkpoint>,
+    tail: DeltaState,
+}
+
+impl PackedExtents {
+    /// An empty list for extents of the filesystem described by `ctx`.
+    pub fn new(ctx: &FsContext) -> Self {
+        Self {
+            ag_blk_log: ctx.ag_blk_log,
+            len: 0,
+            bytes: Vec::new(),
+            checkpoints: Vec::new(),
+            tail: DeltaState::default(),
+        }
+    }
+
+    pub fn from_extents(ctx: &FsContext, extents: &[Extent]) -> Self {
+        let mut packed = Self::new(ctx);
+        for ext in extents {
+            packed.push(ext);
+        }
+        packed.shrink_to_fit();
+        packed
+    }
+
+    /// Append an extent. Any order is accepted, but [`find`](Self::find)
+    /// assumes ascending logical offsets, as in an inode's extent map.
+    pub fn push(&mut self, ext: &Extent) {
+        if self.len.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.checkpoints.push(Checkpoint {
+                pos: self.bytes.len(),
+                first_logical: ext.logical_offset,
+                state: self.tail,
+            });
+        }
+        let fsblock = ((ext.ag_number as u64) << self.ag_blk_log) | ext.ag_block as u64;
+        write_varint(&mut self.bytes, zigzag(ext.logical_offset.wrapping_sub(self.tail.logical_end)));
+        write_varint(&mut self.bytes, (ext.block_count << 1) | ext.is_unwritten as u64);
+        write_varint(&mut self.bytes, zigzag(fsblock.wrapping_sub(self.tail.fsblock_end)));
+        self.tail = DeltaState {
+            logical_end: ext.logical_offset + ext.block_count,
+            fsblock_end: fsblock + ext.block_count,
+        };
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Heap bytes used, for memory accounting.
+    pub fn heap_bytes(&self) -> usize {
+        self.bytes.capacity() + self.checkpoints.capacity() * size_of::<Checkpoint>()
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.bytes.shrink_to_fit();
+        self.checkpoints.shrink_to_fit();
+    }
+
+    pub fn iter(&self) -> PackedIter<'_> {
+        PackedIter {
+            packed: self,
+            pos: 0,
+            remaining: self.len,
+            state: DeltaState::default(),
+        }
+    }
+
+    /// The extent mapping logical block `block`, or `None` for a hole.
+    pub fn find(&self, block: u64) -> Option<Extent> {
+        // Last checkpoint starting at or before `block`.
+        let idx = self.checkpoints.partition_point(|c| c.first_logical <= block).checked_sub(1)?;
+        let cp = self.checkpoints[idx];
+        let iter = PackedIter {
+            packed: self,
+            pos: cp.pos,
+            remaining: self.len.saturating_sub(idx * CHECKPOINT_INTERVAL),
+            state: cp.state,
+        };
+        iter.take(CHECKPOINT_INTERVAL)
+            .take_while(|ext| ext.logical_offset <= block)
+            .find(|ext| block - ext.logical_offset < ext.block_count)
+    }
+
+    /// Decode into plain extents.
+    pub fn to_vec(&self) -> Vec<Extent> {
+        self.iter().collect()
+    }
+}
+
+/// Iterator over a [`PackedExtents`], decoding as it goes.
+#[derive(Debug, Clone)]
+pub struct PackedIter<'a> {
+    packed: &'a PackedExtents,
+    pos: usize,
+    remaining: usize,
+    state: DeltaState,
+}
+
+impl Iterator for PackedIter<'_> {
+    type Item = Extent;
+
+    fn next(&mut self) -> Option<Extent> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let bytes = &self.packed.bytes;
+        let (Some(gap), Some(count_flag), Some(distance)) = (
+            read_varint(bytes, &mut self.pos),
+            read_varint(bytes, &mut self.pos),
+            read_varint(bytes, &mut self.pos),
+        ) else {
+            // Truncated, which only a deserialized list can be.
+            self.remaining = 0;
+            return None;
+        };
+        let logical_offset = self.state.logical_end.wrapping_add(unzigzag(gap));
+        let fsblock = self.state.fsblock_end.wrapping_add(unzigzag(distance));
+        let block_count = count_flag >> 1;
+        self.state = DeltaState {
+            logical_end: logical_offset.wrapping_add(block_count),
+            fsblock_end: fsblock.wrapping_add(block_count),
+        };
+        let ag_blk_log = self.packed.ag_blk_log as u32;
+        Some(Extent {
+            logical_offset,
+            ag_number: fsblock.checked_shr(ag_blk_log).unwrap_or(0) as u32,
+            ag_block: (fsblock & 1u64.checked_shl(ag_blk_log).unwrap_or(0).wrapping_sub(1)) as u32,
+            block_count,
+            is_unwritten: count_flag & 1 != 0,
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for PackedIter<'_> {}
+
+impl<'a> IntoIterator for &'a PackedExtents {
+    type Item = Extent;
+    type IntoIter = PackedIter<'a>;
+
+    fn into_iter(self) -> PackedIter<'a> {
+        self.iter()
+    }
+}
+
+fn zigzag(delta: u64) -> u64 {
+    let signed = delta as i64;
+    ((signed << 1) ^ (signed >> 63)) as u64
+}
+
+fn unzigzag(encoded: u64) -> u64 {
+    (encoded >> 1) ^ (encoded & 1).wrapping_neg()
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Decode a varint written by [`write_varint`], or `None` if the bytes run
+/// out or it is longer than a `u64`.
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
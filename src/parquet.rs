@@ -0,0 +1,278 @@
+//! Scan events written as Arrow record batches into Parquet files.
+//!
+//! [`ParquetExporter`] buffers inodes and directory entries in column
+//! builders and writes a record batch every
+//! [`batch_size`](ParquetExporter::with_batch_size) rows, so a scan of any
+//! size runs in bounded memory and the files load straight into DataFusion,
+//! Polars or Spark.
+//!
+//! Files (times in seconds since the epoch, names as binary):
+//!
+//! - `inodes.parquet`: `ino`, `generation`, `ag`, `mode`, `uid`, `gid`,
+//!   `projid`, `nlink`, `size`, `nblocks`, `atime`, `mtime`, `ctime`,
+//!   `flags`, `flags2`, `rdev` and `internal`, the last two nullable
+//! - `dirents.parquet`: `parent`, `name`, `child`, `file_type`, `.` and `..`
+//!   included
+
+use std::fs::File;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow_array::builder::{
+    BinaryBuilder, StringBuilder, TimestampSecondBuilder, UInt8Builder, UInt16Builder, UInt32Builder, UInt64Builder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+
+use crate::error::FxfspError;
+use crate::staged::InodeInfo;
+use crate::xfs::dir::DirEntryInfo;
+
+/// Rows per record batch unless set otherwise.
+const DEFAULT_BATCH_SIZE: usize = 64 * 1024;
+
+fn inode_schema() -> SchemaRef {
+    let time = || DataType::Timestamp(TimeUnit::Second, None);
+    Arc::new(Schema::new(vec![
+        Field::new("ino", DataType::UInt64, false),
+        Field::new("generation", DataType::UInt32, false),
+        Field::new("ag", DataType::UInt32, false),
+        Field::new("mode", DataType::UInt16, false),
+        Field::new("uid", DataType::UInt32, false),
+        Field::new("gid", DataType::UInt32, false),
+        Field::new("projid", DataType::UInt32, false),
+        Field::new("nlink", DataType::UInt32, false),
+        Field::new("size", DataType::UInt64, false),
+        Field::new("nblocks", DataType::UInt64, false),
+        Field::new("atime", time(), false),
+        Field::new("mtime", time(), false),
+        Field::new("ctime", time(), false),
+        Field::new("flags", DataType::UInt16, false),
+        Field::new("flags2", DataType::UInt64, false),
+        Field::new("rdev", DataType::UInt32, true),
+        Field::new("internal", DataType::Utf8, true),
+    ]))
+}
+
+fn dirent_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("parent", DataType::UInt64, false),
+        Field::new("name", DataType::Binary, false),
+        Field::new("child", DataType::UInt64, false),
+        Field::new("file_type", DataType::UInt8, false),
+    ]))
+}
+
+/// Column builders of the `inodes` table.
+#[derive(Debug, Default)]
+struct InodeColumns {
+    ino: UInt64Builder,
+    generation: UInt32Builder,
+    ag: UInt32Builder,
+    mode: UInt16Builder,
+    uid: UInt32Builder,
+    gid: UInt32Builder,
+    projid: UInt32Builder,
+    nlink: UInt32Builder,
+    size: UInt64Builder,
+    nblocks: UInt64Builder,
+    atime: TimestampSecondBuilder,
+    mtime: TimestampSecondBuilder,
+    ctime: TimestampSecondBuilder,
+    flags: UInt16Builder,
+    flags2: UInt64Builder,
+    rdev: UInt32Builder,
+    internal: StringBuilder,
+}
+
+impl InodeColumns {
+    fn push(&mut self, inode: &InodeInfo) {
+        self.ino.append_value(inode.ino);
+        self.generation.append_value(inode.generation);
+        self.ag.append_value(inode.ag_number);
+        self.mode.append_value(inode.mode);
+        self.uid.append_value(inode.uid);
+        self.gid.append_value(inode.gid);
+        self.projid.append_value(inode.projid);
+        self.nlink.append_value(inode.nlink);
+        self.size.append_value(inode.size);
+        self.nblocks.append_value(inode.nblocks);
+        self.atime.append_value(inode.atime_sec.into());
+        self.mtime.append_value(inode.mtime_sec.into());
+        self.ctime.append_value(inode.ctime_sec.into());
+        self.flags.append_value(inode.flags.bits());
+        self.flags2.append_value(inode.flags2.bits());
+        self.rdev.append_option(inode.rdev);
+        self.internal.append_option(inode.internal.map(|kind| format!("{kind:?}")));
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.ino.finish()),
+            Arc::new(self.generation.finish()),
+            Arc::new(self.ag.finish()),
+            Arc::new(self.mode.finish()),
+            Arc::new(self.uid.finish()),
+            Arc::new(self.gid.finish()),
+            Arc::new(self.projid.finish()),
+            Arc::new(self.nlink.finish()),
+            Arc::new(self.size.finish()),
+            Arc::new(self.nblocks.finish()),
+            Arc::new(self.atime.finish()),
+            Arc::new(self.mtime.finish()),
+            Arc::new(self.ctime.finish()),
+            Arc::new(self.flags.finish()),
+            Arc::new(self.flags2.finish()),
+            Arc::new(self.rdev.finish()),
+            Arc::new(self.internal.finish()),
+        ]
+    }
+}
+
+/// Column builders of the `dirents` table.
+#[derive(Debug, Default)]
+struct DirentColumns {
+    parent: UInt64Builder,
+    name: BinaryBuilder,
+    child: UInt64Builder,
+    file_type: UInt8Builder,
+}
+
+impl DirentColumns {
+    fn push(&mut self, de: &DirEntryInfo<'_>) {
+        self.parent.append_value(de.parent_ino);
+        self.name.append_value(de.name);
+        self.child.append_value(de.child_ino);
+        self.file_type.append_value(de.file_type);
+    }
+
+    fn finish(&mut self) -> Vec<ArrayRef> {
+        vec![
+            Arc::new(self.parent.finish()),
+            Arc::new(self.name.finish()),
+            Arc::new(self.child.finish()),
+            Arc::new(self.file_type.finish()),
+        ]
+    }
+}
+
+/// One output file and the rows not yet written to it.
+struct Table<C, W: Write + Send> {
+    columns: C,
+    rows: usize,
+    schema: SchemaRef,
+    writer: ArrowWriter<W>,
+}
+
+impl<C, W: Write + Send> Table<C, W> {
+    fn new(out: W, schema: SchemaRef, columns: C) -> Result<Self, ParquetError> {
+        let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let writer = ArrowWriter::try_new(out, schema.clone(), Some(props))?;
+        Ok(Self { columns, rows: 0, schema, writer })
+    }
+
+    /// Write the buffered rows as one record batch.
+    fn flush(&mut self, finish: impl FnOnce(&mut C) -> Vec<ArrayRef>) -> Result<(), ParquetError> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        self.rows = 0;
+        let batch = RecordBatch::try_new(self.schema.clone(), finish(&mut self.columns))?;
+        self.writer.write(&batch)
+    }
+}
+
+/// Writes scan events into Parquet files.
+///
+/// Each `add_*` method returns [`ControlFlow::Break`] once a write has
+/// failed, so it can be returned from a scan callback as is and stops the
+/// scan; [`finish`](Self::finish) then reports the error.
+pub struct ParquetExporter<W: Write + Send = File> {
+    inodes: Table<InodeColumns, W>,
+    dirents: Table<DirentColumns, W>,
+    batch_size: usize,
+    error: Option<ParquetError>,
+}
+
+impl ParquetExporter {
+    /// Create `inodes.parquet` and `dirents.parquet` in the directory `dir`,
+    /// which must exist.
+    pub fn create(dir: impl AsRef<Path>) -> Result<Self, FxfspError> {
+        let dir = dir.as_ref();
+        Self::new(File::create(dir.join("inodes.parquet"))?, File::create(dir.join("dirents.parquet"))?)
+    }
+}
+
+impl<W: Write + Send> ParquetExporter<W> {
+    /// Export into two writers, e.g. in-memory buffers.
+    pub fn new(inodes: W, dirents: W) -> Result<Self, FxfspError> {
+        Ok(Self {
+            inodes: Table::new(inodes, inode_schema(), InodeColumns::default())?,
+            dirents: Table::new(dirents, dirent_schema(), DirentColumns::default())?,
+            batch_size: DEFAULT_BATCH_SIZE,
+            error: None,
+        })
+    }
+
+    /// Write a record batch every `rows` rows of a table instead of every
+    /// 65 536. Larger batches compress better and take more memory.
+    pub fn with_batch_size(mut self, rows: usize) -> Self {
+        self.batch_size = rows.max(1);
+        self
+    }
+
+    /// Record an inode.
+    pub fn add_inode(&mut self, inode: &InodeInfo) -> ControlFlow<()> {
+        if self.error.is_some() {
+            return ControlFlow::Break(());
+        }
+        self.inodes.columns.push(inode);
+        self.inodes.rows += 1;
+        if self.inodes.rows >= self.batch_size {
+            let result = self.inodes.flush(InodeColumns::finish);
+            return self.check(result);
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Record a directory entry.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) -> ControlFlow<()> {
+        if self.error.is_some() {
+            return ControlFlow::Break(());
+        }
+        self.dirents.columns.push(de);
+        self.dirents.rows += 1;
+        if self.dirents.rows >= self.batch_size {
+            let result = self.dirents.flush(DirentColumns::finish);
+            return self.check(result);
+        }
+        ControlFlow::Continue(())
+    }
+
+    /// Write the remaining rows and the file footers, and return the
+    /// writers, or the first error a write hit.
+    pub fn finish(mut self) -> Result<(W, W), FxfspError> {
+        if let Some(e) = self.error.take() {
+            return Err(e.into());
+        }
+        self.inodes.flush(InodeColumns::finish)?;
+        self.dirents.flush(DirentColumns::finish)?;
+        Ok((self.inodes.writer.into_inner()?, self.dirents.writer.into_inner()?))
+    }
+
+    fn check(&mut self, result: Result<(), ParquetError>) -> ControlFlow<()> {
+        match result {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                self.error = Some(e);
+                ControlFlow::Break(())
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! The types almost every scan names, for a glob import.
+//!
+//! ```no_run
+//! use fxfsp::prelude::*;
+//!
+//! let image = std::fs::read("disk.xfs")?;
+//! let (_sb, mut scanner) = parse_superblock(SliceReader::new(&image))?;
+//! while let Some(ag) = scanner.next_ag() {
+//!     ag?.scan_inodes(|inode: &InodeInfo| {
+//!         println!("{} {}", inode.ino, inode.size);
+//!         ControlFlow::Continue(())
+//!     })?
+//!     .skip_extents()
+//!     .skip_dirs()?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Only the scan flow, its events and the error and warning types are here;
+//! collectors, decoders and the on-disk parsers stay at the crate root and
+//! under [`xfs`](crate::xfs). Everything is re-exported from the root too.
+
+// Every scan callback returns one.
+pub use core::ops::ControlFlow;
+
+pub use crate::error::FxfspError;
+pub use crate::reader::{IoReader, SliceReader};
+pub use crate::warning::{Provenance, ScanWarning, WarningCode};
+pub use crate::xfs::dir::DirEntryInfo;
+pub use crate::xfs::extent::Extent;
+pub use crate::xfs::inode::{InodeFlags, InodeFlags2, InternalInode};
+
+#[cfg(feature = "std")]
+pub use crate::staged::{
+    AgDirPhase, AgExtentPhase, AgScanner, AgSummary, FileExtentsInfo, FreeSpaceExtent, FreeSpaceOrder, FsScanner,
+    InodeInfo, RmapOwner, RmapRecord, ScanOptions, SharedExtent, SuperblockInfo, SymlinkTargetInfo, XattrInfo,
+    parse_superblock, parse_superblock_with_options,
+};
+
+#[cfg(feature = "io")]
+pub use crate::io::engine::IoEngine;
+#[cfg(feature = "io")]
+pub use crate::io::session::Session;
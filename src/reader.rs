@@ -1,16 +1,24 @@
-use std::fmt;
+use alloc::vec::Vec;
+use core::fmt;
 
 use crate::error::FxfspError;
 
 /// I/O phase labels for analytics and diagnostics.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum IoPhase {
     Superblock,
     Agi,
     InobtWalk,
     InodeChunks,
     BmbtWalk,
+    FreeSpaceWalk,
+    RmapWalk,
+    RefcountWalk,
     DirExtents,
+    SymlinkBlocks,
+    AttrBlocks,
+    /// The on-disk log and, during replay, the blocks it changes.
+    Log,
 }
 
 impl fmt::Display for IoPhase {
@@ -21,7 +29,13 @@ impl fmt::Display for IoPhase {
             Self::InobtWalk => write!(f, "inobt_walk"),
             Self::InodeChunks => write!(f, "inode_chunks"),
             Self::BmbtWalk => write!(f, "bmbt_walk"),
+            Self::FreeSpaceWalk => write!(f, "free_space_walk"),
+            Self::RmapWalk => write!(f, "rmap_walk"),
+            Self::RefcountWalk => write!(f, "refcount_walk"),
             Self::DirExtents => write!(f, "dir_extents"),
+            Self::SymlinkBlocks => write!(f, "symlink_blocks"),
+            Self::AttrBlocks => write!(f, "attr_blocks"),
+            Self::Log => write!(f, "log"),
         }
     }
 }
@@ -55,3 +69,68 @@ pub trait IoReader {
         Ok(())
     }
 }
+
+/// An [`IoReader`] over an externally supplied in-memory image.
+///
+/// Useful where the whole image (or a prefix of it) is already in memory,
+/// e.g. a browser `ArrayBuffer` under wasm32. Reads near the end of the
+/// buffer are clamped, matching `IoEngine`'s behaviour at the device end.
+pub struct SliceReader<B> {
+    image: B,
+}
+
+impl<B: AsRef<[u8]>> SliceReader<B> {
+    pub fn new(image: B) -> Self {
+        Self { image }
+    }
+
+    /// Consume the reader and return the underlying buffer.
+    pub fn into_inner(self) -> B {
+        self.image
+    }
+}
+
+impl<B: AsRef<[u8]>> IoReader for SliceReader<B> {
+    fn read_at(&mut self, offset: u64, len: usize, _phase: IoPhase) -> Result<&[u8], FxfspError> {
+        let image = self.image.as_ref();
+        let start = usize::try_from(offset)
+            .ok()
+            .filter(|&start| start < image.len())
+            .ok_or(FxfspError::Parse("read at or beyond end of image"))?;
+        let end = start + len.min(image.len() - start);
+        Ok(&image[start..end])
+    }
+}
+
+/// An [`IoReader`] that delegates every read to a caller-supplied function.
+///
+/// `fill(offset, buf)` must fill `buf` with the bytes at `offset` and return
+/// how many were read (fewer only at end of image). This is the hook for
+/// host-provided storage such as a JS `FileReaderSync` in a web worker.
+pub struct CallbackReader<F> {
+    fill: F,
+    buf: Vec<u8>,
+}
+
+impl<F> CallbackReader<F>
+where
+    F: FnMut(u64, &mut [u8]) -> Result<usize, FxfspError>,
+{
+    pub fn new(fill: F) -> Self {
+        Self { fill, buf: Vec::new() }
+    }
+}
+
+impl<F> IoReader for CallbackReader<F>
+where
+    F: FnMut(u64, &mut [u8]) -> Result<usize, FxfspError>,
+{
+    fn read_at(&mut self, offset: u64, len: usize, _phase: IoPhase) -> Result<&[u8], FxfspError> {
+        self.buf.resize(len, 0);
+        let n = (self.fill)(offset, &mut self.buf)?;
+        if n == 0 {
+            return Err(FxfspError::Parse("read at or beyond end of image"));
+        }
+        Ok(&self.buf[..n.min(len)])
+    }
+}
@@ -0,0 +1,497 @@
+//! Log recovery in memory, for images of filesystems that were not cleanly
+//! unmounted.
+//!
+//! [`ReplayReader`] wraps a reader, replays the committed transactions of
+//! the internal log the way mount would, and serves reads with the blocks
+//! they change patched in. The device or image is never written. A scan
+//! through it sees what the kernel would after mounting.
+//!
+//! ```no_run
+//! use fxfsp::{ReplayReader, SliceReader, parse_superblock};
+//!
+//! let image = std::fs::read("crashed.img")?;
+//! let reader = ReplayReader::new(SliceReader::new(&image))?;
+//! println!("{:?}", reader.report());
+//! let (_sb, scanner) = parse_superblock(reader)?;
+//! # drop(scanner);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! Buffer and inode items are replayed. Intents (extent frees, reverse
+//! mapping and refcount updates, ...) are counted as unsupported: mount
+//! finishes those after recovery, so free space and reference counts can
+//! lag, but inodes, directories and extent maps are as logged.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::FxfspError;
+use crate::reader::{IoPhase, IoReader};
+use crate::staged::log::read_log;
+use crate::xfs::crc::update_metadata_crc;
+use crate::xfs::inode::{InodeFlags2, V4_CORE_SIZE, V4_LITERAL_OFFSET, V5_CORE_SIZE};
+use crate::xfs::log::{BBSIZE, LogItem, LogSummary, LogTransaction, LogTransactions};
+use crate::xfs::superblock::{FormatVersion, FsContext};
+
+/// Bytes read for the superblock.
+const SUPERBLOCK_READ: usize = 4096;
+
+/// Sector size of the overlay.
+const SECTOR: u64 = BBSIZE as u64;
+
+/// Buffer log format (`xfs_buf_log_format`) fields.
+const BLF_FLAGS: usize = 4;
+const BLF_MAP_SIZE: usize = 16;
+const BLF_DATA_MAP: usize = 20;
+/// The buffer holds inodes; only their unlinked pointers were logged.
+const XFS_BLF_INODE_BUF: u16 = 0x1;
+/// The buffer was freed: earlier changes to it are not replayed.
+const XFS_BLF_CANCEL: u16 = 0x4;
+/// Bytes per bit of the dirty map.
+const XFS_BLF_CHUNK: usize = 128;
+
+/// Size of the 32-bit `xfs_inode_log_format`; current kernels log the
+/// 56-byte 64-bit form.
+const ILF_LEGACY_SIZE: usize = 52;
+
+/// `ilf_fields` bits of an inode item.
+const XFS_ILOG_DDATA: u32 = 0x2;
+const XFS_ILOG_DEXT: u32 = 0x4;
+const XFS_ILOG_DBROOT: u32 = 0x8;
+const XFS_ILOG_DEV: u32 = 0x10;
+const XFS_ILOG_ADATA: u32 = 0x40;
+const XFS_ILOG_AEXT: u32 = 0x80;
+const XFS_ILOG_ABROOT: u32 = 0x100;
+const XFS_ILOG_DFORK: u32 = XFS_ILOG_DDATA | XFS_ILOG_DEXT | XFS_ILOG_DBROOT;
+const XFS_ILOG_AFORK: u32 = XFS_ILOG_ADATA | XFS_ILOG_AEXT | XFS_ILOG_ABROOT;
+
+/// Inode core fields.
+const DI_NEXT_UNLINKED: usize = 96;
+const DI_LSN: usize = 112;
+const DI_FLAGS2: usize = 120;
+
+/// Bmap btree block header sizes, V4 and V5.
+const BMBT_BLOCK_LEN: usize = 24;
+const BMBT_CRC_BLOCK_LEN: usize = 72;
+/// Header of the bmap btree root in an inode fork (`xfs_bmdr_block`).
+const BMDR_BLOCK_LEN: usize = 4;
+
+/// What a replay did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReplayReport {
+    /// Committed transactions replayed.
+    pub transactions: usize,
+    pub buffers: usize,
+    pub inodes: usize,
+    /// Buffer changes dropped because the buffer was freed later.
+    pub cancelled: usize,
+    /// Inode changes already on disk, by the inode's LSN.
+    pub already_written: usize,
+    /// Items of kinds not replayed, or too damaged to replay.
+    pub unsupported: usize,
+}
+
+/// An [`IoReader`] that serves `inner`'s bytes with the internal log
+/// replayed over them.
+pub struct ReplayReader<R> {
+    inner: R,
+    /// Changed sectors by sector number.
+    overlay: BTreeMap<u64, Vec<u8>>,
+    buf: Vec<u8>,
+    log: Option<LogSummary>,
+    report: ReplayReport,
+}
+
+impl<R: IoReader> ReplayReader<R> {
+    /// Read the superblock and log through `inner` and replay the log.
+    ///
+    /// A clean log, or one on an external device, replays nothing.
+    pub fn new(mut inner: R) -> Result<Self, FxfspError> {
+        let ctx = FsContext::from_superblock(inner.read_at(0, SUPERBLOCK_READ, IoPhase::Superblock)?)?;
+        let mut log = read_log(&mut inner, &ctx, LogTransactions::with_regions())?;
+        let mut reader =
+            Self { inner, overlay: BTreeMap::new(), buf: Vec::new(), log: None, report: ReplayReport::default() };
+        if let Some(log) = &mut log {
+            let mut committed: Vec<&LogTransaction> =
+                log.transactions.iter().filter(|tx| tx.commit_lsn.is_some()).collect();
+            // Replayed in commit order.
+            committed.sort_by_key(|tx| tx.commit_lsn);
+            reader.replay(&ctx, &committed)?;
+            for tx in &mut log.transactions {
+                tx.regions = Vec::new();
+            }
+        }
+        reader.log = log;
+        Ok(reader)
+    }
+
+    /// The log as found, `None` if it is on an external device.
+    pub fn log(&self) -> Option<&LogSummary> {
+        self.log.as_ref()
+    }
+
+    pub fn report(&self) -> ReplayReport {
+        self.report
+    }
+
+    /// Consume the reader and return the one it wraps.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn replay(&mut self, ctx: &FsContext, transactions: &[&LogTransaction]) -> Result<(), FxfspError> {
+        // Buffers freed in the log, by (daddr, len): changes before each
+        // cancel are dropped.
+        let mut cancels: HashMap<(u64, u32), usize> = HashMap::new();
+        for tx in transactions {
+            for (item, regions) in tx.items.iter().zip(&tx.regions) {
+                if let LogItem::Buffer { daddr, len } = *item
+                    && buf_flags(&regions[0], tx.little_endian) & XFS_BLF_CANCEL != 0
+                {
+                    *cancels.entry((daddr, len)).or_default() += 1;
+                }
+            }
+        }
+
+        for tx in transactions {
+            self.report.transactions += 1;
+            let endian = Endian(tx.little_endian);
+            for (item, regions) in tx.items.iter().zip(&tx.regions) {
+                match *item {
+                    LogItem::Buffer { daddr, len } => {
+                        let flags = buf_flags(&regions[0], tx.little_endian);
+                        let pending_cancels = cancels.get_mut(&(daddr, len)).filter(|n| **n > 0);
+                        if flags & XFS_BLF_CANCEL != 0 {
+                            if let Some(n) = pending_cancels {
+                                *n -= 1;
+                            }
+                        } else if pending_cancels.is_some() {
+                            self.report.cancelled += 1;
+                        } else if self.replay_buffer(ctx, daddr, len, flags, regions, endian)? {
+                            self.report.buffers += 1;
+                        } else {
+                            self.report.unsupported += 1;
+                        }
+                    }
+                    LogItem::Inode { .. } => match self.replay_inode(ctx, tx.start_lsn, regions, endian)? {
+                        Some(true) => self.report.inodes += 1,
+                        Some(false) => self.report.already_written += 1,
+                        None => self.report.unsupported += 1,
+                    },
+                    LogItem::Other { .. } => self.report.unsupported += 1,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy a buffer item's dirty chunks over the buffer. `false` if the
+    /// item is malformed.
+    fn replay_buffer(
+        &mut self,
+        ctx: &FsContext,
+        daddr: u64,
+        len: u32,
+        flags: u16,
+        regions: &[Vec<u8>],
+        endian: Endian,
+    ) -> Result<bool, FxfspError> {
+        let format = &regions[0];
+        let Some(map_size) = endian.u32(format, BLF_MAP_SIZE) else { return Ok(false) };
+        let map: Option<Vec<u32>> =
+            (0..map_size as usize).map(|i| endian.u32(format, BLF_DATA_MAP + i * 4)).collect();
+        let Some(map) = map else { return Ok(false) };
+        let bit = |n: usize| map[n / 32] & (1 << (n % 32)) != 0;
+        let bits = map.len() * 32;
+
+        // Runs of set bits, one region each: (offset, data).
+        let mut runs = Vec::new();
+        let mut next = 0;
+        for region in &regions[1..] {
+            let Some(start) = (next..bits).find(|&n| bit(n)) else { break };
+            let end = (start..bits).find(|&n| !bit(n)).unwrap_or(bits);
+            runs.push((start * XFS_BLF_CHUNK, region.get(..(end - start) * XFS_BLF_CHUNK).unwrap_or(region)));
+            next = end;
+        }
+
+        let v5 = ctx.version == FormatVersion::V5;
+        let inode_size = ctx.inode_size as usize;
+        self.modify(daddr * SECTOR, len as usize * BBSIZE, |buf| {
+            for (offset, data) in runs {
+                let Some(dst) = buf.get_mut(offset..) else { continue };
+                if flags & XFS_BLF_INODE_BUF == 0 {
+                    let n = data.len().min(dst.len());
+                    dst[..n].copy_from_slice(&data[..n]);
+                    continue;
+                }
+                // Only the unlinked pointers of an inode buffer are current.
+                for inode in (0..buf.len()).step_by(inode_size) {
+                    let at = inode + DI_NEXT_UNLINKED;
+                    if at >= offset && at + 4 <= offset + data.len() {
+                        buf[at..at + 4].copy_from_slice(&data[at - offset..at - offset + 4]);
+                    }
+                }
+            }
+            if v5 {
+                if buf.starts_with(b"IN") {
+                    buf.chunks_exact_mut(inode_size).filter(|i| i.starts_with(b"IN")).for_each(|i| {
+                        update_metadata_crc(i);
+                    });
+                } else {
+                    update_metadata_crc(buf);
+                }
+            }
+        })?;
+        Ok(true)
+    }
+
+    /// Write an inode item's core and forks into its inode. `Some(false)`
+    /// if the inode on disk is already newer, `None` if the item is
+    /// malformed.
+    fn replay_inode(
+        &mut self,
+        ctx: &FsContext,
+        lsn: u64,
+        regions: &[Vec<u8>],
+        endian: Endian,
+    ) -> Result<Option<bool>, FxfspError> {
+        let format = &regions[0];
+        let legacy = format.len() == ILF_LEGACY_SIZE;
+        let at = |v64: usize, v32: usize| if legacy { v32 } else { v64 };
+        let (Some(fields), Some(blkno), Some(boffset)) =
+            (endian.u32(format, 4), endian.u64(format, at(40, 36)), endian.u32(format, at(52, 48)))
+        else {
+            return Ok(None);
+        };
+        let Some(core) = regions.get(1) else { return Ok(None) };
+        let data_fork = regions.get(2).filter(|_| fields & XFS_ILOG_DFORK != 0);
+        let attr_fork = regions.get(if data_fork.is_some() { 3 } else { 2 }).filter(|_| fields & XFS_ILOG_AFORK != 0);
+        let rdev = endian.u32(format, at(24, 20));
+
+        let inode_size = ctx.inode_size as usize;
+        let offset = blkno * SECTOR + boffset as u64;
+        // Read whole sectors: the inner reader may need aligned reads.
+        let start = offset / SECTOR * SECTOR;
+        let end = (offset + inode_size as u64).div_ceil(SECTOR) * SECTOR;
+        let window = self.read_overlaid(start, (end - start) as usize)?;
+        let Some(current) = window.get((offset - start) as usize..).filter(|c| c.len() >= inode_size) else {
+            return Ok(None);
+        };
+        if !current.starts_with(b"IN") {
+            return Ok(None);
+        }
+        let version = current[4];
+        let v3 = version >= 3;
+        if v3 {
+            let disk_lsn = u64::from_be_bytes(current[DI_LSN..DI_LSN + 8].try_into().unwrap());
+            if disk_lsn != 0 && disk_lsn != u64::MAX && disk_lsn > lsn {
+                return Ok(Some(false));
+            }
+        }
+        let (core_size, literal) = if v3 { (V5_CORE_SIZE, V5_CORE_SIZE) } else { (V4_CORE_SIZE, V4_LITERAL_OFFSET) };
+        if core.len() < core_size || inode_size < literal {
+            return Ok(None);
+        }
+
+        let mut replayed = true;
+        self.modify(offset, inode_size, |inode| {
+            let next_unlinked: [u8; 4] = inode[DI_NEXT_UNLINKED..DI_NEXT_UNLINKED + 4].try_into().unwrap();
+            inode[..core_size].copy_from_slice(&core[..core_size]);
+            if endian.0 {
+                swap_log_dinode(inode, v3);
+            }
+            inode[DI_NEXT_UNLINKED..DI_NEXT_UNLINKED + 4].copy_from_slice(&next_unlinked);
+            if v3 {
+                inode[DI_LSN..DI_LSN + 8].copy_from_slice(&lsn.to_be_bytes());
+            }
+
+            let forkoff = inode[82] as usize * 8;
+            let (dsize, asize) = match forkoff {
+                0 => (inode_size - literal, 0),
+                off => (off.min(inode_size - literal), inode_size - literal - off.min(inode_size - literal)),
+            };
+            if let Some(data) = data_fork {
+                let dst = &mut inode[literal..literal + dsize];
+                replayed &= copy_fork(dst, data, fields & XFS_ILOG_DBROOT != 0, v3);
+            } else if fields & XFS_ILOG_DEV != 0
+                && let Some(rdev) = rdev
+            {
+                inode[literal..literal + 4].copy_from_slice(&rdev.to_be_bytes());
+            }
+            if let Some(attr) = attr_fork {
+                let dst = &mut inode[literal + dsize..literal + dsize + asize];
+                replayed &= copy_fork(dst, attr, fields & XFS_ILOG_ABROOT != 0, v3);
+            }
+            if v3 {
+                update_metadata_crc(inode);
+            }
+        })?;
+        Ok(replayed.then_some(true))
+    }
+
+    /// `len` bytes at `offset` as replayed so far.
+    fn read_overlaid(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, FxfspError> {
+        let mut buf = self.inner.read_at(offset, len, IoPhase::Log)?.to_vec();
+        patch(&self.overlay, offset, &mut buf);
+        Ok(buf)
+    }
+
+    /// Apply `f` to the `len` bytes at `offset` and keep the result in the
+    /// overlay.
+    fn modify(&mut self, offset: u64, len: usize, f: impl FnOnce(&mut [u8])) -> Result<(), FxfspError> {
+        let start = offset / SECTOR * SECTOR;
+        let end = (offset + len as u64).div_ceil(SECTOR) * SECTOR;
+        let mut buf = self.read_overlaid(start, (end - start) as usize)?;
+        if buf.len() < (end - start) as usize {
+            return Err(FxfspError::Parse("log replays a block past the end of the device"));
+        }
+        let at = (offset - start) as usize;
+        f(&mut buf[at..at + len]);
+        for (i, sector) in buf.chunks_exact(BBSIZE).enumerate() {
+            self.overlay.insert(start / SECTOR + i as u64, sector.to_vec());
+        }
+        Ok(())
+    }
+}
+
+impl<R: IoReader> IoReader for ReplayReader<R> {
+    fn read_at(&mut self, offset: u64, len: usize, phase: IoPhase) -> Result<&[u8], FxfspError> {
+        let bytes = self.inner.read_at(offset, len, phase)?;
+        if !overlaps(&self.overlay, offset, bytes.len()) {
+            return Ok(bytes);
+        }
+        self.buf.clear();
+        self.buf.extend_from_slice(bytes);
+        patch(&self.overlay, offset, &mut self.buf);
+        Ok(&self.buf)
+    }
+
+    fn coalesced_read_batch<T: Copy, F>(
+        &mut self,
+        requests: &[(u64, usize, T)],
+        mut on_complete: F,
+        phase: IoPhase,
+    ) -> Result<(), FxfspError>
+    where
+        F: FnMut(&[u8], T) -> Result<(), FxfspError>,
+    {
+        let tagged: Vec<(u64, usize, (u64, T))> =
+            requests.iter().map(|&(offset, len, tag)| (offset, len, (offset, tag))).collect();
+        let overlay = &self.overlay;
+        let mut patched = Vec::new();
+        self.inner.coalesced_read_batch(
+            &tagged,
+            |bytes, (offset, tag)| {
+                if !overlaps(overlay, offset, bytes.len()) {
+                    return on_complete(bytes, tag);
+                }
+                patched.clear();
+                patched.extend_from_slice(bytes);
+                patch(overlay, offset, &mut patched);
+                on_complete(&patched, tag)
+            },
+            phase,
+        )
+    }
+}
+
+/// Byte order of a transaction's item regions.
+#[derive(Clone, Copy)]
+struct Endian(bool);
+
+impl Endian {
+    fn u32(self, buf: &[u8], at: usize) -> Option<u32> {
+        let bytes = buf.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.0 { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    fn u64(self, buf: &[u8], at: usize) -> Option<u64> {
+        let bytes = buf.get(at..at + 8)?.try_into().ok()?;
+        Some(if self.0 { u64::from_le_bytes(bytes) } else { u64::from_be_bytes(bytes) })
+    }
+}
+
+fn buf_flags(format: &[u8], little_endian: bool) -> u16 {
+    let Some(bytes) = format.get(BLF_FLAGS..BLF_FLAGS + 2) else { return 0 };
+    let bytes = [bytes[0], bytes[1]];
+    if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) }
+}
+
+/// Does the overlay hold any of the `len` bytes at `offset`?
+fn overlaps(overlay: &BTreeMap<u64, Vec<u8>>, offset: u64, len: usize) -> bool {
+    len > 0 && overlay.range(offset / SECTOR..(offset + len as u64).div_ceil(SECTOR)).next().is_some()
+}
+
+/// Copy the overlay's sectors over `buf`, read from `offset`.
+fn patch(overlay: &BTreeMap<u64, Vec<u8>>, offset: u64, buf: &mut [u8]) {
+    let end = offset + buf.len() as u64;
+    for (&sector, bytes) in overlay.range(offset / SECTOR..end.div_ceil(SECTOR)) {
+        let from = (sector * SECTOR).max(offset);
+        let to = ((sector + 1) * SECTOR).min(end);
+        let src = &bytes[(from - sector * SECTOR) as usize..(to - sector * SECTOR) as usize];
+        buf[(from - offset) as usize..(to - offset) as usize].copy_from_slice(src);
+    }
+}
+
+/// Turn a little-endian log inode core (`xfs_log_dinode`), copied over an
+/// inode, into the big-endian on-disk layout.
+fn swap_log_dinode(inode: &mut [u8], v3: bool) {
+    let flags2 = if v3 { u64::from_le_bytes(inode[DI_FLAGS2..DI_FLAGS2 + 8].try_into().unwrap()) } else { 0 };
+    let flags2 = InodeFlags2::from_bits_retain(flags2);
+    let mut fields: Vec<(usize, usize)> = vec![
+        (0, 2), (2, 2), (8, 4), (12, 4), (16, 4), (20, 2), (22, 2), (56, 8), (64, 8), (72, 4), (76, 4), (80, 2),
+        (84, 4), (88, 2), (90, 2), (92, 4),
+    ];
+    if flags2.contains(InodeFlags2::NREXT64) {
+        fields.push((24, 8));
+    } else if !v3 {
+        // di_flushiter.
+        fields.push((30, 2));
+    }
+    let mut timestamps = vec![32, 40, 48];
+    if v3 {
+        fields.extend([(104, 8), (120, 8), (128, 4), (152, 8)]);
+        timestamps.push(144);
+    }
+    for at in timestamps {
+        if flags2.contains(InodeFlags2::BIGTIME) {
+            fields.push((at, 8));
+        } else {
+            // Seconds and nanoseconds, each a 32-bit word.
+            fields.extend([(at, 4), (at + 4, 4)]);
+        }
+    }
+    for (at, width) in fields {
+        inode[at..at + width].reverse();
+    }
+}
+
+/// Copy a logged fork into the inode's fork area `dst`. A btree root is
+/// logged as a full bmap btree block and stored as the smaller in-inode
+/// root. `false` if it does not fit.
+fn copy_fork(dst: &mut [u8], logged: &[u8], btree_root: bool, v5: bool) -> bool {
+    if !btree_root {
+        let n = logged.len().min(dst.len());
+        dst[..n].copy_from_slice(&logged[..n]);
+        return n == logged.len();
+    }
+    let header = if v5 { BMBT_CRC_BLOCK_LEN } else { BMBT_BLOCK_LEN };
+    if logged.len() < header || dst.len() < BMDR_BLOCK_LEN {
+        return false;
+    }
+    let numrecs = u16::from_be_bytes([logged[6], logged[7]]) as usize;
+    // Keys are followed by pointers, each array sized for the most records
+    // the space holds.
+    let src_max = (logged.len() - header) / 16;
+    let dst_max = (dst.len() - BMDR_BLOCK_LEN) / 16;
+    if numrecs > src_max || numrecs > dst_max {
+        return false;
+    }
+    dst[..BMDR_BLOCK_LEN].copy_from_slice(&logged[4..8]);
+    let keys = header..header + numrecs * 8;
+    let ptrs = header + src_max * 8..header + src_max * 8 + numrecs * 8;
+    dst[BMDR_BLOCK_LEN..BMDR_BLOCK_LEN + numrecs * 8].copy_from_slice(&logged[keys]);
+    let dst_ptrs = BMDR_BLOCK_LEN + dst_max * 8;
+    dst[dst_ptrs..dst_ptrs + numrecs * 8].copy_from_slice(&logged[ptrs]);
+    true
+}
@@ -0,0 +1,321 @@
+//! Rules evaluated as the scan goes.
+//!
+//! A compliance check is a list of [`Rule`]s, each a [`Predicate`] over an
+//! inode's attributes and the name and path of a link to it. [`RuleEngine`]
+//! runs all of them in one pass and hands each match to a [`RuleSink`] as
+//! soon as it is decided, keeping only the inodes and entries some rule
+//! could still match.
+//!
+//! ```
+//! use fxfsp::xfs::inode::S_ISUID;
+//! use fxfsp::{Predicate, Rule};
+//!
+//! let rules = vec![
+//!     Rule::new("setuid", Predicate::ModeBits(S_ISUID)),
+//!     Rule::new("keys-outside-etc", Predicate::All(vec![
+//!         Predicate::NameSuffix(b".pem".to_vec()),
+//!         Predicate::Not(Box::new(Predicate::Under(b"/etc".to_vec()))),
+//!     ])),
+//! ];
+//! ```
+//!
+//! The engine takes inodes, [`end_ag`](RuleEngine::end_ag) after each AG's
+//! inode phase, then directory entries.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::staged::{InodeInfo, SuperblockInfo};
+use crate::tree::{DirTree, is_under};
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::inode::{InodeFlags, S_IFMT};
+
+/// A condition on a link: an inode and one directory entry naming it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Predicate {
+    /// `mode & S_IFMT` is this (`S_IFREG`, `S_IFDIR`, ...).
+    FileType(u16),
+    /// All of these mode bits are set.
+    ModeBits(u16),
+    /// All of these inode flags are set.
+    Flags(InodeFlags),
+    Uid(u32),
+    Gid(u32),
+    /// At least this many bytes (logical size).
+    SizeAtLeast(u64),
+    /// Last modified before this time (seconds since the epoch).
+    ModifiedBefore(i64),
+    /// The entry's name is exactly this.
+    NameEquals(Vec<u8>),
+    /// The entry's name ends with this, e.g. `b".pem"`.
+    NameSuffix(Vec<u8>),
+    /// The link's absolute path is this path or below it. Never true for a
+    /// link cut off from the root.
+    Under(Vec<u8>),
+    All(Vec<Predicate>),
+    Any(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+/// The attributes of an inode that predicates read.
+#[derive(Debug, Clone, Copy)]
+struct Facts {
+    mode: u16,
+    flags: InodeFlags,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    mtime: i64,
+}
+
+impl From<&InodeInfo> for Facts {
+    fn from(inode: &InodeInfo) -> Self {
+        Self {
+            mode: inode.mode,
+            flags: inode.flags,
+            uid: inode.uid,
+            gid: inode.gid,
+            size: inode.size,
+            mtime: inode.mtime_sec as i64,
+        }
+    }
+}
+
+/// What is known about a link so far.
+#[derive(Clone, Copy)]
+struct Link<'a> {
+    inode: Option<&'a Facts>,
+    name: Option<&'a [u8]>,
+    path: Option<&'a [u8]>,
+}
+
+impl Predicate {
+    /// `None` while it depends on something not yet known.
+    fn eval(&self, link: Link<'_>) -> Option<bool> {
+        let inode = |f: &dyn Fn(&Facts) -> bool| link.inode.map(f);
+        match self {
+            Self::FileType(kind) => inode(&|i| i.mode & S_IFMT == *kind),
+            Self::ModeBits(bits) => inode(&|i| i.mode & bits == *bits),
+            Self::Flags(flags) => inode(&|i| i.flags.contains(*flags)),
+            Self::Uid(uid) => inode(&|i| i.uid == *uid),
+            Self::Gid(gid) => inode(&|i| i.gid == *gid),
+            Self::SizeAtLeast(size) => inode(&|i| i.size >= *size),
+            Self::ModifiedBefore(t) => inode(&|i| i.mtime < *t),
+            Self::NameEquals(name) => link.name.map(|n| n == name.as_slice()),
+            Self::NameSuffix(suffix) => link.name.map(|n| n.ends_with(suffix)),
+            Self::Under(prefix) => link.path.map(|p| is_under(p, prefix)),
+            // Decided as soon as one operand decides it.
+            Self::All(preds) => {
+                let mut all = Some(true);
+                for pred in preds {
+                    match pred.eval(link) {
+                        Some(false) => return Some(false),
+                        None => all = None,
+                        Some(true) => {}
+                    }
+                }
+                all
+            }
+            Self::Any(preds) => {
+                let mut any = Some(false);
+                for pred in preds {
+                    match pred.eval(link) {
+                        Some(true) => return Some(true),
+                        None => any = None,
+                        Some(false) => {}
+                    }
+                }
+                any
+            }
+            Self::Not(pred) => pred.eval(link).map(|b| !b),
+        }
+    }
+}
+
+/// A named predicate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rule {
+    pub id: String,
+    pub predicate: Predicate,
+}
+
+impl Rule {
+    pub fn new(id: impl Into<String>, predicate: Predicate) -> Self {
+        Self { id: id.into(), predicate }
+    }
+}
+
+/// A rule matching a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RuleMatch<'a> {
+    /// [`Rule::id`].
+    pub rule: &'a str,
+    pub ino: u64,
+    /// The link's absolute path, raw (see [`escape_name`](crate::escape_name)),
+    /// or `None` if it does not reach the root.
+    pub path: Option<&'a [u8]>,
+}
+
+/// Receives matches from a [`RuleEngine`].
+pub trait RuleSink {
+    fn on_match(&mut self, m: &RuleMatch<'_>);
+}
+
+impl<F: FnMut(&RuleMatch<'_>)> RuleSink for F {
+    fn on_match(&mut self, m: &RuleMatch<'_>) {
+        self(m)
+    }
+}
+
+/// A link waiting for its path.
+#[derive(Debug)]
+struct Unplaced {
+    ino: u64,
+    facts: Facts,
+    parent: u64,
+    name: Vec<u8>,
+    /// The rules that may match it.
+    rules: Vec<usize>,
+}
+
+/// Evaluates [`Rule`]s against scan events.
+///
+/// Each link (directory entry other than `.` and `..`) is a candidate, so
+/// a hard-linked file can match once per link; the root and inodes with no
+/// entry never match. A match is reported as soon as the link's inode,
+/// name and path are known; links whose path is still incomplete wait for
+/// [`finish`](Self::finish).
+#[derive(Debug)]
+pub struct RuleEngine<S> {
+    rules: Vec<Rule>,
+    sink: S,
+    tree: DirTree,
+    /// `inop_blog + agblklog`: an inode number shifted right by this is
+    /// its AG.
+    ag_shift: u32,
+    /// AGs whose inodes have all been added.
+    ags_done: HashSet<u32>,
+    /// Inodes some rule may still match, with those rules' indexes.
+    inodes: HashMap<u64, (Facts, Vec<usize>)>,
+    /// Entries naming inodes not yet seen that some rule may match:
+    /// child → (parent, name).
+    early: HashMap<u64, Vec<(u64, Vec<u8>)>>,
+    /// Links some rule may match, waiting for their path.
+    unplaced: Vec<Unplaced>,
+}
+
+impl<S: RuleSink> RuleEngine<S> {
+    pub fn new(sb: &SuperblockInfo, rules: Vec<Rule>, sink: S) -> Self {
+        // `sb_agblklog` rounds the AG size up to a power of two.
+        let inop_blog = (sb.block_size / sb.inode_size as u32).trailing_zeros();
+        let agblklog = sb.ag_blocks.next_power_of_two().trailing_zeros();
+        Self {
+            rules,
+            sink,
+            tree: DirTree::new(sb.root_ino),
+            ag_shift: inop_blog + agblklog,
+            ags_done: HashSet::new(),
+            inodes: HashMap::new(),
+            early: HashMap::new(),
+            unplaced: Vec::new(),
+        }
+    }
+
+    /// Record an inode.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        self.tree.add_inode(inode);
+        let facts = Facts::from(inode);
+        let link = Link { inode: Some(&facts), name: None, path: None };
+        let candidates: Vec<usize> = (0..self.rules.len())
+            .filter(|&i| self.rules[i].predicate.eval(link) != Some(false))
+            .collect();
+        let early = self.early.remove(&inode.ino).unwrap_or_default();
+        if candidates.is_empty() {
+            return;
+        }
+        for (parent, name) in early {
+            self.evaluate(inode.ino, facts, &candidates, parent, name);
+        }
+        self.inodes.insert(inode.ino, (facts, candidates));
+    }
+
+    /// Mark the end of an AG's inodes. Until then, entries naming its
+    /// inodes that some rule may match on name alone are kept; calling it
+    /// once `scan_inodes` returns keeps that to the AGs in flight.
+    pub fn end_ag(&mut self, ag_number: u32) {
+        self.ags_done.insert(ag_number);
+        let shift = self.ag_shift;
+        self.early.retain(|&ino, _| ino >> shift != ag_number as u64);
+    }
+
+    /// Record a directory entry. `.` and `..` are ignored.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) {
+        self.tree.add_dir_entry(de);
+        if de.is_dot() {
+            return;
+        }
+        if let Some((facts, candidates)) = self.inodes.get(&de.child_ino) {
+            let (facts, candidates) = (*facts, candidates.clone());
+            self.evaluate(de.child_ino, facts, &candidates, de.parent_ino, de.name.to_vec());
+            return;
+        }
+        // Seen, and no rule can match it.
+        if self.ags_done.contains(&((de.child_ino >> self.ag_shift) as u32)) {
+            return;
+        }
+        let link = Link { inode: None, name: Some(de.name), path: None };
+        if self.rules.iter().any(|rule| rule.predicate.eval(link) != Some(false)) {
+            self.early.entry(de.child_ino).or_default().push((de.parent_ino, de.name.to_vec()));
+        }
+    }
+
+    /// Finish: evaluate the links whose path was incomplete, now or never
+    /// reaching the root, and return the sink.
+    pub fn finish(mut self) -> S {
+        self.tree.resolve_untyped();
+        for Unplaced { ino, facts, parent, name, rules } in std::mem::take(&mut self.unplaced) {
+            let path = self.link_path(parent, &name);
+            let link = Link { inode: Some(&facts), name: Some(&name), path: path.as_deref() };
+            for i in rules {
+                if self.rules[i].predicate.eval(link) == Some(true) {
+                    self.sink.on_match(&RuleMatch { rule: &self.rules[i].id, ino, path: path.as_deref() });
+                }
+            }
+        }
+        self.sink
+    }
+
+    /// Evaluate `candidates` on a link to `ino`, keeping the link until
+    /// [`finish`](Self::finish) if some rule may match but its path is
+    /// not complete yet.
+    fn evaluate(&mut self, ino: u64, facts: Facts, candidates: &[usize], parent: u64, name: Vec<u8>) {
+        let link = Link { inode: Some(&facts), name: Some(&name), path: None };
+        let live: Vec<usize> =
+            candidates.iter().copied().filter(|&i| self.rules[i].predicate.eval(link) != Some(false)).collect();
+        if live.is_empty() {
+            return;
+        }
+        let Some(path) = self.link_path(parent, &name) else {
+            self.unplaced.push(Unplaced { ino, facts, parent, name, rules: live });
+            return;
+        };
+        let link = Link { path: Some(&path), ..link };
+        for i in live {
+            if self.rules[i].predicate.eval(link) == Some(true) {
+                self.sink.on_match(&RuleMatch { rule: &self.rules[i].id, ino, path: Some(&path) });
+            }
+        }
+    }
+
+    /// Absolute path of `name` in directory `parent`, if it reaches the
+    /// root.
+    fn link_path(&self, parent: u64, name: &[u8]) -> Option<Vec<u8>> {
+        let mut path = self.tree.path(parent)?;
+        if path != b"/" {
+            path.push(b'/');
+        }
+        path.extend_from_slice(name);
+        Some(path)
+    }
+}
@@ -0,0 +1,195 @@
+//! Hardening report from an offline scan.
+//!
+//! [`SecurityAudit`] is fed the events of a scan and lists what a server
+//! image's security baseline checks for: setuid and setgid programs,
+//! world-writable directories without the sticky bit, device nodes outside
+//! `/dev`, and names built to hide from `ls`.
+
+use std::collections::HashMap;
+
+use crate::name::escape_name;
+use crate::staged::{InodeInfo, SuperblockInfo};
+use crate::tree::{DirTree, EntryKind, is_under};
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::inode::{S_IFBLK, S_IFCHR, S_IFDIR, S_IFMT, S_IFREG, S_ISGID, S_ISUID, S_ISVTX, S_IWOTH};
+
+/// What a finding is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SecurityIssue {
+    /// A regular file that runs as its owner.
+    Setuid { uid: u32 },
+    /// A regular file that runs with its group.
+    Setgid { gid: u32 },
+    /// A directory anyone can write to, and so delete or replace others'
+    /// files in, for want of the sticky bit.
+    WorldWritableDir,
+    /// A character or block device outside `/dev`, which gives whoever can
+    /// open it raw access to the device.
+    DeviceOutsideDev { major: u32, minor: u32 },
+    /// A name containing control characters (newlines, escapes, ...).
+    ControlCharacters,
+    /// A name of nothing but dots and whitespace other than `.` and `..`,
+    /// such as `...` or `. `, easily mistaken for them.
+    DotsAndSpaces,
+}
+
+/// One finding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SecurityFinding {
+    pub issue: SecurityIssue,
+    pub ino: u64,
+    /// Escaped with [`escape_name`], which leaves control characters as
+    /// they are: the flagged entry for a name, otherwise one of the inode's
+    /// links, each reported. `None` when none reaches the root.
+    pub path: Option<String>,
+}
+
+/// Collects scan events into a list of [`SecurityFinding`]s.
+#[derive(Debug)]
+pub struct SecurityAudit {
+    tree: DirTree,
+    /// Every regular file and device seen, with its issues. Devices are
+    /// flagged tentatively until their path is known.
+    inodes: HashMap<u64, Vec<SecurityIssue>>,
+    /// Links of each non-directory not known to be clean: child →
+    /// [(parent, name)].
+    links: HashMap<u64, Vec<(u64, Vec<u8>)>>,
+    /// Directories anyone may write to.
+    open_dirs: Vec<u64>,
+    /// Suspicious names: (issue, parent, child, name).
+    names: Vec<(SecurityIssue, u64, u64, Vec<u8>)>,
+}
+
+impl SecurityAudit {
+    pub fn new(sb: &SuperblockInfo) -> Self {
+        Self {
+            tree: DirTree::new(sb.root_ino),
+            inodes: HashMap::new(),
+            links: HashMap::new(),
+            open_dirs: Vec::new(),
+            names: Vec::new(),
+        }
+    }
+
+    /// Record an inode. Internal inodes are ignored.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        self.tree.add_inode(inode);
+        if inode.internal.is_some() {
+            return;
+        }
+        let mut issues = Vec::new();
+        match inode.mode & S_IFMT {
+            S_IFDIR => {
+                if inode.mode & (S_IWOTH | S_ISVTX) == S_IWOTH {
+                    self.open_dirs.push(inode.ino);
+                }
+                return;
+            }
+            S_IFREG => {
+                if inode.mode & S_ISUID != 0 {
+                    issues.push(SecurityIssue::Setuid { uid: inode.uid });
+                }
+                if inode.mode & S_ISGID != 0 {
+                    issues.push(SecurityIssue::Setgid { gid: inode.gid });
+                }
+            }
+            S_IFCHR | S_IFBLK => {
+                let (major, minor) = inode.device().unwrap_or_default();
+                issues.push(SecurityIssue::DeviceOutsideDev { major, minor });
+            }
+            _ => return,
+        }
+        if issues.is_empty() {
+            self.links.remove(&inode.ino);
+        }
+        self.inodes.insert(inode.ino, issues);
+    }
+
+    /// Record a directory entry.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) {
+        if de.is_dot() {
+            return;
+        }
+        if let Some(issue) = name_issue(de.name) {
+            self.names.push((issue, de.parent_ino, de.child_ino, de.name.to_vec()));
+        }
+        if self.tree.add_dir_entry(de) == EntryKind::Other {
+            self.add_link(de.parent_ino, de.child_ino, de.name);
+        }
+    }
+
+    fn add_link(&mut self, parent: u64, child: u64, name: &[u8]) {
+        // Clean files need no name.
+        if self.inodes.get(&child).is_some_and(Vec::is_empty) {
+            return;
+        }
+        self.links.entry(child).or_default().push((parent, name.to_vec()));
+    }
+
+    /// Finish and return the findings, sorted by path, then issue.
+    pub fn finish(mut self) -> Vec<SecurityFinding> {
+        for (parent, child, name) in self.tree.resolve_untyped() {
+            self.add_link(parent, child, &name);
+        }
+
+        let mut dir_paths: HashMap<u64, Option<Vec<u8>>> = HashMap::new();
+        let mut entry_path = |parent: u64, name: &[u8]| {
+            let mut path = dir_paths.entry(parent).or_insert_with(|| self.tree.path(parent)).clone()?;
+            if path != b"/" {
+                path.push(b'/');
+            }
+            path.extend_from_slice(name);
+            Some(path)
+        };
+
+        let mut findings = Vec::new();
+        for (ino, issues) in &self.inodes {
+            let links = self.links.get(ino).map_or(&[][..], Vec::as_slice);
+            let mut paths: Vec<Option<Vec<u8>>> =
+                links.iter().filter_map(|(parent, name)| entry_path(*parent, name)).map(Some).collect();
+            if paths.is_empty() {
+                paths.push(None);
+            }
+            for path in &paths {
+                let under_dev = path.as_deref().is_some_and(|p| is_under(p, b"/dev"));
+                for &issue in issues {
+                    if matches!(issue, SecurityIssue::DeviceOutsideDev { .. }) && under_dev {
+                        continue;
+                    }
+                    findings.push(SecurityFinding { issue, ino: *ino, path: path.as_deref().map(escaped) });
+                }
+            }
+        }
+        for (issue, parent, child, name) in &self.names {
+            let path = entry_path(*parent, name);
+            findings.push(SecurityFinding { issue: *issue, ino: *child, path: path.as_deref().map(escaped) });
+        }
+        for &dir in &self.open_dirs {
+            let path = self.tree.path(dir);
+            findings.push(SecurityFinding {
+                issue: SecurityIssue::WorldWritableDir,
+                ino: dir,
+                path: path.as_deref().map(escaped),
+            });
+        }
+        findings.sort_unstable_by(|a, b| (&a.path, a.issue, a.ino).cmp(&(&b.path, b.issue, b.ino)));
+        findings
+    }
+}
+
+fn escaped(path: &[u8]) -> String {
+    escape_name(path).into_owned()
+}
+
+/// Why `name` is suspicious, if it is.
+fn name_issue(name: &[u8]) -> Option<SecurityIssue> {
+    if name.iter().any(|&b| b < 0x20 || b == 0x7f) {
+        Some(SecurityIssue::ControlCharacters)
+    } else if name.iter().all(|&b| b == b'.' || b.is_ascii_whitespace()) {
+        Some(SecurityIssue::DotsAndSpaces)
+    } else {
+        None
+    }
+}
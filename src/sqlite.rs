@@ -0,0 +1,229 @@
+//! Scan events written straight into a SQLite database.
+//!
+//! [`SqliteExporter`] inserts inodes, directory entries, extents and
+//! extended attributes as they arrive, committing every
+//! [`batch_size`](SqliteExporter::with_batch_size) rows, and builds the
+//! indexes once at the end, when it is cheapest.
+//!
+//! Tables (times in seconds since the epoch, names and values as blobs):
+//!
+//! - `inodes`: one row per inode, keyed by `ino`
+//! - `dirents`: `(parent, name, child, file_type)`, `.` and `..` included
+//! - `extents`: data fork extents, `(ino, logical_offset, ag, ag_block,
+//!   block_count, unwritten)`
+//! - `xattrs`: `(ino, namespace, name, value)`
+
+use std::ops::ControlFlow;
+use std::path::Path;
+
+use rusqlite::{Connection, params};
+
+use crate::error::FxfspError;
+use crate::staged::{FileExtentsInfo, InodeInfo, XattrInfo};
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::extent::Extent;
+
+/// Rows per transaction unless set otherwise.
+const DEFAULT_BATCH_SIZE: usize = 100_000;
+
+const SCHEMA: &str = "
+    CREATE TABLE inodes (
+        ino INTEGER PRIMARY KEY,
+        generation INTEGER NOT NULL,
+        ag INTEGER NOT NULL,
+        mode INTEGER NOT NULL,
+        uid INTEGER NOT NULL,
+        gid INTEGER NOT NULL,
+        projid INTEGER NOT NULL,
+        nlink INTEGER NOT NULL,
+        size INTEGER NOT NULL,
+        nblocks INTEGER NOT NULL,
+        atime INTEGER NOT NULL,
+        mtime INTEGER NOT NULL,
+        ctime INTEGER NOT NULL,
+        flags INTEGER NOT NULL,
+        flags2 INTEGER NOT NULL,
+        rdev INTEGER,
+        internal TEXT
+    );
+    CREATE TABLE dirents (
+        parent INTEGER NOT NULL,
+        name BLOB NOT NULL,
+        child INTEGER NOT NULL,
+        file_type INTEGER NOT NULL
+    );
+    CREATE TABLE extents (
+        ino INTEGER NOT NULL,
+        logical_offset INTEGER NOT NULL,
+        ag INTEGER NOT NULL,
+        ag_block INTEGER NOT NULL,
+        block_count INTEGER NOT NULL,
+        unwritten INTEGER NOT NULL
+    );
+    CREATE TABLE xattrs (
+        ino INTEGER NOT NULL,
+        namespace TEXT NOT NULL,
+        name BLOB NOT NULL,
+        value BLOB NOT NULL
+    );
+";
+
+const INDEXES: &str = "
+    CREATE INDEX dirents_parent ON dirents (parent, name);
+    CREATE INDEX dirents_child ON dirents (child);
+    CREATE INDEX extents_ino ON extents (ino, logical_offset);
+    CREATE INDEX extents_location ON extents (ag, ag_block);
+    CREATE INDEX xattrs_ino ON xattrs (ino);
+";
+
+/// Writes scan events into a SQLite database.
+///
+/// Each `add_*` method returns [`ControlFlow::Break`] once a write has
+/// failed, so it can be returned from a scan callback as is and stops the
+/// scan; [`finish`](Self::finish) then reports the error.
+#[derive(Debug)]
+pub struct SqliteExporter {
+    conn: Connection,
+    batch_size: usize,
+    /// Rows written in the open transaction.
+    pending: usize,
+    error: Option<rusqlite::Error>,
+}
+
+impl SqliteExporter {
+    /// Create a database at `path`, which must not hold these tables yet.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, FxfspError> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// Export into an open connection, e.g. an in-memory database.
+    pub fn new(conn: Connection) -> Result<Self, FxfspError> {
+        // A half-written export is thrown away, not recovered.
+        conn.execute_batch("PRAGMA synchronous = OFF; PRAGMA journal_mode = MEMORY;")?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute_batch("BEGIN")?;
+        Ok(Self { conn, batch_size: DEFAULT_BATCH_SIZE, pending: 0, error: None })
+    }
+
+    /// Commit every `rows` rows instead of every 100 000.
+    pub fn with_batch_size(mut self, rows: usize) -> Self {
+        self.batch_size = rows.max(1);
+        self
+    }
+
+    /// Record an inode and its inline data fork extents.
+    pub fn add_inode(&mut self, inode: &InodeInfo) -> ControlFlow<()> {
+        self.write(|conn| {
+            conn.prepare_cached(
+                "INSERT OR REPLACE INTO inodes VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )?
+            .execute(params![
+                inode.ino as i64,
+                inode.generation,
+                inode.ag_number,
+                inode.mode,
+                inode.uid,
+                inode.gid,
+                inode.projid,
+                inode.nlink,
+                inode.size as i64,
+                inode.nblocks as i64,
+                inode.atime_sec,
+                inode.mtime_sec,
+                inode.ctime_sec,
+                inode.flags.bits(),
+                inode.flags2.bits() as i64,
+                inode.rdev,
+                inode.internal.map(|kind| format!("{kind:?}")),
+            ])?;
+            Ok(1)
+        })?;
+        match &inode.extents {
+            Some(extents) => self.add_extents(inode.ino, extents),
+            None => ControlFlow::Continue(()),
+        }
+    }
+
+    /// Record the extents of a btree-format file.
+    pub fn add_file_extents(&mut self, info: &FileExtentsInfo) -> ControlFlow<()> {
+        self.add_extents(info.ino, &info.extents)
+    }
+
+    /// Record a directory entry.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) -> ControlFlow<()> {
+        self.write(|conn| {
+            conn.prepare_cached("INSERT INTO dirents VALUES (?, ?, ?, ?)")?.execute(params![
+                de.parent_ino as i64,
+                de.name,
+                de.child_ino as i64,
+                de.file_type,
+            ])?;
+            Ok(1)
+        })
+    }
+
+    /// Record an extended attribute.
+    pub fn add_xattr(&mut self, xattr: &XattrInfo<'_>) -> ControlFlow<()> {
+        self.write(|conn| {
+            conn.prepare_cached("INSERT INTO xattrs VALUES (?, ?, ?, ?)")?.execute(params![
+                xattr.ino as i64,
+                xattr.namespace.to_string(),
+                xattr.name,
+                xattr.value,
+            ])?;
+            Ok(1)
+        })
+    }
+
+    /// Commit, build the indexes and return the connection, or the first
+    /// error a write hit.
+    pub fn finish(mut self) -> Result<Connection, FxfspError> {
+        if let Some(e) = self.error.take() {
+            return Err(e.into());
+        }
+        self.conn.execute_batch("COMMIT")?;
+        self.conn.execute_batch(INDEXES)?;
+        self.conn.execute_batch("ANALYZE")?;
+        Ok(self.conn)
+    }
+
+    fn add_extents(&mut self, ino: u64, extents: &[Extent]) -> ControlFlow<()> {
+        self.write(|conn| {
+            let mut insert = conn.prepare_cached("INSERT INTO extents VALUES (?, ?, ?, ?, ?, ?)")?;
+            for extent in extents {
+                insert.execute(params![
+                    ino as i64,
+                    extent.logical_offset as i64,
+                    extent.ag_number,
+                    extent.ag_block,
+                    extent.block_count as i64,
+                    extent.is_unwritten,
+                ])?;
+            }
+            Ok(extents.len())
+        })
+    }
+
+    /// Run `insert`, which returns the rows it wrote, committing when the
+    /// batch is full.
+    fn write(&mut self, insert: impl FnOnce(&Connection) -> rusqlite::Result<usize>) -> ControlFlow<()> {
+        if self.error.is_some() {
+            return ControlFlow::Break(());
+        }
+        let result = insert(&self.conn).and_then(|rows| {
+            self.pending += rows;
+            if self.pending >= self.batch_size {
+                self.pending = 0;
+                self.conn.execute_batch("COMMIT; BEGIN")?;
+            }
+            Ok(())
+        });
+        match result {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                self.error = Some(e);
+                ControlFlow::Break(())
+            }
+        }
+    }
+}
@@ -8,47 +8,98 @@
 //!
 //! The typestate pattern enforces the correct phase order at compile time.
 
-use std::ops::ControlFlow;
+pub(crate) mod log;
+mod lookup;
 
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::marker::PhantomData;
+use std::ops::{ControlFlow, Range};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::clock::Clock;
 use crate::error::FxfspError;
+use crate::handle::ScanHandle;
+use crate::packed::PackedExtents;
 use crate::reader::{IoPhase, IoReader};
-use crate::xfs::ag::AgiInfo;
-use crate::xfs::bmbt::{BmbtDirInput, collect_all_bmbt_extents};
-use crate::xfs::btree::collect_inobt_records;
-use crate::xfs::dir::block::parse_dir_data_block_staged;
+use crate::xfs::ag::parse_agfl;
+use crate::xfs::alloc::collect_free_space_records;
+use crate::xfs::refcount::collect_refcount_records;
+use crate::xfs::rmap::{XfsRmapRec, collect_rmap_records};
+use crate::xfs::attr::{XattrEntry, XattrNamespace, parse_attr_fork_blocks, parse_shortform_attrs};
+use crate::xfs::bmbt::{BmbtDirInput, bmbt_leaf_capacity, collect_all_bmbt_extents, walk_bmbt_extents};
+use crate::xfs::btree::{check_inobt_records, collect_inobt_records};
+use crate::xfs::crc::{metadata_uuid, verify_metadata_crc};
+use crate::warning::{Provenance, ScanWarning, WarningCode};
+use crate::xfs::dir::block::{DirBlockKind, parse_dir_data_block_staged};
 use crate::xfs::dir::shortform::parse_shortform_dir_staged;
 use crate::xfs::extent::{Extent, parse_extent_list};
 use crate::xfs::inode::{
-    XFS_DINODE_FMT_BTREE, XFS_DINODE_FMT_EXTENTS, XFS_DINODE_FMT_LOCAL,
-    parse_inode_core,
+    InodeFlags, InodeFlags2, InternalInode, S_IFCHR, S_IFMT, XFS_DIFLAG_NODUMP, XFS_DINODE_FMT_BTREE,
+    XFS_DINODE_FMT_EXTENTS, XFS_DINODE_FMT_LOCAL, decode_rdev, parse_inode_core,
 };
-use crate::xfs::superblock::{FormatVersion, FsContext};
+use crate::xfs::superblock::{FormatVersion, FsContext, XFS_INODES_PER_CHUNK};
+use crate::xfs::symlink::{XFS_SYMLINK_MAPS, parse_local_symlink, parse_remote_symlink_block};
+use crate::xfs::types::NULLAGINO;
 
 /// Alignment for direct I/O reads.
 const IO_ALIGN: usize = 512;
 
-/// XFS superblock is always at byte offset 0.
+/// XFS superblock is always at byte offset 0. Default initial read size:
+/// one sector on 4Kn disks, and enough for any superblock sector size.
 const SUPERBLOCK_SIZE: usize = 4096;
 
 /// Superblock information returned at scan start.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct SuperblockInfo {
     pub block_size: u32,
     pub ag_count: u32,
     pub ag_blocks: u32,
     pub inode_size: u16,
     pub root_ino: u64,
+    /// Total data blocks (`sb_dblocks`).
+    pub data_blocks: u64,
+    /// Superblock counters (`sb_icount`, `sb_ifree`, `sb_fdblocks`). On
+    /// lazy-count filesystems these are only current as of the last clean
+    /// unmount.
+    pub inode_count: u64,
+    pub free_inodes: u64,
+    pub free_blocks: u64,
+    /// Sector size in bytes (`sb_sectsize`).
+    pub sect_size: u16,
+    /// RAID stripe unit in filesystem blocks, 0 if unset.
+    pub stripe_unit: u32,
+    /// RAID stripe width in filesystem blocks, 0 if unset.
+    pub stripe_width: u32,
+    /// Log stripe unit in bytes, 0 or 1 if unset.
+    pub log_stripe_unit: u32,
+    /// Filesystem UUID.
+    pub uuid: [u8; 16],
+    /// UUID stamped into V5 metadata blocks (equal to `uuid` unless the
+    /// UUID was changed after mkfs).
+    pub meta_uuid: [u8; 16],
+    /// V5 feature bitmaps; all zero on V4.
+    pub features_compat: u32,
+    pub features_ro_compat: u32,
+    pub features_incompat: u32,
+    pub features_log_incompat: u32,
 }
 
 /// Information about a discovered inode.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct InodeInfo {
     pub ag_number: u32,
     pub ino: u64,
+    /// Inode generation; incremented each time the inode number is reused.
+    pub generation: u32,
     pub mode: u16,
     pub size: u64,
     pub uid: u32,
     pub gid: u32,
+    /// Project ID, for project quota accounting; 0 if unset.
+    pub projid: u32,
     pub nlink: u32,
     pub mtime_sec: u32,
     pub mtime_nsec: u32,
@@ -57,69 +108,472 @@ pub struct InodeInfo {
     pub ctime_sec: u32,
     pub ctime_nsec: u32,
     pub nblocks: u64,
+    /// Extent size hint in filesystem blocks, 0 if unset.
+    pub extsize: u32,
+    /// Copy-on-write extent size hint in filesystem blocks (V5), 0 if unset.
+    pub cowextsize: u32,
+    /// Inode flags (`di_flags`): immutable, append-only, nodump and the like.
+    pub flags: InodeFlags,
+    /// V5 inode flags (`di_flags2`): reflink, DAX, CoW extent size hint.
+    /// Empty on V4.
+    pub flags2: InodeFlags2,
+    /// DMAPI event mask; nonzero when an HSM is watching the file.
+    pub dmevmask: u32,
+    /// DMAPI state; nonzero for HSM-managed (e.g. offline/stubbed) files.
+    pub dmstate: u16,
+    /// Encoded device number (XFS on-disk `dev_t`) for character and block
+    /// device inodes, `None` otherwise.
+    pub rdev: Option<u32>,
     /// Physical extent map for regular files with inline extents.
     /// `None` for directories, non-regular files, and btree-format files
     /// (whose extents arrive via [`FileExtentsInfo`]).
     pub extents: Option<Vec<Extent>>,
+    /// Attribute fork extent map for inodes whose xattrs live in
+    /// extents-format attr blocks (large or numerous xattrs). `None` when the
+    /// attr fork is absent, inline (shortform) or btree-format.
+    pub attr_extents: Option<Vec<Extent>>,
+    /// What the inode is for if it holds filesystem metadata (quotas, the
+    /// realtime bitmap and summary, the metadata directory tree); `None`
+    /// for users' files. Leave these out of user-facing counts.
+    pub internal: Option<InternalInode>,
+    /// How far this record can be trusted.
+    pub provenance: Provenance,
+}
+
+/// Stable identity of a file: inode number plus generation.
+///
+/// An inode number alone is reused once a file is deleted; the generation
+/// distinguishes "same inode, new file" from "same file, modified".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId {
+    pub ino: u64,
+    pub generation: u32,
+}
+
+impl InodeInfo {
+    /// Stable `(ino, generation)` identity of this file.
+    pub fn file_id(&self) -> FileId {
+        FileId { ino: self.ino, generation: self.generation }
+    }
+
+    /// Is this an overlayfs whiteout (a 0:0 character device)?
+    ///
+    /// Overlayfs records deletions in an upperdir this way; image-analysis
+    /// tools must hide the same-named lower entry rather than report a device.
+    pub fn is_overlay_whiteout(&self) -> bool {
+        self.mode & S_IFMT == S_IFCHR && self.rdev == Some(0)
+    }
+
+    /// `(major, minor)` of a character or block device, as `mknod` takes
+    /// them, `None` for other inodes.
+    pub fn device(&self) -> Option<(u32, u32)> {
+        self.rdev.map(decode_rdev)
+    }
+
+    /// Is the file marked "do not dump" (`chattr +d`)?
+    pub fn is_nodump(&self) -> bool {
+        self.flags.contains(InodeFlags::NODUMP)
+    }
+
+    /// Is the file under DMAPI/HSM management?
+    ///
+    /// Such files may be offline stubs whose data lives in an archive; reading
+    /// them through the kernel triggers a recall, and their on-disk size can
+    /// be misleading.
+    pub fn is_hsm_managed(&self) -> bool {
+        self.dmevmask != 0 || self.dmstate != 0
+    }
 }
 
 /// Physical extent map for a btree-format regular file.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct FileExtentsInfo {
     pub ino: u64,
+    /// Inode generation, matching the file's [`InodeInfo::generation`].
+    pub generation: u32,
+    /// Extents in logical order.
     pub extents: Vec<Extent>,
+    /// More events for this file follow, its map having been split per
+    /// [`ScanOptions::max_extents_per_event`]. A file's events are
+    /// consecutive and continue its map in logical order.
+    pub more: bool,
+    /// How far this map can be trusted.
+    pub provenance: Provenance,
+}
+
+impl FileExtentsInfo {
+    /// Stable `(ino, generation)` identity of this file.
+    pub fn file_id(&self) -> FileId {
+        FileId { ino: self.ino, generation: self.generation }
+    }
+
+    /// The extent map in compact form, for consumers that keep it.
+    pub fn packed(&self, ctx: &FsContext) -> PackedExtents {
+        PackedExtents::from_extents(ctx, &self.extents)
+    }
+}
+
+/// Target of a symbolic link.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct SymlinkTargetInfo<'a> {
+    pub ino: u64,
+    /// Inode generation, matching the link's [`InodeInfo::generation`].
+    pub generation: u32,
+    /// The target exactly as stored: not NUL-terminated, possibly relative,
+    /// and not necessarily naming anything that exists.
+    pub target: &'a [u8],
+    /// How far this target can be trusted.
+    pub provenance: Provenance,
+}
+
+impl SymlinkTargetInfo<'_> {
+    /// Stable `(ino, generation)` identity of the link.
+    pub fn file_id(&self) -> FileId {
+        FileId { ino: self.ino, generation: self.generation }
+    }
 }
 
-/// A directory entry.
-pub struct DirEntryInfo<'a> {
-    pub parent_ino: u64,
-    pub child_ino: u64,
+/// An extended attribute held in the inode (shortform attr fork).
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct XattrInfo<'a> {
+    pub ino: u64,
+    /// Inode generation, matching the owner's [`InodeInfo::generation`].
+    pub generation: u32,
+    /// The name without its namespace prefix.
     pub name: &'a [u8],
-    pub file_type: u8,
+    pub value: &'a [u8],
+    pub namespace: XattrNamespace,
+    /// How far this attribute can be trusted.
+    pub provenance: Provenance,
+}
+
+impl XattrInfo<'_> {
+    /// Stable `(ino, generation)` identity of the owner.
+    pub fn file_id(&self) -> FileId {
+        FileId { ino: self.ino, generation: self.generation }
+    }
+}
+
+pub use crate::xfs::ag::{AgfInfo, AgiInfo};
+pub use crate::xfs::alloc::{FreeSpaceExtent, FreeSpaceOrder};
+pub use crate::xfs::refcount::SharedExtent;
+pub use crate::xfs::rmap::{RmapOwner, RmapRecord};
+pub use crate::xfs::dir::DirEntryInfo;
+
+/// Options controlling what the staged scanner reports.
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    /// Skip inodes flagged `XFS_DIFLAG_NODUMP`: they are neither reported nor
+    /// have their extents or directory entries scanned.
+    pub skip_nodump: bool,
+    /// Skip DMAPI/HSM-managed inodes (see [`InodeInfo::is_hsm_managed`]) the
+    /// same way, so backups don't archive stubs as zero-byte files.
+    pub skip_hsm_managed: bool,
+    /// Read only the filesystem blocks holding allocated inodes for chunks
+    /// with at most this many allocated inodes, instead of the whole chunk.
+    /// Trades more (smaller) requests for fewer bytes; useful on SSDs with
+    /// large inodes. 0 (the default) always reads whole chunks.
+    pub sparse_chunk_threshold: u32,
+    /// Bytes to read for the initial superblock probe; 0 (the default)
+    /// reads 4096. Rounded up to a multiple of 512. If `sb_sectsize` turns
+    /// out larger, the full sector is re-read before verifying the CRC.
+    pub superblock_read_size: usize,
+    /// Time source for [`ScanMeta::started_at`]; `None` uses the system clock.
+    pub clock: Option<Arc<dyn Clock>>,
+    /// Backpressure for consumers that queue events (e.g. into a channel):
+    /// once this many delivered events are unacknowledged, the scan stops
+    /// submitting new read batches until the consumer calls
+    /// [`ScanHandle::consumed`]. Inode and directory reads are submitted in
+    /// slices that fit the events left, so the limit is overshot by at most
+    /// one read's events. 0 (the default) disables it.
+    pub max_unconsumed_events: usize,
+    /// Approximate bytes of directory extent maps an AG scan holds between
+    /// the inode and directory phases. Directories past it are remembered
+    /// by inode number only, then re-read and scanned in waves of about
+    /// this size during [`AgDirPhase::scan_dir_entries`]. 0 (the default)
+    /// holds every map.
+    pub dir_work_budget: usize,
+    /// Split btree-format file maps into [`FileExtentsInfo`] events of about
+    /// this many extents (the bmbt leaves covering them), so a file with
+    /// millions of extents is never held whole. 0 (the default) delivers
+    /// each map in one event.
+    pub max_extents_per_event: usize,
+    /// Read the header sectors of every AG in one batched sweep on the first
+    /// [`FsScanner::next_ag`], and skip AGs whose AGI records no inodes
+    /// (`agi_count` 0) without further I/O. For filesystems grown to
+    /// thousands of AGs, where per-AG fixed costs dominate.
+    ///
+    /// Skipped AGs are never handed out, so per-AG consumers such as
+    /// [`SpaceAccounting::add_ag`](crate::SpaceAccounting::add_ag) don't see
+    /// them. [`ParallelScanner`] ignores this option.
+    pub prefetch_ag_headers: bool,
+    /// Check the CRC of every V5 header sector, btree block, inode and
+    /// directory, symlink and attr block as it is read. Off by default; the
+    /// primary superblock's CRC is always checked. Ignored on V4.
+    pub verify_crcs: CrcVerification,
+    /// Compare the UUID stamped into every V5 header sector, btree block,
+    /// inode and directory, symlink and attr block with the superblock's
+    /// metadata UUID as it is read, recording a [`WarningCode::UuidMismatch`]
+    /// for each that differs: a stale block left from an earlier filesystem,
+    /// or one copied in from another image. Ignored on V4.
+    pub verify_uuids: bool,
+    /// Check that each AG's phases account for everything its headers
+    /// promise, to catch inodes or directories dropped silently: the inode
+    /// phase must parse as many in-use inodes as the AGI counts, and
+    /// [`AgDirPhase::scan_dir_entries`] must parse every directory the
+    /// inode phase found (one whose extents are all unwritten has nothing
+    /// to parse, and is reported). Shortfalls are recorded as
+    /// [`WarningCode::InodeCountMismatch`] and
+    /// [`WarningCode::DirectoryNotScanned`]; phases stopped early or
+    /// skipped are not checked.
+    pub verify_coverage: bool,
+    /// Time budget for the scan, from [`parse_superblock`]. Once it is
+    /// spent the scan stops cleanly at its next batch boundary: between
+    /// phases, between the slices of at most 64 MiB that inode, directory,
+    /// symlink and attr blocks are read in, or between the leaf batches of
+    /// the bmbt walk. Reads already submitted are delivered; after them the
+    /// rest of the AG delivers nothing more, and [`FsScanner::next_ag`]
+    /// returns `None` (or [`ParallelScanner::scan_ag`] fails with
+    /// [`FxfspError::DeadlineExceeded`]). The phases before the stop are
+    /// complete, the one it cut short is not; [`ScanHandle::deadline_stops`]
+    /// says where the scan stopped. `None` (the default) never stops.
+    pub deadline: Option<Duration>,
+    /// Don't deliver the `.` and `..` entries of each directory (see
+    /// [`DirEntryInfo::is_dot`]), which most consumers only filter out
+    /// again. Affects [`AgDirPhase::scan_dir_entries`] and
+    /// [`AgScanner::scan_inodes_with_shortform_dirs`].
+    pub skip_dot_entries: bool,
+}
+
+/// What [`ScanOptions::verify_crcs`] does.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CrcVerification {
+    /// Don't check metadata CRCs.
+    #[default]
+    Off,
+    /// Fail the read with [`FxfspError::CrcMismatch`].
+    Strict,
+    /// Parse the buffer anyway and record a [`WarningCode::CrcMismatch`]
+    /// with its byte offset (and inode, for inodes). An AG's mismatches are
+    /// reported once the scan moves on to the next AG.
+    Lenient,
+}
+
+impl ScanOptions {
+    fn skips(&self, inode: &crate::xfs::inode::InodeInfo) -> bool {
+        (self.skip_nodump && inode.flags & XFS_DIFLAG_NODUMP != 0)
+            || (self.skip_hsm_managed && (inode.dmevmask != 0 || inode.dmstate != 0))
+    }
+}
+
+/// Self-describing header for a saved scan.
+///
+/// Consumers that persist scan output should write this first so the record
+/// can later be tied to the device, the fxfsp build and the options used.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ScanMeta {
+    /// Wall-clock time at which the superblock was parsed.
+    pub started_at: SystemTime,
+    /// Device or image path. The scanner only sees a reader, so this is
+    /// `None` until the caller fills it in.
+    pub device: Option<String>,
+    /// Size of the data device in bytes, per the superblock.
+    pub device_size: u64,
+    pub fs_uuid: [u8; 16],
+    pub fxfsp_version: &'static str,
+    /// [`EVENT_SCHEMA_VERSION`](crate::EVENT_SCHEMA_VERSION) of the events
+    /// that follow.
+    pub event_schema_version: u32,
+    pub options: ScanOptions,
+    /// In-use inodes across all AGs, summed from the AGI counters: what the
+    /// inode phases will deliver, less any the options skip. A denominator
+    /// for progress that, unlike the AG count, holds up when AG populations
+    /// are skewed. `None` until every AG's headers have been read up front,
+    /// by [`ScanOptions::prefetch_ag_headers`] or
+    /// [`FsScanner::expected_inodes_total`].
+    pub expected_inodes_total: Option<u64>,
+}
+
+impl ScanMeta {
+    fn new(ctx: &FsContext, options: &ScanOptions, started_at: SystemTime, expected_inodes: Option<u64>) -> Self {
+        ScanMeta {
+            started_at,
+            device: None,
+            device_size: ctx.data_blocks << ctx.block_log,
+            fs_uuid: ctx.uuid,
+            fxfsp_version: env!("CARGO_PKG_VERSION"),
+            event_schema_version: crate::EVENT_SCHEMA_VERSION,
+            options: options.clone(),
+            expected_inodes_total: expected_inodes,
+        }
+    }
+}
+
+/// Disagreement between the superblock geometry and an AG header.
+///
+/// Reported by [`AgScanner::geometry_mismatch`] when `agi_length` differs
+/// from the length implied by `sb_agblocks`/`sb_dblocks`, which usually means
+/// a grown filesystem whose last AG was resized, or corruption.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GeometryMismatch {
+    pub ag_number: u32,
+    /// AG length derived from the superblock, in blocks.
+    pub superblock_length: u32,
+    /// AG length recorded in the AGI header, in blocks.
+    pub agi_length: u32,
+}
+
+/// One AG's space and inode counters, from its AGF and AGI headers.
+///
+/// Returned by [`AgScanner::summary`] without reading anything beyond the
+/// headers, for a per-AG `df` or a first look at fragmentation: a
+/// [`longest_free`](Self::longest_free) far below
+/// [`free_blocks`](Self::free_blocks) means the free space is scattered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct AgSummary {
+    pub ag_number: u32,
+    /// AG length in blocks (`agf_length`).
+    pub length: u32,
+    /// Free blocks in the free space btrees (`agf_freeblks`).
+    pub free_blocks: u32,
+    /// Length of the longest free extent, in blocks (`agf_longest`).
+    pub longest_free: u32,
+    /// Blocks reserved on the AG free list for btree growth (`agf_flcount`).
+    pub free_list_blocks: u32,
+    /// Blocks held by the free space btrees beyond their roots
+    /// (`agf_btreeblks`).
+    pub btree_blocks: u32,
+    /// Blocks held by the reverse mapping and reference count btrees.
+    pub rmap_blocks: u32,
+    pub refcount_blocks: u32,
+    /// Levels of the bnobt, cntbt, rmapbt, refcountbt and inobt; 0 for an
+    /// absent btree.
+    pub bnobt_level: u32,
+    pub cntbt_level: u32,
+    pub rmapbt_level: u32,
+    pub refcountbt_level: u32,
+    pub inobt_level: u32,
+    /// Allocated and free inodes (`agi_count`, `agi_freecount`).
+    pub inodes: u32,
+    pub free_inodes: u32,
 }
 
 /// Parse the superblock and return filesystem metadata plus a scanner.
 ///
 /// This is the entry point for the phased API.
-pub fn parse_superblock<R: IoReader>(mut reader: R) -> Result<(SuperblockInfo, FsScanner<R>), FxfspError> {
-    let sb_read_size = align_up(SUPERBLOCK_SIZE, IO_ALIGN);
+pub fn parse_superblock<R: IoReader>(reader: R) -> Result<(SuperblockInfo, FsScanner<R>), FxfspError> {
+    parse_superblock_with_options(reader, ScanOptions::default())
+}
+
+/// Like [`parse_superblock`], with explicit scan options.
+pub fn parse_superblock_with_options<R: IoReader>(
+    mut reader: R,
+    options: ScanOptions,
+) -> Result<(SuperblockInfo, FsScanner<R>), FxfspError> {
+    let started_at = options.clock.as_ref().map_or_else(SystemTime::now, |clock| clock.now());
+    let sb_read_size = match options.superblock_read_size {
+        0 => SUPERBLOCK_SIZE,
+        n => align_up(n, IO_ALIGN),
+    };
     let sb_buf = reader.read_at(0, sb_read_size, IoPhase::Superblock)?;
     let ctx = FsContext::from_superblock(sb_buf)?;
 
-    let sb_info = SuperblockInfo {
-        block_size: ctx.block_size,
-        ag_count: ctx.ag_count,
-        ag_blocks: ctx.ag_blocks,
-        inode_size: ctx.inode_size,
-        root_ino: ctx.root_ino,
-    };
+    let sect_size = ctx.sect_size as usize;
+    if sb_buf.len() >= sect_size {
+        ctx.verify_superblock_crc(sb_buf)?;
+    } else {
+        let sector = reader.read_at(0, align_up(sect_size, IO_ALIGN), IoPhase::Superblock)?;
+        ctx.verify_superblock_crc(sector)?;
+    }
+
+    let sb_info = SuperblockInfo::from_context(&ctx);
+
+    let mut warnings = Vec::new();
+    if !ctx.has_ftype {
+        warnings.push(ScanWarning::new(WarningCode::FtypeAbsent));
+    }
 
+    let deadline = options.deadline.and_then(|budget| Instant::now().checked_add(budget));
+    let handle = ScanHandle::new(options.max_unconsumed_events, deadline);
     let scanner = FsScanner {
-        reader,
+        reader: CheckedReader::new(reader, &ctx, &options),
         ctx,
+        options,
+        warnings,
+        started_at,
+        handle,
+        scratch: ScanScratch::default(),
+        prefetched: None,
+        expected_inodes: None,
+        empty_ags: Vec::new(),
+        log_dirty: false,
         current_ag: 0,
     };
 
     Ok((sb_info, scanner))
 }
 
+impl SuperblockInfo {
+    fn from_context(ctx: &FsContext) -> Self {
+        SuperblockInfo {
+            block_size: ctx.block_size,
+            ag_count: ctx.ag_count,
+            ag_blocks: ctx.ag_blocks,
+            inode_size: ctx.inode_size,
+            root_ino: ctx.root_ino,
+            data_blocks: ctx.data_blocks,
+            inode_count: ctx.inode_count,
+            free_inodes: ctx.free_inodes,
+            free_blocks: ctx.free_blocks,
+            sect_size: ctx.sect_size,
+            stripe_unit: ctx.stripe_unit,
+            stripe_width: ctx.stripe_width,
+            log_stripe_unit: ctx.log_stripe_unit,
+            uuid: ctx.uuid,
+            meta_uuid: ctx.meta_uuid,
+            features_compat: ctx.features_compat,
+            features_ro_compat: ctx.features_ro_compat,
+            features_incompat: ctx.features_incompat,
+            features_log_incompat: ctx.features_log_incompat,
+        }
+    }
+}
+
 /// Filesystem scanner for iterating through AGs.
+///
+/// `FsScanner<R>` is `Send` whenever `R` is, so a scanner can be handed to a
+/// worker thread between AGs.
 pub struct FsScanner<R: IoReader> {
-    reader: R,
+    reader: CheckedReader<R>,
     ctx: FsContext,
+    options: ScanOptions,
+    warnings: Vec<ScanWarning>,
+    started_at: SystemTime,
+    handle: ScanHandle,
+    scratch: ScanScratch,
+    /// Headers from the [`ScanOptions::prefetch_ag_headers`] sweep, by AG
+    /// number, taken as the scan reaches each AG.
+    prefetched: Option<Vec<Option<Result<AgHeaders, FxfspError>>>>,
+    /// In-use inodes the AGIs count, once every AG's headers were read.
+    expected_inodes: Option<u64>,
+    /// AGs known to hold no inodes, ascending. Reads are not coalesced
+    /// into them from another AG.
+    empty_ags: Vec<u32>,
+    /// [`inspect_log`](Self::inspect_log) found records to replay.
+    log_dirty: bool,
     current_ag: u32,
 }
 
 impl<R: IoReader> FsScanner<R> {
     /// Get the superblock information.
     pub fn superblock(&self) -> SuperblockInfo {
-        SuperblockInfo {
-            block_size: self.ctx.block_size,
-            ag_count: self.ctx.ag_count,
-            ag_blocks: self.ctx.ag_blocks,
-            inode_size: self.ctx.inode_size,
-            root_ino: self.ctx.root_ino,
-        }
+        SuperblockInfo::from_context(&self.ctx)
     }
 
     /// Access full filesystem context for advanced use.
@@ -127,149 +581,833 @@ impl<R: IoReader> FsScanner<R> {
         &self.ctx
     }
 
+    /// Metadata header describing this scan.
+    pub fn scan_meta(&self) -> ScanMeta {
+        ScanMeta::new(&self.ctx, &self.options, self.started_at, self.expected_inodes)
+    }
+
+    /// In-use inodes across all AGs, for
+    /// [`ScanMeta::expected_inodes_total`].
+    ///
+    /// The first call reads every AG's headers in one batched sweep, as
+    /// [`ScanOptions::prefetch_ag_headers`] does, and the scan then uses
+    /// those headers instead of reading them again. AGs whose headers fail
+    /// to parse count as empty; their error surfaces from
+    /// [`next_ag`](Self::next_ag) as usual.
+    pub fn expected_inodes_total(&mut self) -> Result<u64, FxfspError> {
+        if self.expected_inodes.is_none() {
+            self.sweep_ag_headers()?;
+        }
+        Ok(self.expected_inodes.unwrap_or_default())
+    }
+
+    /// Handle for pausing and resuming this scan from another thread.
+    pub fn scan_handle(&self) -> ScanHandle {
+        self.handle.clone()
+    }
+
+    /// Non-fatal conditions observed so far.
+    pub fn warnings(&self) -> &[ScanWarning] {
+        &self.warnings
+    }
+
+    /// Take the warnings observed so far, leaving the list empty.
+    pub fn take_warnings(&mut self) -> Vec<ScanWarning> {
+        self.reader.flush_warnings(&mut self.warnings);
+        std::mem::take(&mut self.warnings)
+    }
+
     /// Get the next AG scanner, or None if all AGs have been processed.
     pub fn next_ag(&mut self) -> Option<Result<AgScanner<'_, R>, FxfspError>> {
+        // Metadata mismatches of the AG just scanned, if any.
+        self.reader.flush_warnings(&mut self.warnings);
+        if self.options.prefetch_ag_headers {
+            return self.next_prefetched_ag();
+        }
         if self.current_ag >= self.ctx.ag_count {
             return None;
         }
 
         let agno = self.current_ag;
+        if self.handle.stops_before(agno) {
+            self.current_ag = self.ctx.ag_count;
+            return None;
+        }
         self.current_ag += 1;
 
-        Some(self.create_ag_scanner(agno))
+        let swept = self.prefetched.as_mut().and_then(|p| p.get_mut(agno as usize)).and_then(Option::take);
+        let headers = match swept {
+            Some(headers) => headers,
+            None => read_ag_headers(&mut self.reader, &self.ctx, &self.handle, &mut self.warnings, agno),
+        };
+        let headers = match headers {
+            Ok(headers) => headers,
+            Err(e) => return Some(Err(e)),
+        };
+        // After a sweep, every empty AG is already known.
+        if headers.agi.count == 0 && self.prefetched.is_none() {
+            self.empty_ags.push(agno);
+        }
+        Some(Ok(AgScanner::new(
+            &mut self.reader,
+            &self.ctx,
+            &self.options,
+            &self.handle,
+            &mut self.warnings,
+            &mut self.scratch,
+            &self.empty_ags,
+            self.log_dirty,
+            agno,
+            headers,
+        )))
+    }
+
+    /// [`next_ag`](Self::next_ag) with [`ScanOptions::prefetch_ag_headers`].
+    fn next_prefetched_ag(&mut self) -> Option<Result<AgScanner<'_, R>, FxfspError>> {
+        if self.current_ag < self.ctx.ag_count && self.handle.stops_before(self.current_ag) {
+            self.current_ag = self.ctx.ag_count;
+            return None;
+        }
+        if self.prefetched.is_none()
+            && self.current_ag < self.ctx.ag_count
+            && let Err(e) = self.sweep_ag_headers()
+        {
+            // The sweep failed as a whole; there is nothing to resume.
+            self.current_ag = self.ctx.ag_count;
+            return Some(Err(e));
+        }
+        let prefetched = self.prefetched.as_mut()?;
+        while self.current_ag < self.ctx.ag_count {
+            let agno = self.current_ag;
+            self.current_ag += 1;
+            let headers = match prefetched.get_mut(agno as usize).and_then(Option::take) {
+                Some(Ok(headers)) => headers,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return Some(Err(FxfspError::Parse("AG headers missing from prefetch"))),
+            };
+            if headers.agi.count == 0 {
+                continue;
+            }
+            self.handle.wait_until_clear();
+            if self.handle.stops_before(agno) {
+                self.current_ag = self.ctx.ag_count;
+                return None;
+            }
+            return Some(Ok(AgScanner::new(
+                &mut self.reader,
+                &self.ctx,
+                &self.options,
+                &self.handle,
+                &mut self.warnings,
+                &mut self.scratch,
+                &self.empty_ags,
+                self.log_dirty,
+                agno,
+                headers,
+            )));
+        }
+        None
+    }
+
+    /// Read every AG's headers ahead of the scan, noting the empty AGs and
+    /// the inodes the AGIs count.
+    fn sweep_ag_headers(&mut self) -> Result<(), FxfspError> {
+        let headers = self.prefetch_ag_headers()?;
+        let agis = || headers.iter().flatten().flatten().map(|h| &h.agi);
+        self.empty_ags = agis().filter(|agi| agi.count == 0).map(|agi| agi.ag_number).collect();
+        self.expected_inodes = Some(agis().map(|agi| agi.count.saturating_sub(agi.free_count) as u64).sum());
+        self.prefetched = Some(headers);
+        Ok(())
+    }
+
+    /// Read and parse the headers of every AG in one batch.
+    fn prefetch_ag_headers(&mut self) -> Result<Vec<Option<Result<AgHeaders, FxfspError>>>, FxfspError> {
+        self.handle.wait_until_clear();
+        let len = ag_header_read_size(&self.ctx);
+        let requests: Vec<(u64, usize, u32)> = (0..self.ctx.ag_count)
+            .map(|agno| (self.ctx.ag_start_byte(agno), len, agno))
+            .collect();
+        let mut headers = Vec::new();
+        headers.resize_with(requests.len(), || None);
+        let (ctx, warnings) = (&self.ctx, &mut self.warnings);
+        self.reader.coalesced_read_batch(
+            &requests,
+            |buf, agno| {
+                headers[agno as usize] = Some(parse_ag_headers(buf, ctx, agno, warnings));
+                Ok(())
+            },
+            IoPhase::Agi,
+        )?;
+        self.reader.flush_warnings(&mut self.warnings);
+        Ok(headers)
+    }
+
+    /// Convert into a [`ParallelScanner`] that opens a fresh reader per AG.
+    ///
+    /// The scanner's own reader is dropped; `reader_factory` is called with
+    /// the AG number each time an AG is scanned, so every AG can run on its
+    /// own `IoEngine` (and thread) independently of the others.
+    pub fn into_parallel<R2, F>(self, reader_factory: F) -> ParallelScanner<R2, F>
+    where
+        R2: IoReader,
+        F: Fn(u32) -> Result<R2, FxfspError>,
+    {
+        ParallelScanner {
+            ctx: self.ctx,
+            options: self.options,
+            warnings: Mutex::new(self.warnings),
+            started_at: self.started_at,
+            expected_inodes: self.expected_inodes,
+            log_dirty: self.log_dirty,
+            handle: self.handle,
+            scratch_pool: Mutex::new(vec![self.scratch]),
+            reader_factory,
+            _reader: PhantomData,
+        }
+    }
+}
+
+/// Filesystem scanner whose AGs can be scanned independently and concurrently.
+///
+/// Created by [`FsScanner::into_parallel`]. `ParallelScanner` is `Sync` when
+/// the factory is, so a shared reference can be handed to a thread pool and
+/// each worker calls [`scan_ag`](Self::scan_ag) for the AGs it owns.
+pub struct ParallelScanner<R, F> {
+    ctx: FsContext,
+    options: ScanOptions,
+    warnings: Mutex<Vec<ScanWarning>>,
+    started_at: SystemTime,
+    /// Carried over from [`FsScanner::expected_inodes_total`].
+    expected_inodes: Option<u64>,
+    log_dirty: bool,
+    handle: ScanHandle,
+    /// Scratch buffers of finished AG scans, one per concurrent worker at
+    /// most.
+    scratch_pool: Mutex<Vec<ScanScratch>>,
+    reader_factory: F,
+    _reader: PhantomData<fn() -> R>,
+}
+
+impl<R, F> ParallelScanner<R, F>
+where
+    R: IoReader,
+    F: Fn(u32) -> Result<R, FxfspError>,
+{
+    /// Get the superblock information.
+    pub fn superblock(&self) -> SuperblockInfo {
+        SuperblockInfo::from_context(&self.ctx)
+    }
+
+    /// Access full filesystem context for advanced use.
+    pub fn context(&self) -> &FsContext {
+        &self.ctx
+    }
+
+    /// Metadata header describing this scan.
+    pub fn scan_meta(&self) -> ScanMeta {
+        ScanMeta::new(&self.ctx, &self.options, self.started_at, self.expected_inodes)
+    }
+
+    /// Handle for pausing and resuming this scan from another thread.
+    pub fn scan_handle(&self) -> ScanHandle {
+        self.handle.clone()
     }
 
-    fn create_ag_scanner(&mut self, agno: u32) -> Result<AgScanner<'_, R>, FxfspError> {
-        // Read AGI header
-        let agi_offset = self.ctx.agi_byte_offset(agno);
-        let agi_block_offset = agi_offset & !(self.ctx.block_size as u64 - 1);
-        let agi_read_size = align_up(self.ctx.block_size as usize, IO_ALIGN);
-        let agi_buf = self.reader.read_at(agi_block_offset, agi_read_size, IoPhase::Agi)?;
-        let agi_within_block = (agi_offset - agi_block_offset) as usize;
-        let agi = AgiInfo::from_buf(&agi_buf[agi_within_block..], agno, self.ctx.version)?;
+    /// Number of AGs available for scanning.
+    pub fn ag_count(&self) -> u32 {
+        self.ctx.ag_count
+    }
+
+    /// Take the warnings observed by all AG scans so far.
+    pub fn take_warnings(&self) -> Vec<ScanWarning> {
+        std::mem::take(&mut *self.warnings.lock().unwrap_or_else(|e| e.into_inner()))
+    }
 
-        Ok(AgScanner {
-            reader: &mut self.reader,
-            ctx: &self.ctx,
+    /// Open a fresh reader for AG `agno` and run `scan` over its scanner.
+    ///
+    /// The reader lives for the duration of `scan`, so all phases must be
+    /// driven inside the closure.
+    pub fn scan_ag<T, S>(&self, agno: u32, scan: S) -> Result<T, FxfspError>
+    where
+        S: FnOnce(AgScanner<'_, R>) -> Result<T, FxfspError>,
+    {
+        if agno >= self.ctx.ag_count {
+            return Err(FxfspError::Parse("AG number out of range"));
+        }
+        if self.handle.cut_short(agno, IoPhase::Agi) {
+            return Err(FxfspError::DeadlineExceeded);
+        }
+        let mut reader = CheckedReader::new((self.reader_factory)(agno)?, &self.ctx, &self.options);
+        let mut warnings = Vec::new();
+        let pool = || self.scratch_pool.lock().unwrap_or_else(|e| e.into_inner());
+        let mut scratch = pool().pop().unwrap_or_default();
+        let result = open_ag_scanner(
+            &mut reader,
+            &self.ctx,
+            &self.options,
+            &self.handle,
+            &mut warnings,
+            &mut scratch,
+            &[],
+            self.log_dirty,
             agno,
-            agi,
-        })
+        )
+        .and_then(scan);
+        pool().push(scratch);
+        reader.flush_warnings(&mut warnings);
+        if !warnings.is_empty() {
+            self.warnings
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .append(&mut warnings);
+        }
+        result
+    }
+}
+
+/// Read the AG headers (AGF, AGI, AGFL) of `agno` and build its scanner.
+#[allow(clippy::too_many_arguments)]
+fn open_ag_scanner<'a, R: IoReader>(
+    reader: &'a mut CheckedReader<R>,
+    ctx: &'a FsContext,
+    options: &'a ScanOptions,
+    handle: &'a ScanHandle,
+    warnings: &'a mut Vec<ScanWarning>,
+    scratch: &'a mut ScanScratch,
+    empty_ags: &'a [u32],
+    log_dirty: bool,
+    agno: u32,
+) -> Result<AgScanner<'a, R>, FxfspError> {
+    let headers = read_ag_headers(reader, ctx, handle, warnings, agno)?;
+    Ok(AgScanner::new(reader, ctx, options, handle, warnings, scratch, empty_ags, log_dirty, agno, headers))
+}
+
+/// Read and parse the AGF, AGI and AGFL of `agno`.
+fn read_ag_headers<R: IoReader>(
+    reader: &mut CheckedReader<R>,
+    ctx: &FsContext,
+    handle: &ScanHandle,
+    warnings: &mut Vec<ScanWarning>,
+    agno: u32,
+) -> Result<AgHeaders, FxfspError> {
+    handle.wait_until_clear();
+    let hdr_buf = reader.read_at(ctx.ag_start_byte(agno), ag_header_read_size(ctx), IoPhase::Agi)?;
+    parse_ag_headers(hdr_buf, ctx, agno, warnings)
+}
+
+/// Records of `agno`'s rmapbt overlapping the AG blocks `blocks`.
+fn rmap_records<R: IoReader>(
+    reader: &mut CheckedReader<R>,
+    ctx: &FsContext,
+    warnings: &mut Vec<ScanWarning>,
+    agno: u32,
+    agf: &AgfInfo,
+    blocks: Range<u32>,
+) -> Result<Vec<XfsRmapRec>, FxfspError> {
+    if !ctx.feature_report().rmapbt {
+        return Err(FxfspError::Parse("filesystem has no rmapbt"));
+    }
+    if agf.rmapbt_root >= agf.length.min(ctx.ag_length(agno)) {
+        return Err(FxfspError::Parse("rmapbt root beyond end of AG"));
+    }
+    let records = collect_rmap_records(reader, ctx, agno, agf.rmapbt_root, agf.rmapbt_level, blocks);
+    reader.flush_warnings(warnings);
+    records
+}
+
+/// The parsed header sectors of one AG.
+struct AgHeaders {
+    agi: AgiInfo,
+    agf: AgfInfo,
+    free_list: Vec<u32>,
+}
+
+/// Bytes covering the four AG header sectors (SB copy, AGF, AGI, AGFL),
+/// read in one go.
+fn ag_header_read_size(ctx: &FsContext) -> usize {
+    align_up((ctx.block_size as usize).max(4 * ctx.sect_size as usize), IO_ALIGN)
+}
+
+/// Parse the AGF, AGI and AGFL of `agno` from a read of
+/// [`ag_header_read_size`] bytes at the start of the AG.
+fn parse_ag_headers(
+    hdr_buf: &[u8],
+    ctx: &FsContext,
+    agno: u32,
+    warnings: &mut Vec<ScanWarning>,
+) -> Result<AgHeaders, FxfspError> {
+    let ag_start = ctx.ag_start_byte(agno);
+    let sect_size = ctx.sect_size as usize;
+    if hdr_buf.len() < ag_header_read_size(ctx) {
+        warnings.push(
+            ScanWarning::new(WarningCode::ShortRead)
+                .with_ag(agno)
+                .with_byte_offset(ag_start),
+        );
+    }
+    let sector = |offset: u64| {
+        hdr_buf
+            .get((offset - ag_start) as usize..)
+            .ok_or(FxfspError::Parse("AG header read too short"))
+    };
+    let agf = AgfInfo::from_buf(sector(ctx.agf_byte_offset(agno))?, agno)?;
+    let agi = AgiInfo::from_buf(sector(ctx.agi_byte_offset(agno))?, agno, ctx.version)?;
+    let free_list = parse_agfl(sector(ctx.agfl_byte_offset(agno))?, &agf, sect_size, ctx.version)?;
+
+    if agi.inobt_root >= agi.length.max(ctx.ag_length(agno)) {
+        return Err(FxfspError::Parse("inobt root beyond end of AG"));
+    }
+    if agi.length != ctx.ag_length(agno) {
+        warnings.push(ScanWarning::new(WarningCode::AgLengthMismatch).with_ag(agno));
     }
+
+    Ok(AgHeaders { agi, agf, free_list })
+}
+
+/// Read and parse a single on-disk inode.
+fn read_inode<R: IoReader>(
+    reader: &mut R,
+    ctx: &FsContext,
+    ino: u64,
+) -> Result<(Vec<u8>, crate::xfs::inode::InodeInfo), FxfspError> {
+    let loc = ctx.locate_inode(ino)?;
+    let inode_size = ctx.inode_size as usize;
+    let within = (loc.agino & ((1u32 << ctx.inop_blog) - 1)) as usize * inode_size;
+
+    let block = reader.read_at(
+        ctx.ag_block_to_byte(loc.agno, loc.ag_block),
+        align_up(ctx.block_size as usize, IO_ALIGN),
+        IoPhase::InodeChunks,
+    )?;
+    let buf = block
+        .get(within..within + inode_size)
+        .ok_or(FxfspError::Parse("inode beyond end of device"))?
+        .to_vec();
+    let is_v5 = ctx.version == FormatVersion::V5;
+    let info = parse_inode_core(&buf, ino, is_v5, ctx.has_nrext64, ctx.inode_size)?;
+    Ok((buf, info))
+}
+
+fn broken_chain(agno: u32, ino: u64) -> ScanWarning {
+    ScanWarning::new(WarningCode::UnlinkedChainBroken).with_ag(agno).with_ino(ino)
 }
 
 /// Per-AG scanner for phased processing.
+///
+/// `AgScanner` and the phases that follow it are `Send` whenever `R` is.
 pub struct AgScanner<'a, R: IoReader> {
-    reader: &'a mut R,
+    reader: &'a mut CheckedReader<R>,
     ctx: &'a FsContext,
+    options: &'a ScanOptions,
+    handle: &'a ScanHandle,
+    warnings: &'a mut Vec<ScanWarning>,
+    /// Warnings recorded before this AG's scan began.
+    warnings_base: usize,
+    scratch: &'a mut ScanScratch,
+    empty_ags: &'a [u32],
+    log_dirty: bool,
     agno: u32,
     agi: AgiInfo,
+    agf: AgfInfo,
+    free_list: Vec<u32>,
 }
 
 impl<'a, R: IoReader> AgScanner<'a, R> {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        reader: &'a mut CheckedReader<R>,
+        ctx: &'a FsContext,
+        options: &'a ScanOptions,
+        handle: &'a ScanHandle,
+        warnings: &'a mut Vec<ScanWarning>,
+        scratch: &'a mut ScanScratch,
+        empty_ags: &'a [u32],
+        log_dirty: bool,
+        agno: u32,
+        headers: AgHeaders,
+    ) -> Self {
+        let AgHeaders { agi, agf, free_list } = headers;
+        AgScanner {
+            reader,
+            ctx,
+            options,
+            handle,
+            warnings_base: warnings.len(),
+            warnings,
+            scratch,
+            empty_ags,
+            log_dirty,
+            agno,
+            agi,
+            agf,
+            free_list,
+        }
+    }
+
     /// Get the AG number being scanned.
     pub fn ag_number(&self) -> u32 {
         self.agno
     }
 
-    /// Phase 1: Scan inodes, returns scanner for next phase.
-    pub fn scan_inodes<F>(self, mut callback: F) -> Result<AgExtentPhase<'a, R>, FxfspError>
+    /// Length of this AG in blocks, as recorded in its AGI header.
+    pub fn ag_length(&self) -> u32 {
+        self.agi.length
+    }
+
+    /// The AG's free space header.
+    pub fn agf(&self) -> &AgfInfo {
+        &self.agf
+    }
+
+    /// The AG's inode header.
+    pub fn agi(&self) -> &AgiInfo {
+        &self.agi
+    }
+
+    /// The AG's space and inode counters.
+    pub fn summary(&self) -> AgSummary {
+        let (agf, agi) = (&self.agf, &self.agi);
+        AgSummary {
+            ag_number: self.agno,
+            length: agf.length,
+            free_blocks: agf.free_blocks,
+            longest_free: agf.longest_free,
+            free_list_blocks: agf.fl_count,
+            btree_blocks: agf.btree_blocks,
+            rmap_blocks: agf.rmap_blocks,
+            refcount_blocks: agf.refcount_blocks,
+            bnobt_level: agf.bnobt_level,
+            cntbt_level: agf.cntbt_level,
+            rmapbt_level: agf.rmapbt_level,
+            refcountbt_level: agf.refcountbt_level,
+            inobt_level: agi.inobt_level,
+            inodes: agi.count,
+            free_inodes: agi.free_count,
+        }
+    }
+
+    /// AG blocks on the AG free list (AGFL), in list order.
+    pub fn free_list(&self) -> &[u32] {
+        &self.free_list
+    }
+
+    /// Inodes on this AG's unlinked bucket chains: unlinked while still
+    /// open (or awaiting inactivation after a crash), still holding space.
+    ///
+    /// Reads one inode per chain entry. A chain that loops or reaches an
+    /// unreadable inode is cut short with an
+    /// [`UnlinkedChainBroken`](WarningCode::UnlinkedChainBroken) warning.
+    pub fn unlinked_inodes(&mut self) -> Result<Vec<u64>, FxfspError> {
+        let mut inodes = Vec::new();
+        let mut seen = HashSet::new();
+        for &head in &self.agi.unlinked {
+            let mut agino = head;
+            while agino != NULLAGINO {
+                let ino = self.ctx.agino_to_ino(self.agno, agino);
+                if !seen.insert(agino) {
+                    self.warnings.push(broken_chain(self.agno, ino));
+                    break;
+                }
+                match read_inode(self.reader, self.ctx, ino) {
+                    Ok((_, info)) => {
+                        inodes.push(ino);
+                        agino = info.next_unlinked;
+                    }
+                    Err(FxfspError::Io(e)) => return Err(FxfspError::Io(e)),
+                    Err(_) => {
+                        self.warnings.push(broken_chain(self.agno, ino));
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(inodes)
+    }
+
+    /// Walk one of the AG's free space btrees, delivering each free extent
+    /// in `order`.
+    ///
+    /// Can be called any number of times before [`scan_inodes`](Self::scan_inodes);
+    /// both btrees hold the same extents, so one walk lists all free space.
+    /// Breaking from `callback` stops the walk.
+    pub fn scan_free_space<F>(&mut self, order: FreeSpaceOrder, mut callback: F) -> Result<(), FxfspError>
     where
-        F: FnMut(&InodeInfo) -> ControlFlow<()>,
+        F: FnMut(&FreeSpaceExtent) -> ControlFlow<()>,
     {
-        let is_v5 = self.ctx.version == FormatVersion::V5;
+        let (root, level) = match order {
+            FreeSpaceOrder::ByBlock => (self.agf.bnobt_root, self.agf.bnobt_level),
+            FreeSpaceOrder::BySize => (self.agf.cntbt_root, self.agf.cntbt_level),
+        };
+        if root >= self.agf.length.min(self.ctx.ag_length(self.agno)) {
+            return Err(FxfspError::Parse("free space btree root beyond end of AG"));
+        }
+        let records = collect_free_space_records(self.reader, self.ctx, self.agno, order, root, level);
+        self.reader.flush_warnings(self.warnings);
+        for rec in &records? {
+            if callback(&FreeSpaceExtent::new(self.agno, rec)).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
 
-        // Collect all inobt records
-        let mut inobt_records = collect_inobt_records(
-            self.reader,
-            self.ctx,
-            self.agno,
-            self.agi.inobt_root,
-            self.agi.inobt_level,
-        )?;
+    /// Walk the AG's reverse mapping btree, delivering each extent and its
+    /// owner in block order.
+    ///
+    /// Fails on filesystems without the rmapbt feature. Can be called any
+    /// number of times before [`scan_inodes`](Self::scan_inodes). Breaking
+    /// from `callback` stops the walk.
+    pub fn scan_rmap<F>(&mut self, mut callback: F) -> Result<(), FxfspError>
+    where
+        F: FnMut(&RmapRecord) -> ControlFlow<()>,
+    {
+        for rec in &rmap_records(self.reader, self.ctx, self.warnings, self.agno, &self.agf, 0..u32::MAX)? {
+            if callback(&RmapRecord::new(self.agno, rec)).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
 
-        // Sort by physical offset
-        inobt_records.sort_by_key(|r| r.start_ino());
+    /// Walk the AG's reference count btree, delivering each extent shared
+    /// by several files (reflinked or deduplicated) in block order.
+    ///
+    /// Staging extents of copy-on-write writes in flight are skipped. Fails
+    /// on filesystems without the reflink feature. Can be called any number
+    /// of times before [`scan_inodes`](Self::scan_inodes). Breaking from
+    /// `callback` stops the walk.
+    pub fn scan_shared_extents<F>(&mut self, mut callback: F) -> Result<(), FxfspError>
+    where
+        F: FnMut(&SharedExtent) -> ControlFlow<()>,
+    {
+        if !self.ctx.feature_report().reflink {
+            return Err(FxfspError::Parse("filesystem has no refcountbt"));
+        }
+        let (root, level) = (self.agf.refcountbt_root, self.agf.refcountbt_level);
+        if root >= self.agf.length.min(self.ctx.ag_length(self.agno)) {
+            return Err(FxfspError::Parse("refcountbt root beyond end of AG"));
+        }
+        let records = collect_refcount_records(self.reader, self.ctx, self.agno, root, level);
+        self.reader.flush_warnings(self.warnings);
+        for rec in records?.iter().filter(|rec| !rec.is_cow_staging()) {
+            if callback(&SharedExtent::new(self.agno, rec)).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
 
-        // Pre-compute chunk byte ranges
-        let chunk_blocks = 64usize * self.ctx.inode_size as usize / self.ctx.block_size as usize;
-        let chunk_byte_len = chunk_blocks * self.ctx.block_size as usize;
+    /// Compare the AGI's recorded length with the superblock geometry.
+    pub fn geometry_mismatch(&self) -> Option<GeometryMismatch> {
+        let superblock_length = self.ctx.ag_length(self.agno);
+        (superblock_length != self.agi.length).then_some(GeometryMismatch {
+            ag_number: self.agno,
+            superblock_length,
+            agi_length: self.agi.length,
+        })
+    }
 
-        struct ChunkMeta {
-            byte_offset: u64,
-            rec_idx: usize,
-        }
+    /// Phase 1: Scan inodes, returns scanner for next phase.
+    pub fn scan_inodes<F>(self, callback: F) -> Result<AgExtentPhase<'a, R>, FxfspError>
+    where
+        F: FnMut(&InodeInfo) -> ControlFlow<()>,
+    {
+        self.scan_inodes_inner(callback, None)
+    }
 
-        let chunks: Vec<ChunkMeta> = inobt_records
-            .iter()
-            .enumerate()
-            .map(|(idx, rec)| {
-                let chunk_ag_block = rec.start_ino() / self.ctx.inodes_per_block as u32;
-                ChunkMeta {
-                    byte_offset: self.ctx.ag_block_to_byte(self.agno, chunk_ag_block),
-                    rec_idx: idx,
-                }
-            })
-            .collect();
+    /// Phase 1, also delivering the entries of shortform directories.
+    ///
+    /// Like [`scan_inodes`](Self::scan_inodes), but a shortform directory's
+    /// entries go to `dir_callback` right after its inode, instead of being
+    /// kept for [`AgDirPhase::scan_dir_entries`]. That saves a copy of every
+    /// small directory's fork and a second pass over them. Entries of block
+    /// and btree directories still come from the directory phase.
+    ///
+    /// Breaking from either callback stops the phase.
+    pub fn scan_inodes_with_shortform_dirs<F, D>(
+        self,
+        callback: F,
+        mut dir_callback: D,
+    ) -> Result<AgExtentPhase<'a, R>, FxfspError>
+    where
+        F: FnMut(&InodeInfo) -> ControlFlow<()>,
+        D: FnMut(&DirEntryInfo) -> ControlFlow<()>,
+    {
+        let handle = self.handle;
+        let skip_dots = self.options.skip_dot_entries;
+        let mut dir_callback = |de: &DirEntryInfo| {
+            if skip_dots && de.is_dot() {
+                return ControlFlow::Continue(());
+            }
+            handle.delivered();
+            dir_callback(de)
+        };
+        self.scan_inodes_inner(callback, Some(&mut dir_callback))
+    }
 
-        let mut dir_work: Vec<DirWorkItem> = Vec::new();
-        let mut shortform_dirs: Vec<ShortformDirItem> = Vec::new();
-        let mut btree_dirs: Vec<BtreeItem> = Vec::new();
-        let mut btree_files: Vec<BtreeItem> = Vec::new();
-        let mut stopped = false;
+    fn scan_inodes_inner<F>(
+        self,
+        mut callback: F,
+        mut shortform_callback: Option<&mut ShortformCallback<'_>>,
+    ) -> Result<AgExtentPhase<'a, R>, FxfspError>
+    where
+        F: FnMut(&InodeInfo) -> ControlFlow<()>,
+    {
+        let handle = self.handle;
+        let mut callback = |inode: &InodeInfo| {
+            handle.delivered();
+            callback(inode)
+        };
+        let is_v5 = self.ctx.version == FormatVersion::V5;
 
-        let requests: Vec<(u64, usize, usize)> = chunks
-            .iter()
-            .enumerate()
-            .map(|(idx, c)| (c.byte_offset, chunk_byte_len, idx))
-            .collect();
+        self.handle.wait_until_clear();
+        let mut stopped = self.handle.cut_short(self.agno, IoPhase::InobtWalk);
+        // Collect all inobt records, validated and sorted by physical offset
+        let inobt_records = if stopped {
+            Vec::new()
+        } else {
+            collect_inobt_records(self.reader, self.ctx, self.agno, self.agi.inobt_root, self.agi.inobt_level)?
+        };
+        let inobt_records = check_inobt_records(self.ctx, self.agno, inobt_records, self.warnings);
+
+        // With more than 64 inodes per block (small inodes in large blocks)
+        // a chunk is part of a block and need not start at its beginning.
+        let chunk_byte_len = XFS_INODES_PER_CHUNK as usize * self.ctx.inode_size as usize;
+        let chunk_offset =
+            |rec_idx: usize| self.ctx.agino_byte_offset(self.agno, inobt_records[rec_idx].start_ino());
+
+        let scratch = self.scratch;
+        scratch.clear();
+        let mut dir_work = DirWork::new(self.options.dir_work_budget);
+        dir_work.items = core::mem::take(&mut scratch.dir_items);
+        dir_work.coverage = self.options.verify_coverage.then(DirCoverage::default);
+        dir_work.skip_dots = self.options.skip_dot_entries;
+        // In-use inodes parsed, delivered or skipped.
+        let mut parsed = 0u64;
+
+        let inode_size = self.ctx.inode_size as usize;
+        let inodes_per_block = (self.ctx.inodes_per_block as u32).clamp(1, 64);
+        let threshold = self.options.sparse_chunk_threshold;
+
+        let requests = &mut scratch.requests;
+        for (rec_idx, rec) in inobt_records.iter().enumerate() {
+            let byte_offset = chunk_offset(rec_idx);
+            let allocated = rec.allocated_mask();
+            if allocated.count_ones() > threshold {
+                requests.push((byte_offset, chunk_byte_len, ChunkRead { rec_idx, first: 0, end: 64 }));
+                continue;
+            }
+            // Sparse chunk: one request per run of blocks holding allocated inodes.
+            let block_mask = |i: u32| (u64::MAX >> (64 - inodes_per_block)) << i;
+            let mut i = 0;
+            while i < 64 {
+                if allocated & block_mask(i) == 0 {
+                    i += inodes_per_block;
+                    continue;
+                }
+                let first = i;
+                while i < 64 && allocated & block_mask(i) != 0 {
+                    i += inodes_per_block;
+                }
+                requests.push((
+                    byte_offset + (first as usize * inode_size) as u64,
+                    (i - first) as usize * inode_size,
+                    ChunkRead { rec_idx, first, end: i },
+                ));
+            }
+        }
 
-        self.reader.coalesced_read_batch(
-            &requests,
-            |buf, idx| {
+        self.handle.wait_until_clear();
+        if stopped || self.handle.cut_short(self.agno, IoPhase::InodeChunks) {
+            scratch.requests.clear();
+            stopped = true;
+        }
+        let cut = read_batch_paced(
+            self.reader,
+            self.handle,
+            self.agno,
+            &scratch.requests,
+            inode_size,
+            |buf, read| {
                 if stopped {
                     return Ok(());
                 }
-                let rec = &inobt_records[chunks[idx].rec_idx];
+                if buf.len() < (read.end - read.first) as usize * inode_size {
+                    let offset = chunk_offset(read.rec_idx) + (read.first as usize * inode_size) as u64;
+                    self.warnings.push(
+                        ScanWarning::new(WarningCode::ShortRead)
+                            .with_ag(self.agno)
+                            .with_byte_offset(offset),
+                    );
+                }
+                let rec = &inobt_records[read.rec_idx];
                 let result = process_inode_chunk_staged(
                     buf,
                     rec,
+                    read.first..read.end,
                     self.agno,
                     self.ctx,
+                    self.options,
                     is_v5,
+                    event_provenance(self.log_dirty, self.warnings, self.warnings_base),
                     &mut callback,
+                    shortform_callback.as_deref_mut(),
                     &mut dir_work,
-                    &mut shortform_dirs,
-                    &mut btree_dirs,
-                    &mut btree_files,
+                    &mut scratch.forks,
+                    &mut scratch.shortform_dirs,
+                    &mut scratch.btree_dirs,
+                    &mut scratch.btree_files,
+                    &mut scratch.symlinks,
+                    &mut scratch.xattrs,
                 );
-                if let Err(FxfspError::Stopped) = result {
-                    stopped = true;
-                    return Ok(());
+                match result {
+                    Ok(n) => parsed += n as u64,
+                    Err(FxfspError::Stopped) => stopped = true,
+                    Err(e) => return Err(e),
                 }
-                result
+                Ok(())
             },
             IoPhase::InodeChunks,
         )?;
+        stopped |= cut;
+
+        if self.options.verify_coverage && !stopped {
+            let in_use = self.agi.count.saturating_sub(self.agi.free_count) as u64;
+            if parsed != in_use {
+                self.warnings.push(ScanWarning::new(WarningCode::InodeCountMismatch).with_ag(self.agno));
+            }
+        }
 
         Ok(AgExtentPhase {
             reader: self.reader,
             ctx: self.ctx,
+            handle: self.handle,
+            warnings: self.warnings,
+            warnings_base: self.warnings_base,
+            log_dirty: self.log_dirty,
+            agno: self.agno,
+            empty_ags: self.empty_ags,
+            max_extents_per_event: self.options.max_extents_per_event,
             dir_work,
-            shortform_dirs,
-            btree_dirs,
-            btree_files,
+            scratch,
         })
     }
 }
 
 /// Phase 1.5: Emit extents for btree-format files.
 pub struct AgExtentPhase<'a, R: IoReader> {
-    reader: &'a mut R,
+    reader: &'a mut CheckedReader<R>,
     ctx: &'a FsContext,
-    dir_work: Vec<DirWorkItem>,
-    shortform_dirs: Vec<ShortformDirItem>,
-    btree_dirs: Vec<BtreeItem>,
-    btree_files: Vec<BtreeItem>,
+    handle: &'a ScanHandle,
+    warnings: &'a mut Vec<ScanWarning>,
+    warnings_base: usize,
+    log_dirty: bool,
+    agno: u32,
+    empty_ags: &'a [u32],
+    max_extents_per_event: usize,
+    dir_work: DirWork,
+    /// Kept forks and the btree inodes found in phase 1.
+    scratch: &'a mut ScanScratch,
 }
 
 impl<'a, R: IoReader> AgExtentPhase<'a, R> {
@@ -278,139 +1416,568 @@ impl<'a, R: IoReader> AgExtentPhase<'a, R> {
     where
         F: FnMut(&FileExtentsInfo) -> ControlFlow<()>,
     {
-        if !self.btree_dirs.is_empty() || !self.btree_files.is_empty() {
-            let inputs: Vec<BmbtDirInput> = self.btree_dirs
+        let handle = self.handle;
+        let mut callback = |fe: &FileExtentsInfo| {
+            handle.delivered();
+            callback(fe)
+        };
+        self.handle.wait_until_clear();
+        let scratch = &*self.scratch;
+        let has_btrees = !scratch.btree_dirs.is_empty() || !scratch.btree_files.is_empty();
+        if has_btrees && !self.handle.cut_short(self.agno, IoPhase::BmbtWalk) {
+            let inputs: Vec<BmbtDirInput> = scratch.btree_dirs
                 .iter()
-                .chain(self.btree_files.iter())
+                .chain(scratch.btree_files.iter())
                 .map(|item| BmbtDirInput {
                     ino: item.ino,
-                    fork_data: &item.fork_data,
+                    fork_data: scratch.forks.get(&item.fork),
                     data_fork_size: item.data_fork_size,
                 })
                 .collect();
 
-            let bmbt_results = collect_all_bmbt_extents(self.reader, self.ctx, &inputs)?;
+            let dir_inos: HashSet<u64> =
+                scratch.btree_dirs.iter().map(|d| d.ino).collect();
+            let file_gens: HashMap<u64, u32> =
+                scratch.btree_files.iter().map(|f| (f.ino, f.generation)).collect();
+
+            let provenance = event_provenance(self.log_dirty, self.warnings, self.warnings_base);
+            let max = self.max_extents_per_event;
+            let leaf_batch = if max == 0 { 0 } else { max.div_ceil(bmbt_leaf_capacity(self.ctx)) };
+            let mut dir_extents: BTreeMap<u64, Vec<Extent>> = BTreeMap::new();
+            // The event being built; sent once the next leaf shows whether
+            // the file continues.
+            let mut held: Option<FileExtentsInfo> = None;
+            // Early termination requested, but the walk still finishes for
+            // the directories, and we still return the dir phase.
+            let mut stopped = false;
+            let mut deliver = |fe: FileExtentsInfo, stopped: &mut bool| {
+                if !*stopped && callback(&fe).is_break() {
+                    *stopped = true;
+                }
+            };
+
+            let (handle, agno) = (self.handle, self.agno);
+            let mut reader = SkipEmptyAgs::new(self.reader, self.ctx, self.empty_ags);
+            let walked = walk_bmbt_extents(&mut reader, self.ctx, &inputs, leaf_batch, |ino, extents| {
+                // Between leaf batches: past the deadline, the rest of the
+                // AG is not scanned, so neither files nor directories need
+                // more of their maps.
+                if handle.cut_short(agno, IoPhase::BmbtWalk) {
+                    return Err(FxfspError::Stopped);
+                }
+                if dir_inos.contains(&ino) {
+                    dir_extents.entry(ino).or_default().extend(extents);
+                    return Ok(());
+                }
+                match &mut held {
+                    Some(fe) if fe.ino == ino && (max == 0 || fe.extents.len() < max) => {
+                        fe.extents.extend(extents);
+                        return Ok(());
+                    }
+                    Some(fe) if fe.ino == ino => fe.more = true,
+                    _ => {}
+                }
+                if let Some(fe) = held.take() {
+                    deliver(fe, &mut stopped);
+                }
+                let generation = file_gens.get(&ino).copied().unwrap_or_default();
+                held = Some(FileExtentsInfo { ino, generation, extents, more: false, provenance });
+                Ok(())
+            });
+            match walked {
+                Ok(()) => {
+                    if let Some(fe) = held {
+                        deliver(fe, &mut stopped);
+                    }
+                    for (ino, extents) in dir_extents {
+                        self.dir_work.items.push(DirWorkItem { ino, extents });
+                    }
+                }
+                Err(FxfspError::Stopped) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(AgDirPhase {
+            reader: self.reader,
+            ctx: self.ctx,
+            handle: self.handle,
+            warnings: self.warnings,
+            warnings_base: self.warnings_base,
+            log_dirty: self.log_dirty,
+            agno: self.agno,
+            empty_ags: self.empty_ags,
+            dir_work: self.dir_work,
+            scratch: self.scratch,
+        })
+    }
 
-            let dir_inos: std::collections::HashSet<u64> =
-                self.btree_dirs.iter().map(|d| d.ino).collect();
+    /// Skip if file extents are not needed.
+    pub fn skip_extents(mut self) -> AgDirPhase<'a, R> {
+        self.handle.wait_until_clear();
+        // Still need to process btree dirs to get their extents for dir phase
+        if !self.handle.cut_short(self.agno, IoPhase::BmbtWalk) {
+            walk_dir_bmbts(
+                &mut SkipEmptyAgs::new(self.reader, self.ctx, self.empty_ags),
+                self.ctx,
+                self.agno,
+                self.warnings,
+                &self.scratch.btree_dirs,
+                &self.scratch.forks,
+                &mut self.dir_work,
+            );
+        }
 
-            for (ino, extents) in bmbt_results {
-                if extents.is_empty() {
+        AgDirPhase {
+            reader: self.reader,
+            ctx: self.ctx,
+            handle: self.handle,
+            warnings: self.warnings,
+            warnings_base: self.warnings_base,
+            log_dirty: self.log_dirty,
+            agno: self.agno,
+            empty_ags: self.empty_ags,
+            dir_work: self.dir_work,
+            scratch: self.scratch,
+        }
+    }
+}
+
+/// Phase 2: Scan directory entries.
+pub struct AgDirPhase<'a, R: IoReader> {
+    reader: &'a mut CheckedReader<R>,
+    ctx: &'a FsContext,
+    handle: &'a ScanHandle,
+    warnings: &'a mut Vec<ScanWarning>,
+    warnings_base: usize,
+    log_dirty: bool,
+    agno: u32,
+    empty_ags: &'a [u32],
+    dir_work: DirWork,
+    scratch: &'a mut ScanScratch,
+}
+
+impl<'a, R: IoReader> AgDirPhase<'a, R> {
+    /// Phase 2: Scan directory entries.
+    pub fn scan_dir_entries<F>(mut self, mut callback: F) -> Result<(), FxfspError>
+    where
+        F: FnMut(&DirEntryInfo) -> ControlFlow<()>,
+    {
+        if self.handle.cut_short(self.agno, IoPhase::DirExtents) {
+            return Ok(());
+        }
+        let handle = self.handle;
+        let skip_dots = self.dir_work.skip_dots;
+        let mut callback = |de: &DirEntryInfo| {
+            if skip_dots && de.is_dot() {
+                return ControlFlow::Continue(());
+            }
+            handle.delivered();
+            callback(de)
+        };
+        // First, process shortform directories (no I/O needed)
+        let provenance = event_provenance(self.log_dirty, self.warnings, self.warnings_base);
+        let mut shortform_callback = |de: &DirEntryInfo| callback(&de.with_provenance(provenance));
+        for sf in &self.scratch.shortform_dirs {
+            let fork = self.scratch.forks.get(&sf.fork);
+            let result = parse_shortform_dir_staged(fork, sf.ino, self.ctx, &mut shortform_callback);
+            if let Err(FxfspError::Stopped) = result {
+                return Ok(()); // Early termination is not an error
+            }
+            result?;
+            self.dir_work.scanned(sf.ino);
+        }
+
+        let items = core::mem::take(&mut self.dir_work.items);
+        if self.read_dir_blocks(items, &mut callback)? {
+            return Ok(());
+        }
+
+        // Directories past the budget: re-read their inodes and scan them
+        // in waves that each fit the budget again, reusing the scratch
+        // buffers.
+        let deferred = core::mem::take(&mut self.dir_work.deferred);
+        let mut next = 0;
+        while next < deferred.len() {
+            let mut wave = DirWork::new(self.dir_work.budget);
+            wave.items = core::mem::take(&mut self.scratch.dir_items);
+            self.scratch.forks.clear();
+            self.scratch.btree_dirs.clear();
+            while next < deferred.len() && wave.deferred.is_empty() {
+                let (buf, info) = read_inode(self.reader, self.ctx, deferred[next])?;
+                if info.is_dir() {
+                    handle_directory_staged(
+                        &buf,
+                        &info,
+                        self.ctx,
+                        &mut wave,
+                        &mut self.scratch.forks,
+                        &mut Vec::new(),
+                        &mut self.scratch.btree_dirs,
+                    )?;
+                }
+                if wave.deferred.is_empty() {
+                    next += 1;
+                }
+            }
+            walk_dir_bmbts(
+                &mut SkipEmptyAgs::new(self.reader, self.ctx, self.empty_ags),
+                self.ctx,
+                self.agno,
+                self.warnings,
+                &self.scratch.btree_dirs,
+                &self.scratch.forks,
+                &mut wave,
+            );
+            if self.read_dir_blocks(wave.items, &mut callback)? {
+                return Ok(());
+            }
+        }
+
+        if let Some(coverage) = self.dir_work.coverage.take() {
+            for ino in coverage.found.into_iter().filter(|ino| !coverage.scanned.contains(ino)) {
+                self.warnings.push(
+                    ScanWarning::new(WarningCode::DirectoryNotScanned).with_ag(self.agno).with_ino(ino),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Deliver the targets of this AG's symbolic links, in inode order.
+    ///
+    /// Targets held in the inode cost no I/O; longer ones are read in one
+    /// batch. A link whose target can't be decoded is skipped with a
+    /// [`BadSymlink`](WarningCode::BadSymlink) warning. Call it before
+    /// [`scan_dir_entries`](Self::scan_dir_entries), which consumes the
+    /// phase; breaking from `callback` stops the delivery only.
+    pub fn scan_symlink_targets<F>(&mut self, mut callback: F) -> Result<(), FxfspError>
+    where
+        F: FnMut(&SymlinkTargetInfo) -> ControlFlow<()>,
+    {
+        if self.handle.cut_short(self.agno, IoPhase::SymlinkBlocks) {
+            return Ok(());
+        }
+        let symlinks = &self.scratch.symlinks;
+        let forks = &self.scratch.forks;
+        let block_size = self.ctx.block_size as usize;
+
+        // Remote targets: one read per extent, tagged (link, logical block).
+        let mut requests = Vec::new();
+        for (idx, link) in symlinks.iter().enumerate().filter(|(_, link)| link.nextents > 0) {
+            let Ok(extents) = parse_extent_list(forks.get(&link.fork), link.nextents, self.ctx) else {
+                continue;
+            };
+            for ext in extents {
+                let blocks = ext.block_count.min(XFS_SYMLINK_MAPS) as usize;
+                requests.push((ext.start_byte(self.ctx), blocks * block_size, (idx, ext.logical_offset)));
+            }
+        }
+        requests.sort_unstable_by_key(|r| r.0);
+
+        let mut pieces: Vec<(usize, u64, Vec<u8>)> = Vec::new();
+        let cut = read_batch_paced(
+            &mut SkipEmptyAgs::new(self.reader, self.ctx, self.empty_ags),
+            self.handle,
+            self.agno,
+            &requests,
+            0,
+            |buf, (idx, logical)| {
+                pieces.push((idx, logical, buf.to_vec()));
+                Ok(())
+            },
+            IoPhase::SymlinkBlocks,
+        )?;
+        if cut {
+            return Ok(());
+        }
+        pieces.sort_unstable_by_key(|&(idx, logical, _)| (idx, logical));
+
+        // `None` once a block of the link failed to decode; its later
+        // pieces are skipped.
+        let mut remote: HashMap<usize, Option<Vec<u8>>> = HashMap::new();
+        for (idx, _, bytes) in &pieces {
+            let slot = remote.entry(*idx).or_insert_with(|| Some(Vec::new()));
+            let Some(target) = slot.as_mut() else {
+                continue;
+            };
+            let mut blocks = bytes.chunks_exact(block_size);
+            if blocks.any(|block| parse_remote_symlink_block(block, self.ctx, target).is_err()) {
+                *slot = None;
+            }
+        }
+
+        for (idx, link) in symlinks.iter().enumerate() {
+            let size = usize::try_from(link.size).unwrap_or(usize::MAX);
+            let target = if link.nextents == 0 {
+                parse_local_symlink(forks.get(&link.fork), link.size).ok()
+            } else {
+                remote.get(&idx).and_then(Option::as_ref).and_then(|target| target.get(..size))
+            };
+            let Some(target) = target.filter(|target| !target.is_empty()) else {
+                self.warnings.push(
+                    ScanWarning::new(WarningCode::BadSymlink)
+                        .with_ag(self.agno)
+                        .with_ino(link.ino),
+                );
+                continue;
+            };
+            let info = SymlinkTargetInfo {
+                ino: link.ino,
+                generation: link.generation,
+                target,
+                provenance: event_provenance(self.log_dirty, self.warnings, self.warnings_base),
+            };
+            self.handle.delivered();
+            if callback(&info).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deliver this AG's extended attributes, in inode order.
+    ///
+    /// Shortform attr forks need no I/O. The blocks of leaf and node forks,
+    /// remote values included, are read in batches of about 64 MiB (or one
+    /// larger fork), each held only until its forks' attributes are
+    /// delivered. A fork that can't be decoded is skipped with a
+    /// [`BadAttrFork`](WarningCode::BadAttrFork) warning, after any of its
+    /// attributes that came before the damage. Call it before
+    /// [`scan_dir_entries`](Self::scan_dir_entries), which consumes the
+    /// phase; breaking from `callback` stops the delivery only.
+    pub fn scan_xattrs<F>(&mut self, mut callback: F) -> Result<(), FxfspError>
+    where
+        F: FnMut(&XattrInfo) -> ControlFlow<()>,
+    {
+        if self.handle.cut_short(self.agno, IoPhase::AttrBlocks) {
+            return Ok(());
+        }
+        let maps = self.map_attr_forks()?;
+        let block_size = self.ctx.block_size as usize;
+        let fork_bytes = |idx: usize| -> u64 {
+            maps.get(&idx).map_or(0, |extents| extents.iter().map(|ext| ext.block_count << self.ctx.block_log).sum())
+        };
+
+        let mut start = 0;
+        while start < self.scratch.xattrs.len() {
+            // The forks from `start` whose blocks fit a batch, at least one.
+            let mut end = start + 1;
+            let mut bytes = fork_bytes(start);
+            while end < self.scratch.xattrs.len() && bytes + fork_bytes(end) <= MAX_ATTR_BATCH {
+                bytes += fork_bytes(end);
+                end += 1;
+            }
+            let Some(attr_blocks) = self.read_attr_blocks(&maps, start..end)? else {
+                return Ok(());
+            };
+
+            for (idx, item) in self.scratch.xattrs.iter().enumerate().take(end).skip(start) {
+                let provenance = event_provenance(self.log_dirty, self.warnings, self.warnings_base);
+                let mut deliver = |attr: &XattrEntry| {
+                    self.handle.delivered();
+                    callback(&XattrInfo {
+                        ino: item.ino,
+                        generation: item.generation,
+                        name: attr.name,
+                        value: attr.value,
+                        namespace: attr.namespace,
+                        provenance,
+                    })
+                };
+                let result = if item.format == XFS_DINODE_FMT_LOCAL {
+                    parse_shortform_attrs(self.scratch.forks.get(&item.fork), &mut deliver)
+                } else if let Some(pieces) = attr_blocks.get(&idx) {
+                    let mapped_blocks = pieces.iter().map(|(_, bytes)| bytes.len() / block_size).sum();
+                    let block = |lblk: u32| attr_fork_block(pieces, block_size, lblk);
+                    parse_attr_fork_blocks(self.ctx, block, mapped_blocks, &mut deliver)
+                } else {
+                    // Its map couldn't be decoded; warned while mapping.
                     continue;
+                };
+                match result {
+                    Ok(()) => {}
+                    Err(FxfspError::Stopped) => return Ok(()),
+                    Err(_) => self.warnings.push(
+                        ScanWarning::new(WarningCode::BadAttrFork)
+                            .with_ag(self.agno)
+                            .with_ino(item.ino),
+                    ),
                 }
-                if dir_inos.contains(&ino) {
-                    self.dir_work.push(DirWorkItem { ino, extents });
-                } else {
-                    let fe = FileExtentsInfo { ino, extents };
-                    if callback(&fe).is_break() {
-                        // Early termination requested, but we still return the dir phase
-                        break;
+            }
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Map the blocks of this AG's block-format attr forks, keyed by index
+    /// into [`ScanScratch::xattrs`]. Forks whose map can't be decoded are
+    /// left out, each with a [`BadAttrFork`](WarningCode::BadAttrFork)
+    /// warning.
+    fn map_attr_forks(&mut self) -> Result<HashMap<usize, Vec<Extent>>, FxfspError> {
+        let items = &self.scratch.xattrs;
+        let forks = &self.scratch.forks;
+        let (agno, warnings) = (self.agno, &mut *self.warnings);
+        let mut bad_fork =
+            |ino: u64| warnings.push(ScanWarning::new(WarningCode::BadAttrFork).with_ag(agno).with_ino(ino));
+        let mut maps: HashMap<usize, Vec<Extent>> = HashMap::new();
+        let mut btree_inputs = Vec::new();
+        for (idx, item) in items.iter().enumerate() {
+            match item.format {
+                XFS_DINODE_FMT_LOCAL => {}
+                XFS_DINODE_FMT_EXTENTS => match parse_extent_list(forks.get(&item.fork), item.nextents, self.ctx) {
+                    Ok(extents) => {
+                        maps.insert(idx, extents);
                     }
+                    Err(_) => bad_fork(item.ino),
+                },
+                XFS_DINODE_FMT_BTREE => btree_inputs.push((
+                    idx,
+                    BmbtDirInput { ino: item.ino, fork_data: forks.get(&item.fork), data_fork_size: item.fork_size },
+                )),
+                _ => bad_fork(item.ino),
+            }
+        }
+        if !btree_inputs.is_empty() {
+            let (indexes, inputs): (Vec<usize>, Vec<BmbtDirInput>) = btree_inputs.into_iter().unzip();
+            let mut reader = SkipEmptyAgs::new(self.reader, self.ctx, self.empty_ags);
+            match collect_all_bmbt_extents(&mut reader, self.ctx, &inputs) {
+                Ok(walked) => {
+                    let by_ino: HashMap<u64, usize> =
+                        inputs.iter().zip(indexes).map(|(i, idx)| (i.ino, idx)).collect();
+                    maps.extend(walked.into_iter().map(|(ino, extents)| (by_ino[&ino], extents)));
                 }
+                Err(FxfspError::Io(e)) => return Err(FxfspError::Io(e)),
+                // The batch is walked as one; a damaged bmbt leaves all of
+                // them unmapped.
+                Err(_) => inputs.iter().for_each(|input| bad_fork(input.ino)),
             }
         }
-
-        Ok(AgDirPhase {
-            reader: self.reader,
-            ctx: self.ctx,
-            dir_work: self.dir_work,
-            shortform_dirs: self.shortform_dirs,
-        })
+        Ok(maps)
     }
 
-    /// Skip if file extents are not needed.
-    pub fn skip_extents(mut self) -> AgDirPhase<'a, R> {
-        // Still need to process btree dirs to get their extents for dir phase
-        if !self.btree_dirs.is_empty() {
-            let inputs: Vec<BmbtDirInput> = self.btree_dirs
-                .iter()
-                .map(|item| BmbtDirInput {
-                    ino: item.ino,
-                    fork_data: &item.fork_data,
-                    data_fork_size: item.data_fork_size,
-                })
-                .collect();
-
-            if let Ok(bmbt_results) = collect_all_bmbt_extents(self.reader, self.ctx, &inputs) {
-                for (ino, extents) in bmbt_results {
-                    if !extents.is_empty() {
-                        self.dir_work.push(DirWorkItem { ino, extents });
-                    }
+    /// Read the blocks of the attr forks `items` (indexes into
+    /// [`ScanScratch::xattrs`]) mapped in `maps`. Every mapped fork gets an
+    /// entry, even with no blocks read, so delivery tells it from one that
+    /// failed to map. `None` if the deadline passed before every block was
+    /// read.
+    fn read_attr_blocks(
+        &mut self,
+        maps: &HashMap<usize, Vec<Extent>>,
+        items: Range<usize>,
+    ) -> Result<Option<HashMap<usize, AttrForkBlocks>>, FxfspError> {
+        // Split long extents as directory reads are, keeping buffers small.
+        let mut requests = Vec::new();
+        let mut blocks: HashMap<usize, AttrForkBlocks> = HashMap::new();
+        for idx in items {
+            let Some(extents) = maps.get(&idx) else {
+                continue;
+            };
+            blocks.insert(idx, Vec::new());
+            for ext in extents {
+                let len = ext.block_count << self.ctx.block_log;
+                for chunk in (0..len).step_by(MAX_DIR_READ as usize) {
+                    let logical = ext.logical_offset + (chunk >> self.ctx.block_log);
+                    let byte_len = (len - chunk).min(MAX_DIR_READ) as usize;
+                    requests.push((ext.start_byte(self.ctx) + chunk, byte_len, (idx, logical)));
                 }
             }
         }
+        requests.sort_unstable_by_key(|r| r.0);
 
-        AgDirPhase {
-            reader: self.reader,
-            ctx: self.ctx,
-            dir_work: self.dir_work,
-            shortform_dirs: self.shortform_dirs,
+        let cut = read_batch_paced(
+            &mut SkipEmptyAgs::new(self.reader, self.ctx, self.empty_ags),
+            self.handle,
+            self.agno,
+            &requests,
+            0,
+            |buf, (idx, logical)| {
+                blocks.entry(idx).or_default().push((logical, buf.to_vec()));
+                Ok(())
+            },
+            IoPhase::AttrBlocks,
+        )?;
+        if cut {
+            return Ok(None);
+        }
+        for pieces in blocks.values_mut() {
+            pieces.sort_unstable_by_key(|p| p.0);
         }
+        Ok(Some(blocks))
     }
-}
-
-/// Phase 2: Scan directory entries.
-pub struct AgDirPhase<'a, R: IoReader> {
-    reader: &'a mut R,
-    ctx: &'a FsContext,
-    dir_work: Vec<DirWorkItem>,
-    shortform_dirs: Vec<ShortformDirItem>,
-}
 
-impl<'a, R: IoReader> AgDirPhase<'a, R> {
-    /// Phase 2: Scan directory entries.
-    pub fn scan_dir_entries<F>(self, mut callback: F) -> Result<(), FxfspError>
+    /// Read and parse the data blocks of `items`, then hand the emptied
+    /// list back to the scratch buffers. Returns whether the callback asked
+    /// to stop.
+    fn read_dir_blocks<F>(&mut self, mut items: Vec<DirWorkItem>, callback: &mut F) -> Result<bool, FxfspError>
     where
         F: FnMut(&DirEntryInfo) -> ControlFlow<()>,
     {
-        // First, process shortform directories (no I/O needed)
-        for sf in &self.shortform_dirs {
-            let result = parse_shortform_dir_staged(&sf.fork_data, sf.ino, self.ctx, &mut callback);
-            if let Err(FxfspError::Stopped) = result {
-                return Ok(()); // Early termination is not an error
-            }
-            result?;
-        }
-
-        if self.dir_work.is_empty() {
-            return Ok(());
-        }
+        let result = self.read_dir_blocks_of(&items, callback);
+        items.clear();
+        self.scratch.dir_items = items;
+        result
+    }
 
-        // Build one request per directory extent
-        let mut requests: Vec<(u64, usize, u64)> = Vec::new();
-        for item in &self.dir_work {
-            for ext in &item.extents {
-                if ext.block_count > 0 && !ext.is_unwritten {
-                    let byte_offset = ext.start_byte(self.ctx);
-                    let byte_len = (ext.block_count as usize) << self.ctx.block_log as usize;
-                    requests.push((byte_offset, byte_len, item.ino));
-                }
-            }
+    fn read_dir_blocks_of<F>(&mut self, items: &[DirWorkItem], callback: &mut F) -> Result<bool, FxfspError>
+    where
+        F: FnMut(&DirEntryInfo) -> ControlFlow<()>,
+    {
+        if items.is_empty() {
+            return Ok(false);
         }
 
-        // Sort by disk offset
-        requests.sort_by_key(|r| r.0);
+        let (requests, splits) = plan_dir_reads(self.ctx, items, self.agno, self.warnings);
 
         let dir_blk_size = self.ctx.dir_blk_size() as usize;
         let mut stopped = false;
 
-        self.reader.coalesced_read_batch(
+        self.handle.wait_until_clear();
+        if self.handle.cut_short(self.agno, IoPhase::DirExtents) {
+            return Ok(true);
+        }
+        let cut = read_batch_paced(
+            &mut SkipEmptyAgs::new(self.reader, self.ctx, self.empty_ags),
+            self.handle,
+            self.agno,
             &requests,
-            |buf, ino| {
+            MIN_DIRENT_SIZE,
+            |buf, read| {
                 if stopped {
                     return Ok(());
                 }
+                if buf.len() < read.byte_len {
+                    self.warnings.push(
+                        ScanWarning::new(WarningCode::ShortRead)
+                            .with_ag(self.agno)
+                            .with_ino(read.ino)
+                            .with_byte_offset(read.byte_offset),
+                    );
+                }
+                let provenance = event_provenance(self.log_dirty, self.warnings, self.warnings_base);
                 let mut off = 0;
                 while off + dir_blk_size <= buf.len() {
+                    let block = read.first_block + (off / dir_blk_size) as u64;
+                    let mut callback = |de: &DirEntryInfo| callback(&de.in_block(block).with_provenance(provenance));
                     let result = parse_dir_data_block_staged(
                         &buf[off..off + dir_blk_size],
-                        ino,
+                        read.ino,
                         self.ctx,
                         &mut callback,
                     );
-                    if let Err(FxfspError::Stopped) = result {
-                        stopped = true;
-                        return Ok(());
+                    match result {
+                        Err(FxfspError::Stopped) => {
+                            stopped = true;
+                            return Ok(());
+                        }
+                        Ok(DirBlockKind::Unknown) => self.warnings.push(
+                            ScanWarning::new(WarningCode::UnexpectedDirBlockMagic)
+                                .with_ag(self.agno)
+                                .with_ino(read.ino)
+                                .with_byte_offset(read.byte_offset + off as u64),
+                        ),
+                        Ok(DirBlockKind::Data) => self.dir_work.scanned(read.ino),
+                        _ => {}
                     }
                     result?;
                     off += dir_blk_size;
@@ -419,8 +1986,30 @@ impl<'a, R: IoReader> AgDirPhase<'a, R> {
             },
             IoPhase::DirExtents,
         )?;
+        if stopped || cut {
+            return Ok(true);
+        }
 
-        Ok(())
+        for split in &splits {
+            let buf = read_split_dir_block(self.reader, split)?;
+            let provenance = event_provenance(self.log_dirty, self.warnings, self.warnings_base);
+            let mut callback = |de: &DirEntryInfo| callback(&de.in_block(split.block).with_provenance(provenance));
+            let result = parse_dir_data_block_staged(&buf, split.ino, self.ctx, &mut callback);
+            match result {
+                Err(FxfspError::Stopped) => return Ok(true),
+                Ok(DirBlockKind::Unknown) => self.warnings.push(
+                    ScanWarning::new(WarningCode::UnexpectedDirBlockMagic)
+                        .with_ag(self.agno)
+                        .with_ino(split.ino)
+                        .with_byte_offset(split.pieces[0].0),
+                ),
+                Ok(DirBlockKind::Data) => self.dir_work.scanned(split.ino),
+                _ => {}
+            }
+            result?;
+        }
+
+        Ok(false)
     }
 
     /// Skip if directory entries are not needed.
@@ -431,51 +2020,641 @@ impl<'a, R: IoReader> AgDirPhase<'a, R> {
 
 // Internal types
 
+/// A read covering inodes `first..end` of the inobt record at `rec_idx`.
+#[derive(Clone, Copy)]
+struct ChunkRead {
+    rec_idx: usize,
+    first: u32,
+    end: u32,
+}
+
+/// A read of one directory extent.
+#[derive(Clone, Copy)]
+struct DirRead {
+    ino: u64,
+    /// Logical directory block index of the first block read.
+    first_block: u64,
+    byte_offset: u64,
+    byte_len: usize,
+}
+
 struct DirWorkItem {
     ino: u64,
     extents: Vec<Extent>,
 }
 
+/// Directory extent maps awaiting the directory phase.
+struct DirWork {
+    items: Vec<DirWorkItem>,
+    /// Reserved bytes, estimated from `di_nextents`.
+    bytes: usize,
+    /// [`ScanOptions::dir_work_budget`].
+    budget: usize,
+    /// Directories that did not fit, to be re-read in the directory phase.
+    deferred: Vec<u64>,
+    /// Under [`ScanOptions::verify_coverage`], the AG's directories.
+    coverage: Option<DirCoverage>,
+    /// [`ScanOptions::skip_dot_entries`].
+    skip_dots: bool,
+}
+
+/// Directories found by the inode phase, and those whose entries were
+/// parsed since.
+#[derive(Default)]
+struct DirCoverage {
+    found: Vec<u64>,
+    scanned: HashSet<u64>,
+}
+
+impl DirWork {
+    fn new(budget: usize) -> Self {
+        Self { items: Vec::new(), bytes: 0, budget, deferred: Vec::new(), coverage: None, skip_dots: false }
+    }
+
+    fn scanned(&mut self, ino: u64) {
+        if let Some(coverage) = &mut self.coverage {
+            coverage.scanned.insert(ino);
+        }
+    }
+
+    /// Reserve room for a directory's extent map, or defer it if that would
+    /// exceed the budget. The first directory always fits, however large.
+    fn reserve(&mut self, ino: u64, nextents: u32) -> bool {
+        let cost = (nextents as usize).saturating_mul(size_of::<Extent>()) + size_of::<DirWorkItem>();
+        if self.budget == 0 || self.bytes == 0 || self.bytes.saturating_add(cost) <= self.budget {
+            self.bytes = self.bytes.saturating_add(cost);
+            true
+        } else {
+            self.deferred.push(ino);
+            false
+        }
+    }
+}
+
+/// A directory block whose filesystem blocks are split across extents,
+/// as `(byte_offset, byte_len)` pieces in logical order.
+struct SplitDirBlock {
+    ino: u64,
+    /// Logical directory block index.
+    block: u64,
+    pieces: Vec<(u64, usize)>,
+}
+
+/// Directory data lives below this logical byte offset; leaf and free-index
+/// blocks sit above it and hold no names.
+const XFS_DIR2_LEAF_OFFSET: u64 = 32 << 30;
+
+/// Longest single directory or attr fork read. Extents can reach gigabytes;
+/// splitting them keeps buffers small and lengths within a 32-bit `usize`.
+/// A multiple of every directory block size.
+const MAX_DIR_READ: u64 = 16 << 20;
+
+/// Bytes of attr fork blocks [`AgDirPhase::scan_xattrs`] reads and holds at
+/// a time, unless one fork is larger.
+const MAX_ATTR_BATCH: u64 = 64 << 20;
+
+/// Plan the reads of the directory data blocks of `items`.
+///
+/// Extent ranges holding whole directory blocks become batch requests,
+/// sorted by disk offset. Directory blocks that straddle extents (possible
+/// when dir blocks are larger than fs blocks) are returned separately to be
+/// stitched together. Blocks in the leaf/free region are skipped, and so
+/// are directory blocks with unmapped pieces, each with an
+/// [`IncompleteDirBlock`](WarningCode::IncompleteDirBlock) warning.
+fn plan_dir_reads(
+    ctx: &FsContext,
+    items: &[DirWorkItem],
+    agno: u32,
+    warnings: &mut Vec<ScanWarning>,
+) -> (Vec<(u64, usize, DirRead)>, Vec<SplitDirBlock>) {
+    let dirblk_fsb = ctx.dir_blk_fsblocks() as u64;
+    let leaf_fsb = XFS_DIR2_LEAF_OFFSET >> ctx.block_log;
+    let dir_blk_size = ctx.dir_blk_size() as usize;
+    let fsb_bytes = |fsb: u64| fsb << ctx.block_log;
+
+    let mut requests = Vec::new();
+    let mut splits = Vec::new();
+    for item in items {
+        // Pieces of straddling dir blocks: dir block index -> (logical, byte, len).
+        let mut partial: BTreeMap<u64, Vec<(u64, u64, usize)>> = BTreeMap::new();
+        for ext in &item.extents {
+            let start = ext.logical_offset;
+            let end = (start + ext.block_count).min(leaf_fsb);
+            if ext.is_unwritten || start >= end {
+                continue;
+            }
+            let byte_at = |logical: u64| ext.start_byte(ctx) + ((logical - start) << ctx.block_log);
+            let whole_start = start.next_multiple_of(dirblk_fsb);
+            let whole_end = end / dirblk_fsb * dirblk_fsb;
+            if whole_start < whole_end {
+                let run_start = byte_at(whole_start);
+                let run_len = fsb_bytes(whole_end - whole_start);
+                for chunk in (0..run_len).step_by(MAX_DIR_READ as usize) {
+                    let byte_offset = run_start + chunk;
+                    let byte_len = (run_len - chunk).min(MAX_DIR_READ) as usize;
+                    let first_block = (whole_start + (chunk >> ctx.block_log)) / dirblk_fsb;
+                    let read = DirRead { ino: item.ino, first_block, byte_offset, byte_len };
+                    requests.push((byte_offset, byte_len, read));
+                }
+            }
+            let head = (start, whole_start.min(end));
+            let tail = (whole_end.max(whole_start), end);
+            for (from, to) in [head, tail] {
+                if from < to {
+                    // Less than one directory block.
+                    let len = fsb_bytes(to - from) as usize;
+                    partial.entry(from / dirblk_fsb).or_default().push((from, byte_at(from), len));
+                }
+            }
+        }
+        for (block, mut pieces) in partial {
+            pieces.sort_unstable_by_key(|p| p.0);
+            if pieces.iter().map(|p| p.2).sum::<usize>() != dir_blk_size {
+                warnings.push(
+                    ScanWarning::new(WarningCode::IncompleteDirBlock)
+                        .with_ag(agno)
+                        .with_ino(item.ino)
+                        .with_byte_offset(pieces[0].1),
+                );
+                continue;
+            }
+            splits.push(SplitDirBlock {
+                ino: item.ino,
+                block,
+                pieces: pieces.into_iter().map(|(_, byte, len)| (byte, len)).collect(),
+            });
+        }
+    }
+    requests.sort_by_key(|r| r.0);
+    (requests, splits)
+}
+
+/// Read the pieces of a straddling directory block into one buffer.
+fn read_split_dir_block<R: IoReader>(reader: &mut R, split: &SplitDirBlock) -> Result<Vec<u8>, FxfspError> {
+    let mut buf = Vec::new();
+    for &(byte_offset, len) in &split.pieces {
+        let data = reader.read_at(byte_offset, len, IoPhase::DirExtents)?;
+        buf.extend_from_slice(data.get(..len).ok_or(FxfspError::Parse("directory block read too short"))?);
+    }
+    Ok(buf)
+}
+
+/// Walk the bmbts of `btree_dirs` and add their extent maps to `dir_work`.
+/// A failed walk is reported as a warning per directory.
+fn walk_dir_bmbts<R: IoReader>(
+    reader: &mut R,
+    ctx: &FsContext,
+    agno: u32,
+    warnings: &mut Vec<ScanWarning>,
+    btree_dirs: &[BtreeItem],
+    forks: &ForkArena,
+    dir_work: &mut DirWork,
+) {
+    if btree_dirs.is_empty() {
+        return;
+    }
+    let inputs: Vec<BmbtDirInput> = btree_dirs
+        .iter()
+        .map(|item| BmbtDirInput {
+            ino: item.ino,
+            fork_data: forks.get(&item.fork),
+            data_fork_size: item.data_fork_size,
+        })
+        .collect();
+
+    match collect_all_bmbt_extents(reader, ctx, &inputs) {
+        Ok(bmbt_results) => {
+            for (ino, extents) in bmbt_results {
+                if !extents.is_empty() {
+                    dir_work.items.push(DirWorkItem { ino, extents });
+                }
+            }
+        }
+        Err(_) => {
+            for item in btree_dirs {
+                warnings.push(
+                    ScanWarning::new(WarningCode::BmbtWalkFailed)
+                        .with_ag(agno)
+                        .with_ino(item.ino),
+                );
+            }
+        }
+    }
+}
+
+/// An [`IoReader`] that splits coalesced batches where the gap between two
+/// requests crosses an AG boundary next to an AG known to hold no inodes,
+/// so the reader never gap-fills into it from another AG.
+///
+/// Bmbt and directory blocks can live in any AG; with a large merge gap a
+/// batch reaching into a distant AG would otherwise read the dead AGs in
+/// between, or the dead end of the AG it starts in.
+struct SkipEmptyAgs<'r, R> {
+    reader: &'r mut R,
+    ctx: &'r FsContext,
+    /// Ascending.
+    empty_ags: &'r [u32],
+}
+
+impl<'r, R: IoReader> SkipEmptyAgs<'r, R> {
+    fn new(reader: &'r mut R, ctx: &'r FsContext, empty_ags: &'r [u32]) -> Self {
+        Self { reader, ctx, empty_ags }
+    }
+
+    /// Whether the byte range `from..to` crosses an AG boundary and touches
+    /// an empty AG, on either side of the boundary.
+    fn borders_empty_ag(&self, from: u64, to: u64) -> bool {
+        let ag_bytes = self.ctx.ag_start_byte(1);
+        if ag_bytes == 0 || self.empty_ags.is_empty() || to <= from {
+            return false;
+        }
+        // The range touches AGs `first..=last`.
+        let first = from / ag_bytes;
+        let last = (to - 1) / ag_bytes;
+        if first == last {
+            return false;
+        }
+        let idx = self.empty_ags.partition_point(|&agno| (agno as u64) < first);
+        self.empty_ags.get(idx).is_some_and(|&agno| agno as u64 <= last)
+    }
+}
+
+impl<R: IoReader> IoReader for SkipEmptyAgs<'_, R> {
+    fn read_at(&mut self, offset: u64, len: usize, phase: IoPhase) -> Result<&[u8], FxfspError> {
+        self.reader.read_at(offset, len, phase)
+    }
+
+    fn coalesced_read_batch<T: Copy, F>(
+        &mut self,
+        requests: &[(u64, usize, T)],
+        mut on_complete: F,
+        phase: IoPhase,
+    ) -> Result<(), FxfspError>
+    where
+        F: FnMut(&[u8], T) -> Result<(), FxfspError>,
+    {
+        let mut start = 0;
+        for i in 1..=requests.len() {
+            let split = i == requests.len() || {
+                let (offset, len, _) = requests[i - 1];
+                self.borders_empty_ag(offset + len as u64, requests[i].0)
+            };
+            if split {
+                self.reader.coalesced_read_batch(&requests[start..i], &mut on_complete, phase)?;
+                start = i;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Smallest directory data entry: inode number, name length, a one-byte
+/// name and tag, 8-byte aligned.
+const MIN_DIRENT_SIZE: usize = 16;
+
+/// Bytes read between deadline checks when a scan has a deadline.
+const DEADLINE_SLICE_BYTES: usize = 64 << 20;
+
+/// Read `requests` of AG `agno`, which deliver about one event per
+/// `event_bytes` bytes (0 if the reads deliver none), in slices, checking
+/// the handle between them instead of inside completion handling. Under an
+/// event limit each slice holds the requests that fit the events left;
+/// with a deadline it holds at most [`DEADLINE_SLICE_BYTES`], at least one
+/// request either way. Otherwise the batch is read whole.
+///
+/// Returns whether the deadline passed before every slice was read.
+fn read_batch_paced<R: IoReader, T: Copy, F>(
+    reader: &mut R,
+    handle: &ScanHandle,
+    agno: u32,
+    requests: &[(u64, usize, T)],
+    event_bytes: usize,
+    mut on_complete: F,
+    phase: IoPhase,
+) -> Result<bool, FxfspError>
+where
+    F: FnMut(&[u8], T) -> Result<(), FxfspError>,
+{
+    let mut rest = requests;
+    while !rest.is_empty() {
+        handle.wait_until_clear();
+        if handle.cut_short(agno, phase) {
+            return Ok(true);
+        }
+        let events_left = handle.events_left().filter(|_| event_bytes > 0);
+        let timed = handle.has_deadline();
+        let (mut events, mut bytes) = (0u64, 0usize);
+        let past_slice = |&(_, len, _): &(u64, usize, T)| {
+            events += len.div_ceil(event_bytes.max(1)) as u64;
+            bytes += len;
+            events_left.is_some_and(|left| events > left) || (timed && bytes > DEADLINE_SLICE_BYTES)
+        };
+        let len = rest.iter().position(past_slice).unwrap_or(rest.len()).max(1);
+        let (slice, tail) = rest.split_at(len);
+        reader.coalesced_read_batch(slice, &mut on_complete, phase)?;
+        rest = tail;
+    }
+    Ok(false)
+}
+
+/// The scanner's reader, checking V5 metadata per
+/// [`ScanOptions::verify_crcs`] and [`ScanOptions::verify_uuids`].
+///
+/// Every buffer is cut into the units its phase reads (sectors, inodes,
+/// directory or filesystem blocks) and each unit with a V5 magic is checked.
+/// Mismatches that don't fail the read wait in `mismatches` until the scan
+/// next calls [`flush_warnings`](Self::flush_warnings).
+struct CheckedReader<R> {
+    inner: R,
+    check: MetadataCheck,
+}
+
+struct MetadataCheck {
+    crcs: CrcVerification,
+    uuids: bool,
+    ctx: FsContext,
+    /// Mismatches to report, by byte offset and whether each was an inode.
+    mismatches: Vec<(WarningCode, u64, bool)>,
+}
+
+impl<R: IoReader> CheckedReader<R> {
+    fn new(inner: R, ctx: &FsContext, options: &ScanOptions) -> Self {
+        // V4 AG headers share the V5 magics but have neither field.
+        let v5 = ctx.version == FormatVersion::V5;
+        let check = MetadataCheck {
+            crcs: if v5 { options.verify_crcs } else { CrcVerification::Off },
+            uuids: v5 && options.verify_uuids,
+            ctx: ctx.clone(),
+            mismatches: Vec::new(),
+        };
+        Self { inner, check }
+    }
+
+    /// Move the mismatches seen so far into `warnings`.
+    fn flush_warnings(&mut self, warnings: &mut Vec<ScanWarning>) {
+        let ctx = &self.check.ctx;
+        let ag_bytes = ctx.ag_start_byte(1).max(1);
+        warnings.extend(self.check.mismatches.drain(..).map(|(code, offset, is_inode)| {
+            let agno = (offset / ag_bytes) as u32;
+            let warning = ScanWarning::new(code).with_ag(agno).with_byte_offset(offset);
+            if !is_inode {
+                return warning;
+            }
+            let in_ag = offset - ctx.ag_start_byte(agno);
+            let slot = (in_ag & (ctx.block_size as u64 - 1)) >> ctx.inode_log;
+            let agino = ((in_ag >> ctx.block_log) << ctx.inop_blog) | slot;
+            warning.with_ino(ctx.agino_to_ino(agno, agino as u32))
+        }));
+    }
+}
+
+impl MetadataCheck {
+    fn enabled(&self) -> bool {
+        self.crcs != CrcVerification::Off || self.uuids
+    }
+
+    fn verify(&mut self, buf: &[u8], offset: u64, phase: IoPhase) -> Result<(), FxfspError> {
+        let unit = match phase {
+            _ if !self.enabled() => return Ok(()),
+            // Its CRC is always checked while parsing, and it defines the UUID.
+            IoPhase::Superblock => return Ok(()),
+            // Log records carry no metadata block headers.
+            IoPhase::Log => return Ok(()),
+            IoPhase::Agi => self.ctx.sect_size as usize,
+            IoPhase::InodeChunks => self.ctx.inode_size as usize,
+            IoPhase::DirExtents => (self.ctx.block_size as usize) << self.ctx.dir_blk_log,
+            _ => self.ctx.block_size as usize,
+        };
+        let is_inode = matches!(phase, IoPhase::InodeChunks);
+        // A partial unit (a piece of a split directory block, or the tail of
+        // a short read) can't be checked.
+        for (i, piece) in buf.chunks_exact(unit).enumerate() {
+            // Whole-chunk reads can cover sparse holes, which hold file data.
+            if is_inode && !piece.starts_with(b"IN") {
+                continue;
+            }
+            let at = offset + (i * unit) as u64;
+            if self.crcs != CrcVerification::Off {
+                match verify_metadata_crc(piece) {
+                    Ok(_) => {}
+                    Err(e) if self.crcs == CrcVerification::Strict => return Err(e),
+                    Err(_) => self.mismatches.push((WarningCode::CrcMismatch, at, is_inode)),
+                }
+            }
+            if self.uuids && metadata_uuid(piece).is_some_and(|uuid| uuid != self.ctx.meta_uuid) {
+                self.mismatches.push((WarningCode::UuidMismatch, at, is_inode));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: IoReader> IoReader for CheckedReader<R> {
+    fn read_at(&mut self, offset: u64, len: usize, phase: IoPhase) -> Result<&[u8], FxfspError> {
+        let buf = self.inner.read_at(offset, len, phase)?;
+        self.check.verify(buf, offset, phase)?;
+        Ok(buf)
+    }
+
+    fn coalesced_read_batch<T: Copy, F>(
+        &mut self,
+        requests: &[(u64, usize, T)],
+        mut on_complete: F,
+        phase: IoPhase,
+    ) -> Result<(), FxfspError>
+    where
+        F: FnMut(&[u8], T) -> Result<(), FxfspError>,
+    {
+        if !self.check.enabled() {
+            return self.inner.coalesced_read_batch(requests, on_complete, phase);
+        }
+        // Carry each request's offset through to its completion.
+        let requests: Vec<(u64, usize, (u64, T))> =
+            requests.iter().map(|&(offset, len, tag)| (offset, len, (offset, tag))).collect();
+        let check = &mut self.check;
+        self.inner.coalesced_read_batch(
+            &requests,
+            |buf, (offset, tag)| {
+                check.verify(buf, offset, phase)?;
+                on_complete(buf, tag)
+            },
+            phase,
+        )
+    }
+}
+
+/// Buffers the phases of one AG fill and the next AG reuses.
+///
+/// Owned by [`FsScanner`] (pooled in [`ParallelScanner`]) and lent to each
+/// [`AgScanner`] and its phases, so scanning thousands of AGs does not
+/// reallocate them per AG.
+#[derive(Default)]
+struct ScanScratch {
+    /// Inode chunk reads of phase 1.
+    requests: Vec<(u64, usize, ChunkRead)>,
+    forks: ForkArena,
+    shortform_dirs: Vec<ShortformDirItem>,
+    btree_dirs: Vec<BtreeItem>,
+    btree_files: Vec<BtreeItem>,
+    symlinks: Vec<SymlinkItem>,
+    xattrs: Vec<XattrItem>,
+    /// Spare [`DirWork::items`], emptied.
+    dir_items: Vec<DirWorkItem>,
+}
+
+impl ScanScratch {
+    /// Empty every buffer, keeping its capacity.
+    fn clear(&mut self) {
+        self.requests.clear();
+        self.forks.clear();
+        self.shortform_dirs.clear();
+        self.btree_dirs.clear();
+        self.btree_files.clear();
+        self.symlinks.clear();
+        self.xattrs.clear();
+        self.dir_items.clear();
+    }
+}
+
+/// Inode forks kept from phase 1 for the later phases, copied into one
+/// buffer per AG rather than a `Vec` each.
+#[derive(Default)]
+struct ForkArena {
+    bytes: Vec<u8>,
+}
+
+impl ForkArena {
+    fn push(&mut self, fork: &[u8]) -> std::ops::Range<usize> {
+        let start = self.bytes.len();
+        self.bytes.extend_from_slice(fork);
+        start..self.bytes.len()
+    }
+
+    fn get(&self, fork: &std::ops::Range<usize>) -> &[u8] {
+        &self.bytes[fork.clone()]
+    }
+
+    fn clear(&mut self) {
+        self.bytes.clear();
+    }
+}
+
+/// Provenance of the events an AG scan emits now, `warnings_base` being the
+/// number of warnings recorded before it began.
+fn event_provenance(log_dirty: bool, warnings: &[ScanWarning], warnings_base: usize) -> Provenance {
+    if warnings.len() > warnings_base {
+        Provenance::ReadAfterError
+    } else if log_dirty {
+        Provenance::LogNotReplayed
+    } else {
+        Provenance::Clean
+    }
+}
+
+/// Receives shortform directory entries during the inode phase.
+type ShortformCallback<'c> = dyn FnMut(&DirEntryInfo) -> ControlFlow<()> + 'c;
+
+/// The data fork of a shortform directory: `di_size` bytes after the core.
+fn shortform_fork<'b>(inode_buf: &'b [u8], info: &crate::xfs::inode::InodeInfo) -> Result<&'b [u8], FxfspError> {
+    let len = usize::try_from(info.size).unwrap_or(usize::MAX);
+    inode_buf
+        .get(info.data_fork_offset..info.data_fork_offset.saturating_add(len))
+        .ok_or(FxfspError::Parse("shortform dir fork out of bounds"))
+}
+
 /// Shortform directory: inline data in inode fork.
 struct ShortformDirItem {
     ino: u64,
-    fork_data: Vec<u8>,
+    /// The fork, in the AG's [`ForkArena`].
+    fork: std::ops::Range<usize>,
+}
+
+/// Symlink whose target the directory phase can deliver.
+struct SymlinkItem {
+    ino: u64,
+    generation: u32,
+    size: u64,
+    /// Extents of a remote target; 0 for a target held in the fork.
+    nextents: u32,
+    /// The data fork, in the AG's [`ForkArena`].
+    fork: std::ops::Range<usize>,
+}
+
+/// The blocks read for one attr fork: `(logical block, bytes)` per extent,
+/// in logical order.
+type AttrForkBlocks = Vec<(u64, Vec<u8>)>;
+
+/// The filesystem block at attr fork logical block `lblk`, if it was read.
+fn attr_fork_block(pieces: &AttrForkBlocks, block_size: usize, lblk: u32) -> Option<&[u8]> {
+    let (start, bytes) = &pieces[pieces.partition_point(|p| p.0 <= lblk as u64).checked_sub(1)?];
+    let offset = usize::try_from(lblk as u64 - start).ok()?.checked_mul(block_size)?;
+    bytes.get(offset..offset + block_size)
+}
+
+/// Inode whose attr fork the directory phase can deliver.
+struct XattrItem {
+    ino: u64,
+    generation: u32,
+    /// `di_aformat`.
+    format: u8,
+    /// Attr fork extents, for the extents format.
+    nextents: u32,
+    /// The attr fork, in the AG's [`ForkArena`].
+    fork: std::ops::Range<usize>,
+    /// Full size of the fork in the inode, which places a bmbt root's
+    /// child pointers.
+    fork_size: usize,
 }
 
 struct BtreeItem {
     ino: u64,
-    fork_data: Vec<u8>,
+    generation: u32,
+    /// The fork root, in the AG's [`ForkArena`].
+    fork: std::ops::Range<usize>,
     data_fork_size: usize,
 }
 
-/// Process all allocated inodes in a single inobt chunk.
+/// Process the allocated inodes in `range` of a single inobt chunk.
+/// `chunk_buf` starts at the first inode of `range`. Returns how many were
+/// parsed, whether delivered or skipped by the options.
+#[allow(clippy::too_many_arguments)]
 fn process_inode_chunk_staged<F>(
     chunk_buf: &[u8],
     rec: &crate::xfs::btree::XfsInobtRec,
+    range: std::ops::Range<u32>,
     agno: u32,
     ctx: &FsContext,
+    options: &ScanOptions,
     is_v5: bool,
+    provenance: Provenance,
     callback: &mut F,
-    dir_work: &mut Vec<DirWorkItem>,
+    mut shortform_callback: Option<&mut ShortformCallback<'_>>,
+    dir_work: &mut DirWork,
+    forks: &mut ForkArena,
     shortform_dirs: &mut Vec<ShortformDirItem>,
     btree_dirs: &mut Vec<BtreeItem>,
     btree_files: &mut Vec<BtreeItem>,
-) -> Result<(), FxfspError>
+    symlinks: &mut Vec<SymlinkItem>,
+    xattrs: &mut Vec<XattrItem>,
+) -> Result<u32, FxfspError>
 where
     F: FnMut(&InodeInfo) -> ControlFlow<()>,
 {
     let start_agino = rec.start_ino();
+    let mut parsed = 0;
 
-    for i in 0..64u32 {
-        let group = i / 4;
-        let is_hole = (rec.ir_holemask.get() & (1u16 << group)) != 0;
-        if is_hole || !rec.is_allocated(i) {
-            continue;
-        }
+    let first = range.start;
+    // Allocated inodes of the range as a bitmap, visited lowest bit first:
+    // free and hole slots cost nothing.
+    let in_range = u64::MAX.checked_shl(range.start).unwrap_or(0) & u64::MAX.checked_shr(64 - range.end).unwrap_or(0);
+    let mut pending = rec.allocated_mask() & in_range;
+    while pending != 0 {
+        let i = pending.trailing_zeros();
+        pending &= pending - 1;
 
         let agino = start_agino + i;
         let abs_ino = ctx.agino_to_ino(agno, agino);
-        let inode_offset = i as usize * ctx.inode_size as usize;
+        let inode_offset = (i - first) as usize * ctx.inode_size as usize;
 
         if inode_offset + ctx.inode_size as usize > chunk_buf.len() {
             break;
@@ -483,6 +2662,11 @@ where
 
         let inode_buf = &chunk_buf[inode_offset..];
         let info = parse_inode_core(inode_buf, abs_ino, is_v5, ctx.has_nrext64, ctx.inode_size)?;
+        parsed += 1;
+
+        if options.skips(&info) {
+            continue;
+        }
 
         // Extract inline extents for regular files
         let extents = if info.is_regular() && info.format == XFS_DINODE_FMT_EXTENTS && info.nextents > 0 {
@@ -492,13 +2676,23 @@ where
             None
         };
 
+        // Extract attr fork extents for inodes with block-based xattrs
+        let attr_extents = if info.aformat == XFS_DINODE_FMT_EXTENTS && info.anextents > 0 {
+            let fork_buf = &inode_buf[info.attr_fork_offset..];
+            Some(parse_extent_list(fork_buf, info.anextents, ctx)?)
+        } else {
+            None
+        };
+
         let inode_info = InodeInfo {
             ag_number: agno,
             ino: info.ino,
+            generation: info.generation,
             mode: info.mode,
             size: info.size,
             uid: info.uid,
             gid: info.gid,
+            projid: info.projid,
             nlink: info.nlink,
             mtime_sec: info.mtime_sec,
             mtime_nsec: info.mtime_nsec,
@@ -507,57 +2701,105 @@ where
             ctime_sec: info.ctime_sec,
             ctime_nsec: info.ctime_nsec,
             nblocks: info.nblocks,
+            extsize: info.extsize,
+            cowextsize: info.cowextsize,
+            flags: InodeFlags::from_bits_retain(info.flags),
+            flags2: InodeFlags2::from_bits_retain(info.flags2),
+            dmevmask: info.dmevmask,
+            dmstate: info.dmstate,
+            rdev: info.rdev,
             extents,
+            attr_extents,
+            internal: ctx.internal_inode(&info),
+            provenance,
         };
 
         if callback(&inode_info).is_break() {
             return Err(FxfspError::Stopped);
         }
 
+        let has_attrs = match info.aformat {
+            XFS_DINODE_FMT_LOCAL | XFS_DINODE_FMT_BTREE => info.attr_fork_size > 0,
+            XFS_DINODE_FMT_EXTENTS => info.anextents > 0,
+            _ => false,
+        };
+        if has_attrs {
+            let fork_start = info.attr_fork_offset.min(inode_buf.len());
+            let fork_end = (fork_start + info.attr_fork_size).min(inode_buf.len());
+            xattrs.push(XattrItem {
+                ino: info.ino,
+                generation: info.generation,
+                format: info.aformat,
+                nextents: info.anextents,
+                fork: forks.push(&inode_buf[fork_start..fork_end]),
+                fork_size: info.attr_fork_size,
+            });
+        }
+
         if info.is_dir() {
-            handle_directory_staged(inode_buf, &info, ctx, dir_work, shortform_dirs, btree_dirs)?;
+            if let Some(coverage) = &mut dir_work.coverage {
+                coverage.found.push(info.ino);
+            }
+            match shortform_callback.as_deref_mut() {
+                Some(dir_callback) if info.format == XFS_DINODE_FMT_LOCAL => {
+                    let fork = shortform_fork(inode_buf, &info)?;
+                    let mut dir_callback = |de: &DirEntryInfo| dir_callback(&de.with_provenance(provenance));
+                    parse_shortform_dir_staged(fork, info.ino, ctx, &mut dir_callback)?;
+                    dir_work.scanned(info.ino);
+                }
+                _ => handle_directory_staged(inode_buf, &info, ctx, dir_work, forks, shortform_dirs, btree_dirs)?,
+            }
         } else if info.is_regular() && info.format == XFS_DINODE_FMT_BTREE {
             let fork_start = info.data_fork_offset;
             let fork_end = (fork_start + info.data_fork_size).min(inode_buf.len());
-            let fork_data = inode_buf[fork_start..fork_end].to_vec();
             btree_files.push(BtreeItem {
                 ino: info.ino,
-                fork_data,
+                generation: info.generation,
+                fork: forks.push(&inode_buf[fork_start..fork_end]),
                 data_fork_size: info.data_fork_size,
             });
+        } else if info.is_symlink() && matches!(info.format, XFS_DINODE_FMT_LOCAL | XFS_DINODE_FMT_EXTENTS) {
+            let fork_start = info.data_fork_offset.min(inode_buf.len());
+            let fork_end = (fork_start + info.data_fork_size).min(inode_buf.len());
+            symlinks.push(SymlinkItem {
+                ino: info.ino,
+                generation: info.generation,
+                size: info.size,
+                nextents: if info.format == XFS_DINODE_FMT_EXTENTS { info.nextents } else { 0 },
+                fork: forks.push(&inode_buf[fork_start..fork_end]),
+            });
         }
     }
 
-    Ok(())
+    Ok(parsed)
 }
 
 /// Handle a directory inode: store shortform data or defer to Phase 2.
+///
+/// Extent and btree directories that don't fit `dir_work`'s budget are only
+/// recorded in [`DirWork::deferred`].
 fn handle_directory_staged(
     inode_buf: &[u8],
     info: &crate::xfs::inode::InodeInfo,
     ctx: &FsContext,
-    dir_work: &mut Vec<DirWorkItem>,
+    dir_work: &mut DirWork,
+    forks: &mut ForkArena,
     shortform_dirs: &mut Vec<ShortformDirItem>,
     btree_dirs: &mut Vec<BtreeItem>,
 ) -> Result<(), FxfspError> {
     match info.format {
         XFS_DINODE_FMT_LOCAL => {
             // Store shortform directory data for parsing in dir phase
-            let fork_start = info.data_fork_offset;
-            let fork_end = fork_start + info.size as usize;
-            if fork_end > inode_buf.len() {
-                return Err(FxfspError::Parse("shortform dir fork out of bounds"));
-            }
-            let fork_data = inode_buf[fork_start..fork_end].to_vec();
             shortform_dirs.push(ShortformDirItem {
                 ino: info.ino,
-                fork_data,
+                fork: forks.push(shortform_fork(inode_buf, info)?),
             });
         }
+        XFS_DINODE_FMT_EXTENTS | XFS_DINODE_FMT_BTREE if !dir_work.reserve(info.ino, info.nextents) => {}
         XFS_DINODE_FMT_EXTENTS => {
             let fork_buf = &inode_buf[info.data_fork_offset..];
             let extents = parse_extent_list(fork_buf, info.nextents, ctx)?;
-            dir_work.push(DirWorkItem {
+            dir_work.items.push(DirWorkItem {
                 ino: info.ino,
                 extents,
             });
@@ -565,10 +2807,10 @@ fn handle_directory_staged(
         XFS_DINODE_FMT_BTREE => {
             let fork_start = info.data_fork_offset;
             let fork_end = (fork_start + info.data_fork_size).min(inode_buf.len());
-            let fork_data = inode_buf[fork_start..fork_end].to_vec();
             btree_dirs.push(BtreeItem {
                 ino: info.ino,
-                fork_data,
+                generation: info.generation,
+                fork: forks.push(&inode_buf[fork_start..fork_end]),
                 data_fork_size: info.data_fork_size,
             });
         }
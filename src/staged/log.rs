@@ -0,0 +1,153 @@
+//! Read-only walk of the internal log.
+
+use super::FsScanner;
+use crate::error::FxfspError;
+use crate::reader::{IoPhase, IoReader};
+use crate::xfs::extent::fsblock_to_byte;
+use crate::xfs::log::{
+    BBSIZE, LogRecordHeader, LogSummary, LogTransactions, XLOG_UNMOUNT_TRANS, lsn_block, lsn_cycle, parse_log_ops,
+    parse_log_record_header, unstamp_log_record,
+};
+use crate::xfs::superblock::FsContext;
+
+/// Log sectors read per request while looking for record headers.
+const SEARCH_CHUNK_BLOCKS: u64 = 2048;
+
+/// The internal log, as a ring of 512-byte log blocks.
+struct LogRing<'a, R> {
+    reader: &'a mut R,
+    /// Byte offset of log block 0.
+    start: u64,
+    blocks: u64,
+}
+
+impl<R: IoReader> LogRing<'_, R> {
+    /// `count` log blocks from `block`, wrapping at the end of the log.
+    fn read(&mut self, block: u64, count: u64) -> Result<Vec<u8>, FxfspError> {
+        if count > self.blocks {
+            return Err(FxfspError::Parse("log record larger than the log"));
+        }
+        let mut buf = Vec::with_capacity(count as usize * BBSIZE);
+        let first = count.min(self.blocks - block);
+        for (at, n) in [(block, first), (0, count - first)] {
+            if n == 0 {
+                continue;
+            }
+            let len = n as usize * BBSIZE;
+            let bytes = self.reader.read_at(self.start + at * BBSIZE as u64, len, IoPhase::Log)?;
+            if bytes.len() < len {
+                return Err(FxfspError::Parse("short read in log"));
+            }
+            buf.extend_from_slice(&bytes[..len]);
+        }
+        Ok(buf)
+    }
+
+    /// Every record header whose LSN matches its place in the log, by LSN.
+    fn find_headers(&mut self) -> Result<Vec<LogRecordHeader>, FxfspError> {
+        let mut headers = Vec::new();
+        for chunk in (0..self.blocks).step_by(SEARCH_CHUNK_BLOCKS as usize) {
+            let buf = self.read(chunk, SEARCH_CHUNK_BLOCKS.min(self.blocks - chunk))?;
+            for (i, sector) in buf.chunks_exact(BBSIZE).enumerate() {
+                let Ok(hdr) = parse_log_record_header(sector) else { continue };
+                if lsn_block(hdr.lsn) as u64 == chunk + i as u64 && lsn_cycle(hdr.lsn) == hdr.cycle {
+                    headers.push(hdr);
+                }
+            }
+        }
+        headers.sort_unstable_by_key(|hdr| hdr.lsn);
+        Ok(headers)
+    }
+
+    /// A record's data, unstamped, or `None` if it was torn or its header
+    /// claims more than the whole log.
+    fn record_data(&mut self, hdr: &LogRecordHeader) -> Result<Option<Vec<u8>>, FxfspError> {
+        if hdr.total_blocks() as u64 > self.blocks {
+            return Ok(None);
+        }
+        let mut record = self.read(lsn_block(hdr.lsn) as u64, hdr.total_blocks() as u64)?;
+        if unstamp_log_record(hdr, &mut record).is_err() {
+            return Ok(None);
+        }
+        let header_len = hdr.header_blocks() as usize * BBSIZE;
+        record.drain(..header_len);
+        record.truncate(hdr.len as usize);
+        Ok(Some(record))
+    }
+}
+
+impl<R: IoReader> FsScanner<R> {
+    /// Walk the internal log from its tail to its head, listing the
+    /// transactions recovery would replay.
+    ///
+    /// Metadata they change may not have reached its home blocks yet, so a
+    /// scan of a filesystem that was not cleanly unmounted can show it as it
+    /// was before: see [`LogSummary::pending_inodes`] and
+    /// [`LogSummary::pending_buffers`], or scan through a
+    /// [`ReplayReader`](crate::ReplayReader) instead. The whole log is read
+    /// once to find its head, the newest record written out in full. A
+    /// torn or invalid record behind the head ends the walk there.
+    ///
+    /// If the log does not end with an unmount record, events of AGs opened
+    /// afterwards are marked [`LogNotReplayed`](crate::Provenance::LogNotReplayed).
+    ///
+    /// Returns `None` if the log is on an external device.
+    pub fn inspect_log(&mut self) -> Result<Option<LogSummary>, FxfspError> {
+        let summary = read_log(&mut self.reader, &self.ctx, LogTransactions::new())?;
+        self.log_dirty = summary.as_ref().is_some_and(|log| !log.clean);
+        Ok(summary)
+    }
+}
+
+/// Walk the internal log, assembling its transactions with `transactions`.
+/// `None` if the log is on an external device.
+pub(crate) fn read_log<R: IoReader>(
+    reader: &mut R,
+    ctx: &FsContext,
+    mut transactions: LogTransactions,
+) -> Result<Option<LogSummary>, FxfspError> {
+    if ctx.log_start == 0 {
+        return Ok(None);
+    }
+    let mut ring = LogRing {
+        reader,
+        start: fsblock_to_byte(ctx, ctx.log_start),
+        blocks: ctx.log_blocks as u64 * ctx.block_size as u64 / BBSIZE as u64,
+    };
+    let headers = ring.find_headers()?;
+
+    // A crash can leave the newest records half written.
+    let mut head = None;
+    for hdr in headers.iter().rev() {
+        if let Some(data) = ring.record_data(hdr)? {
+            head = Some((*hdr, data));
+            break;
+        }
+    }
+    let (head, head_data) = head.ok_or(FxfspError::Parse("no intact log record"))?;
+    let head_ops = parse_log_ops(&head_data, head.num_logops)?;
+    let clean = head_ops.iter().any(|op| op.flags & XLOG_UNMOUNT_TRANS != 0);
+    let tail_lsn = if clean { head.lsn } else { head.tail_lsn };
+
+    let mut records: Vec<LogRecordHeader> =
+        headers.into_iter().filter(|hdr| (tail_lsn..=head.lsn).contains(&hdr.lsn)).collect();
+    // A torn or invalid record behind the head ends the walk, as it would
+    // end recovery.
+    let mut walked = 0;
+    for hdr in &records {
+        let data = if hdr.lsn == head.lsn {
+            head_data.clone()
+        } else {
+            match ring.record_data(hdr)? {
+                Some(data) => data,
+                None => break,
+            }
+        };
+        let ops = parse_log_ops(&data, hdr.num_logops)?;
+        transactions.add_record(hdr.lsn, &data, &ops);
+        walked += 1;
+    }
+    records.truncate(walked);
+
+    Ok(Some(LogSummary { tail_lsn, head_lsn: head.lsn, clean, records, transactions: transactions.finish() }))
+}
@@ -0,0 +1,308 @@
+//! On-demand lookups: inode → path, and block → owners.
+
+use std::ops::{ControlFlow, Range};
+
+use super::{
+    AttrForkBlocks, DirWork, DirWorkItem, ForkArena, FsScanner, MAX_DIR_READ, ScanOptions, attr_fork_block,
+    handle_directory_staged, open_ag_scanner, plan_dir_reads, read_ag_headers, read_inode, read_split_dir_block,
+    rmap_records,
+};
+use crate::error::FxfspError;
+use crate::handle::ScanHandle;
+use crate::reader::{IoPhase, IoReader};
+use crate::warning::ScanWarning;
+use crate::xfs::attr::{XattrEntry, XattrNamespace, parse_attr_fork_blocks, parse_shortform_attrs};
+use crate::xfs::bmbt::{BmbtDirInput, collect_all_bmbt_extents};
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::dir::block::parse_dir_data_block_staged;
+use crate::xfs::dir::shortform::parse_shortform_dir_staged;
+use crate::xfs::extent::{fsblock_to_ag, parse_extent_list};
+use crate::xfs::inode::{InodeInfo, XFS_DINODE_FMT_BTREE, XFS_DINODE_FMT_EXTENTS, XFS_DINODE_FMT_LOCAL};
+use crate::xfs::rmap::RmapRecord;
+use crate::xfs::superblock::FsContext;
+
+/// Deepest directory chain followed before assuming a loop.
+const MAX_PATH_DEPTH: usize = 4096;
+
+/// AGs whose directories are scanned for a file's name on filesystems
+/// without parent pointers, the file's own AG first.
+const MAX_LINK_SEARCH_AGS: u32 = 8;
+
+/// A directory's entries as `(child_ino, name)`.
+type Entries = Vec<(u64, Vec<u8>)>;
+
+impl<R: IoReader> FsScanner<R> {
+    /// Resolve `ino` to an absolute path without a full scan.
+    ///
+    /// Directories are walked upwards through their `..` entries, reading
+    /// one directory per level. For other inodes the parent comes from the
+    /// inode's parent pointers on filesystems that keep them. Otherwise it
+    /// is found by scanning the directories of the inode's own AG (where
+    /// XFS places most files) and of the AGs after it, eight in all. A
+    /// hard-linked file resolves to the first link found.
+    ///
+    /// Returns `None` if the inode is free or has no reachable name, or
+    /// none within the AGs searched.
+    pub fn path_of(&mut self, ino: u64) -> Result<Option<Vec<u8>>, FxfspError> {
+        let root = self.ctx.root_ino;
+        let (_, info) = read_inode(&mut self.reader, &self.ctx, ino)?;
+        if info.mode == 0 {
+            return Ok(None);
+        }
+
+        let mut components: Vec<Vec<u8>> = Vec::new();
+        let mut cur = ino;
+        // Entries of `cur`, when already read as the previous level's parent.
+        let mut cur_entries: Option<Entries> = None;
+
+        if !info.is_dir() {
+            let Some((parent, name)) = self.find_link(ino)? else {
+                return Ok(None);
+            };
+            components.push(name);
+            cur = parent;
+        }
+
+        while cur != root {
+            if components.len() > MAX_PATH_DEPTH {
+                return Err(FxfspError::Parse("directory chain too deep (loop?)"));
+            }
+            let entries = match cur_entries.take() {
+                Some(entries) => entries,
+                None => dir_entries(&mut self.reader, &self.ctx, &mut self.warnings, cur)?,
+            };
+            let Some(parent) = entries.iter().find(|(_, name)| name == b"..").map(|e| e.0) else {
+                return Ok(None);
+            };
+            if parent == cur {
+                return Ok(None);
+            }
+            let parent_entries = dir_entries(&mut self.reader, &self.ctx, &mut self.warnings, parent)?;
+            let Some((_, name)) = parent_entries.iter().find(|(child, name)| *child == cur && !is_dot(name))
+            else {
+                return Ok(None);
+            };
+            components.push(name.clone());
+            cur = parent;
+            cur_entries = Some(parent_entries);
+        }
+
+        let mut path = Vec::new();
+        for name in components.iter().rev() {
+            path.push(b'/');
+            path.extend_from_slice(name);
+        }
+        if path.is_empty() {
+            path.push(b'/');
+        }
+        Ok(Some(path))
+    }
+
+    /// Owners of the filesystem blocks `blocks`, e.g. the file holding a bad
+    /// sector, looked up in the reverse mapping btrees without a full scan.
+    ///
+    /// Block numbers are filesystem blocks as `xfs_db` and the kernel print
+    /// them, the AG number in the high bits. Returns every rmapbt record
+    /// overlapping `blocks`, in AG and block order; blocks shared by
+    /// reflinked files have a record per owner. Fails on filesystems
+    /// without the rmapbt feature.
+    pub fn owners_of(&mut self, blocks: Range<u64>) -> Result<Vec<RmapRecord>, FxfspError> {
+        let mut owners = Vec::new();
+        if blocks.is_empty() {
+            return Ok(owners);
+        }
+        let (first_ag, first_block) = fsblock_to_ag(&self.ctx, blocks.start);
+        let (last_ag, last_block) = fsblock_to_ag(&self.ctx, blocks.end - 1);
+        for agno in first_ag..=last_ag.min(self.ctx.ag_count.saturating_sub(1)) {
+            let start = if agno == first_ag { first_block } else { 0 };
+            let end = if agno == last_ag { last_block.saturating_add(1) } else { u32::MAX };
+            // The lookup delivers no events, so the scan's event limit must not hold it up.
+            let handle = ScanHandle::default();
+            let agf = read_ag_headers(&mut self.reader, &self.ctx, &handle, &mut self.warnings, agno)?.agf;
+            let records = rmap_records(&mut self.reader, &self.ctx, &mut self.warnings, agno, &agf, start..end)?;
+            owners.extend(records.iter().map(|rec| RmapRecord::new(agno, rec)));
+        }
+        Ok(owners)
+    }
+
+    /// Find a directory entry naming `ino`: from its parent pointers if the
+    /// filesystem has them, else by scanning directories, its own AG first.
+    fn find_link(&mut self, ino: u64) -> Result<Option<(u64, Vec<u8>)>, FxfspError> {
+        if self.ctx.feature_report().parent_pointers {
+            return parent_pointer(&mut self.reader, &self.ctx, ino);
+        }
+        // The caller never sees the search's events, so it runs unthrottled
+        // and without the options that filter what a scan delivers.
+        let handle = ScanHandle::default();
+        let options = ScanOptions {
+            sparse_chunk_threshold: self.options.sparse_chunk_threshold,
+            dir_work_budget: self.options.dir_work_budget,
+            ..ScanOptions::default()
+        };
+        let own = self.ctx.ino_to_agno(ino);
+        for agno in (own..self.ctx.ag_count).chain(0..own).take(MAX_LINK_SEARCH_AGS as usize) {
+            let mut found = None;
+            open_ag_scanner(
+                &mut self.reader,
+                &self.ctx,
+                &options,
+                &handle,
+                &mut self.warnings,
+                &mut self.scratch,
+                &self.empty_ags,
+                self.log_dirty,
+                agno,
+            )?
+            .scan_inodes(|_| ControlFlow::Continue(()))?
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| {
+                if de.child_ino == ino && !is_dot(de.name) {
+                    found = Some((de.parent_ino, de.name.to_vec()));
+                    return ControlFlow::Break(());
+                }
+                ControlFlow::Continue(())
+            })?;
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn is_dot(name: &[u8]) -> bool {
+    name == b"." || name == b".."
+}
+
+/// The parent directory and name of the first parent pointer in the attr
+/// fork of `ino`, if it has any.
+fn parent_pointer<R: IoReader>(
+    reader: &mut R,
+    ctx: &FsContext,
+    ino: u64,
+) -> Result<Option<(u64, Vec<u8>)>, FxfspError> {
+    let (buf, info) = read_inode(reader, ctx, ino)?;
+    if !info.has_attr_fork() {
+        return Ok(None);
+    }
+    let fork = buf
+        .get(info.attr_fork_offset..info.attr_fork_offset + info.attr_fork_size)
+        .ok_or(FxfspError::Parse("attr fork beyond end of inode"))?;
+
+    let mut link = None;
+    // The name is the directory entry's; the value an xfs_parent_rec, the
+    // parent's inode number and generation.
+    let mut find = |attr: &XattrEntry| match attr.value.get(..8) {
+        Some(parent) if attr.namespace == XattrNamespace::Parent => {
+            link = Some((u64::from_be_bytes(parent.try_into().unwrap()), attr.name.to_vec()));
+            ControlFlow::Break(())
+        }
+        _ => ControlFlow::Continue(()),
+    };
+    let result = match info.aformat {
+        XFS_DINODE_FMT_LOCAL => parse_shortform_attrs(fork, &mut find),
+        XFS_DINODE_FMT_EXTENTS | XFS_DINODE_FMT_BTREE => {
+            let pieces = read_attr_fork(reader, ctx, &info, fork)?;
+            let block_size = ctx.block_size as usize;
+            let mapped_blocks = pieces.iter().map(|(_, bytes)| bytes.len() / block_size).sum();
+            let block = |lblk: u32| attr_fork_block(&pieces, block_size, lblk);
+            parse_attr_fork_blocks(ctx, block, mapped_blocks, &mut find)
+        }
+        _ => Err(FxfspError::Parse("unknown attr fork format")),
+    };
+    match result {
+        Ok(()) | Err(FxfspError::Stopped) => Ok(link),
+        Err(e) => Err(e),
+    }
+}
+
+/// Read the blocks of the block-format attr fork `fork` of inode `info`.
+fn read_attr_fork<R: IoReader>(
+    reader: &mut R,
+    ctx: &FsContext,
+    info: &InodeInfo,
+    fork: &[u8],
+) -> Result<AttrForkBlocks, FxfspError> {
+    let extents = if info.aformat == XFS_DINODE_FMT_EXTENTS {
+        parse_extent_list(fork, info.anextents, ctx)?
+    } else {
+        let input = BmbtDirInput { ino: info.ino, fork_data: fork, data_fork_size: info.attr_fork_size };
+        collect_all_bmbt_extents(reader, ctx, &[input])?.into_iter().flat_map(|(_, extents)| extents).collect()
+    };
+    let mut pieces = Vec::new();
+    for ext in &extents {
+        let len = ext.block_count << ctx.block_log;
+        for chunk in (0..len).step_by(MAX_DIR_READ as usize) {
+            let logical = ext.logical_offset + (chunk >> ctx.block_log);
+            let byte_len = (len - chunk).min(MAX_DIR_READ) as usize;
+            let bytes = reader.read_at(ext.start_byte(ctx) + chunk, byte_len, IoPhase::AttrBlocks)?;
+            pieces.push((logical, bytes.to_vec()));
+        }
+    }
+    pieces.sort_unstable_by_key(|p| p.0);
+    Ok(pieces)
+}
+
+/// Read every entry of directory `dir_ino`, including `.` and `..`.
+fn dir_entries<R: IoReader>(
+    reader: &mut R,
+    ctx: &FsContext,
+    warnings: &mut Vec<ScanWarning>,
+    dir_ino: u64,
+) -> Result<Entries, FxfspError> {
+    let (buf, info) = read_inode(reader, ctx, dir_ino)?;
+    if !info.is_dir() {
+        return Err(FxfspError::Parse("parent is not a directory"));
+    }
+
+    let mut dir_work = DirWork::new(0);
+    let mut forks = ForkArena::default();
+    let mut shortform = Vec::new();
+    let mut btree = Vec::new();
+    handle_directory_staged(&buf, &info, ctx, &mut dir_work, &mut forks, &mut shortform, &mut btree)?;
+
+    let mut entries = Vec::new();
+    let mut push = |de: &DirEntryInfo| {
+        entries.push((de.child_ino, de.name.to_vec()));
+        ControlFlow::Continue(())
+    };
+
+    for sf in &shortform {
+        parse_shortform_dir_staged(forks.get(&sf.fork), sf.ino, ctx, &mut push)?;
+    }
+
+    if !btree.is_empty() {
+        let inputs: Vec<BmbtDirInput> = btree
+            .iter()
+            .map(|item| BmbtDirInput {
+                ino: item.ino,
+                fork_data: forks.get(&item.fork),
+                data_fork_size: item.data_fork_size,
+            })
+            .collect();
+        for (ino, extents) in collect_all_bmbt_extents(reader, ctx, &inputs)? {
+            dir_work.items.push(DirWorkItem { ino, extents });
+        }
+    }
+
+    let (requests, splits) = plan_dir_reads(ctx, &dir_work.items, ctx.ino_to_agno(dir_ino), warnings);
+    let dir_blk_size = ctx.dir_blk_size() as usize;
+    reader.coalesced_read_batch(
+        &requests,
+        |data, _| {
+            let mut off = 0;
+            while off + dir_blk_size <= data.len() {
+                parse_dir_data_block_staged(&data[off..off + dir_blk_size], dir_ino, ctx, &mut push)?;
+                off += dir_blk_size;
+            }
+            Ok(())
+        },
+        IoPhase::DirExtents,
+    )?;
+    for split in &splits {
+        let buf = read_split_dir_block(reader, split)?;
+        parse_dir_data_block_staged(&buf, dir_ino, ctx, &mut push)?;
+    }
+
+    Ok(entries)
+}
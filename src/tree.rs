@@ -0,0 +1,160 @@
+//! Directory tree assembled from scan events.
+//!
+//! Report collectors use [`DirTree`] to resolve paths after a scan. Only
+//! directories are kept (child → parent and name); collectors keep whatever
+//! they need about other inodes themselves.
+
+use std::collections::HashMap;
+
+use crate::staged::InodeInfo;
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::inode::{S_IFDIR, S_IFMT};
+
+/// `XFS_DIR3_FT_DIR`.
+const FT_DIR: u8 = 2;
+/// `XFS_DIR3_FT_UNKNOWN`: the filesystem has no ftype.
+const FT_UNKNOWN: u8 = 0;
+
+/// How [`DirTree::add_dir_entry`] classified an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    /// `.` or `..`.
+    Dot,
+    Directory,
+    /// Names a non-directory; the caller handles it.
+    Other,
+    /// Type unknown until the child's inode is seen; returned from
+    /// [`DirTree::resolve_untyped`] if it is not a directory.
+    Deferred,
+}
+
+#[derive(Debug)]
+pub(crate) struct DirTree {
+    root_ino: u64,
+    /// Directory → (parent, name), first link wins.
+    dirs: HashMap<u64, (u64, Vec<u8>)>,
+    dir_inos: Vec<u64>,
+    untyped: Vec<(u64, u64, Vec<u8>)>,
+}
+
+impl DirTree {
+    pub(crate) fn new(root_ino: u64) -> Self {
+        Self {
+            root_ino,
+            dirs: HashMap::new(),
+            dir_inos: Vec::new(),
+            untyped: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add_inode(&mut self, inode: &InodeInfo) {
+        if inode.mode & S_IFMT == S_IFDIR {
+            self.dir_inos.push(inode.ino);
+        }
+    }
+
+    pub(crate) fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) -> EntryKind {
+        if de.name == b"." || de.name == b".." {
+            return EntryKind::Dot;
+        }
+        match de.file_type {
+            FT_DIR => {
+                self.dirs.entry(de.child_ino).or_insert((de.parent_ino, de.name.to_vec()));
+                EntryKind::Directory
+            }
+            FT_UNKNOWN => {
+                self.untyped.push((de.parent_ino, de.child_ino, de.name.to_vec()));
+                EntryKind::Deferred
+            }
+            _ => EntryKind::Other,
+        }
+    }
+
+    /// Classify deferred entries now that every inode has been seen, and
+    /// return the `(parent, child, name)` of those that are not directories.
+    pub(crate) fn resolve_untyped(&mut self) -> Vec<(u64, u64, Vec<u8>)> {
+        if self.untyped.is_empty() {
+            return Vec::new();
+        }
+        self.dir_inos.sort_unstable();
+        let mut others = Vec::new();
+        for (parent, child, name) in std::mem::take(&mut self.untyped) {
+            if self.dir_inos.binary_search(&child).is_ok() {
+                self.dirs.entry(child).or_insert((parent, name));
+            } else {
+                others.push((parent, child, name));
+            }
+        }
+        others
+    }
+
+    /// Name of directory `dir` in its parent.
+    pub(crate) fn name(&self, dir: u64) -> Option<&[u8]> {
+        self.dirs.get(&dir).map(|(_, name)| name.as_slice())
+    }
+
+    /// The child of the root that `dir` lives under (the root itself for
+    /// the root), or `None` if the chain does not reach the root.
+    pub(crate) fn top_dir(&self, dir: u64, memo: &mut HashMap<u64, Option<u64>>) -> Option<u64> {
+        let mut chain = Vec::new();
+        let mut cur = dir;
+        let top = loop {
+            if let Some(&top) = memo.get(&cur) {
+                break top;
+            }
+            if cur == self.root_ino {
+                break Some(cur);
+            }
+            match self.dirs.get(&cur) {
+                Some(&(parent, _)) if parent == self.root_ino => break Some(cur),
+                // A chain longer than the directory count is a loop.
+                Some(&(parent, _)) if chain.len() <= self.dirs.len() => {
+                    chain.push(cur);
+                    cur = parent;
+                }
+                _ => break None,
+            }
+        };
+        memo.insert(cur, top);
+        for dir in chain {
+            memo.insert(dir, top);
+        }
+        top
+    }
+
+    /// Absolute path of directory `dir`, or `None` if it does not reach the
+    /// root.
+    pub(crate) fn path(&self, dir: u64) -> Option<Vec<u8>> {
+        let mut components = Vec::new();
+        let mut cur = dir;
+        while cur != self.root_ino {
+            if components.len() > self.dirs.len() {
+                return None;
+            }
+            let (parent, name) = self.dirs.get(&cur)?;
+            components.push(name.as_slice());
+            cur = *parent;
+        }
+        let mut path = Vec::new();
+        for name in components.iter().rev() {
+            path.push(b'/');
+            path.extend_from_slice(name);
+        }
+        if path.is_empty() {
+            path.push(b'/');
+        }
+        Some(path)
+    }
+}
+
+/// Is `path` equal to or below `prefix`, on a component boundary?
+pub(crate) fn is_under(path: &[u8], prefix: &[u8]) -> bool {
+    let prefix = match prefix.strip_suffix(b"/") {
+        Some(p) => p,
+        None => prefix,
+    };
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with(b"/"),
+        None => false,
+    }
+}
@@ -0,0 +1,225 @@
+//! Space usage by file extension and by top-level directory.
+//!
+//! [`UsageCollector`] is fed the events of a scan and produces a
+//! [`UsageReport`]: the "what is filling this volume" breakdown that would
+//! otherwise need every event exported and joined externally, plus
+//! [`KindCounts`] of every inode by type and risky permission bits.
+//!
+//! Only regular files are counted, each once under its first link seen.
+//! Sizes are logical (`di_size`), not allocated blocks.
+
+use std::collections::HashMap;
+
+use crate::name::escape_name;
+use crate::staged::{InodeInfo, SuperblockInfo};
+use crate::tree::{DirTree, EntryKind};
+use crate::xfs::dir::DirEntryInfo;
+use crate::xfs::inode::{
+    S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFMT, S_IFREG, S_IFSOCK, S_ISGID, S_ISUID, S_IWOTH,
+};
+
+/// Bucket key for files directly in the root directory.
+pub const ROOT_BUCKET: &str = "/";
+
+/// File count and total size for one report key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsageBucket {
+    /// Extension (lowercased, without the dot, empty for none) or top-level
+    /// directory name, escaped with [`escape_name`].
+    pub key: String,
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Inodes by type, and by the permission bits security baselines look for.
+/// Internal inodes are only counted in `internal`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KindCounts {
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    pub char_devices: u64,
+    pub block_devices: u64,
+    pub fifos: u64,
+    pub sockets: u64,
+    /// Mode bits matching no file type.
+    pub unknown: u64,
+    /// Regular files with the setuid bit.
+    pub setuid: u64,
+    /// Regular files with the setgid bit (on a directory it only makes
+    /// new entries inherit the group, and is not counted).
+    pub setgid: u64,
+    /// Inodes other than symlinks (always `rwxrwxrwx`) writable by others.
+    pub world_writable: u64,
+    /// Filesystem metadata inodes ([`InodeInfo::internal`]).
+    pub internal: u64,
+}
+
+impl KindCounts {
+    /// Count an inode. Unlinked inodes are counted too.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        if inode.internal.is_some() {
+            self.internal += 1;
+            return;
+        }
+        let kind = inode.mode & S_IFMT;
+        let counter = match kind {
+            S_IFREG => &mut self.files,
+            S_IFDIR => &mut self.dirs,
+            S_IFLNK => &mut self.symlinks,
+            S_IFCHR => &mut self.char_devices,
+            S_IFBLK => &mut self.block_devices,
+            S_IFIFO => &mut self.fifos,
+            S_IFSOCK => &mut self.sockets,
+            _ => &mut self.unknown,
+        };
+        *counter += 1;
+        if kind == S_IFREG {
+            self.setuid += u64::from(inode.mode & S_ISUID != 0);
+            self.setgid += u64::from(inode.mode & S_ISGID != 0);
+        }
+        if kind != S_IFLNK {
+            self.world_writable += u64::from(inode.mode & S_IWOTH != 0);
+        }
+    }
+}
+
+/// Usage breakdowns, each sorted by `bytes` descending.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UsageReport {
+    pub by_extension: Vec<UsageBucket>,
+    /// Keyed by the directory under `/` that holds the file, or
+    /// [`ROOT_BUCKET`]. Files whose ancestry is unreachable are left out.
+    pub by_top_dir: Vec<UsageBucket>,
+    /// Every inode seen, by type.
+    pub kinds: KindCounts,
+}
+
+/// Collects scan events into a [`UsageReport`].
+#[derive(Debug)]
+pub struct UsageCollector {
+    tree: DirTree,
+    /// Regular file sizes.
+    file_sizes: HashMap<u64, u64>,
+    /// First link of each non-directory: child → (parent, extension id).
+    links: HashMap<u64, (u64, u32)>,
+    extensions: Vec<Vec<u8>>,
+    extension_ids: HashMap<Vec<u8>, u32>,
+    kinds: KindCounts,
+}
+
+impl UsageCollector {
+    pub fn new(sb: &SuperblockInfo) -> Self {
+        Self {
+            tree: DirTree::new(sb.root_ino),
+            file_sizes: HashMap::new(),
+            links: HashMap::new(),
+            extensions: Vec::new(),
+            extension_ids: HashMap::new(),
+            kinds: KindCounts::default(),
+        }
+    }
+
+    /// Record an inode's type and size.
+    pub fn add_inode(&mut self, inode: &InodeInfo) {
+        self.tree.add_inode(inode);
+        self.kinds.add_inode(inode);
+        if inode.mode & S_IFMT == S_IFREG {
+            self.file_sizes.insert(inode.ino, inode.size);
+        }
+    }
+
+    /// Record a directory entry.
+    pub fn add_dir_entry(&mut self, de: &DirEntryInfo<'_>) {
+        if self.tree.add_dir_entry(de) == EntryKind::Other {
+            self.add_link(de.parent_ino, de.child_ino, de.name);
+        }
+    }
+
+    fn add_link(&mut self, parent: u64, child: u64, name: &[u8]) {
+        if self.links.contains_key(&child) {
+            return;
+        }
+        let ext = extension_of(name);
+        let id = match self.extension_ids.get(&ext) {
+            Some(&id) => id,
+            None => {
+                let id = self.extensions.len() as u32;
+                self.extensions.push(ext.clone());
+                self.extension_ids.insert(ext, id);
+                id
+            }
+        };
+        self.links.insert(child, (parent, id));
+    }
+
+    /// Finish and build the report.
+    pub fn finish(mut self) -> UsageReport {
+        for (parent, child, name) in self.tree.resolve_untyped() {
+            self.add_link(parent, child, &name);
+        }
+
+        let mut by_extension = vec![(0u64, 0u64); self.extensions.len()];
+        let mut by_top_dir: HashMap<u64, (u64, u64)> = HashMap::new();
+        let mut top_of: HashMap<u64, Option<u64>> = HashMap::new();
+
+        for (child, &(parent, ext)) in &self.links {
+            let Some(&size) = self.file_sizes.get(child) else {
+                continue;
+            };
+            let bucket = &mut by_extension[ext as usize];
+            bucket.0 += 1;
+            bucket.1 += size;
+
+            if let Some(top) = self.tree.top_dir(parent, &mut top_of) {
+                let bucket = by_top_dir.entry(top).or_default();
+                bucket.0 += 1;
+                bucket.1 += size;
+            }
+        }
+
+        let by_extension = by_extension
+            .into_iter()
+            .zip(&self.extensions)
+            .filter(|((files, _), _)| *files > 0)
+            .map(|((files, bytes), ext)| UsageBucket {
+                key: escape_name(ext).into_owned(),
+                files,
+                bytes,
+            })
+            .collect();
+        let by_top_dir = by_top_dir
+            .into_iter()
+            .map(|(top, (files, bytes))| UsageBucket {
+                key: match self.tree.name(top) {
+                    Some(name) => escape_name(name).into_owned(),
+                    None => ROOT_BUCKET.into(),
+                },
+                files,
+                bytes,
+            })
+            .collect();
+        UsageReport {
+            by_extension: sorted(by_extension),
+            by_top_dir: sorted(by_top_dir),
+            kinds: self.kinds,
+        }
+    }
+}
+
+/// Lowercased extension of `name`, empty if it has none. A leading dot
+/// (hidden file) does not start an extension.
+fn extension_of(name: &[u8]) -> Vec<u8> {
+    match name.iter().rposition(|&b| b == b'.') {
+        Some(dot) if dot > 0 && dot + 1 < name.len() => name[dot + 1..].to_ascii_lowercase(),
+        _ => Vec::new(),
+    }
+}
+
+fn sorted(mut buckets: Vec<UsageBucket>) -> Vec<UsageBucket> {
+    buckets.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.key.cmp(&b.key)));
+    buckets
+}
@@ -0,0 +1,169 @@
+//! Non-fatal scan diagnostics.
+//!
+//! Conditions that do not stop a scan but that an operator should know
+//! about (skipped blocks, clamped reads, geometry disagreements) are
+//! recorded as [`ScanWarning`]s instead of being dropped silently.
+
+use core::fmt;
+
+/// What kind of non-fatal condition was observed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum WarningCode {
+    /// The filesystem does not record file types in directory entries, so
+    /// every `DirEntryInfo::file_type` is 0 (unknown).
+    FtypeAbsent,
+    /// The AGI's `agi_length` differs from the superblock geometry.
+    AgLengthMismatch,
+    /// A directory extent block had neither a data nor an index magic and
+    /// was skipped.
+    UnexpectedDirBlockMagic,
+    /// A read returned fewer bytes than requested (clamped at the end of
+    /// the device); the missing tail was not parsed.
+    ShortRead,
+    /// Walking a btree-format directory's bmbt failed while extents were
+    /// being skipped; that directory's entries are missing.
+    BmbtWalkFailed,
+    /// An inobt record has an impossible inode or free count; it was dropped.
+    InobtBadRecord,
+    /// inobt records are not in increasing start-inode order.
+    InobtOutOfOrder,
+    /// An inobt record's chunk overlaps the previous one; it was dropped.
+    InobtOverlap,
+    /// An AGI unlinked bucket chain loops or leads to an unreadable inode;
+    /// the rest of that chain was not followed.
+    UnlinkedChainBroken,
+    /// A directory block split across extents has unmapped pieces; it was
+    /// skipped.
+    IncompleteDirBlock,
+    /// A symlink's target has a bad size, magic or missing block; the link
+    /// was skipped by [`scan_symlink_targets`](crate::AgDirPhase::scan_symlink_targets).
+    BadSymlink,
+    /// An inode's shortform attr fork is malformed; its attributes were
+    /// skipped by [`scan_xattrs`](crate::AgDirPhase::scan_xattrs).
+    BadAttrFork,
+    /// A V5 structure's CRC does not match its contents; it was parsed
+    /// anyway under [`CrcVerification::Lenient`](crate::CrcVerification::Lenient).
+    CrcMismatch,
+    /// A V5 structure carries a different UUID from the superblock's
+    /// metadata UUID; see [`ScanOptions::verify_uuids`](crate::ScanOptions::verify_uuids).
+    UuidMismatch,
+    /// The inode phase parsed a different number of in-use inodes than the
+    /// AGI counts; see [`ScanOptions::verify_coverage`](crate::ScanOptions::verify_coverage).
+    InodeCountMismatch,
+    /// A directory found by the inode phase had no entries parsed by the
+    /// directory phase: its blocks were unwritten, unreadable or lost in
+    /// the phase plumbing. Recorded under
+    /// [`ScanOptions::verify_coverage`](crate::ScanOptions::verify_coverage).
+    DirectoryNotScanned,
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FtypeAbsent => write!(f, "ftype_absent"),
+            Self::AgLengthMismatch => write!(f, "ag_length_mismatch"),
+            Self::UnexpectedDirBlockMagic => write!(f, "unexpected_dir_block_magic"),
+            Self::ShortRead => write!(f, "short_read"),
+            Self::BmbtWalkFailed => write!(f, "bmbt_walk_failed"),
+            Self::InobtBadRecord => write!(f, "inobt_bad_record"),
+            Self::InobtOutOfOrder => write!(f, "inobt_out_of_order"),
+            Self::InobtOverlap => write!(f, "inobt_overlap"),
+            Self::UnlinkedChainBroken => write!(f, "unlinked_chain_broken"),
+            Self::IncompleteDirBlock => write!(f, "incomplete_dir_block"),
+            Self::BadSymlink => write!(f, "bad_symlink"),
+            Self::BadAttrFork => write!(f, "bad_attr_fork"),
+            Self::CrcMismatch => write!(f, "crc_mismatch"),
+            Self::UuidMismatch => write!(f, "uuid_mismatch"),
+            Self::InodeCountMismatch => write!(f, "inode_count_mismatch"),
+            Self::DirectoryNotScanned => write!(f, "directory_not_scanned"),
+        }
+    }
+}
+
+/// A non-fatal condition observed during a scan, with the object it
+/// concerns where known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanWarning {
+    pub code: WarningCode,
+    pub ag_number: Option<u32>,
+    pub ino: Option<u64>,
+    /// Byte offset on the device, for block-level conditions.
+    pub byte_offset: Option<u64>,
+}
+
+impl ScanWarning {
+    pub fn new(code: WarningCode) -> Self {
+        Self {
+            code,
+            ag_number: None,
+            ino: None,
+            byte_offset: None,
+        }
+    }
+
+    pub fn with_ag(mut self, agno: u32) -> Self {
+        self.ag_number = Some(agno);
+        self
+    }
+
+    pub fn with_ino(mut self, ino: u64) -> Self {
+        self.ino = Some(ino);
+        self
+    }
+
+    pub fn with_byte_offset(mut self, offset: u64) -> Self {
+        self.byte_offset = Some(offset);
+        self
+    }
+}
+
+impl fmt::Display for ScanWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code)?;
+        if let Some(agno) = self.ag_number {
+            write!(f, " ag={agno}")?;
+        }
+        if let Some(ino) = self.ino {
+            write!(f, " ino={ino}")?;
+        }
+        if let Some(offset) = self.byte_offset {
+            write!(f, " offset={offset}")?;
+        }
+        Ok(())
+    }
+}
+
+/// How far an event's data can be trusted, for consumers that want to
+/// treat uncertain records differently (re-verify, flag, or drop them).
+///
+/// Events carry the most specific applicable marker: an event that is both
+/// [`ReadAfterError`](Self::ReadAfterError) and
+/// [`LogNotReplayed`](Self::LogNotReplayed) reports the former.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum Provenance {
+    /// Read from a consistent-looking filesystem with nothing amiss.
+    #[default]
+    Clean,
+    /// [`FsScanner::inspect_log`](crate::FsScanner::inspect_log) found the
+    /// log does not end with an unmount record, so on-disk metadata may lag
+    /// what the log records. Without that call the log is not read and
+    /// events are never marked so.
+    LogNotReplayed,
+    /// Read after the same AG's scan hit a problem it recovered from, such
+    /// as a short read or a dropped inobt record; see the scan's
+    /// [`ScanWarning`]s.
+    ReadAfterError,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Clean => write!(f, "clean"),
+            Self::LogNotReplayed => write!(f, "log_not_replayed"),
+            Self::ReadAfterError => write!(f, "read_after_error"),
+        }
+    }
+}
@@ -1,4 +1,5 @@
-use zerocopy::{FromBytes, Immutable, KnownLayout};
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
+use alloc::vec::Vec;
 use zerocopy::byteorder::big_endian::U32;
 
 use crate::error::FxfspError;
@@ -6,9 +7,162 @@ use crate::xfs::superblock::FormatVersion;
 
 /// AGI magic: "XAGI"
 const XFS_AGI_MAGIC: u32 = 0x58414749;
+/// AGF magic: "XAGF"
+const XFS_AGF_MAGIC: u32 = 0x58414746;
+/// V5 AGFL magic: "XAFL"
+const XFS_AGFL_MAGIC: u32 = 0x5841464c;
+/// V5 AGFL header size (magic, seqno, uuid, lsn, crc); V4 AGFLs have none.
+const XFS_AGFL_V5_HDR_SIZE: usize = 36;
+
+/// On-disk AG free space header (AGF).
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+pub struct XfsAgf {
+    pub agf_magicnum: U32,
+    pub agf_versionnum: U32,
+    pub agf_seqno: U32,
+    pub agf_length: U32,
+    /// Roots of the bnobt, cntbt and rmapbt.
+    pub agf_roots: [U32; 3],
+    pub agf_levels: [U32; 3],
+    pub agf_flfirst: U32,
+    pub agf_fllast: U32,
+    pub agf_flcount: U32,
+    pub agf_freeblks: U32,
+    pub agf_longest: U32,
+    pub agf_btreeblks: U32,
+    // V5 fields; zero on V4.
+    pub agf_uuid: [u8; 16],
+    pub agf_rmap_blocks: U32,
+    pub agf_refcount_blocks: U32,
+    pub agf_refcount_root: U32,
+    pub agf_refcount_level: U32,
+    // Spare space, lsn and crc follow but we don't need them.
+}
+
+/// Parsed AGF information.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AgfInfo {
+    pub ag_number: u32,
+    /// AG length in blocks as recorded in the AGF (`agf_length`).
+    pub length: u32,
+    /// Free space by block number btree.
+    pub bnobt_root: u32,
+    pub bnobt_level: u32,
+    /// Free space by size btree.
+    pub cntbt_root: u32,
+    pub cntbt_level: u32,
+    /// Reverse mapping btree (0 without the rmapbt feature).
+    pub rmapbt_root: u32,
+    pub rmapbt_level: u32,
+    /// Reference count btree (0 without the reflink feature).
+    pub refcountbt_root: u32,
+    pub refcountbt_level: u32,
+    /// First, last and number of active AGFL slots.
+    pub fl_first: u32,
+    pub fl_last: u32,
+    pub fl_count: u32,
+    /// Free blocks in the AG.
+    pub free_blocks: u32,
+    /// Length of the longest free extent, in blocks.
+    pub longest_free: u32,
+    /// Blocks held by the free space btrees (beyond their roots).
+    pub btree_blocks: u32,
+    /// Blocks held by the reverse mapping btree (V5 only, else 0).
+    pub rmap_blocks: u32,
+    /// Blocks held by the reference count btree (V5 only, else 0).
+    pub refcount_blocks: u32,
+}
+
+impl AgfInfo {
+    /// Parse AGF from buffer. `agno` is used for error context.
+    pub fn from_buf(buf: &[u8], agno: u32) -> Result<Self, FxfspError> {
+        let agf = XfsAgf::ref_from_prefix(buf)
+            .map_err(|_| FxfspError::Parse("buffer too small for AGF"))?
+            .0;
+
+        if agf.agf_magicnum.get() != XFS_AGF_MAGIC {
+            return Err(FxfspError::BadMagic("AGF header"));
+        }
+        if agf.agf_seqno.get() != agno {
+            return Err(FxfspError::Parse("AGF sequence number mismatch"));
+        }
+
+        Ok(AgfInfo {
+            ag_number: agno,
+            length: agf.agf_length.get(),
+            bnobt_root: agf.agf_roots[0].get(),
+            bnobt_level: agf.agf_levels[0].get(),
+            cntbt_root: agf.agf_roots[1].get(),
+            cntbt_level: agf.agf_levels[1].get(),
+            rmapbt_root: agf.agf_roots[2].get(),
+            rmapbt_level: agf.agf_levels[2].get(),
+            refcountbt_root: agf.agf_refcount_root.get(),
+            refcountbt_level: agf.agf_refcount_level.get(),
+            fl_first: agf.agf_flfirst.get(),
+            fl_last: agf.agf_fllast.get(),
+            fl_count: agf.agf_flcount.get(),
+            free_blocks: agf.agf_freeblks.get(),
+            longest_free: agf.agf_longest.get(),
+            btree_blocks: agf.agf_btreeblks.get(),
+            rmap_blocks: agf.agf_rmap_blocks.get(),
+            refcount_blocks: agf.agf_refcount_blocks.get(),
+        })
+    }
+}
+
+/// Parse the active entries of an AGFL sector: the AG blocks reserved for
+/// refilling the free space btrees, in list order.
+///
+/// The list is circular; entries run from `agf.fl_first` for `agf.fl_count`
+/// slots, wrapping at the end of the sector.
+pub fn parse_agfl(
+    buf: &[u8],
+    agf: &AgfInfo,
+    sect_size: usize,
+    version: FormatVersion,
+) -> Result<Vec<u32>, FxfspError> {
+    let sector = buf
+        .get(..sect_size)
+        .ok_or(FxfspError::Parse("buffer too small for AGFL"))?;
+    let slots = match version {
+        FormatVersion::V4 => sector,
+        FormatVersion::V5 => {
+            if sector.len() < XFS_AGFL_V5_HDR_SIZE {
+                return Err(FxfspError::Parse("buffer too small for AGFL"));
+            }
+            let magic = u32::from_be_bytes([sector[0], sector[1], sector[2], sector[3]]);
+            if magic != XFS_AGFL_MAGIC {
+                return Err(FxfspError::BadMagic("AGFL"));
+            }
+            let seqno = u32::from_be_bytes([sector[4], sector[5], sector[6], sector[7]]);
+            if seqno != agf.ag_number {
+                return Err(FxfspError::Parse("AGFL sequence number mismatch"));
+            }
+            &sector[XFS_AGFL_V5_HDR_SIZE..]
+        }
+    };
+    let size = (slots.len() / 4) as u32;
+    if agf.fl_count > size || (agf.fl_count > 0 && agf.fl_first >= size) {
+        return Err(FxfspError::Parse("AGFL indices out of range"));
+    }
+
+    let mut blocks = Vec::with_capacity(agf.fl_count as usize);
+    for i in 0..agf.fl_count {
+        let slot = ((agf.fl_first + i) % size) as usize * 4;
+        blocks.push(u32::from_be_bytes([
+            slots[slot],
+            slots[slot + 1],
+            slots[slot + 2],
+            slots[slot + 3],
+        ]));
+    }
+    Ok(blocks)
+}
 
 /// On-disk AG inode header (AGI). We only need the first portion.
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsAgi {
     pub agi_magicnum: U32,
@@ -26,10 +180,22 @@ pub struct XfsAgi {
 }
 
 /// Parsed AGI information we need for traversal.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AgiInfo {
     pub ag_number: u32,
+    /// AG length in blocks as recorded in the AGI (`agi_length`).
+    pub length: u32,
+    /// Allocated inodes in this AG (`agi_count`).
+    pub count: u32,
+    /// Free inodes in allocated chunks (`agi_freecount`).
+    pub free_count: u32,
     pub inobt_root: u32,
     pub inobt_level: u32,
+    /// Heads of the unlinked inode bucket chains (`agi_unlinked`),
+    /// AG-relative, `NULLAGINO` for an empty bucket.
+    #[cfg_attr(feature = "serde", serde(with = "unlinked_buckets"))]
+    pub unlinked: [u32; 64],
 }
 
 impl AgiInfo {
@@ -50,9 +216,30 @@ impl AgiInfo {
 
         Ok(AgiInfo {
             ag_number: agno,
+            length: agi.agi_length.get(),
+            count: agi.agi_count.get(),
+            free_count: agi.agi_freecount.get(),
             inobt_root: agi.agi_root.get(),
             inobt_level: agi.agi_level.get(),
+            unlinked: agi.agi_unlinked.map(|head| head.get()),
         })
     }
 }
 
+
+/// Serde for `agi_unlinked`: serde's array impls stop at 32 elements.
+#[cfg(feature = "serde")]
+mod unlinked_buckets {
+    use alloc::vec::Vec;
+
+    pub fn serialize<S: serde::Serializer>(buckets: &[u32; 64], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(buckets)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<[u32; 64], D::Error> {
+        let buckets: Vec<u32> = serde::Deserialize::deserialize(deserializer)?;
+        buckets
+            .try_into()
+            .map_err(|b: Vec<u32>| serde::de::Error::invalid_length(b.len(), &"64 unlinked buckets"))
+    }
+}
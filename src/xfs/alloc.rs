@@ -0,0 +1,113 @@
+//! Free space btrees: the bnobt, keyed by start block, and the cntbt, keyed
+//! by length and then start block. Both hold the same records, one per free
+//! extent of the AG.
+
+use alloc::vec::Vec;
+
+use zerocopy::byteorder::big_endian::U32;
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
+
+use crate::error::FxfspError;
+use crate::reader::{IoPhase, IoReader};
+use crate::xfs::btree::{ShortBtree, ShortBtreeBlock};
+use crate::xfs::superblock::{FormatVersion, FsContext};
+
+/// "ABTB" / "AB3B": by-block btree, V4 and V5.
+const XFS_ABTB_MAGICS: [u32; 2] = [0x41425442, 0x41423342];
+/// "ABTC" / "AB3C": by-size btree, V4 and V5.
+const XFS_ABTC_MAGICS: [u32; 2] = [0x41425443, 0x41423343];
+
+/// Which free space btree to walk, and so the order of its records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FreeSpaceOrder {
+    /// The bnobt: by start block.
+    ByBlock,
+    /// The cntbt: by length, then start block.
+    BySize,
+}
+
+impl FreeSpaceOrder {
+    fn magic(self, version: FormatVersion) -> u32 {
+        let magics = match self {
+            Self::ByBlock => XFS_ABTB_MAGICS,
+            Self::BySize => XFS_ABTC_MAGICS,
+        };
+        match version {
+            FormatVersion::V4 => magics[0],
+            FormatVersion::V5 => magics[1],
+        }
+    }
+
+    fn btree(self, ctx: &FsContext) -> ShortBtree {
+        // Keys are records.
+        ShortBtree {
+            magic: self.magic(ctx.version),
+            name: "free space btree block",
+            key_size: core::mem::size_of::<XfsAllocRec>(),
+            phase: IoPhase::FreeSpaceWalk,
+        }
+    }
+}
+
+/// Free space btree record (8 bytes).
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned, Clone, Copy)]
+#[repr(C)]
+pub struct XfsAllocRec {
+    pub ar_startblock: U32,
+    pub ar_blockcount: U32,
+}
+
+/// One free extent of an AG.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct FreeSpaceExtent {
+    pub ag_number: u32,
+    /// First free AG block.
+    pub start_block: u32,
+    pub block_count: u32,
+}
+
+#[cfg(feature = "std")]
+impl FreeSpaceExtent {
+    pub(crate) fn new(ag_number: u32, rec: &XfsAllocRec) -> Self {
+        Self { ag_number, start_block: rec.ar_startblock.get(), block_count: rec.ar_blockcount.get() }
+    }
+}
+
+/// Contents of one free space btree block.
+#[derive(Clone)]
+pub enum AllocBlock {
+    /// Leaf (level 0): records in key order.
+    Leaf(Vec<XfsAllocRec>),
+    /// Interior node: child AG block numbers in key order.
+    Node { level: u16, children: Vec<u32> },
+}
+
+/// Decode a single bnobt (`order` [`ByBlock`](FreeSpaceOrder::ByBlock)) or
+/// cntbt block read from disk. `buf` holds one filesystem block.
+pub fn parse_alloc_block(buf: &[u8], ctx: &FsContext, order: FreeSpaceOrder) -> Result<AllocBlock, FxfspError> {
+    Ok(match order.btree(ctx).parse_block(buf, ctx, |_| true)? {
+        ShortBtreeBlock::Leaf(records) => AllocBlock::Leaf(records),
+        ShortBtreeBlock::Node { level, children } => AllocBlock::Node { level, children },
+    })
+}
+
+/// Walk the free space btree rooted at `root_block` (AG-relative) with
+/// `level` levels, as recorded in the AGF, and collect its records in key
+/// order.
+///
+/// Reads level by level, each level's blocks sorted by disk offset and read
+/// in one coalesced sweep, as
+/// [`collect_inobt_records`](crate::xfs::btree::collect_inobt_records) does.
+pub fn collect_free_space_records<R: IoReader>(
+    engine: &mut R,
+    ctx: &FsContext,
+    agno: u32,
+    order: FreeSpaceOrder,
+    root_block: u32,
+    level: u32,
+) -> Result<Vec<XfsAllocRec>, FxfspError> {
+    order.btree(ctx).collect_records(engine, ctx, agno, root_block, level, |_| true)
+}
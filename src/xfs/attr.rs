@@ -0,0 +1,325 @@
+//! Extended attributes.
+//!
+//! Small attribute sets are packed into the inode's attr fork (shortform,
+//! `XFS_DINODE_FMT_LOCAL`). Larger ones live in attr fork blocks: a leaf,
+//! or a dabtree of node blocks over a chain of leaves, with values too big
+//! for a leaf in "remote" value blocks of the same fork.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::ControlFlow;
+
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
+use zerocopy::byteorder::big_endian::U16;
+
+use crate::error::FxfspError;
+use crate::xfs::superblock::{FormatVersion, FsContext};
+
+/// `XFS_ATTR_LOCAL`: a leaf entry's value is in the leaf.
+const XFS_ATTR_LOCAL: u8 = 1 << 0;
+
+/// `XFS_ATTR_ROOT`: the attribute is in the `trusted` namespace.
+const XFS_ATTR_ROOT: u8 = 1 << 1;
+/// `XFS_ATTR_SECURE`: the attribute is in the `security` namespace.
+const XFS_ATTR_SECURE: u8 = 1 << 2;
+/// `XFS_ATTR_PARENT`: a parent pointer (PARENT feature).
+const XFS_ATTR_PARENT: u8 = 1 << 3;
+/// `XFS_ATTR_INCOMPLETE`: a set or remove was interrupted.
+const XFS_ATTR_INCOMPLETE: u8 = 1 << 7;
+
+/// V4 / V5 attr leaf block magics (16-bit, in the da block info).
+const XFS_ATTR_LEAF_MAGIC: u16 = 0xfbee;
+const XFS_ATTR3_LEAF_MAGIC: u16 = 0x3bee;
+/// V4 / V5 dabtree node magics.
+const XFS_DA_NODE_MAGIC: u16 = 0xfebe;
+const XFS_DA3_NODE_MAGIC: u16 = 0x3ebe;
+/// V5 remote value block magic: "XARM"
+const XFS_ATTR3_RMT_MAGIC: u32 = 0x5841524d;
+/// V5 remote value block header size.
+const XFS_ATTR3_RMT_HDR_SIZE: usize = 56;
+
+/// Shortform attr fork header.
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+pub struct XfsAttrSfHdr {
+    /// Bytes used in the fork, header included.
+    pub totsize: U16,
+    pub count: u8,
+    pub padding: u8,
+}
+
+/// Which namespace an attribute name belongs to, from its on-disk flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum XattrNamespace {
+    User,
+    Trusted,
+    Security,
+    /// Parent pointers; the name and value encode a parent directory entry.
+    Parent,
+}
+
+impl XattrNamespace {
+    fn from_flags(flags: u8) -> Self {
+        if flags & XFS_ATTR_PARENT != 0 {
+            Self::Parent
+        } else if flags & XFS_ATTR_SECURE != 0 {
+            Self::Security
+        } else if flags & XFS_ATTR_ROOT != 0 {
+            Self::Trusted
+        } else {
+            Self::User
+        }
+    }
+}
+
+/// The Linux prefix, as in `getfattr` output (`user`, `trusted`, ...).
+impl fmt::Display for XattrNamespace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User => write!(f, "user"),
+            Self::Trusted => write!(f, "trusted"),
+            Self::Security => write!(f, "security"),
+            Self::Parent => write!(f, "parent"),
+        }
+    }
+}
+
+/// One extended attribute. `name` has no namespace prefix.
+#[derive(Debug, Clone, Copy)]
+pub struct XattrEntry<'a> {
+    pub name: &'a [u8],
+    pub value: &'a [u8],
+    pub namespace: XattrNamespace,
+}
+
+/// Parse a shortform attr fork, calling `callback` for each attribute in
+/// fork order.
+///
+/// `fork_buf` is the attr fork as it sits in the inode
+/// ([`InodeInfo::attr_fork_offset`](crate::xfs::inode::InodeInfo::attr_fork_offset)
+/// onwards). Entries left incomplete by an interrupted update are skipped;
+/// breaking from `callback` returns [`FxfspError::Stopped`].
+pub fn parse_shortform_attrs<F>(fork_buf: &[u8], callback: &mut F) -> Result<(), FxfspError>
+where
+    F: FnMut(&XattrEntry) -> ControlFlow<()>,
+{
+    let (hdr, _) = XfsAttrSfHdr::ref_from_prefix(fork_buf)
+        .map_err(|_| FxfspError::Parse("buffer too small for shortform attr header"))?;
+    let fork = fork_buf
+        .get(..hdr.totsize.get() as usize)
+        .ok_or(FxfspError::Parse("shortform attr fork beyond end of inode"))?;
+
+    let mut offset = size_of::<XfsAttrSfHdr>();
+    for _ in 0..hdr.count {
+        let [namelen, valuelen, flags] = *fork
+            .get(offset..offset + 3)
+            .and_then(|b| <&[u8; 3]>::try_from(b).ok())
+            .ok_or(FxfspError::Parse("shortform attr entry out of bounds"))?;
+        let name_start = offset + 3;
+        let value_start = name_start + namelen as usize;
+        let end = value_start + valuelen as usize;
+        if end > fork.len() {
+            return Err(FxfspError::Parse("shortform attr entry out of bounds"));
+        }
+        offset = end;
+        if flags & XFS_ATTR_INCOMPLETE != 0 {
+            continue;
+        }
+
+        let entry = XattrEntry {
+            name: &fork[name_start..value_start],
+            value: &fork[value_start..end],
+            namespace: XattrNamespace::from_flags(flags),
+        };
+        if callback(&entry).is_break() {
+            return Err(FxfspError::Stopped);
+        }
+    }
+    Ok(())
+}
+
+/// One entry of an attr leaf block.
+#[derive(Debug, Clone, Copy)]
+pub enum AttrLeafEntry<'a> {
+    /// Name and value both in the leaf.
+    Local(XattrEntry<'a>),
+    /// The value is `value_len` bytes in remote blocks of the attr fork,
+    /// starting at logical block `value_block`.
+    Remote {
+        name: &'a [u8],
+        namespace: XattrNamespace,
+        value_block: u32,
+        value_len: u32,
+    },
+}
+
+/// Contents of one attr fork block.
+#[derive(Debug, Clone)]
+pub enum AttrBlock<'a> {
+    /// A leaf: its entries in hash order (incomplete ones left out), and
+    /// the next leaf's logical block, 0 for the last.
+    Leaf { forw: u32, entries: Vec<AttrLeafEntry<'a>> },
+    /// A dabtree node: child logical blocks in hash order.
+    Node { level: u16, children: Vec<u32> },
+}
+
+fn be16(buf: &[u8], at: usize) -> Option<u16> {
+    buf.get(at..at + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn be32(buf: &[u8], at: usize) -> Option<u32> {
+    buf.get(at..at + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Decode one attr leaf or node block (`buf` is one filesystem block).
+pub fn parse_attr_block<'a>(buf: &'a [u8], ctx: &FsContext) -> Result<AttrBlock<'a>, FxfspError> {
+    const TOO_SMALL: FxfspError = FxfspError::Parse("buffer too small for attr block header");
+    // Size of the da block info, then the leaf and node header sizes.
+    let (info, leaf_hdr, node_hdr, leaf_magic, node_magic) = match ctx.version {
+        FormatVersion::V4 => (12, 32, 16, XFS_ATTR_LEAF_MAGIC, XFS_DA_NODE_MAGIC),
+        FormatVersion::V5 => (56, 80, 64, XFS_ATTR3_LEAF_MAGIC, XFS_DA3_NODE_MAGIC),
+    };
+    let magic = be16(buf, 8).ok_or(TOO_SMALL)?;
+    let count = be16(buf, info).ok_or(TOO_SMALL)? as usize;
+
+    if magic == node_magic {
+        let level = be16(buf, info + 2).ok_or(TOO_SMALL)?;
+        let children = (0..count)
+            .map(|i| be32(buf, node_hdr + i * 8 + 4))
+            .collect::<Option<Vec<u32>>>()
+            .ok_or(FxfspError::Parse("attr node entry out of bounds"))?;
+        return Ok(AttrBlock::Node { level, children });
+    }
+    if magic != leaf_magic {
+        return Err(FxfspError::BadMagic("attr leaf block"));
+    }
+
+    const OUT_OF_BOUNDS: FxfspError = FxfspError::Parse("attr leaf entry out of bounds");
+    let forw = be32(buf, 0).ok_or(TOO_SMALL)?;
+    let mut entries = Vec::with_capacity(count);
+    for i in 0..count {
+        let at = leaf_hdr + i * 8;
+        let nameidx = be16(buf, at + 4).ok_or(OUT_OF_BOUNDS)? as usize;
+        let flags = *buf.get(at + 6).ok_or(OUT_OF_BOUNDS)?;
+        if flags & XFS_ATTR_INCOMPLETE != 0 {
+            continue;
+        }
+        let namespace = XattrNamespace::from_flags(flags);
+        let entry = if flags & XFS_ATTR_LOCAL != 0 {
+            let value_len = be16(buf, nameidx).ok_or(OUT_OF_BOUNDS)? as usize;
+            let name_len = *buf.get(nameidx + 2).ok_or(OUT_OF_BOUNDS)? as usize;
+            let name_start = nameidx + 3;
+            let value_start = name_start + name_len;
+            let value = buf.get(value_start..value_start + value_len).ok_or(OUT_OF_BOUNDS)?;
+            AttrLeafEntry::Local(XattrEntry { name: &buf[name_start..value_start], value, namespace })
+        } else {
+            let value_block = be32(buf, nameidx).ok_or(OUT_OF_BOUNDS)?;
+            let value_len = be32(buf, nameidx + 4).ok_or(OUT_OF_BOUNDS)?;
+            let name_len = *buf.get(nameidx + 8).ok_or(OUT_OF_BOUNDS)? as usize;
+            let name = buf.get(nameidx + 9..nameidx + 9 + name_len).ok_or(OUT_OF_BOUNDS)?;
+            AttrLeafEntry::Remote { name, namespace, value_block, value_len }
+        };
+        entries.push(entry);
+    }
+    Ok(AttrBlock::Leaf { forw, entries })
+}
+
+/// Filesystem blocks holding a remote value of `value_len` bytes.
+pub fn remote_value_blocks(ctx: &FsContext, value_len: u32) -> u32 {
+    let per_block = match ctx.version {
+        FormatVersion::V4 => ctx.block_size as usize,
+        FormatVersion::V5 => ctx.block_size as usize - XFS_ATTR3_RMT_HDR_SIZE,
+    };
+    (value_len as usize).div_ceil(per_block) as u32
+}
+
+/// Append the value bytes held in one remote value block to `value`, a
+/// value of `value_len` bytes assembled in logical block order.
+pub fn parse_remote_value_block(
+    buf: &[u8],
+    ctx: &FsContext,
+    value_len: u32,
+    value: &mut Vec<u8>,
+) -> Result<(), FxfspError> {
+    let remaining = (value_len as usize).saturating_sub(value.len());
+    let payload = match ctx.version {
+        FormatVersion::V4 => &buf[..buf.len().min(remaining)],
+        FormatVersion::V5 => {
+            if be32(buf, 0) != Some(XFS_ATTR3_RMT_MAGIC) {
+                return Err(FxfspError::BadMagic("remote attr value block"));
+            }
+            if be32(buf, 4) != Some(value.len() as u32) {
+                return Err(FxfspError::Parse("remote attr value block out of order"));
+            }
+            let bytes = be32(buf, 8).unwrap_or(0) as usize;
+            buf.get(XFS_ATTR3_RMT_HDR_SIZE..XFS_ATTR3_RMT_HDR_SIZE + bytes.min(remaining))
+                .ok_or(FxfspError::Parse("remote attr value bytes out of bounds"))?
+        }
+    };
+    value.extend_from_slice(payload);
+    Ok(())
+}
+
+/// Walk a block-format attr fork and call `callback` for each attribute,
+/// remote values assembled.
+///
+/// `block` returns the filesystem block at an attr fork logical block, or
+/// `None` for one that isn't mapped (or wasn't read). The dabtree is
+/// descended from block 0 to the first leaf and the leaf chain followed
+/// from there; at most `max_blocks` blocks are visited, which bounds a
+/// corrupt chain. Breaking from `callback` returns [`FxfspError::Stopped`].
+pub fn parse_attr_fork_blocks<'b, B, F>(
+    ctx: &FsContext,
+    block: B,
+    max_blocks: usize,
+    callback: &mut F,
+) -> Result<(), FxfspError>
+where
+    B: Fn(u32) -> Option<&'b [u8]>,
+    F: FnMut(&XattrEntry) -> ControlFlow<()>,
+{
+    const UNMAPPED: FxfspError = FxfspError::Parse("attr fork block not mapped");
+    let mut lblk = 0;
+    let mut visited = 0;
+    let mut value = Vec::new();
+    loop {
+        visited += 1;
+        if visited > max_blocks {
+            return Err(FxfspError::Parse("attr fork block chain too long"));
+        }
+        let (forw, entries) = match parse_attr_block(block(lblk).ok_or(UNMAPPED)?, ctx)? {
+            AttrBlock::Node { children, .. } => {
+                lblk = *children.first().ok_or(FxfspError::Parse("empty attr node"))?;
+                continue;
+            }
+            AttrBlock::Leaf { forw, entries } => (forw, entries),
+        };
+
+        for entry in entries {
+            let entry = match entry {
+                AttrLeafEntry::Local(entry) => entry,
+                AttrLeafEntry::Remote { name, namespace, value_block, value_len } => {
+                    value.clear();
+                    for i in 0..remote_value_blocks(ctx, value_len) {
+                        let buf = block(value_block.wrapping_add(i)).ok_or(UNMAPPED)?;
+                        parse_remote_value_block(buf, ctx, value_len, &mut value)?;
+                    }
+                    if value.len() != value_len as usize {
+                        return Err(FxfspError::Parse("remote attr value truncated"));
+                    }
+                    XattrEntry { name, value: &value, namespace }
+                }
+            };
+            if callback(&entry).is_break() {
+                return Err(FxfspError::Stopped);
+            }
+        }
+
+        if forw == 0 {
+            return Ok(());
+        }
+        lblk = forw;
+    }
+}
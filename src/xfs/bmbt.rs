@@ -1,9 +1,10 @@
-//! Walk the bmap B-tree (bmbt) in directory inodes' data forks to collect
+//! Walk the bmap B-tree (bmbt) in btree-format inodes' data forks to collect
 //! all extent records.  Uses level-by-level sorted batch reads across all
-//! btree-format directories at once, replacing depth-first per-directory
+//! btree-format inodes of an AG at once, replacing depth-first per-inode
 //! traversal which caused random seeks.
 
-use std::collections::HashMap;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 
 use zerocopy::FromBytes;
 
@@ -25,7 +26,7 @@ fn bmbt_block_hdr_size(version: FormatVersion) -> usize {
     }
 }
 
-/// Input for one btree-format directory whose bmbt needs walking.
+/// Input for one btree-format inode whose bmbt needs walking.
 pub struct BmbtDirInput<'a> {
     pub ino: u64,
     pub fork_data: &'a [u8],
@@ -37,6 +38,64 @@ struct PendingBlock {
     fsblock: u64,
     owner_ino: u64,
     expected_level: u32,
+    /// Key order among the blocks of this walk step: the parent's `seq`
+    /// and this block's index in the parent, renumbered after each step.
+    seq: u64,
+    child: u32,
+}
+
+/// Contents of one bmbt block, or of the bmbt root in an inode's data fork.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BmbtBlock {
+    /// Leaf (level 0): extent records in logical order.
+    Leaf(Vec<Extent>),
+    /// Interior node: child blocks (absolute fsblock numbers) in key order.
+    Node { level: u16, children: Vec<u64> },
+}
+
+/// Decode a single on-disk bmbt block (`BMAP`/`BMA3`).
+///
+/// `buf` holds one filesystem block. Nothing further is read, so this
+/// suits hex viewers and recovery tools looking at one block at a time.
+pub fn parse_bmbt_block(buf: &[u8], ctx: &FsContext) -> Result<BmbtBlock, FxfspError> {
+    let (hdr_size, level, numrecs) = bmbt_block_header(buf, ctx)?;
+    if level == 0 {
+        let mut extents = Vec::with_capacity(numrecs);
+        push_bmbt_records(buf, hdr_size, numrecs, ctx, &mut extents)?;
+        Ok(BmbtBlock::Leaf(extents))
+    } else {
+        let maxrecs = (ctx.block_size as usize - hdr_size) / (8 + 8);
+        let children = bmbt_child_ptrs(buf, hdr_size + maxrecs * 8, numrecs).collect();
+        Ok(BmbtBlock::Node { level, children })
+    }
+}
+
+/// Decode the bmbt root of a btree-format inode's fork.
+///
+/// `fork_data` is the fork as it sits in the inode and `data_fork_size` its
+/// full size ([`InodeInfo::data_fork_size`](crate::xfs::inode::InodeInfo::data_fork_size)),
+/// which fixes where the child pointers start.
+pub fn parse_bmbt_root(fork_data: &[u8], data_fork_size: usize, ctx: &FsContext) -> Result<BmbtBlock, FxfspError> {
+    if fork_data.len() < 4 {
+        return Err(FxfspError::Parse("bmbt root too small"));
+    }
+    let level = u16::from_be_bytes([fork_data[0], fork_data[1]]);
+    let numrecs = u16::from_be_bytes([fork_data[2], fork_data[3]]) as usize;
+    if level == 0 {
+        let mut extents = Vec::with_capacity(numrecs);
+        push_bmbt_records(fork_data, 4, numrecs, ctx, &mut extents)?;
+        Ok(BmbtBlock::Leaf(extents))
+    } else {
+        let maxrecs = data_fork_size.saturating_sub(4) / (8 + 8);
+        let children = bmbt_child_ptrs(fork_data, 4 + maxrecs * 8, numrecs).collect();
+        Ok(BmbtBlock::Node { level, children })
+    }
+}
+
+/// Extent records in a full bmbt leaf block.
+pub fn bmbt_leaf_capacity(ctx: &FsContext) -> usize {
+    (ctx.block_size as usize - bmbt_block_hdr_size(ctx.version)) / size_of::<XfsBmbtRec>()
 }
 
 /// Collect extent records from all btree-format directories in one batched walk.
@@ -45,54 +104,80 @@ struct PendingBlock {
 /// collects child pointers from all directories, sorts them by disk offset, and
 /// reads each tree level in a single sorted coalesced pass.
 ///
-/// Returns `(inode_number, extents)` pairs for each directory that has extents.
+/// Returns `(inode_number, extents)` pairs for each directory that has extents,
+/// with extents in logical order.
 pub fn collect_all_bmbt_extents<R: IoReader>(
     engine: &mut R,
     ctx: &FsContext,
     dirs: &[BmbtDirInput],
 ) -> Result<Vec<(u64, Vec<Extent>)>, FxfspError> {
-    let mut results: HashMap<u64, Vec<Extent>> = HashMap::new();
+    let mut results: BTreeMap<u64, Vec<Extent>> = BTreeMap::new();
+    walk_bmbt_extents(engine, ctx, dirs, 0, |ino, extents| {
+        results.entry(ino).or_default().extend(extents);
+        Ok(())
+    })?;
+    Ok(results.into_iter().collect())
+}
+
+/// Walk the bmbts of `inputs` and pass each leaf's extents to `emit`.
+///
+/// Interior levels are read as in [`collect_all_bmbt_extents`]. Leaves are
+/// then read `leaf_batch` at a time (0 for all at once), each batch sorted
+/// by disk offset, and emitted in key order: every inode's extents arrive
+/// consecutively and in logical order, so at most one batch of leaves is
+/// held in memory however large a single file's map is.
+pub fn walk_bmbt_extents<R, F>(
+    engine: &mut R,
+    ctx: &FsContext,
+    inputs: &[BmbtDirInput],
+    leaf_batch: usize,
+    mut emit: F,
+) -> Result<(), FxfspError>
+where
+    R: IoReader,
+    F: FnMut(u64, Vec<Extent>) -> Result<(), FxfspError>,
+{
     let mut pending: Vec<PendingBlock> = Vec::new();
+    let mut leaves: Vec<PendingBlock> = Vec::new();
 
     // Parse all inline roots — no I/O needed for this step.
-    for dir in dirs {
-        if dir.fork_data.len() < 4 {
-            return Err(FxfspError::Parse("bmbt root too small"));
-        }
-
-        let level = u16::from_be_bytes([dir.fork_data[0], dir.fork_data[1]]);
-        let numrecs = u16::from_be_bytes([dir.fork_data[2], dir.fork_data[3]]) as usize;
-
-        if level == 0 {
+    let mut roots: Vec<&BmbtDirInput> = inputs.iter().collect();
+    roots.sort_unstable_by_key(|input| input.ino);
+    for input in roots {
+        match parse_bmbt_root(input.fork_data, input.data_fork_size, ctx)? {
             // Leaf-level root: extent records inline in the fork.
-            let extents = parse_bmbt_leaf_inline(dir.fork_data, numrecs, ctx)?;
-            if !extents.is_empty() {
-                results.entry(dir.ino).or_default().extend(extents);
-            }
-        } else {
-            // Interior root: extract child fsblock pointers.
-            let maxrecs = (dir.data_fork_size - 4) / (8 + 8);
-            let ptr_start = 4 + maxrecs * 8;
-
-            for i in 0..numrecs {
-                let off = ptr_start + i * 8;
-                if off + 8 > dir.fork_data.len() {
-                    break;
+            BmbtBlock::Leaf(extents) => {
+                if !extents.is_empty() {
+                    emit(input.ino, extents)?;
                 }
-                let fsblock = u64::from_be_bytes(dir.fork_data[off..off + 8].try_into().unwrap());
-                pending.push(PendingBlock {
+            }
+            BmbtBlock::Node { level, children } => {
+                pending.extend(children.into_iter().enumerate().map(|(i, fsblock)| PendingBlock {
                     fsblock,
-                    owner_ino: dir.ino,
+                    owner_ino: input.ino,
                     expected_level: level as u32 - 1,
-                });
+                    seq: 0,
+                    child: i as u32,
+                }));
             }
         }
     }
+    // The roots' children are already in key order.
+    for (seq, p) in pending.iter_mut().enumerate() {
+        p.seq = seq as u64;
+    }
 
     let block_size = ctx.block_size as usize;
 
-    // Process pending blocks level by level with sorted batch reads.
-    while !pending.is_empty() {
+    // Interior levels, level by level with sorted batch reads. Leaf
+    // pointers are set aside until the whole map is known.
+    loop {
+        let (leaf, interior): (Vec<_>, Vec<_>) = pending.drain(..).partition(|p| p.expected_level == 0);
+        leaves.extend(leaf);
+        pending = interior;
+        if pending.is_empty() {
+            break;
+        }
         pending.sort_unstable_by_key(|p| p.fsblock);
 
         let requests: Vec<(u64, usize, usize)> = pending
@@ -106,92 +191,127 @@ pub fn collect_all_bmbt_extents<R: IoReader>(
         engine.coalesced_read_batch(
             &requests,
             |buf, idx| {
-                let owner_ino = pending[idx].owner_ino;
-                let expected_level = pending[idx].expected_level;
-
-                if buf.len() < 8 {
-                    return Err(FxfspError::Parse("bmbt block too small"));
-                }
+                let parent = &pending[idx];
+                let (hdr_size, numrecs) = check_block(buf, ctx, parent.expected_level)?;
 
-                let magic = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
-                let hdr_size = match ctx.version {
-                    FormatVersion::V5 => {
-                        if magic != XFS_BMAP3_MAGIC {
-                            return Err(FxfspError::BadMagic("bmbt V5 block"));
-                        }
-                        bmbt_block_hdr_size(FormatVersion::V5)
-                    }
-                    FormatVersion::V4 => {
-                        if magic != XFS_BMAP_MAGIC {
-                            return Err(FxfspError::BadMagic("bmbt V4 block"));
-                        }
-                        bmbt_block_hdr_size(FormatVersion::V4)
-                    }
-                };
-
-                let level = u16::from_be_bytes([buf[4], buf[5]]);
-                let numrecs = u16::from_be_bytes([buf[6], buf[7]]) as usize;
-
-                if level as u32 != expected_level {
-                    return Err(FxfspError::Parse("bmbt level mismatch"));
-                }
-
-                if level == 0 {
-                    // Leaf: extract extent records.
-                    for i in 0..numrecs {
-                        let offset = hdr_size + i * 16;
-                        if offset + 16 > buf.len() {
-                            break;
-                        }
-                        let rec = <XfsBmbtRec as FromBytes>::ref_from_prefix(&buf[offset..])
-                            .map_err(|_| FxfspError::Parse("bmbt leaf record parse failed"))?
-                            .0;
-                        results.entry(owner_ino).or_default().push(rec.unpack_with_context(ctx));
-                    }
-                } else {
-                    // Interior: extract child fsblock pointers.
-                    let key_size = 8usize;
-                    let ptr_size = 8usize;
-                    let maxrecs = (block_size - hdr_size) / (key_size + ptr_size);
-                    let ptr_start = hdr_size + maxrecs * key_size;
-
-                    for i in 0..numrecs {
-                        let off = ptr_start + i * ptr_size;
-                        if off + ptr_size > buf.len() {
-                            break;
-                        }
-                        let fsblock = u64::from_be_bytes(buf[off..off + 8].try_into().unwrap());
-                        next_pending.push(PendingBlock {
-                            fsblock,
-                            owner_ino,
-                            expected_level: expected_level - 1,
-                        });
-                    }
-                }
+                // Interior: extract child fsblock pointers.
+                let maxrecs = (block_size - hdr_size) / (8 + 8);
+                let children = bmbt_child_ptrs(buf, hdr_size + maxrecs * 8, numrecs);
+                next_pending.extend(children.enumerate().map(|(i, fsblock)| PendingBlock {
+                    fsblock,
+                    owner_ino: parent.owner_ino,
+                    expected_level: parent.expected_level - 1,
+                    seq: parent.seq,
+                    child: i as u32,
+                }));
 
                 Ok(())
             },
             IoPhase::BmbtWalk,
         )?;
 
+        next_pending.sort_unstable_by_key(|p| (p.owner_ino, p.seq, p.child));
+        for (seq, p) in next_pending.iter_mut().enumerate() {
+            p.seq = seq as u64;
+        }
         pending = next_pending;
     }
 
-    Ok(results.into_iter().collect())
+    leaves.sort_unstable_by_key(|p| (p.owner_ino, p.seq, p.child));
+    let batch_len = if leaf_batch == 0 { leaves.len().max(1) } else { leaf_batch };
+    for batch in leaves.chunks(batch_len) {
+        let mut requests: Vec<(u64, usize, usize)> = batch
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| (fsblock_to_byte(ctx, p.fsblock), block_size, idx))
+            .collect();
+        requests.sort_unstable_by_key(|r| r.0);
+
+        let mut parsed: Vec<Vec<Extent>> = (0..batch.len()).map(|_| Vec::new()).collect();
+        engine.coalesced_read_batch(
+            &requests,
+            |buf, idx| {
+                let (hdr_size, numrecs) = check_block(buf, ctx, 0)?;
+                let extents = &mut parsed[idx];
+                extents.reserve(numrecs);
+                push_bmbt_records(buf, hdr_size, numrecs, ctx, extents)
+            },
+            IoPhase::BmbtWalk,
+        )?;
+
+        for (leaf, extents) in batch.iter().zip(parsed) {
+            if !extents.is_empty() {
+                emit(leaf.owner_ino, extents)?;
+            }
+        }
+    }
+
+    Ok(())
 }
 
-/// Parse extent records from an inline leaf-level bmbt root (in the inode fork).
-fn parse_bmbt_leaf_inline(fork_data: &[u8], numrecs: usize, ctx: &FsContext) -> Result<Vec<Extent>, FxfspError> {
-    let mut extents = Vec::with_capacity(numrecs);
+/// Check a bmbt block's magic; returns `(header size, level, numrecs)`.
+fn bmbt_block_header(buf: &[u8], ctx: &FsContext) -> Result<(usize, u16, usize), FxfspError> {
+    if buf.len() < 8 {
+        return Err(FxfspError::Parse("bmbt block too small"));
+    }
+
+    let magic = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let hdr_size = match ctx.version {
+        FormatVersion::V5 => {
+            if magic != XFS_BMAP3_MAGIC {
+                return Err(FxfspError::BadMagic("bmbt V5 block"));
+            }
+            bmbt_block_hdr_size(FormatVersion::V5)
+        }
+        FormatVersion::V4 => {
+            if magic != XFS_BMAP_MAGIC {
+                return Err(FxfspError::BadMagic("bmbt V4 block"));
+            }
+            bmbt_block_hdr_size(FormatVersion::V4)
+        }
+    };
+
+    let level = u16::from_be_bytes([buf[4], buf[5]]);
+    let numrecs = u16::from_be_bytes([buf[6], buf[7]]) as usize;
+    Ok((hdr_size, level, numrecs))
+}
+
+/// Check a bmbt block's magic and level; returns `(header size, numrecs)`.
+fn check_block(buf: &[u8], ctx: &FsContext, expected_level: u32) -> Result<(usize, usize), FxfspError> {
+    let (hdr_size, level, numrecs) = bmbt_block_header(buf, ctx)?;
+    if level as u32 != expected_level {
+        return Err(FxfspError::Parse("bmbt level mismatch"));
+    }
+    Ok((hdr_size, numrecs))
+}
+
+/// Append the `numrecs` extent records starting at `start`, stopping early
+/// at the end of `buf`.
+fn push_bmbt_records(
+    buf: &[u8],
+    start: usize,
+    numrecs: usize,
+    ctx: &FsContext,
+    extents: &mut Vec<Extent>,
+) -> Result<(), FxfspError> {
     for i in 0..numrecs {
-        let offset = 4 + i * 16;
-        if offset + 16 > fork_data.len() {
+        let offset = start + i * 16;
+        if offset + 16 > buf.len() {
             break;
         }
-        let rec = <XfsBmbtRec as FromBytes>::ref_from_prefix(&fork_data[offset..])
+        let rec = <XfsBmbtRec as FromBytes>::ref_from_prefix(&buf[offset..])
             .map_err(|_| FxfspError::Parse("bmbt leaf record parse failed"))?
             .0;
         extents.push(rec.unpack_with_context(ctx));
     }
-    Ok(extents)
+    Ok(())
+}
+
+/// The `numrecs` 64-bit child pointers starting at `ptr_start`, stopping
+/// early at the end of `buf`.
+fn bmbt_child_ptrs(buf: &[u8], ptr_start: usize, numrecs: usize) -> impl Iterator<Item = u64> + '_ {
+    (0..numrecs).map_while(move |i| {
+        let off = ptr_start + i * 8;
+        buf.get(off..off + 8).map(|ptr| u64::from_be_bytes(ptr.try_into().unwrap()))
+    })
 }
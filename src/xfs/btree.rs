@@ -1,9 +1,13 @@
-use zerocopy::{FromBytes, Immutable, KnownLayout};
+use alloc::vec;
+use alloc::vec::Vec;
+
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
 use zerocopy::byteorder::big_endian::{U16, U32, U64};
 
 use crate::error::FxfspError;
 use crate::reader::{IoPhase, IoReader};
-use crate::xfs::superblock::{FormatVersion, FsContext};
+use crate::warning::{ScanWarning, WarningCode};
+use crate::xfs::superblock::{FormatVersion, FsContext, XFS_INODES_PER_CHUNK, XFS_INODES_PER_HOLEMASK_BIT};
 
 /// Short-form B-tree block magic: "IABT" (V4 inode allocation B-tree).
 const XFS_IBT_MAGIC: u32 = 0x49414254;
@@ -11,7 +15,7 @@ const XFS_IBT_MAGIC: u32 = 0x49414254;
 const XFS_IBT3_MAGIC: u32 = 0x49414233;
 
 /// V4 short-form B-tree block header (16 bytes).
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsBtreeShortBlockV4 {
     pub bb_magic: U32,
@@ -22,7 +26,7 @@ pub struct XfsBtreeShortBlockV4 {
 }
 
 /// V5 short-form B-tree block header (56 bytes).
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsBtreeShortBlockV5 {
     pub bb_magic: U32,
@@ -38,7 +42,7 @@ pub struct XfsBtreeShortBlockV5 {
 }
 
 /// Inode B-tree record (16 bytes).
-#[derive(FromBytes, KnownLayout, Immutable, Clone, Copy)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned, Clone, Copy)]
 #[repr(C)]
 pub struct XfsInobtRec {
     pub ir_startino: U32,
@@ -55,17 +59,48 @@ impl XfsInobtRec {
         (free_mask & (1u64 << i)) == 0
     }
 
+    /// Bitmap of inodes (bit `i` = inode `startino + i`) that are allocated
+    /// and not in a sparse-chunk hole.
+    pub fn allocated_mask(&self) -> u64 {
+        let mut holemask = self.ir_holemask.get();
+        let mut holes = 0u64;
+        while holemask != 0 {
+            holes |= 0xF << (holemask.trailing_zeros() * 4);
+            holemask &= holemask - 1;
+        }
+        !self.ir_free.get() & !holes
+    }
+
     /// Starting AG-relative inode number.
     pub fn start_ino(&self) -> u32 {
         self.ir_startino.get()
     }
+
+    /// Does this record's chunk span AG inode `agino`?
+    pub fn contains(&self, agino: u32) -> bool {
+        agino.wrapping_sub(self.start_ino()) < XFS_INODES_PER_CHUNK
+    }
+
+    /// Is AG inode `agino` in this chunk and not in a sparse-chunk hole?
+    pub fn is_present(&self, agino: u32) -> bool {
+        let index = agino.wrapping_sub(self.start_ino());
+        index < XFS_INODES_PER_CHUNK
+            && self.ir_holemask.get() & (1 << (index / XFS_INODES_PER_HOLEMASK_BIT)) == 0
+    }
+}
+
+/// The record covering AG inode `agino` among inobt `records` in key order
+/// (as [`collect_inobt_records`] and [`InobtBlock::Leaf`] return them).
+pub fn find_inobt_record(records: &[XfsInobtRec], agino: u32) -> Option<&XfsInobtRec> {
+    let idx = records.partition_point(|r| r.start_ino() <= agino).checked_sub(1)?;
+    records.get(idx).filter(|r| r.contains(agino))
 }
 
 /// Size of the B-tree block header depending on version.
 fn btree_header_size(version: FormatVersion) -> usize {
     match version {
-        FormatVersion::V4 => std::mem::size_of::<XfsBtreeShortBlockV4>(),
-        FormatVersion::V5 => std::mem::size_of::<XfsBtreeShortBlockV5>(),
+        FormatVersion::V4 => core::mem::size_of::<XfsBtreeShortBlockV4>(),
+        FormatVersion::V5 => core::mem::size_of::<XfsBtreeShortBlockV5>(),
     }
 }
 
@@ -95,11 +130,39 @@ fn parse_btree_header(buf: &[u8], version: FormatVersion) -> Result<(u16, u16),
     }
 }
 
-/// Walk the inode B-tree rooted at `root_block` (AG-relative) and collect all inobt records.
+/// Contents of one inode B-tree block.
+#[derive(Clone)]
+pub enum InobtBlock {
+    /// Leaf (level 0): inode chunk records in key order.
+    Leaf(Vec<XfsInobtRec>),
+    /// Interior node: child AG block numbers in key order.
+    Node { level: u16, children: Vec<u32> },
+}
+
+/// Decode a single inobt block (`IABT`/`IAB3`) read from disk.
+///
+/// `buf` holds one filesystem block. Nothing is read or validated beyond
+/// the magic and the record bounds, so this suits hex viewers and recovery
+/// tools looking at one block at a time.
+pub fn parse_inobt_block(buf: &[u8], ctx: &FsContext) -> Result<InobtBlock, FxfspError> {
+    let hdr_size = btree_header_size(ctx.version);
+    let (level, numrecs) = parse_btree_header(buf, ctx.version)?;
+    if level == 0 {
+        Ok(InobtBlock::Leaf(parse_inobt_leaf(buf, hdr_size, numrecs)?))
+    } else {
+        let children = extract_inobt_children(buf, hdr_size, numrecs, ctx.block_size as usize)?;
+        Ok(InobtBlock::Node { level, children })
+    }
+}
+
+/// Walk the inode B-tree rooted at `root_block` (AG-relative) and collect all
+/// inobt records in key order.
 ///
 /// Uses level-by-level sorted batch reads: at each tree level the child block
 /// pointers are sorted by disk offset and read in one coalesced forward sweep,
 /// replacing the previous depth-first traversal which caused random seeks.
+/// Results are put back in tree order so [`check_inobt_records`] can verify
+/// the key order.
 pub fn collect_inobt_records<R: IoReader>(
     engine: &mut R,
     ctx: &FsContext,
@@ -127,56 +190,106 @@ pub fn collect_inobt_records<R: IoReader>(
     // Root is interior — extract child pointers for the next level.
     let mut current_blocks = extract_inobt_children(buf, hdr_size, numrecs, block_size)?;
 
-    // Walk down level by level with sorted batch reads.
+    // Walk down level by level with sorted batch reads. `current_blocks` is
+    // in key order; the tag is each block's position in it.
     for current_level in (0..root_level).rev() {
-        current_blocks.sort_unstable();
-
-        let requests: Vec<(u64, usize, usize)> = current_blocks
+        let mut requests: Vec<(u64, usize, usize)> = current_blocks
             .iter()
             .enumerate()
             .map(|(idx, &block)| (ctx.ag_block_to_byte(agno, block), block_size, idx))
             .collect();
+        requests.sort_unstable_by_key(|&(offset, _, _)| offset);
 
         if current_level == 0 {
             // Leaf level — collect records.
-            let mut records = Vec::new();
+            let mut leaves = vec![Vec::new(); current_blocks.len()];
             engine.coalesced_read_batch(
                 &requests,
-                |buf, _idx| {
+                |buf, idx| {
                     let (_lvl, numrecs) = parse_btree_header(buf, ctx.version)?;
-                    let recs = parse_inobt_leaf(buf, hdr_size, numrecs)?;
-                    records.extend(recs);
+                    leaves[idx] = parse_inobt_leaf(buf, hdr_size, numrecs)?;
                     Ok(())
                 },
                 IoPhase::InobtWalk,
             )?;
-            return Ok(records);
+            return Ok(leaves.concat());
         }
 
         // Interior level — collect next level's block numbers.
-        let mut next_blocks = Vec::new();
+        let mut children = vec![Vec::new(); current_blocks.len()];
         engine.coalesced_read_batch(
             &requests,
-            |buf, _idx| {
+            |buf, idx| {
                 let (blk_level, numrecs) = parse_btree_header(buf, ctx.version)?;
                 if blk_level as u32 != current_level {
                     return Err(FxfspError::Parse("inobt level mismatch"));
                 }
-                let children = extract_inobt_children(buf, hdr_size, numrecs, block_size)?;
-                next_blocks.extend(children);
+                children[idx] = extract_inobt_children(buf, hdr_size, numrecs, block_size)?;
                 Ok(())
             },
             IoPhase::InobtWalk,
         )?;
-        current_blocks = next_blocks;
+        current_blocks = children.concat();
     }
 
     unreachable!("loop always returns at leaf level")
 }
 
+/// Validate inobt records in tree order, dropping the ones that can't be
+/// trusted and recording a warning for each violation.
+///
+/// - Records with `ir_count` or `ir_freecount` above 64 are dropped.
+/// - A record whose chunk overlaps the previous kept record is dropped, so
+///   no inode is reported twice.
+/// - Start inodes must increase strictly; an out-of-order record that does
+///   not overlap is kept (its inodes are still real) but reported.
+///
+/// The returned records are sorted by start inode.
+pub fn check_inobt_records(
+    ctx: &FsContext,
+    agno: u32,
+    records: Vec<XfsInobtRec>,
+    warnings: &mut Vec<ScanWarning>,
+) -> Vec<XfsInobtRec> {
+    let warn = |code, rec: &XfsInobtRec| {
+        ScanWarning::new(code)
+            .with_ag(agno)
+            .with_ino(ctx.agino_to_ino(agno, rec.start_ino()))
+    };
+
+    let mut prev_start: Option<u32> = None;
+    let mut kept: Vec<XfsInobtRec> = Vec::with_capacity(records.len());
+    for rec in records {
+        if rec.ir_count > 64 || rec.ir_freecount > 64 {
+            warnings.push(warn(WarningCode::InobtBadRecord, &rec));
+            continue;
+        }
+        let start = rec.start_ino();
+        if prev_start.is_some_and(|prev| start <= prev) {
+            warnings.push(warn(WarningCode::InobtOutOfOrder, &rec));
+        }
+        prev_start = Some(start);
+        kept.push(rec);
+    }
+
+    kept.sort_by_key(|r| r.start_ino());
+    let mut out: Vec<XfsInobtRec> = Vec::with_capacity(kept.len());
+    for rec in kept {
+        let overlaps = out
+            .last()
+            .is_some_and(|last| rec.start_ino() < last.start_ino().saturating_add(64));
+        if overlaps {
+            warnings.push(warn(WarningCode::InobtOverlap, &rec));
+            continue;
+        }
+        out.push(rec);
+    }
+    out
+}
+
 /// Parse inobt leaf records from a block buffer.
 fn parse_inobt_leaf(buf: &[u8], hdr_size: usize, numrecs: u16) -> Result<Vec<XfsInobtRec>, FxfspError> {
-    let rec_size = std::mem::size_of::<XfsInobtRec>();
+    let rec_size = core::mem::size_of::<XfsInobtRec>();
     let mut records = Vec::with_capacity(numrecs as usize);
     for i in 0..numrecs as usize {
         let start = hdr_size + i * rec_size;
@@ -199,16 +312,160 @@ fn extract_inobt_children(buf: &[u8], hdr_size: usize, numrecs: u16, block_size:
     // in the block), NOT the current numrecs.
     let key_size = 4usize;
     let ptr_size = 4usize;
-    let maxrecs = (block_size - hdr_size) / (key_size + ptr_size);
+    let maxrecs = block_size.saturating_sub(hdr_size) / (key_size + ptr_size);
     let ptr_offset = hdr_size + maxrecs * key_size;
 
     let mut children = Vec::with_capacity(numrecs as usize);
     for i in 0..numrecs as usize {
         let start = ptr_offset + i * ptr_size;
-        let ptr = U32::ref_from_prefix(&buf[start..])
-            .map_err(|_| FxfspError::Parse("inobt ptr out of bounds"))?
+        let ptr = buf
+            .get(start..)
+            .and_then(|rest| U32::ref_from_prefix(rest).ok())
+            .ok_or(FxfspError::Parse("inobt ptr out of bounds"))?
             .0;
         children.push(ptr.get());
     }
     Ok(children)
 }
+
+/// Layout of one of the AG btrees other than the inobt, whose records carry
+/// no inode numbers: the free space btrees, the rmapbt and the refcountbt.
+/// All use short-form block headers and AG-relative 4-byte pointers.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ShortBtree {
+    /// Block magic for the filesystem's format version.
+    pub magic: u32,
+    /// Names the btree in [`FxfspError::BadMagic`].
+    pub name: &'static str,
+    /// Bytes per interior-node key slot: both keys for an overlapping
+    /// btree.
+    pub key_size: usize,
+    pub phase: IoPhase,
+}
+
+/// Contents of one [`ShortBtree`] block.
+pub(crate) enum ShortBtreeBlock<T> {
+    Leaf(Vec<T>),
+    Node { level: u16, children: Vec<u32> },
+}
+
+impl ShortBtree {
+    /// Level and record count of one of its blocks.
+    fn parse_header(&self, buf: &[u8]) -> Result<(u16, u16), FxfspError> {
+        // The V5 header extends the V4 one.
+        let hdr = XfsBtreeShortBlockV4::ref_from_prefix(buf)
+            .map_err(|_| FxfspError::Parse("buffer too small for btree header"))?
+            .0;
+        if hdr.bb_magic.get() != self.magic {
+            return Err(FxfspError::BadMagic(self.name));
+        }
+        Ok((hdr.bb_level.get(), hdr.bb_numrecs.get()))
+    }
+
+    /// Decode one block. Interior nodes keep the children whose key slot
+    /// `keep_child` accepts.
+    pub fn parse_block<T>(
+        &self,
+        buf: &[u8],
+        ctx: &FsContext,
+        keep_child: impl Fn(&[u8]) -> bool,
+    ) -> Result<ShortBtreeBlock<T>, FxfspError>
+    where
+        T: FromBytes + KnownLayout + Immutable + Unaligned + Copy,
+    {
+        let hdr_size = btree_header_size(ctx.version);
+        let (level, numrecs) = self.parse_header(buf)?;
+        let numrecs = numrecs as usize;
+        if level == 0 {
+            let rec_size = core::mem::size_of::<T>();
+            let recs = buf
+                .get(hdr_size..hdr_size + numrecs * rec_size)
+                .ok_or(FxfspError::Parse("btree record out of bounds"))?;
+            let records = recs
+                .chunks_exact(rec_size)
+                .map(|rec| *T::ref_from_bytes(rec).expect("chunk is one record"));
+            return Ok(ShortBtreeBlock::Leaf(records.collect()));
+        }
+        // Keys and pointers are both arrays sized for the most that fit.
+        let maxrecs = (ctx.block_size as usize).saturating_sub(hdr_size) / (self.key_size + 4);
+        let ptr_offset = hdr_size + maxrecs * self.key_size;
+        let keys = buf
+            .get(hdr_size..hdr_size + numrecs * self.key_size)
+            .ok_or(FxfspError::Parse("btree key out of bounds"))?;
+        let ptrs = buf
+            .get(ptr_offset..ptr_offset + numrecs * 4)
+            .ok_or(FxfspError::Parse("btree ptr out of bounds"))?;
+        let children = keys
+            .chunks_exact(self.key_size)
+            .zip(ptrs.chunks_exact(4))
+            .filter(|(key, _)| keep_child(key))
+            .map(|(_, p)| u32::from_be_bytes([p[0], p[1], p[2], p[3]]))
+            .collect();
+        Ok(ShortBtreeBlock::Node { level, children })
+    }
+
+    /// Walk the btree rooted at `root_block` (AG-relative) with `level`
+    /// levels, as recorded in the AGF, and collect the records of the
+    /// leaves reached in key order, descending only into children whose key
+    /// slot `keep_child` accepts.
+    ///
+    /// Reads level by level, as [`collect_inobt_records`] does.
+    pub fn collect_records<R, T>(
+        &self,
+        engine: &mut R,
+        ctx: &FsContext,
+        agno: u32,
+        root_block: u32,
+        level: u32,
+        keep_child: impl Fn(&[u8]) -> bool,
+    ) -> Result<Vec<T>, FxfspError>
+    where
+        R: IoReader,
+        T: FromBytes + KnownLayout + Immutable + Unaligned + Copy,
+    {
+        let root_level = level.saturating_sub(1);
+        let block_size = ctx.block_size as usize;
+        let parse_level = |buf: &[u8], expected: u32| match self.parse_block(buf, ctx, &keep_child)? {
+            ShortBtreeBlock::Leaf(records) if expected == 0 => Ok(ShortBtreeBlock::Leaf(records)),
+            ShortBtreeBlock::Node { level, children } if level as u32 == expected => {
+                Ok(ShortBtreeBlock::Node { level, children })
+            }
+            _ => Err(FxfspError::Parse("btree level mismatch")),
+        };
+
+        let buf = engine.read_at(ctx.ag_block_to_byte(agno, root_block), block_size, self.phase)?;
+        let mut current_blocks = match parse_level(buf, root_level)? {
+            ShortBtreeBlock::Leaf(records) => return Ok(records),
+            ShortBtreeBlock::Node { children, .. } => children,
+        };
+
+        for current_level in (0..root_level).rev() {
+            let mut requests: Vec<(u64, usize, usize)> = current_blocks
+                .iter()
+                .enumerate()
+                .map(|(idx, &block)| (ctx.ag_block_to_byte(agno, block), block_size, idx))
+                .collect();
+            requests.sort_unstable_by_key(|&(offset, _, _)| offset);
+
+            let mut leaves = vec![Vec::new(); current_blocks.len()];
+            let mut children = vec![Vec::new(); current_blocks.len()];
+            engine.coalesced_read_batch(
+                &requests,
+                |buf, idx| {
+                    match parse_level(buf, current_level)? {
+                        ShortBtreeBlock::Leaf(records) => leaves[idx] = records,
+                        ShortBtreeBlock::Node { children: next, .. } => children[idx] = next,
+                    }
+                    Ok(())
+                },
+                self.phase,
+            )?;
+            if current_level == 0 {
+                return Ok(leaves.concat());
+            }
+            current_blocks = children.concat();
+        }
+
+        unreachable!("loop always returns at leaf level")
+    }
+}
@@ -0,0 +1,120 @@
+//! CRC32c and UUID checks of V5 metadata.
+//!
+//! Every V5 metadata block, header sector and inode carries a CRC32c of its
+//! whole buffer, computed with the CRC field itself zeroed and stored
+//! little-endian, and all but the superblock carry the filesystem's
+//! metadata UUID. Where the fields live depends on the structure, which is
+//! told from the magic.
+
+use crate::error::FxfspError;
+
+/// A self-describing V5 structure: its name and where its CRC and UUID are.
+struct V5Header {
+    name: &'static str,
+    crc_off: usize,
+    /// `None` for the superblock, which defines the UUID.
+    uuid_off: Option<usize>,
+}
+
+const fn hdr(name: &'static str, crc_off: usize, uuid_off: usize) -> V5Header {
+    V5Header { name, crc_off, uuid_off: Some(uuid_off) }
+}
+
+/// `IN` at offset 0 with inode version 3.
+const XFS_DINODE_MAGIC: u16 = 0x494e;
+const DINODE: V5Header = hdr("inode", 100, 160);
+
+/// Structures with a 32-bit magic at offset 0.
+const MAGICS: [(u32, V5Header); 16] = [
+    (0x58465342, V5Header { name: "superblock", crc_off: 224, uuid_off: None }), // XFSB
+    (0x58414746, hdr("agf", 216, 64)),                 // XAGF
+    (0x58414749, hdr("agi", 312, 296)),                // XAGI
+    (0x5841464c, hdr("agfl", 32, 8)),                  // XAFL
+    (0x49414233, hdr("inobt block", 52, 32)),          // IAB3
+    (0x46494233, hdr("finobt block", 52, 32)),         // FIB3
+    (0x41423342, hdr("bnobt block", 52, 32)),          // AB3B
+    (0x41423343, hdr("cntbt block", 52, 32)),          // AB3C
+    (0x524d4233, hdr("rmapbt block", 52, 32)),         // RMB3
+    (0x52334643, hdr("refcountbt block", 52, 32)),     // R3FC
+    (0x424d4133, hdr("bmbt block", 64, 40)),           // BMA3
+    (0x58444233, hdr("dir block", 4, 24)),             // XDB3
+    (0x58444433, hdr("dir data block", 4, 24)),        // XDD3
+    (0x58444633, hdr("dir free block", 4, 24)),        // XDF3
+    (0x58534c4d, hdr("symlink block", 12, 16)),        // XSLM
+    (0x5841524d, hdr("attr remote block", 12, 16)),    // XARM
+];
+
+/// Dabtree blocks, with a 16-bit magic at offset 8.
+const DA3_MAGICS: [(u16, V5Header); 4] = [
+    (0x3df1, hdr("dir leaf block", 12, 32)),
+    (0x3dff, hdr("dir leafn block", 12, 32)),
+    (0x3ebe, hdr("da node block", 12, 32)),
+    (0x3bee, hdr("attr leaf block", 12, 32)),
+];
+
+/// Tell a V5 structure from its magic.
+fn identify(buf: &[u8]) -> Option<&'static V5Header> {
+    let head = buf.get(..4)?;
+    let magic = u32::from_be_bytes([head[0], head[1], head[2], head[3]]);
+    if let Some((_, hdr)) = MAGICS.iter().find(|(m, _)| *m == magic) {
+        return Some(hdr);
+    }
+    if u16::from_be_bytes([head[0], head[1]]) == XFS_DINODE_MAGIC && buf.get(4) == Some(&3) {
+        return Some(&DINODE);
+    }
+    let da = buf.get(8..10).map(|m| u16::from_be_bytes([m[0], m[1]]))?;
+    DA3_MAGICS.iter().find(|(m, _)| *m == da).map(|(_, hdr)| hdr)
+}
+
+/// Whether the CRC32c stored at `crc_off` matches the rest of `buf`.
+pub fn crc_matches(buf: &[u8], crc_off: usize) -> bool {
+    let Some(field) = buf.get(crc_off..crc_off + 4) else {
+        return false;
+    };
+    let stored = u32::from_le_bytes([field[0], field[1], field[2], field[3]]);
+    let crc = crc32c::crc32c(&buf[..crc_off]);
+    let crc = crc32c::crc32c_append(crc, &[0; 4]);
+    crc32c::crc32c_append(crc, &buf[crc_off + 4..]) == stored
+}
+
+/// Verify the CRC of one V5 structure: a header sector, an inode, or a
+/// filesystem or directory block, exactly as long as the CRC covers.
+///
+/// Returns `Ok(false)` if `buf` has no V5 magic this module knows (V4
+/// structures, or ones the scanner never reads), `Ok(true)` if it was
+/// checked and matched, and [`FxfspError::CrcMismatch`] otherwise. V4
+/// headers share the V5 magics but carry no CRC, so only call this on V5.
+pub fn verify_metadata_crc(buf: &[u8]) -> Result<bool, FxfspError> {
+    match identify(buf) {
+        None => Ok(false),
+        Some(hdr) if crc_matches(buf, hdr.crc_off) => Ok(true),
+        Some(hdr) => Err(FxfspError::CrcMismatch(hdr.name)),
+    }
+}
+
+/// Recompute and store the CRC of one V5 structure, laid out as for
+/// [`verify_metadata_crc`], after changing it. Returns `false`, leaving
+/// `buf` alone, if it has no V5 magic this module knows.
+pub fn update_metadata_crc(buf: &mut [u8]) -> bool {
+    let Some(hdr) = identify(buf) else {
+        return false;
+    };
+    let off = hdr.crc_off;
+    if buf.len() < off + 4 {
+        return false;
+    }
+    let crc = crc32c::crc32c(&buf[..off]);
+    let crc = crc32c::crc32c_append(crc, &[0; 4]);
+    let crc = crc32c::crc32c_append(crc, &buf[off + 4..]);
+    buf[off..off + 4].copy_from_slice(&crc.to_le_bytes());
+    true
+}
+
+/// The metadata UUID stamped into one V5 structure, to compare with
+/// [`FsContext::meta_uuid`](crate::FsContext::meta_uuid). `None` if `buf`
+/// has no V5 magic this module knows, or is a superblock. Only meaningful
+/// on V5, as for [`verify_metadata_crc`].
+pub fn metadata_uuid(buf: &[u8]) -> Option<&[u8]> {
+    let off = identify(buf)?.uuid_off?;
+    buf.get(off..off + 16)
+}
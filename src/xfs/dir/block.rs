@@ -1,10 +1,11 @@
-use std::ops::ControlFlow;
+use core::ops::ControlFlow;
 
-use zerocopy::{FromBytes, Immutable, KnownLayout};
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
 use zerocopy::byteorder::big_endian::{U16, U32, U64};
 
 use crate::error::FxfspError;
-use crate::staged::DirEntryInfo;
+use crate::warning::Provenance;
+use crate::xfs::dir::{DirEntryInfo, DirEntryLocation};
 use crate::xfs::superblock::{FormatVersion, FsContext};
 
 /// V4 data block magic: "XD2D"
@@ -16,8 +17,33 @@ const XFS_DIR3_DATA_MAGIC: u32 = 0x58444433;
 /// V5 block format magic: "XDB3"
 const XFS_DIR3_BLOCK_MAGIC: u32 = 0x58444233;
 
+/// V4 free index block magic: "XD2F"
+const XFS_DIR2_FREE_MAGIC: u32 = 0x58443246;
+/// V5 free index block magic: "XDF3"
+const XFS_DIR3_FREE_MAGIC: u32 = 0x58444633;
+/// V4 leaf1 / leafn / da-node magics (16-bit, at offset 8 of the da blkinfo).
+const XFS_DIR2_LEAF1_MAGIC: u16 = 0xd2f1;
+const XFS_DIR2_LEAFN_MAGIC: u16 = 0xd2ff;
+const XFS_DA_NODE_MAGIC: u16 = 0xfebe;
+/// V5 leaf1 / leafn / da-node magics.
+const XFS_DIR3_LEAF1_MAGIC: u16 = 0x3df1;
+const XFS_DIR3_LEAFN_MAGIC: u16 = 0x3dff;
+const XFS_DA3_NODE_MAGIC: u16 = 0x3ebe;
+
+/// What a block found in a directory's extents turned out to be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DirBlockKind {
+    /// Data or single-block directory block; its entries were parsed.
+    Data,
+    /// Leaf, node or free-index block; holds no entries of its own.
+    Index,
+    /// Unrecognized magic; the block was skipped.
+    Unknown,
+}
+
 /// V4 directory data block header.
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsDir2DataHdrV4 {
     pub magic: U32,
@@ -26,7 +52,7 @@ pub struct XfsDir2DataHdrV4 {
 }
 
 /// V5 directory data block header.
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsDir3DataHdr {
     pub magic: U32,
@@ -40,7 +66,7 @@ pub struct XfsDir3DataHdr {
 }
 
 /// Free space entry in directory data block header.
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsDir2DataFree {
     pub offset: U16,
@@ -67,6 +93,32 @@ fn is_data_block_magic(magic: u32, version: FormatVersion) -> bool {
     }
 }
 
+/// Is this a leaf, node or free-index directory block?
+fn is_index_block(buf: &[u8], version: FormatVersion) -> bool {
+    let magic = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+    let da_magic = if buf.len() >= 10 {
+        u16::from_be_bytes([buf[8], buf[9]])
+    } else {
+        0
+    };
+    match version {
+        FormatVersion::V4 => {
+            magic == XFS_DIR2_FREE_MAGIC
+                || matches!(
+                    da_magic,
+                    XFS_DIR2_LEAF1_MAGIC | XFS_DIR2_LEAFN_MAGIC | XFS_DA_NODE_MAGIC
+                )
+        }
+        FormatVersion::V5 => {
+            magic == XFS_DIR3_FREE_MAGIC
+                || matches!(
+                    da_magic,
+                    XFS_DIR3_LEAF1_MAGIC | XFS_DIR3_LEAFN_MAGIC | XFS_DA3_NODE_MAGIC
+                )
+        }
+    }
+}
+
 fn is_block_format(magic: u32) -> bool {
     magic == XFS_DIR2_BLOCK_MAGIC || magic == XFS_DIR3_BLOCK_MAGIC
 }
@@ -91,12 +143,20 @@ fn data_end_offset(buf: &[u8], magic: u32) -> usize {
 }
 
 /// Parse directory data entries from a data block.
+///
+/// `buf` holds one directory block (`ctx.dir_blk_size()` bytes) of the
+/// directory `parent_ino`; each live entry is passed to `callback`, and
+/// breaking from it returns [`FxfspError::Stopped`]. No I/O is done, so
+/// this decodes a single block as well as it does a scan's.
+///
+/// Blocks that are not data blocks are skipped; the returned kind tells the
+/// caller whether that was expected (index blocks) or not.
 pub fn parse_dir_data_block_staged<F>(
     buf: &[u8],
     parent_ino: u64,
     ctx: &FsContext,
     callback: &mut F,
-) -> Result<(), FxfspError>
+) -> Result<DirBlockKind, FxfspError>
 where
     F: FnMut(&DirEntryInfo) -> ControlFlow<()>,
 {
@@ -107,7 +167,11 @@ where
     let magic = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
     if !is_data_block_magic(magic, ctx.version) {
         // Not a data block (could be a leaf/node block or gap filler). Skip.
-        return Ok(());
+        return Ok(if is_index_block(buf, ctx.version) {
+            DirBlockKind::Index
+        } else {
+            DirBlockKind::Unknown
+        });
     }
 
     let hdr_size = data_hdr_size(ctx.version);
@@ -154,6 +218,8 @@ where
             child_ino: inumber,
             name,
             file_type: ftype,
+            provenance: Provenance::Clean,
+            location: Some(DirEntryLocation { block: 0, offset: offset as u32 }),
         };
         if callback(&entry).is_break() {
             return Err(FxfspError::Stopped);
@@ -164,5 +230,5 @@ where
         offset += padded_size;
     }
 
-    Ok(())
+    Ok(DirBlockKind::Data)
 }
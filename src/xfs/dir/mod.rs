@@ -1,2 +1,61 @@
 pub mod block;
 pub mod shortform;
+
+use crate::warning::Provenance;
+
+/// Where a directory entry is stored within its directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DirEntryLocation {
+    /// Logical directory block index (directory blocks, not filesystem
+    /// blocks); 0 for a shortform directory.
+    pub block: u64,
+    /// Byte offset of the entry within the directory block, or within the
+    /// data fork of a shortform directory.
+    pub offset: u32,
+}
+
+/// A directory entry.
+#[non_exhaustive]
+pub struct DirEntryInfo<'a> {
+    pub parent_ino: u64,
+    pub child_ino: u64,
+    pub name: &'a [u8],
+    pub file_type: u8,
+    /// How far the entry can be trusted. Always [`Provenance::Clean`] from
+    /// the block parsers; the staged scanner fills it in.
+    pub provenance: Provenance,
+    /// Where the entry is stored; `None` for the `.` and `..` a shortform
+    /// directory implies. The block parser can't know the block index and
+    /// reports 0; the staged scanner fills it in.
+    pub location: Option<DirEntryLocation>,
+}
+
+impl<'a> DirEntryInfo<'a> {
+    /// Is this the `.` or `..` entry?
+    pub fn is_dot(&self) -> bool {
+        self.name == b"." || self.name == b".."
+    }
+
+    /// A copy of this entry tagged `provenance`.
+    #[cfg(feature = "std")]
+    pub(crate) fn with_provenance(&self, provenance: Provenance) -> Self {
+        Self {
+            parent_ino: self.parent_ino,
+            child_ino: self.child_ino,
+            name: self.name,
+            file_type: self.file_type,
+            provenance,
+            location: self.location,
+        }
+    }
+
+    /// A copy of this entry located in directory block `block`.
+    #[cfg(feature = "std")]
+    pub(crate) fn in_block(&self, block: u64) -> Self {
+        Self {
+            location: self.location.map(|loc| DirEntryLocation { block, ..loc }),
+            ..self.with_provenance(self.provenance)
+        }
+    }
+}
@@ -1,14 +1,15 @@
-use std::ops::ControlFlow;
+use core::ops::ControlFlow;
 
-use zerocopy::{FromBytes, Immutable, KnownLayout};
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
 use zerocopy::byteorder::big_endian::{U32, U64};
 
 use crate::error::FxfspError;
-use crate::staged::DirEntryInfo;
+use crate::warning::Provenance;
+use crate::xfs::dir::{DirEntryInfo, DirEntryLocation};
 use crate::xfs::superblock::FsContext;
 
 /// Shortform directory header (when parent inode fits in 4 bytes).
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsDirSfHdr4 {
     pub count: u8,
@@ -17,7 +18,7 @@ pub struct XfsDirSfHdr4 {
 }
 
 /// Shortform directory header (when parent inode needs 8 bytes).
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsDirSfHdr8 {
     pub count: u8,
@@ -26,6 +27,10 @@ pub struct XfsDirSfHdr8 {
 }
 
 /// Parse a shortform directory from the inode's data fork.
+///
+/// `fork_buf` is the fork of directory `parent_ino` (`di_size` bytes from
+/// the inode's data fork offset). `.` and `..` come first, then each entry
+/// in fork order; breaking from `callback` returns [`FxfspError::Stopped`].
 pub fn parse_shortform_dir_staged<F>(
     fork_buf: &[u8],
     parent_ino: u64,
@@ -60,6 +65,8 @@ where
         child_ino: parent_ino,
         name: b".",
         file_type: 0,
+        provenance: Provenance::Clean,
+        location: None,
     };
     if callback(&dot).is_break() {
         return Err(FxfspError::Stopped);
@@ -71,6 +78,8 @@ where
         child_ino: hdr_parent_ino,
         name: b"..",
         file_type: 0,
+        provenance: Provenance::Clean,
+        location: None,
     };
     if callback(&dotdot).is_break() {
         return Err(FxfspError::Stopped);
@@ -119,6 +128,8 @@ where
             child_ino,
             name,
             file_type: ftype,
+            provenance: Provenance::Clean,
+            location: Some(DirEntryLocation { block: 0, offset: offset as u32 }),
         };
         if callback(&entry).is_break() {
             return Err(FxfspError::Stopped);
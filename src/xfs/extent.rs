@@ -1,4 +1,6 @@
-use zerocopy::{FromBytes, Immutable, KnownLayout};
+use alloc::vec::Vec;
+
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
 use zerocopy::byteorder::big_endian::U64;
 
 use crate::error::FxfspError;
@@ -11,7 +13,7 @@ use crate::xfs::superblock::FsContext;
 /// - Bits 126..73:   logical file offset (54 bits)
 /// - Bits 72..21:    absolute filesystem block number (52 bits)
 /// - Bits 20..0:     block count (21 bits)
-#[derive(FromBytes, KnownLayout, Immutable, Clone, Copy)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned, Clone, Copy)]
 #[repr(C)]
 pub struct XfsBmbtRec {
     pub l0: U64,
@@ -20,6 +22,7 @@ pub struct XfsBmbtRec {
 
 /// Unpacked extent with decomposed AG information.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Extent {
     pub logical_offset: u64,
     pub ag_number: u32,
@@ -58,7 +61,7 @@ pub fn parse_extent_list(
     nextents: u32,
     ctx: &FsContext,
 ) -> Result<Vec<Extent>, FxfspError> {
-    let rec_size = std::mem::size_of::<XfsBmbtRec>();
+    let rec_size = core::mem::size_of::<XfsBmbtRec>();
     let mut extents = Vec::with_capacity(nextents as usize);
 
     for i in 0..nextents as usize {
@@ -80,6 +83,31 @@ impl Extent {
     pub fn start_byte(&self, ctx: &FsContext) -> u64 {
         ctx.ag_block_to_byte(self.ag_number, self.ag_block)
     }
+
+    /// Logical block just past the end of this extent.
+    ///
+    /// Cannot overflow: the offset is 54 bits and the count 21.
+    pub fn logical_end(&self) -> u64 {
+        self.logical_offset + self.block_count
+    }
+
+    /// File byte range covered by this extent, or `None` if a corrupt record
+    /// puts it beyond `u64` (a 54-bit block offset shifted by the block size
+    /// can need up to 70 bits).
+    pub fn logical_byte_range(&self, ctx: &FsContext) -> Option<core::ops::Range<u64>> {
+        let to_bytes = |block: u64| {
+            (block.leading_zeros() >= ctx.block_log as u32).then(|| block << ctx.block_log)
+        };
+        Some(to_bytes(self.logical_offset)?..to_bytes(self.logical_end())?)
+    }
+
+    /// Does this extent start on a stripe unit boundary?
+    ///
+    /// Always true when the filesystem has no stripe geometry. The allocator
+    /// aligns AG-relative block numbers, so that is what is checked here.
+    pub fn is_stripe_aligned(&self, ctx: &FsContext) -> bool {
+        ctx.stripe_unit == 0 || self.ag_block.is_multiple_of(ctx.stripe_unit)
+    }
 }
 
 /// Convert an absolute filesystem block number to a byte offset on disk.
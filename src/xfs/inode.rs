@@ -1,7 +1,9 @@
-use zerocopy::{FromBytes, Immutable, KnownLayout};
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
 use zerocopy::byteorder::big_endian::{U16, U32, U64};
+use zerocopy::byteorder::little_endian;
 
 use crate::error::FxfspError;
+use crate::xfs::types::NULLAGINO;
 
 /// Inode magic: "IN"
 const XFS_DINODE_MAGIC: u16 = 0x494e;
@@ -13,15 +15,101 @@ pub const XFS_DINODE_FMT_EXTENTS: u8 = 2;
 pub const XFS_DINODE_FMT_BTREE: u8 = 3;
 pub const XFS_DINODE_FMT_UUID: u8 = 4;
 
+/// Inode flag (`di_flags`): exclude this file from dumps/backups.
+pub const XFS_DIFLAG_NODUMP: u16 = 0x0080;
+
+bitflags::bitflags! {
+    /// Inode flags (`di_flags`, `XFS_DIFLAG_*`), as `xfs_io -c lsattr`
+    /// shows them. Bits this type does not name are kept.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+    pub struct InodeFlags: u16 {
+        /// Data lives on the realtime device.
+        const REALTIME = 0x0001;
+        /// Has preallocated space past EOF.
+        const PREALLOC = 0x0002;
+        const NEWRTBM = 0x0004;
+        /// `chattr +i`.
+        const IMMUTABLE = 0x0008;
+        /// `chattr +a`.
+        const APPEND = 0x0010;
+        const SYNC = 0x0020;
+        const NOATIME = 0x0040;
+        /// `chattr +d`: exclude from dumps and backups.
+        const NODUMP = XFS_DIFLAG_NODUMP;
+        const RTINHERIT = 0x0100;
+        const PROJINHERIT = 0x0200;
+        const NOSYMLINKS = 0x0400;
+        /// `di_extsize` is an extent size hint.
+        const EXTSIZE = 0x0800;
+        const EXTSZINHERIT = 0x1000;
+        const NODEFRAG = 0x2000;
+        const FILESTREAM = 0x4000;
+    }
+}
+
+bitflags::bitflags! {
+    /// V5 inode flags (`di_flags2`, `XFS_DIFLAG2_*`); empty on V4. Bits
+    /// this type does not name are kept.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+    pub struct InodeFlags2: u64 {
+        /// Use DAX for this file.
+        const DAX = 1 << 0;
+        /// May share blocks with other files.
+        const REFLINK = 1 << 1;
+        /// `di_cowextsize` is a copy-on-write extent size hint.
+        const COWEXTSIZE = 1 << 2;
+        /// Timestamps use the bigtime encoding.
+        const BIGTIME = 1 << 3;
+        /// Extent counters are 64-bit.
+        const NREXT64 = 1 << 4;
+        /// Filesystem metadata, under the metadata directory tree.
+        const METADATA = 1 << 5;
+    }
+}
+
+/// What an internal inode (one holding filesystem metadata rather than a
+/// user's file) is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InternalInode {
+    /// Realtime bitmap (`sb_rbmino`).
+    RealtimeBitmap,
+    /// Realtime summary (`sb_rsumino`).
+    RealtimeSummary,
+    /// User quotas (`sb_uquotino`).
+    UserQuota,
+    /// Group quotas (`sb_gquotino`), or on V4 project quotas when those are
+    /// enabled instead.
+    GroupQuota,
+    /// Project quotas (`sb_pquotino`, V5).
+    ProjectQuota,
+    /// Root of the metadata directory tree (`sb_metadirino`).
+    MetadataDirectory,
+    /// Any other inode of the metadata directory tree: flagged
+    /// [`InodeFlags2::METADATA`].
+    Metadata,
+}
+
 /// S_IFMT mask.
 pub const S_IFMT: u16 = 0o170000;
 pub const S_IFDIR: u16 = 0o040000;
 pub const S_IFREG: u16 = 0o100000;
 pub const S_IFLNK: u16 = 0o120000;
+pub const S_IFCHR: u16 = 0o020000;
+pub const S_IFBLK: u16 = 0o060000;
+pub const S_IFIFO: u16 = 0o010000;
+pub const S_IFSOCK: u16 = 0o140000;
+/// Permission bits.
+pub const S_ISUID: u16 = 0o4000;
+pub const S_ISGID: u16 = 0o2000;
+pub const S_ISVTX: u16 = 0o1000;
+pub const S_IWOTH: u16 = 0o0002;
 
 /// On-disk XFS dinode core (V4 layout). V5 extends this.
 /// The V4 core is 96 bytes; V5 core is 176 bytes.
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsDinodeCore {
     pub di_magic: U16,
@@ -52,8 +140,26 @@ pub struct XfsDinodeCore {
     pub di_gen: U32,
 }
 
+/// On-disk fields following the dinode core on V5 (v3 inode) filesystems,
+/// starting at byte offset 96.
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+pub struct XfsDinodeV3 {
+    pub di_next_unlinked: U32,
+    /// CRC32c of the inode, stored little-endian.
+    pub di_crc: little_endian::U32,
+    pub di_changecount: U64,
+    pub di_lsn: U64,
+    pub di_flags2: U64,
+    pub di_cowextsize: U32,
+    pub di_pad2: [u8; 12],
+    pub di_crtime: XfsTimestamp,
+    pub di_ino: U64,
+    pub di_uuid: [u8; 16],
+}
+
 /// On-disk XFS timestamp.
-#[derive(FromBytes, KnownLayout, Immutable)]
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsTimestamp {
     pub t_sec: U32,
@@ -63,10 +169,16 @@ pub struct XfsTimestamp {
 /// Size of the V4 dinode core.
 pub const V4_CORE_SIZE: usize = 96;
 
+/// Start of the data fork in a V4 (v1/v2) inode: the core plus
+/// `di_next_unlinked`.
+pub const V4_LITERAL_OFFSET: usize = V4_CORE_SIZE + 4;
+
 /// Size of the V5 dinode core.
 pub const V5_CORE_SIZE: usize = 176;
 
 /// Parsed inode information.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct InodeInfo {
     pub ino: u64,
     pub mode: u16,
@@ -74,6 +186,9 @@ pub struct InodeInfo {
     pub size: u64,
     pub uid: u32,
     pub gid: u32,
+    /// Project ID, `di_projid_hi` above `di_projid` (the high half is 0
+    /// unless the filesystem has `projid32bit`).
+    pub projid: u32,
     pub nlink: u32,
     pub nextents: u32,
     pub mtime_sec: u32,
@@ -83,10 +198,38 @@ pub struct InodeInfo {
     pub ctime_sec: u32,
     pub ctime_nsec: u32,
     pub nblocks: u64,
+    /// Extent size hint in filesystem blocks (`di_extsize`), 0 if unset.
+    pub extsize: u32,
+    /// Copy-on-write extent size hint in blocks (`di_cowextsize`, V5 only).
+    pub cowextsize: u32,
+    /// Inode flags (`di_flags`, `XFS_DIFLAG_*`).
+    pub flags: u16,
+    /// V5 inode flags (`di_flags2`, `XFS_DIFLAG2_*`), 0 on V4.
+    pub flags2: u64,
+    /// DMAPI event mask (`di_dmevmask`).
+    pub dmevmask: u32,
+    /// DMAPI state (`di_dmstate`).
+    pub dmstate: u16,
+    /// Inode generation number (`di_gen`).
+    pub generation: u32,
     /// Byte offset of the data fork within the on-disk inode.
     pub data_fork_offset: usize,
     /// Size of the data fork in bytes (up to attr fork or end of inode).
     pub data_fork_size: usize,
+    /// Encoded device number for `XFS_DINODE_FMT_DEV` inodes (the first
+    /// 4 bytes of the data fork), `None` for other formats.
+    pub rdev: Option<u32>,
+    /// Attribute fork format (`di_aformat`).
+    pub aformat: u8,
+    /// Number of attribute fork extents.
+    pub anextents: u32,
+    /// Byte offset of the attr fork within the on-disk inode, 0 if absent.
+    pub attr_fork_offset: usize,
+    /// Size of the attr fork in bytes, 0 if absent.
+    pub attr_fork_size: usize,
+    /// Next inode (AG-relative) on the AGI unlinked bucket chain
+    /// (`di_next_unlinked`), `NULLAGINO` at the end or when not on a chain.
+    pub next_unlinked: u32,
 }
 
 impl InodeInfo {
@@ -101,6 +244,22 @@ impl InodeInfo {
     pub fn is_symlink(&self) -> bool {
         (self.mode & S_IFMT) == S_IFLNK
     }
+
+    pub fn is_device(&self) -> bool {
+        matches!(self.mode & S_IFMT, S_IFCHR | S_IFBLK)
+    }
+
+    pub fn has_attr_fork(&self) -> bool {
+        self.attr_fork_size > 0
+    }
+}
+
+/// Split an on-disk device number into `(major, minor)`.
+///
+/// XFS stores `dev_t` in the old System V layout: a 14-bit major above an
+/// 18-bit minor (`sysv_encode_dev` in the kernel).
+pub fn decode_rdev(rdev: u32) -> (u32, u32) {
+    ((rdev >> 18) & 0x3fff, rdev & 0x3ffff)
 }
 
 /// Parse a dinode core from `buf` starting at byte 0.
@@ -123,7 +282,18 @@ pub fn parse_inode_core(
         return Err(FxfspError::BadMagic("dinode"));
     }
 
-    let data_fork_offset = if is_v5 { V5_CORE_SIZE } else { V4_CORE_SIZE };
+    let data_fork_offset = if is_v5 { V5_CORE_SIZE } else { V4_LITERAL_OFFSET };
+
+    let v3 = if is_v5 {
+        let ext = buf
+            .get(V4_CORE_SIZE..)
+            .and_then(|tail| XfsDinodeV3::ref_from_prefix(tail).ok())
+            .ok_or(FxfspError::Parse("buffer too small for v3 dinode"))?
+            .0;
+        Some(ext)
+    } else {
+        None
+    };
 
     // Data fork size: if di_forkoff > 0, the attr fork starts at
     // data_fork_offset + di_forkoff*8. Otherwise the data fork extends
@@ -134,18 +304,39 @@ pub fn parse_inode_core(
         inode_size as usize - data_fork_offset
     };
 
-    // With NREXT64, di_nextents (offset 76) is zeroed; the actual data fork
-    // extent count is stored as the lower 48 bits of a U64 at inode byte
-    // offset 24 (overlapping the old di_pad + di_flushiter fields).
-    let nextents = if has_nrext64 {
-        if buf.len() < 32 {
-            return Err(FxfspError::Parse("buffer too small for nrext64 extent count"));
-        }
-        let big = u64::from_be_bytes(buf[24..32].try_into().unwrap());
-        // Lower 48 bits = data fork extent count.
-        (big & 0x0000_FFFF_FFFF_FFFF) as u32
+    // Attr fork: present when di_forkoff > 0, running from the end of the
+    // data fork to the end of the inode.
+    let (attr_fork_offset, attr_fork_size) = if core.di_forkoff > 0 {
+        let offset = data_fork_offset + data_fork_size;
+        (offset, (inode_size as usize).saturating_sub(offset))
+    } else {
+        (0, 0)
+    };
+
+    // With NREXT64, the data fork extent count is stored as the lower 48 bits
+    // of a U64 at inode byte offset 24 (overlapping the old di_pad +
+    // di_flushiter fields), and the 32-bit slot at offset 76 that used to
+    // hold di_nextents holds the attr fork extent count instead.
+    let (nextents, anextents) = if has_nrext64 {
+        // Lower 48 bits of the U64 = the last four pad bytes and
+        // di_flushiter; a u32 count only needs the low 32 of those.
+        let pad = core.di_pad;
+        let big = u32::from_be_bytes([pad[4], pad[5], 0, 0]) | core.di_flushiter.get() as u32;
+        (big, core.di_nextents.get())
+    } else {
+        (core.di_nextents.get(), core.di_anextents.get() as u32)
+    };
+
+    let rdev = if core.di_format == XFS_DINODE_FMT_DEV {
+        Some(read_be_u32(buf, data_fork_offset).ok_or(FxfspError::Parse("buffer too small for device number"))?)
     } else {
-        core.di_nextents.get()
+        None
+    };
+
+    // di_next_unlinked immediately follows the V4 core on both versions.
+    let next_unlinked = match v3 {
+        Some(v3) => v3.di_next_unlinked.get(),
+        None => read_be_u32(buf, V4_CORE_SIZE).unwrap_or(NULLAGINO),
     };
 
     Ok(InodeInfo {
@@ -155,6 +346,7 @@ pub fn parse_inode_core(
         size: core.di_size.get(),
         uid: core.di_uid.get(),
         gid: core.di_gid.get(),
+        projid: (core.di_projid_hi.get() as u32) << 16 | core.di_projid.get() as u32,
         nlink: core.di_nlink.get(),
         nextents,
         mtime_sec: core.di_mtime.t_sec.get(),
@@ -164,7 +356,25 @@ pub fn parse_inode_core(
         ctime_sec: core.di_ctime.t_sec.get(),
         ctime_nsec: core.di_ctime.t_nsec.get(),
         nblocks: core.di_nblocks.get(),
+        extsize: core.di_extsize.get(),
+        cowextsize: v3.map_or(0, |v3| v3.di_cowextsize.get()),
+        flags: core.di_flags.get(),
+        flags2: v3.map_or(0, |v3| v3.di_flags2.get()),
+        dmevmask: core.di_dmevmask.get(),
+        dmstate: core.di_dmstate.get(),
+        generation: core.di_gen.get(),
         data_fork_offset,
         data_fork_size,
+        rdev,
+        aformat: core.di_aformat,
+        anextents,
+        attr_fork_offset,
+        attr_fork_size,
+        next_unlinked,
     })
 }
+
+fn read_be_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    let (value, _) = U32::ref_from_prefix(buf.get(offset..)?).ok()?;
+    Some(value.get())
+}
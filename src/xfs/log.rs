@@ -0,0 +1,469 @@
+//! The on-disk log.
+//!
+//! The log is a ring of records, each one or more header sectors followed
+//! by `h_len` bytes of operations. A record's LSN is its cycle (one per
+//! pass around the ring) and its starting log block (512-byte units). The
+//! first word of every data sector is overwritten with the cycle number
+//! when written, and saved in the header to be put back on read.
+//!
+//! Operations carry pieces of transactions: a start, a transaction header,
+//! one item per logged object (each one or more regions), then a commit.
+//! An item or region may continue in the next record. Items are written in
+//! the byte order of the host that wrote them; the transaction header magic
+//! tells which.
+//!
+//! Changes committed between the tail and the head may not have reached
+//! their home blocks yet: a mount or `xfs_repair` replays them.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use zerocopy::byteorder::big_endian::{U32, U64};
+use zerocopy::byteorder::little_endian;
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
+
+use crate::error::FxfspError;
+
+/// Log record header magic.
+const XLOG_HEADER_MAGIC: u32 = 0xfeed_babe;
+
+/// Log block size: LSNs, `h_len` and buffer addresses count these.
+pub const BBSIZE: usize = 512;
+
+/// Record bytes covered by one header sector's cycle data.
+const XLOG_HEADER_CYCLE_SIZE: usize = 32 * 1024;
+
+/// Cycle data words per header sector.
+const XLOG_CYCLE_DATA_WORDS: usize = XLOG_HEADER_CYCLE_SIZE / BBSIZE;
+
+/// Offset of the cycle data in an extended header sector, after `xh_cycle`.
+const XLOG_EXT_CYCLE_DATA_OFF: usize = 4;
+
+/// Operation header size (`xlog_op_header`).
+const XLOG_OP_HEADER_SIZE: usize = 12;
+
+/// Operation flags (`oh_flags`).
+pub const XLOG_START_TRANS: u8 = 0x01;
+pub const XLOG_COMMIT_TRANS: u8 = 0x02;
+pub const XLOG_CONTINUE_TRANS: u8 = 0x04;
+pub const XLOG_WAS_CONT_TRANS: u8 = 0x08;
+pub const XLOG_END_TRANS: u8 = 0x10;
+pub const XLOG_UNMOUNT_TRANS: u8 = 0x20;
+
+/// Transaction header magic: "TRAN", in the writer's byte order.
+const XFS_TRANS_HEADER_MAGIC: u32 = 0x5452_414e;
+
+/// Log item types (`xfs_log_item.li_type`) decoded into [`LogItem`]s.
+const XFS_LI_INODE: u16 = 0x123b;
+const XFS_LI_BUF: u16 = 0x123c;
+
+/// Size of `xfs_inode_log_format_32`, written by 32-bit hosts without the
+/// padding that moves `ilf_ino` to offset 16.
+const XFS_INODE_LOG_FORMAT_32_SIZE: usize = 52;
+
+/// Log record header sector (`xlog_rec_header`, 324 bytes used).
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+pub struct XlogRecHeader {
+    pub h_magicno: U32,
+    pub h_cycle: U32,
+    pub h_version: U32,
+    /// Bytes of operations after the header sectors.
+    pub h_len: U32,
+    pub h_lsn: U64,
+    pub h_tail_lsn: U64,
+    /// Unlike metadata CRCs, stored little-endian.
+    pub h_crc: little_endian::U32,
+    pub h_prev_block: U32,
+    pub h_num_logops: U32,
+    /// The first word of each of the first 64 data sectors.
+    pub h_cycle_data: [U32; XLOG_CYCLE_DATA_WORDS],
+    pub h_fmt: U32,
+    pub h_fs_uuid: [u8; 16],
+    /// Size of the in-core log buffer the record was written from.
+    pub h_size: U32,
+}
+
+/// Cycle of an LSN.
+pub fn lsn_cycle(lsn: u64) -> u32 {
+    (lsn >> 32) as u32
+}
+
+/// Log block of an LSN.
+pub fn lsn_block(lsn: u64) -> u32 {
+    lsn as u32
+}
+
+/// A log record header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogRecordHeader {
+    pub cycle: u32,
+    /// Log format version: 1, or 2 for logs with a stripe unit or
+    /// in-core buffers over 32 KiB.
+    pub version: u32,
+    /// Bytes of operations after the header sectors.
+    pub len: u32,
+    pub lsn: u64,
+    /// Oldest LSN recovery still needed when the record was written.
+    pub tail_lsn: u64,
+    pub num_logops: u32,
+    /// In-core log buffer size, which sets the number of header sectors.
+    pub size: u32,
+}
+
+impl LogRecordHeader {
+    /// Header sectors: one, plus one extended header per further 32 KiB of
+    /// buffer on version 2 logs.
+    pub fn header_blocks(&self) -> u32 {
+        if self.version == 2 && self.size as usize > XLOG_HEADER_CYCLE_SIZE {
+            (self.size as usize).div_ceil(XLOG_HEADER_CYCLE_SIZE) as u32
+        } else {
+            1
+        }
+    }
+
+    /// Header and data sectors together.
+    pub fn total_blocks(&self) -> u32 {
+        self.header_blocks() + self.len.div_ceil(BBSIZE as u32)
+    }
+}
+
+/// Parse the header sector of a log record.
+pub fn parse_log_record_header(buf: &[u8]) -> Result<LogRecordHeader, FxfspError> {
+    let (hdr, _) =
+        XlogRecHeader::ref_from_prefix(buf).map_err(|_| FxfspError::Parse("buffer too small for log record header"))?;
+    if hdr.h_magicno.get() != XLOG_HEADER_MAGIC {
+        return Err(FxfspError::BadMagic("log record header"));
+    }
+    Ok(LogRecordHeader {
+        cycle: hdr.h_cycle.get(),
+        version: hdr.h_version.get(),
+        len: hdr.h_len.get(),
+        lsn: hdr.h_lsn.get(),
+        tail_lsn: hdr.h_tail_lsn.get(),
+        num_logops: hdr.h_num_logops.get(),
+        size: hdr.h_size.get(),
+    })
+}
+
+/// Put back the first word of each data sector of a record from the copies
+/// in its header sectors.
+///
+/// `record` is the whole record, [`total_blocks`](LogRecordHeader::total_blocks)
+/// sectors from its first header sector; the data after the headers is
+/// restored in place. A data sector not stamped with the record's cycle
+/// means the record was torn by a crash mid-write, and is a parse error.
+pub fn unstamp_log_record(hdr: &LogRecordHeader, record: &mut [u8]) -> Result<(), FxfspError> {
+    let header_len = hdr.header_blocks() as usize * BBSIZE;
+    let data_len = hdr.len.div_ceil(BBSIZE as u32) as usize * BBSIZE;
+    if record.len() < header_len + data_len {
+        return Err(FxfspError::Parse("log record out of bounds"));
+    }
+    let (headers, data) = record.split_at_mut(header_len);
+    for (i, sector) in data[..data_len].chunks_exact_mut(BBSIZE).enumerate() {
+        if sector[..4] != hdr.cycle.to_be_bytes() {
+            return Err(FxfspError::Parse("torn log record"));
+        }
+        let (header, word) = (i / XLOG_CYCLE_DATA_WORDS, i % XLOG_CYCLE_DATA_WORDS);
+        let at = match header {
+            0 => core::mem::offset_of!(XlogRecHeader, h_cycle_data),
+            n => n * BBSIZE + XLOG_EXT_CYCLE_DATA_OFF,
+        } + word * 4;
+        sector[..4].copy_from_slice(&headers[at..at + 4]);
+    }
+    Ok(())
+}
+
+/// One operation of a log record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogOp {
+    /// Transaction (or checkpoint) the operation belongs to.
+    pub tid: u32,
+    pub client_id: u8,
+    /// `XLOG_*_TRANS` bits.
+    pub flags: u8,
+    /// Payload offset within the record's data.
+    pub offset: usize,
+    pub len: usize,
+}
+
+/// List the operations in a record's (unstamped) data.
+pub fn parse_log_ops(data: &[u8], num_ops: u32) -> Result<Vec<LogOp>, FxfspError> {
+    let mut ops = Vec::new();
+    let mut at = 0;
+    for _ in 0..num_ops {
+        let head = data
+            .get(at..at + XLOG_OP_HEADER_SIZE)
+            .ok_or(FxfspError::Parse("log operation header out of bounds"))?;
+        let offset = at + XLOG_OP_HEADER_SIZE;
+        let len = u32::from_be_bytes(head[4..8].try_into().unwrap()) as usize;
+        if data.len() - offset < len {
+            return Err(FxfspError::Parse("log operation out of bounds"));
+        }
+        ops.push(LogOp {
+            tid: u32::from_be_bytes(head[0..4].try_into().unwrap()),
+            client_id: head[8],
+            flags: head[9],
+            offset,
+            len,
+        });
+        at = offset + len;
+    }
+    Ok(ops)
+}
+
+/// An object a transaction logs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LogItem {
+    /// Changes to an inode's core, forks or attributes.
+    Inode { ino: u64 },
+    /// Changes to a metadata buffer: `len` 512-byte sectors at sector
+    /// `daddr` of the data device.
+    Buffer { daddr: u64, len: u32 },
+    /// Any other item (intents, quota, ...), by `XFS_LI_*` type.
+    Other { kind: u16 },
+}
+
+/// A transaction found in the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogTransaction {
+    /// Transaction ID; with delayed logging, one per checkpoint of many
+    /// transactions.
+    pub tid: u32,
+    /// `th_type`.
+    pub kind: u32,
+    /// LSN of the record holding its start.
+    pub start_lsn: u64,
+    /// LSN of the record holding its commit, `None` if the log ends first:
+    /// recovery discards such a transaction.
+    pub commit_lsn: Option<u64>,
+    pub items: Vec<LogItem>,
+    /// The writer's byte order, which item regions other than buffer
+    /// contents are in.
+    pub little_endian: bool,
+    /// Each item's regions as logged, the format region first, when
+    /// assembled by [`LogTransactions::with_regions`]; empty otherwise.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub regions: Vec<Vec<Vec<u8>>>,
+}
+
+/// A transaction being put together.
+#[derive(Debug)]
+struct OpenTransaction {
+    tx: LogTransaction,
+    /// Items are little-endian, known from the transaction header.
+    little_endian: Option<bool>,
+    /// Regions of the current item: all of them if keeping regions, else
+    /// just the first, which says what the item is.
+    item: Vec<Vec<u8>>,
+    /// Regions of the current item seen so far.
+    regions: usize,
+    keep_regions: bool,
+}
+
+impl OpenTransaction {
+    fn new(tid: u32, start_lsn: u64, keep_regions: bool) -> Self {
+        let tx = LogTransaction {
+            tid,
+            kind: 0,
+            start_lsn,
+            commit_lsn: None,
+            items: Vec::new(),
+            little_endian: false,
+            regions: Vec::new(),
+        };
+        Self { tx, little_endian: None, item: Vec::new(), regions: 0, keep_regions }
+    }
+
+    fn read_u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.item.first()?.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian? { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn read_u64(&self, at: usize) -> Option<u64> {
+        let bytes = self.item.first()?.get(at..at + 8)?.try_into().ok()?;
+        Some(if self.little_endian? { u64::from_le_bytes(bytes) } else { u64::from_be_bytes(bytes) })
+    }
+
+    /// Add a region, or the rest of the last one, cut short by the end of
+    /// the previous record.
+    fn add_region(&mut self, payload: &[u8], rest_of_last: bool) {
+        if self.little_endian.is_none() {
+            // The transaction header.
+            let Some(magic) = payload.get(..4) else { return };
+            let magic: [u8; 4] = magic.try_into().unwrap();
+            self.little_endian = match XFS_TRANS_HEADER_MAGIC {
+                m if m == u32::from_le_bytes(magic) => Some(true),
+                m if m == u32::from_be_bytes(magic) => Some(false),
+                _ => return,
+            };
+            self.tx.little_endian = self.little_endian == Some(true);
+            if let Some(kind) = payload.get(4..8) {
+                let kind = kind.try_into().unwrap();
+                self.tx.kind = if self.little_endian == Some(true) {
+                    u32::from_le_bytes(kind)
+                } else {
+                    u32::from_be_bytes(kind)
+                };
+            }
+            return;
+        }
+        if rest_of_last {
+            if let Some(last) = self.item.last_mut()
+                && (self.keep_regions || self.regions == 1)
+            {
+                last.extend_from_slice(payload);
+            }
+            return;
+        }
+        let total = self.read_u16(2).map_or(1, usize::from);
+        if self.regions == 0 || self.regions >= total {
+            self.end_item();
+        }
+        if self.regions == 0 || self.keep_regions {
+            self.item.push(payload.to_vec());
+        }
+        self.regions += 1;
+    }
+
+    /// Decode the current item, if any, into the transaction.
+    fn end_item(&mut self) {
+        if self.regions == 0 {
+            return;
+        }
+        let item = match self.read_u16(0) {
+            Some(XFS_LI_INODE) => {
+                let at = if self.item[0].len() == XFS_INODE_LOG_FORMAT_32_SIZE { 12 } else { 16 };
+                self.read_u64(at).map(|ino| LogItem::Inode { ino })
+            }
+            Some(XFS_LI_BUF) => self.read_u64(8).zip(self.read_u16(6)).map(|(daddr, len)| LogItem::Buffer {
+                daddr,
+                len: len.into(),
+            }),
+            Some(kind) => Some(LogItem::Other { kind }),
+            None => None,
+        };
+        if let Some(item) = item {
+            self.tx.items.push(item);
+            if self.keep_regions {
+                self.tx.regions.push(core::mem::take(&mut self.item));
+            }
+        }
+        self.item.clear();
+        self.regions = 0;
+    }
+}
+
+/// Puts the operations of consecutive log records back together into
+/// [`LogTransaction`]s.
+///
+/// Feed records in LSN order from the tail. Operations of transactions
+/// that started before the first record are ignored.
+#[derive(Debug, Default)]
+pub struct LogTransactions {
+    open: BTreeMap<u32, OpenTransaction>,
+    done: Vec<LogTransaction>,
+    keep_regions: bool,
+}
+
+impl LogTransactions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also keep every item's regions, as needed to replay them. They can
+    /// take as much memory as the log.
+    pub fn with_regions() -> Self {
+        Self { keep_regions: true, ..Self::default() }
+    }
+
+    /// Add the operations of the record at `lsn`; `data` is its unstamped
+    /// data and `ops` its [`parse_log_ops`] result.
+    pub fn add_record(&mut self, lsn: u64, data: &[u8], ops: &[LogOp]) {
+        for op in ops {
+            let payload = &data[op.offset..op.offset + op.len];
+            if op.flags & XLOG_START_TRANS != 0 {
+                self.open.insert(op.tid, OpenTransaction::new(op.tid, lsn, self.keep_regions));
+                continue;
+            }
+            let Some(open) = self.open.get_mut(&op.tid) else { continue };
+            if op.flags & XLOG_COMMIT_TRANS != 0 {
+                let mut open = self.open.remove(&op.tid).unwrap();
+                open.end_item();
+                open.tx.commit_lsn = Some(lsn);
+                self.done.push(open.tx);
+                continue;
+            }
+            if op.flags & XLOG_UNMOUNT_TRANS != 0 {
+                continue;
+            }
+            open.add_region(payload, op.flags & XLOG_WAS_CONT_TRANS != 0);
+        }
+    }
+
+    /// Finish and return the transactions, committed or not, by start LSN.
+    pub fn finish(self) -> Vec<LogTransaction> {
+        let mut transactions = self.done;
+        transactions.extend(self.open.into_values().map(|mut open| {
+            open.end_item();
+            open.tx
+        }));
+        transactions.sort_by_key(|tx| tx.start_lsn);
+        transactions
+    }
+}
+
+/// What the log holds between its tail and head.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogSummary {
+    /// Oldest LSN recovery would start from.
+    pub tail_lsn: u64,
+    /// LSN of the newest intact record.
+    pub head_lsn: u64,
+    /// The log ends with an unmount record: there is nothing to replay.
+    pub clean: bool,
+    /// Records from tail to head, or to the first torn one before it.
+    pub records: Vec<LogRecordHeader>,
+    /// Transactions started in those records, by start LSN.
+    pub transactions: Vec<LogTransaction>,
+}
+
+impl LogSummary {
+    /// Inodes changed by committed transactions, sorted and deduplicated:
+    /// a scan may show them as they were before those changes.
+    pub fn pending_inodes(&self) -> Vec<u64> {
+        let mut inodes: Vec<u64> = self
+            .committed_items()
+            .filter_map(|item| match item {
+                LogItem::Inode { ino } => Some(ino),
+                _ => None,
+            })
+            .collect();
+        inodes.sort_unstable();
+        inodes.dedup();
+        inodes
+    }
+
+    /// Metadata buffers changed by committed transactions, as `(daddr,
+    /// len)` in 512-byte sectors, sorted and deduplicated.
+    pub fn pending_buffers(&self) -> Vec<(u64, u32)> {
+        let mut buffers: Vec<(u64, u32)> = self
+            .committed_items()
+            .filter_map(|item| match item {
+                LogItem::Buffer { daddr, len } => Some((daddr, len)),
+                _ => None,
+            })
+            .collect();
+        buffers.sort_unstable();
+        buffers.dedup();
+        buffers
+    }
+
+    fn committed_items(&self) -> impl Iterator<Item = LogItem> + '_ {
+        self.transactions.iter().filter(|tx| tx.commit_lsn.is_some()).flat_map(|tx| tx.items.iter().copied())
+    }
+}
@@ -1,8 +1,44 @@
+//! On-disk XFS structures and parsers.
+//!
+//! Every on-disk struct derives `Unaligned`: fields are byte-order wrappers
+//! with alignment 1, so `ref_from_prefix` succeeds at any buffer offset
+//! (AGI within a sector, inode within a chunk, FFI or wasm buffers). A
+//! field that would reintroduce an alignment requirement fails to compile.
+//!
+//! The parsers take plain byte slices and do no I/O, so a single block can
+//! be decoded without running a scan:
+//!
+//! - superblock: [`FsContext::from_superblock`](superblock::FsContext::from_superblock)
+//! - AG headers: [`AgfInfo::from_buf`](ag::AgfInfo::from_buf),
+//!   [`AgiInfo::from_buf`](ag::AgiInfo::from_buf), [`parse_agfl`](ag::parse_agfl)
+//! - inodes: [`parse_inode_core`](inode::parse_inode_core),
+//!   [`parse_extent_list`](extent::parse_extent_list)
+//! - btrees: [`parse_inobt_block`](btree::parse_inobt_block),
+//!   [`parse_bmbt_block`](bmbt::parse_bmbt_block), [`parse_bmbt_root`](bmbt::parse_bmbt_root),
+//!   [`parse_alloc_block`](alloc::parse_alloc_block), [`parse_rmap_block`](rmap::parse_rmap_block),
+//!   [`parse_refcount_block`](refcount::parse_refcount_block)
+//! - directories: [`parse_dir_data_block_staged`](dir::block::parse_dir_data_block_staged),
+//!   [`parse_shortform_dir_staged`](dir::shortform::parse_shortform_dir_staged)
+//! - symlinks: [`parse_local_symlink`](symlink::parse_local_symlink),
+//!   [`parse_remote_symlink_block`](symlink::parse_remote_symlink_block)
+//! - extended attributes: [`parse_shortform_attrs`](attr::parse_shortform_attrs),
+//!   [`parse_attr_block`](attr::parse_attr_block), [`parse_attr_fork_blocks`](attr::parse_attr_fork_blocks)
+//! - log: [`parse_log_record_header`](log::parse_log_record_header),
+//!   [`parse_log_ops`](log::parse_log_ops), [`LogTransactions`](log::LogTransactions)
+
 pub mod ag;
+pub mod alloc;
+pub mod attr;
 pub mod bmbt;
 pub mod btree;
+#[cfg(feature = "std")]
+pub mod crc;
 pub mod dir;
 pub mod extent;
 pub mod inode;
+pub mod log;
+pub mod refcount;
+pub mod rmap;
 pub mod superblock;
+pub mod symlink;
 pub mod types;
@@ -0,0 +1,199 @@
+//! Reverse mapping btree (rmapbt, V5 only): one record per extent of an AG
+//! with its owner, so the file or metadata structure holding any block can
+//! be looked up.
+//!
+//! The rmapbt is an overlapping btree: extents of different owners can
+//! share blocks (reflink), so interior nodes carry a low and a high key per
+//! child, and a block range query follows every child whose key range
+//! overlaps it.
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use zerocopy::byteorder::big_endian::{U32, U64};
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
+
+use crate::error::FxfspError;
+use crate::reader::{IoPhase, IoReader};
+use crate::xfs::btree::{ShortBtree, ShortBtreeBlock};
+use crate::xfs::superblock::FsContext;
+
+/// "RMB3": rmapbt block.
+const XFS_RMAP_CRC_MAGIC: u32 = 0x524d4233;
+
+/// Owners with the top bit set are not inodes.
+const XFS_RMAP_NON_INODE_OWNER: u64 = 1 << 63;
+const XFS_RMAP_OWN_UNKNOWN: u64 = -2i64 as u64;
+const XFS_RMAP_OWN_FS: u64 = -3i64 as u64;
+const XFS_RMAP_OWN_LOG: u64 = -4i64 as u64;
+const XFS_RMAP_OWN_AG: u64 = -5i64 as u64;
+const XFS_RMAP_OWN_INOBT: u64 = -6i64 as u64;
+const XFS_RMAP_OWN_INODES: u64 = -7i64 as u64;
+const XFS_RMAP_OWN_REFC: u64 = -8i64 as u64;
+const XFS_RMAP_OWN_COW: u64 = -9i64 as u64;
+
+/// Flags in the high bits of `rm_offset`, the file offset below them.
+const XFS_RMAP_OFF_ATTR_FORK: u64 = 1 << 63;
+const XFS_RMAP_OFF_BMBT_BLOCK: u64 = 1 << 62;
+const XFS_RMAP_OFF_UNWRITTEN: u64 = 1 << 61;
+const XFS_RMAP_OFF_MASK: u64 = (1 << 54) - 1;
+
+/// Reverse mapping btree record (24 bytes).
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned, Clone, Copy)]
+#[repr(C)]
+pub struct XfsRmapRec {
+    pub rm_startblock: U32,
+    pub rm_blockcount: U32,
+    pub rm_owner: U64,
+    pub rm_offset: U64,
+}
+
+/// Reverse mapping btree key (20 bytes). Interior nodes hold a low and a
+/// high key per child.
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned, Clone, Copy)]
+#[repr(C)]
+pub struct XfsRmapKey {
+    pub rm_startblock: U32,
+    pub rm_owner: U64,
+    pub rm_offset: U64,
+}
+
+/// What an extent belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum RmapOwner {
+    /// A file, directory or other inode: its data, attr fork or bmbt blocks.
+    Inode(u64),
+    /// The AG headers: superblock, AGF, AGI and AGFL sectors.
+    FsHeaders,
+    /// The internal log.
+    Log,
+    /// The free space btrees, the rmapbt itself and blocks on the AGFL.
+    AgBtrees,
+    /// The inobt and finobt.
+    Inobt,
+    /// Inode chunks.
+    Inodes,
+    /// The refcount btree.
+    Refcountbt,
+    /// Staging extents of copy-on-write writes.
+    Cow,
+    /// Owner not known, as left by `xfs_repair` for some extents.
+    Unknown,
+    /// Another special owner value.
+    Other(u64),
+}
+
+impl RmapOwner {
+    fn from_raw(owner: u64) -> Self {
+        if owner & XFS_RMAP_NON_INODE_OWNER == 0 {
+            return Self::Inode(owner);
+        }
+        match owner {
+            XFS_RMAP_OWN_FS => Self::FsHeaders,
+            XFS_RMAP_OWN_LOG => Self::Log,
+            XFS_RMAP_OWN_AG => Self::AgBtrees,
+            XFS_RMAP_OWN_INOBT => Self::Inobt,
+            XFS_RMAP_OWN_INODES => Self::Inodes,
+            XFS_RMAP_OWN_REFC => Self::Refcountbt,
+            XFS_RMAP_OWN_COW => Self::Cow,
+            XFS_RMAP_OWN_UNKNOWN => Self::Unknown,
+            other => Self::Other(other),
+        }
+    }
+}
+
+/// One extent of an AG and its owner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct RmapRecord {
+    pub ag_number: u32,
+    /// First AG block.
+    pub start_block: u32,
+    pub block_count: u32,
+    pub owner: RmapOwner,
+    /// Offset in the owning file's fork, in blocks. 0 for other owners and
+    /// for bmbt blocks.
+    pub offset: u64,
+    /// Maps the attr fork rather than the data fork.
+    pub attr_fork: bool,
+    /// A block of the owner's bmbt rather than file contents.
+    pub bmbt_block: bool,
+    pub unwritten: bool,
+}
+
+impl RmapRecord {
+    /// Decode a record of AG `ag_number`, e.g. from [`parse_rmap_block`].
+    pub fn new(ag_number: u32, rec: &XfsRmapRec) -> Self {
+        let offset = rec.rm_offset.get();
+        Self {
+            ag_number,
+            start_block: rec.rm_startblock.get(),
+            block_count: rec.rm_blockcount.get(),
+            owner: RmapOwner::from_raw(rec.rm_owner.get()),
+            offset: offset & XFS_RMAP_OFF_MASK,
+            attr_fork: offset & XFS_RMAP_OFF_ATTR_FORK != 0,
+            bmbt_block: offset & XFS_RMAP_OFF_BMBT_BLOCK != 0,
+            unwritten: offset & XFS_RMAP_OFF_UNWRITTEN != 0,
+        }
+    }
+}
+
+/// Contents of one rmapbt block.
+#[derive(Clone)]
+pub enum RmapBlock {
+    /// Leaf (level 0): records in key order.
+    Leaf(Vec<XfsRmapRec>),
+    /// Interior node: child AG block numbers in key order.
+    Node { level: u16, children: Vec<u32> },
+}
+
+/// The rmapbt; each key slot holds a low and a high key.
+const RMAP_BTREE: ShortBtree = ShortBtree {
+    magic: XFS_RMAP_CRC_MAGIC,
+    name: "rmapbt block",
+    key_size: 2 * core::mem::size_of::<XfsRmapKey>(),
+    phase: IoPhase::RmapWalk,
+};
+
+/// Decode a single rmapbt block read from disk. `buf` holds one filesystem
+/// block.
+pub fn parse_rmap_block(buf: &[u8], ctx: &FsContext) -> Result<RmapBlock, FxfspError> {
+    Ok(match RMAP_BTREE.parse_block(buf, ctx, |_| true)? {
+        ShortBtreeBlock::Leaf(records) => RmapBlock::Leaf(records),
+        ShortBtreeBlock::Node { level, children } => RmapBlock::Node { level, children },
+    })
+}
+
+/// Walk the rmapbt rooted at `root_block` (AG-relative) with `level`
+/// levels, as recorded in the AGF, and collect the records overlapping the
+/// AG blocks `blocks` in key order; `0..u32::MAX` collects them all.
+///
+/// Descends only into children whose key range overlaps `blocks`.
+pub fn collect_rmap_records<R: IoReader>(
+    engine: &mut R,
+    ctx: &FsContext,
+    agno: u32,
+    root_block: u32,
+    level: u32,
+    blocks: Range<u32>,
+) -> Result<Vec<XfsRmapRec>, FxfspError> {
+    if blocks.is_empty() {
+        return Ok(Vec::new());
+    }
+    let overlaps = |keys: &[u8]| {
+        let (low, high) = keys.split_at(keys.len() / 2);
+        let low = XfsRmapKey::ref_from_bytes(low).expect("half is one key").rm_startblock.get();
+        // The high key holds the last block of the child's extents.
+        let high = XfsRmapKey::ref_from_bytes(high).expect("half is one key").rm_startblock.get();
+        low < blocks.end && high >= blocks.start
+    };
+    let mut records: Vec<XfsRmapRec> = RMAP_BTREE.collect_records(engine, ctx, agno, root_block, level, overlaps)?;
+    records.retain(|rec| {
+        let start = rec.rm_startblock.get();
+        start < blocks.end && start as u64 + rec.rm_blockcount.get() as u64 > blocks.start as u64
+    });
+    Ok(records)
+}
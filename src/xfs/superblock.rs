@@ -1,13 +1,49 @@
-use zerocopy::{FromBytes, Immutable, KnownLayout};
+use alloc::vec::Vec;
+
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
 use zerocopy::byteorder::big_endian::{U16, U32, U64};
+use zerocopy::byteorder::little_endian;
 
 use crate::error::FxfspError;
+use crate::xfs::inode::{InodeFlags2, InodeInfo, InternalInode};
+use crate::xfs::types::NULLFSINO;
 
 /// XFS superblock magic: "XFSB"
 const XFS_SB_MAGIC: u32 = 0x58465342;
 
-/// On-disk XFS superblock (first 264 bytes, enough for all fields we need).
-#[derive(FromBytes, KnownLayout, Immutable)]
+/// Byte offset of `sb_crc` within the superblock.
+#[cfg(feature = "std")]
+const XFS_SB_CRC_OFF: usize = 224;
+
+/// `sb_versionnum` bits.
+pub const XFS_SB_VERSION_QUOTABIT: u16 = 0x0040;
+pub const XFS_SB_VERSION_LOGV2BIT: u16 = 0x0400;
+
+/// `sb_features2` bits.
+pub const XFS_SB_VERSION2_LAZYSBCOUNTBIT: u32 = 0x0002;
+pub const XFS_SB_VERSION2_ATTR2BIT: u32 = 0x0008;
+pub const XFS_SB_VERSION2_PROJID32BIT: u32 = 0x0080;
+pub const XFS_SB_VERSION2_FTYPE: u32 = 0x0200;
+
+/// `sb_features_ro_compat` bits.
+pub const XFS_SB_FEAT_RO_COMPAT_FINOBT: u32 = 1 << 0;
+pub const XFS_SB_FEAT_RO_COMPAT_RMAPBT: u32 = 1 << 1;
+pub const XFS_SB_FEAT_RO_COMPAT_REFLINK: u32 = 1 << 2;
+pub const XFS_SB_FEAT_RO_COMPAT_INOBTCNT: u32 = 1 << 3;
+
+/// `sb_features_incompat` bits.
+pub const XFS_SB_FEAT_INCOMPAT_FTYPE: u32 = 1 << 0;
+pub const XFS_SB_FEAT_INCOMPAT_SPINODES: u32 = 1 << 1;
+pub const XFS_SB_FEAT_INCOMPAT_META_UUID: u32 = 1 << 2;
+pub const XFS_SB_FEAT_INCOMPAT_BIGTIME: u32 = 1 << 3;
+pub const XFS_SB_FEAT_INCOMPAT_NEEDSREPAIR: u32 = 1 << 4;
+pub const XFS_SB_FEAT_INCOMPAT_NREXT64: u32 = 1 << 5;
+pub const XFS_SB_FEAT_INCOMPAT_EXCHRANGE: u32 = 1 << 6;
+pub const XFS_SB_FEAT_INCOMPAT_PARENT: u32 = 1 << 7;
+pub const XFS_SB_FEAT_INCOMPAT_METADIR: u32 = 1 << 8;
+
+/// On-disk XFS superblock, V4 portion (first 208 bytes).
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
 #[repr(C)]
 pub struct XfsDsb {
     pub sb_magicnum: U32,
@@ -56,22 +92,118 @@ pub struct XfsDsb {
     pub sb_logsunit: U32,
     pub sb_features2: U32,
     pub sb_bad_features2: U32,
-    // V5 fields follow but we parse them separately if needed.
+    // V5 fields follow in XfsDsbV5.
+}
+
+/// V5 superblock fields, starting at byte offset 208.
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+pub struct XfsDsbV5 {
+    pub sb_features_compat: U32,
+    pub sb_features_ro_compat: U32,
+    pub sb_features_incompat: U32,
+    pub sb_features_log_incompat: U32,
+    /// CRC32C of the superblock sector; stored little-endian, unlike
+    /// everything around it.
+    pub sb_crc: little_endian::U32,
+    pub sb_spino_align: U32,
+    pub sb_pquotino: U64,
+    pub sb_lsn: U64,
+    pub sb_meta_uuid: [u8; 16],
+    /// Root of the metadata directory tree, with
+    /// [`XFS_SB_FEAT_INCOMPAT_METADIR`].
+    pub sb_metadirino: U64,
 }
 
 /// Which XFS format version we're dealing with.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FormatVersion {
     V4,
     V5,
 }
 
+/// Version and feature flags of a filesystem, as `xfs_info` shows them.
+///
+/// Booleans are resolved across versions (e.g. `ftype` comes from
+/// `sb_features2` on V4 and is implied on V5); the raw bitmaps are included
+/// for bits this struct does not name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct FeatureReport {
+    /// On-disk format version, 4 or 5.
+    pub version: u8,
+    pub crc: bool,
+    pub ftype: bool,
+    pub finobt: bool,
+    pub rmapbt: bool,
+    pub reflink: bool,
+    pub inobtcount: bool,
+    pub sparse_inodes: bool,
+    pub bigtime: bool,
+    pub nrext64: bool,
+    pub meta_uuid: bool,
+    pub needs_repair: bool,
+    pub exchange_range: bool,
+    pub parent_pointers: bool,
+    pub metadir: bool,
+    pub lazy_sb_counters: bool,
+    pub attr2: bool,
+    pub projid32: bool,
+    pub quota: bool,
+    pub log_v2: bool,
+    pub versionnum: u16,
+    pub features2: u32,
+    pub features_compat: u32,
+    pub features_ro_compat: u32,
+    pub features_incompat: u32,
+    pub features_log_incompat: u32,
+}
+
+/// Inodes per inode chunk, and so per inobt record.
+pub const XFS_INODES_PER_CHUNK: u32 = 64;
+/// Inodes covered by one bit of a sparse chunk's `ir_holemask`.
+pub const XFS_INODES_PER_HOLEMASK_BIT: u32 = 4;
+
+/// Where an inode sits on disk, from [`FsContext::locate_inode`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InodeLocation {
+    pub agno: u32,
+    pub agino: u32,
+    /// First AG inode of the 64-inode chunk: the `ir_startino` of the inobt
+    /// record covering this inode.
+    pub chunk_start_agino: u32,
+    /// Position within the chunk (0..64): the bit of `ir_free`.
+    pub chunk_index: u32,
+    /// The bit of `ir_holemask` covering this inode (0..16).
+    pub holemask_bit: u32,
+    /// AG block holding the inode.
+    pub ag_block: u32,
+    /// Byte offset of the inode within the filesystem.
+    pub byte_offset: u64,
+    /// Byte offset of the chunk within the filesystem. On a sparse chunk
+    /// the start may be a hole, holding no inodes.
+    pub chunk_byte_offset: u64,
+}
+
 /// Filesystem context extracted from the superblock.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FsContext {
     pub version: FormatVersion,
     pub block_size: u32,
     pub block_log: u8,
+    /// Total data blocks in the filesystem (`sb_dblocks`).
+    pub data_blocks: u64,
+    /// Allocated inodes (`sb_icount`). Like the other superblock counters,
+    /// only current as of the last clean unmount on lazy-count filesystems.
+    pub inode_count: u64,
+    /// Free inodes in allocated chunks (`sb_ifree`).
+    pub free_inodes: u64,
+    /// Free data blocks (`sb_fdblocks`).
+    pub free_blocks: u64,
     pub ag_count: u32,
     pub ag_blocks: u32,
     pub ag_blk_log: u8,
@@ -82,14 +214,93 @@ pub struct FsContext {
     pub dir_blk_log: u8,
     pub root_ino: u64,
     pub sect_size: u16,
+    /// Filesystem UUID (`sb_uuid`), as shown by `blkid`.
+    pub uuid: [u8; 16],
+    /// UUID stamped into V5 metadata blocks. Differs from `uuid` only when
+    /// the UUID was changed after mkfs (META_UUID feature); equal otherwise.
+    pub meta_uuid: [u8; 16],
+    /// Version number and feature bits (`sb_versionnum`).
+    pub versionnum: u16,
+    /// Additional V4 feature bits (`sb_features2`).
+    pub features2: u32,
+    /// V5 feature bitmaps (`sb_features_*`); all zero on V4.
+    pub features_compat: u32,
+    pub features_ro_compat: u32,
+    pub features_incompat: u32,
+    pub features_log_incompat: u32,
+    /// First filesystem block of the internal log (`sb_logstart`), 0 if
+    /// the log is on an external device.
+    pub log_start: u64,
+    /// Log size in filesystem blocks (`sb_logblocks`).
+    pub log_blocks: u32,
+    /// Log stripe unit in bytes (`sb_logsunit`), 0 or 1 if unset.
+    pub log_stripe_unit: u32,
+    /// RAID stripe unit in filesystem blocks (`sb_unit`), 0 if unset.
+    pub stripe_unit: u32,
+    /// RAID stripe width in filesystem blocks (`sb_width`), 0 if unset.
+    pub stripe_width: u32,
     /// Does the filesystem store ftype in directory entries?
     pub has_ftype: bool,
     /// NREXT64: extent counts stored as 64-bit at inode offset 24.
     pub has_nrext64: bool,
+    /// Inodes the superblock names as internal, with what each is for.
+    /// Unset ones (0 or `NULLFSINO` on disk) are left out.
+    pub internal_inodes: Vec<(u64, InternalInode)>,
 }
 
 impl FsContext {
+    /// What `inode` is for if it holds filesystem metadata: one the
+    /// superblock names, or one of the metadata directory tree. `None` for
+    /// users' files and directories.
+    pub fn internal_inode(&self, inode: &InodeInfo) -> Option<InternalInode> {
+        if let Some(&(_, kind)) = self.internal_inodes.iter().find(|(ino, _)| *ino == inode.ino) {
+            return Some(kind);
+        }
+        InodeFlags2::from_bits_retain(inode.flags2).contains(InodeFlags2::METADATA).then_some(InternalInode::Metadata)
+    }
+
+    /// Enumerate the filesystem's version and feature flags.
+    pub fn feature_report(&self) -> FeatureReport {
+        let v5 = self.version == FormatVersion::V5;
+        let ro = |bit| self.features_ro_compat & bit != 0;
+        let incompat = |bit| self.features_incompat & bit != 0;
+        // V5 requires these; on V4 they are optional feature bits.
+        let f2 = |bit| v5 || self.features2 & bit != 0;
+        FeatureReport {
+            version: if v5 { 5 } else { 4 },
+            crc: v5,
+            ftype: self.has_ftype,
+            finobt: ro(XFS_SB_FEAT_RO_COMPAT_FINOBT),
+            rmapbt: ro(XFS_SB_FEAT_RO_COMPAT_RMAPBT),
+            reflink: ro(XFS_SB_FEAT_RO_COMPAT_REFLINK),
+            inobtcount: ro(XFS_SB_FEAT_RO_COMPAT_INOBTCNT),
+            sparse_inodes: incompat(XFS_SB_FEAT_INCOMPAT_SPINODES),
+            bigtime: incompat(XFS_SB_FEAT_INCOMPAT_BIGTIME),
+            nrext64: incompat(XFS_SB_FEAT_INCOMPAT_NREXT64),
+            meta_uuid: incompat(XFS_SB_FEAT_INCOMPAT_META_UUID),
+            needs_repair: incompat(XFS_SB_FEAT_INCOMPAT_NEEDSREPAIR),
+            exchange_range: incompat(XFS_SB_FEAT_INCOMPAT_EXCHRANGE),
+            parent_pointers: incompat(XFS_SB_FEAT_INCOMPAT_PARENT),
+            metadir: incompat(XFS_SB_FEAT_INCOMPAT_METADIR),
+            lazy_sb_counters: f2(XFS_SB_VERSION2_LAZYSBCOUNTBIT),
+            attr2: f2(XFS_SB_VERSION2_ATTR2BIT),
+            projid32: f2(XFS_SB_VERSION2_PROJID32BIT),
+            quota: self.versionnum & XFS_SB_VERSION_QUOTABIT != 0,
+            log_v2: v5 || self.versionnum & XFS_SB_VERSION_LOGV2BIT != 0,
+            versionnum: self.versionnum,
+            features2: self.features2,
+            features_compat: self.features_compat,
+            features_ro_compat: self.features_ro_compat,
+            features_incompat: self.features_incompat,
+            features_log_incompat: self.features_log_incompat,
+        }
+    }
+
     /// Parse the superblock from the given buffer and build an FsContext.
+    ///
+    /// Fails with [`FxfspError::Parse`] if the geometry is impossible (sizes
+    /// that are not powers of two, logs that disagree with them, no AGs),
+    /// so every `FsContext` built here is safe to do offset arithmetic with.
     pub fn from_superblock(buf: &[u8]) -> Result<Self, FxfspError> {
         let sb = XfsDsb::ref_from_prefix(buf)
             .map_err(|_| FxfspError::Parse("buffer too small for superblock"))?
@@ -99,6 +310,8 @@ impl FsContext {
             return Err(FxfspError::BadMagic("superblock"));
         }
 
+        check_geometry(sb)?;
+
         let versionnum = sb.sb_versionnum.get();
         // V5 superblocks have version number 5 in the low nibble.
         let version = if (versionnum & 0x000f) >= 5 {
@@ -108,25 +321,49 @@ impl FsContext {
         };
 
         let features2 = sb.sb_features2.get();
-        // XFS_SB_VERSION2_FTYPE = 0x00000200
-        let has_ftype_v4 = (features2 & 0x0200) != 0;
+        let has_ftype_v4 = (features2 & XFS_SB_VERSION2_FTYPE) != 0;
 
         // For V5, ftype is always present.
         let has_ftype = version == FormatVersion::V5 || has_ftype_v4;
 
-        // V5: check incompat features for NREXT64 (bit 5).
-        // sb_features_incompat is at byte offset 216 in the superblock.
-        let has_nrext64 = if version == FormatVersion::V5 && buf.len() >= 220 {
-            let incompat = u32::from_be_bytes([buf[216], buf[217], buf[218], buf[219]]);
-            (incompat & 0x20) != 0 // XFS_SB_FEAT_INCOMPAT_NREXT64 = 1 << 5
-        } else {
-            false
+        let v5 = match version {
+            FormatVersion::V5 => Some(
+                buf.get(size_of::<XfsDsb>()..)
+                    .and_then(|tail| XfsDsbV5::ref_from_prefix(tail).ok())
+                    .ok_or(FxfspError::Parse("buffer too small for V5 superblock"))?
+                    .0,
+            ),
+            FormatVersion::V4 => None,
+        };
+        let features = |f: fn(&XfsDsbV5) -> u32| v5.map_or(0, f);
+        let features_incompat = features(|v5| v5.sb_features_incompat.get());
+
+        let has_nrext64 = (features_incompat & XFS_SB_FEAT_INCOMPAT_NREXT64) != 0;
+        let metadir = (features_incompat & XFS_SB_FEAT_INCOMPAT_METADIR) != 0;
+        let internal_inodes = [
+            (sb.sb_rbmino.get(), InternalInode::RealtimeBitmap),
+            (sb.sb_rsumino.get(), InternalInode::RealtimeSummary),
+            (sb.sb_uquotino.get(), InternalInode::UserQuota),
+            (sb.sb_gquotino.get(), InternalInode::GroupQuota),
+            (v5.map_or(0, |v5| v5.sb_pquotino.get()), InternalInode::ProjectQuota),
+            (v5.filter(|_| metadir).map_or(0, |v5| v5.sb_metadirino.get()), InternalInode::MetadataDirectory),
+        ]
+        .into_iter()
+        .filter(|&(ino, _)| ino != 0 && ino != NULLFSINO)
+        .collect();
+        let meta_uuid = match v5 {
+            Some(v5) if (features_incompat & XFS_SB_FEAT_INCOMPAT_META_UUID) != 0 => v5.sb_meta_uuid,
+            _ => sb.sb_uuid,
         };
 
         Ok(FsContext {
             version,
             block_size: sb.sb_blocksize.get(),
             block_log: sb.sb_blocklog,
+            data_blocks: sb.sb_dblocks.get(),
+            inode_count: sb.sb_icount.get(),
+            free_inodes: sb.sb_ifree.get(),
+            free_blocks: sb.sb_fdblocks.get(),
             ag_count: sb.sb_agcount.get(),
             ag_blocks: sb.sb_agblocks.get(),
             ag_blk_log: sb.sb_agblklog,
@@ -137,11 +374,42 @@ impl FsContext {
             dir_blk_log: sb.sb_dirblklog,
             root_ino: sb.sb_rootino.get(),
             sect_size: sb.sb_sectsize.get(),
+            uuid: sb.sb_uuid,
+            meta_uuid,
+            versionnum,
+            features2,
+            features_compat: features(|v5| v5.sb_features_compat.get()),
+            features_ro_compat: features(|v5| v5.sb_features_ro_compat.get()),
+            features_incompat,
+            features_log_incompat: features(|v5| v5.sb_features_log_incompat.get()),
+            log_start: sb.sb_logstart.get(),
+            log_blocks: sb.sb_logblocks.get(),
+            log_stripe_unit: sb.sb_logsunit.get(),
+            stripe_unit: sb.sb_unit.get(),
+            stripe_width: sb.sb_width.get(),
             has_ftype,
             has_nrext64,
+            internal_inodes,
         })
     }
 
+    /// Verify the V5 superblock CRC over a buffer holding at least one
+    /// sector (`sect_size` bytes). V4 superblocks carry no CRC and pass.
+    #[cfg(feature = "std")]
+    pub fn verify_superblock_crc(&self, buf: &[u8]) -> Result<(), FxfspError> {
+        if self.version == FormatVersion::V4 {
+            return Ok(());
+        }
+        let sector = buf
+            .get(..self.sect_size as usize)
+            .filter(|s| s.len() >= XFS_SB_CRC_OFF + 4)
+            .ok_or(FxfspError::Parse("buffer smaller than superblock sector"))?;
+        if !crate::xfs::crc::crc_matches(sector, XFS_SB_CRC_OFF) {
+            return Err(FxfspError::CrcMismatch("superblock"));
+        }
+        Ok(())
+    }
+
     /// Convert an absolute inode number to (ag_number, ag_relative_inode).
     pub fn ino_to_agno(&self, ino: u64) -> u32 {
         (ino >> (self.inop_blog as u64 + self.ag_blk_log as u64)) as u32
@@ -157,6 +425,49 @@ impl FsContext {
         ((agno as u64) << (self.inop_blog as u64 + self.ag_blk_log as u64)) | (agino as u64)
     }
 
+    /// Locate inode `ino` on disk: its AG, chunk, inobt record bits and
+    /// byte offsets.
+    ///
+    /// Fails if the number cannot name an inode of this geometry: beyond
+    /// the last AG, past the end of its AG, or inside the AG headers. It
+    /// says nothing about whether the inode is allocated; look up
+    /// [`chunk_start_agino`](InodeLocation::chunk_start_agino) in the inobt
+    /// for that.
+    pub fn locate_inode(&self, ino: u64) -> Result<InodeLocation, FxfspError> {
+        let agno = ino >> (self.inop_blog as u64 + self.ag_blk_log as u64);
+        if agno >= self.ag_count as u64 {
+            return Err(FxfspError::Parse("inode number beyond last AG"));
+        }
+        let agno = agno as u32;
+        let agino = self.ino_to_agino(ino);
+        let ag_block = agino >> self.inop_blog;
+        if ag_block >= self.ag_length(agno) {
+            return Err(FxfspError::Parse("inode number beyond end of AG"));
+        }
+        // The superblock, AGF, AGI and AGFL take the first four sectors.
+        if ((ag_block as u64) << self.block_log) < 4 * self.sect_size as u64 {
+            return Err(FxfspError::Parse("inode number inside AG headers"));
+        }
+        let chunk_start_agino = agino & !(XFS_INODES_PER_CHUNK - 1);
+        let chunk_index = agino - chunk_start_agino;
+        Ok(InodeLocation {
+            agno,
+            agino,
+            chunk_start_agino,
+            chunk_index,
+            holemask_bit: chunk_index / XFS_INODES_PER_HOLEMASK_BIT,
+            ag_block,
+            byte_offset: self.agino_byte_offset(agno, agino),
+            chunk_byte_offset: self.agino_byte_offset(agno, chunk_start_agino),
+        })
+    }
+
+    /// Byte offset of AG inode `agino` of AG `agno` within the filesystem.
+    pub fn agino_byte_offset(&self, agno: u32, agino: u32) -> u64 {
+        let within = (agino & ((1u32 << self.inop_blog) - 1)) as u64 * self.inode_size as u64;
+        self.ag_block_to_byte(agno, agino >> self.inop_blog) + within
+    }
+
     /// Byte offset of an AG-relative block within the filesystem.
     pub fn ag_block_to_byte(&self, agno: u32, agblock: u32) -> u64 {
         let abs_block = (agno as u64) * (self.ag_blocks as u64) + (agblock as u64);
@@ -168,12 +479,38 @@ impl FsContext {
         (agno as u64) * (self.ag_blocks as u64) * (self.block_size as u64)
     }
 
+    /// Expected length of AG `agno` in blocks.
+    ///
+    /// Every AG is `ag_blocks` long except the last, which holds whatever
+    /// remains of `sb_dblocks` (shorter after `xfs_growfs` or odd sizes).
+    pub fn ag_length(&self, agno: u32) -> u32 {
+        let start = agno as u64 * self.ag_blocks as u64;
+        self.data_blocks
+            .saturating_sub(start)
+            .min(self.ag_blocks as u64) as u32
+    }
+
+    /// Byte offset of the AGF header for a given AG (sector 1 of the AG).
+    pub fn agf_byte_offset(&self, agno: u32) -> u64 {
+        self.ag_start_byte(agno) + self.sect_size as u64
+    }
+
+    /// Byte offset of the AGFL for a given AG (sector 3 of the AG).
+    pub fn agfl_byte_offset(&self, agno: u32) -> u64 {
+        self.ag_start_byte(agno) + 3 * self.sect_size as u64
+    }
+
     /// Byte offset of the AGI header for a given AG.
     /// AGI is at disk-address sector 2 within the AG (sector = sb_sectsize).
     pub fn agi_byte_offset(&self, agno: u32) -> u64 {
         self.ag_start_byte(agno) + 2 * self.sect_size as u64
     }
 
+    /// Stripe width in bytes, or 0 if the filesystem has no stripe geometry.
+    pub fn stripe_width_bytes(&self) -> u64 {
+        (self.stripe_width as u64) << self.block_log
+    }
+
     /// Number of filesystem blocks in a directory block.
     pub fn dir_blk_fsblocks(&self) -> u32 {
         1u32 << self.dir_blk_log
@@ -184,3 +521,49 @@ impl FsContext {
         self.block_size * self.dir_blk_fsblocks()
     }
 }
+
+/// Reject geometry the offset arithmetic cannot survive, much as the
+/// kernel's `xfs_validate_sb_common` does. Everything downstream shifts and
+/// divides by these fields unchecked, so a fuzzed superblock must fail here
+/// rather than panic later.
+fn check_geometry(sb: &XfsDsb) -> Result<(), FxfspError> {
+    // A power of two within `min..=max`, and equal to `1 << log`.
+    let sized = |size: u32, log: u8, min: u32, max: u32| {
+        (min..=max).contains(&size) && 1u32.checked_shl(log as u32) == Some(size)
+    };
+    let block_size = sb.sb_blocksize.get();
+    if !sized(block_size, sb.sb_blocklog, 512, 65536) {
+        return Err(FxfspError::Parse("superblock: bad block size"));
+    }
+    let sect_size = sb.sb_sectsize.get() as u32;
+    if !sized(sect_size, sb.sb_sectlog, 512, 32768) || sect_size > block_size {
+        return Err(FxfspError::Parse("superblock: bad sector size"));
+    }
+    let inode_size = sb.sb_inodesize.get() as u32;
+    if !sized(inode_size, sb.sb_inodelog, 256, 2048) || inode_size > block_size {
+        return Err(FxfspError::Parse("superblock: bad inode size"));
+    }
+    if sb.sb_inopblock.get() as u32 != block_size / inode_size || sb.sb_inopblog != sb.sb_blocklog - sb.sb_inodelog {
+        return Err(FxfspError::Parse("superblock: bad inodes per block"));
+    }
+    let ag_blocks = sb.sb_agblocks.get();
+    let ag_count = sb.sb_agcount.get();
+    // AG inode numbers are `agblklog + inopblog` bits wide and must fit a u32.
+    if ag_blocks == 0
+        || ag_count == 0
+        || sb.sb_agblklog as u32 + sb.sb_inopblog as u32 > 32
+        || ag_blocks as u64 > 1u64 << sb.sb_agblklog
+    {
+        return Err(FxfspError::Parse("superblock: bad AG geometry"));
+    }
+    // Byte offsets of every AG must fit a u64.
+    let fs_blocks = ag_count as u64 * ag_blocks as u64;
+    if sb.sb_dblocks.get() > fs_blocks || fs_blocks.checked_mul(block_size as u64).is_none() {
+        return Err(FxfspError::Parse("superblock: bad data block count"));
+    }
+    // Directory blocks are at most 64 KiB.
+    if sb.sb_blocklog as u32 + sb.sb_dirblklog as u32 > 16 {
+        return Err(FxfspError::Parse("superblock: bad directory block size"));
+    }
+    Ok(())
+}
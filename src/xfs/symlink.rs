@@ -0,0 +1,77 @@
+//! Symbolic link targets.
+//!
+//! Short targets live in the inode's data fork (`XFS_DINODE_FMT_LOCAL`);
+//! longer ones in up to a few filesystem blocks mapped by an extent list.
+//! On V5 each of those blocks starts with an `XSLM` header.
+
+use alloc::vec::Vec;
+
+use zerocopy::{FromBytes, Immutable, KnownLayout, Unaligned};
+use zerocopy::byteorder::big_endian::{U32, U64};
+
+use crate::error::FxfspError;
+use crate::xfs::superblock::{FormatVersion, FsContext};
+
+/// Longest symlink target XFS stores (`XFS_SYMLINK_MAXLEN`).
+pub const XFS_SYMLINK_MAXLEN: usize = 1024;
+
+/// Most filesystem blocks a remote target spans (`XFS_SYMLINK_MAPS`).
+pub const XFS_SYMLINK_MAPS: u64 = 3;
+
+/// V5 remote symlink block magic: "XSLM"
+const XFS_SYMLINK_MAGIC: u32 = 0x58534c4d;
+
+/// V5 remote symlink block header (56 bytes).
+#[derive(FromBytes, KnownLayout, Immutable, Unaligned)]
+#[repr(C)]
+pub struct XfsDsymlinkHdr {
+    pub sl_magic: U32,
+    /// Byte offset of this block's bytes within the target.
+    pub sl_offset: U32,
+    /// Target bytes held in this block.
+    pub sl_bytes: U32,
+    pub sl_crc: U32,
+    pub sl_uuid: [u8; 16],
+    /// Inode number of the link.
+    pub sl_owner: U64,
+    pub sl_blkno: U64,
+    pub sl_lsn: U64,
+}
+
+/// The target of a local-format symlink: `di_size` bytes of `fork_buf`,
+/// the inode's data fork.
+pub fn parse_local_symlink(fork_buf: &[u8], size: u64) -> Result<&[u8], FxfspError> {
+    let len = usize::try_from(size).unwrap_or(usize::MAX);
+    if len > XFS_SYMLINK_MAXLEN {
+        return Err(FxfspError::Parse("symlink target too long"));
+    }
+    fork_buf
+        .get(..len)
+        .ok_or(FxfspError::Parse("local symlink target out of bounds"))
+}
+
+/// Append the target bytes held in one remote symlink block to `target`.
+///
+/// `buf` is one filesystem block of the link's extents, visited in logical
+/// order. V4 blocks are raw target bytes, so the caller truncates the
+/// result to `di_size`; V5 blocks say how many bytes they hold.
+pub fn parse_remote_symlink_block(buf: &[u8], ctx: &FsContext, target: &mut Vec<u8>) -> Result<(), FxfspError> {
+    let payload = match ctx.version {
+        FormatVersion::V4 => buf,
+        FormatVersion::V5 => {
+            let (hdr, rest) = XfsDsymlinkHdr::ref_from_prefix(buf)
+                .map_err(|_| FxfspError::Parse("buffer too small for symlink header"))?;
+            if hdr.sl_magic.get() != XFS_SYMLINK_MAGIC {
+                return Err(FxfspError::BadMagic("symlink block"));
+            }
+            if hdr.sl_offset.get() as usize != target.len() {
+                return Err(FxfspError::Parse("symlink block out of order"));
+            }
+            rest.get(..hdr.sl_bytes.get() as usize)
+                .ok_or(FxfspError::Parse("symlink block bytes out of bounds"))?
+        }
+    };
+    let room = XFS_SYMLINK_MAXLEN.saturating_sub(target.len());
+    target.extend_from_slice(&payload[..payload.len().min(room)]);
+    Ok(())
+}
@@ -10,6 +10,12 @@ pub type XfsAgblock = u32;
 /// XFS AG-relative inode number (within the AG).
 pub type XfsAgino = u32;
 
+/// Null AG-relative inode number, terminating unlinked bucket chains.
+pub const NULLAGINO: XfsAgino = u32::MAX;
+
+/// Null absolute inode number, for superblock inode fields left unset.
+pub const NULLFSINO: u64 = u64::MAX;
+
 /// XFS filesystem block number (absolute, 64-bit).
 pub type XfsFsblock = u64;
 
@@ -0,0 +1,150 @@
+#!/bin/bash
+#
+# Build the scan_fixture.rs test images with mkfs.xfs protofiles (no mount
+# or root needed). Every image holds the same tree:
+#
+#   /hello.txt         "hello\n"
+#   /empty_file
+#   /subdir/nested.txt "nested\n"
+#   /subdir/file_1 .. file_200 (empty)
+#
+# plus test_symlinks*.xfs (V5 and V4), each holding two symlinks:
+#
+#   /short -> hello.txt                 (target in the inode)
+#   /long  -> segment_001/ .. segment_080/  (960 bytes, in a remote block)
+#
+# and test_bigdir*.xfs, each holding one node-format directory:
+#
+#   /big/entry_0 .. entry_999999 (empty)
+#
+# With root, also test_bmbt.xfs (see below) and test_xattrs.xfs:
+#
+#   /tagged  user.color=blue, trusted.origin=fixture  (shortform attr fork)
+#   /heavy   user.attr_000 .. attr_099 = "value_NNN", and user.big =
+#            8000 x "x"                                (leaf + remote value)
+#
+# Usage: tests/fixtures/make_fixtures.sh   (needs xfsprogs; V4 needs a
+# version that still accepts -m crc=0)
+#
+set -euo pipefail
+
+OUT="$(cd "$(dirname "$0")" && pwd)"
+WORK="$(mktemp -d)"
+trap 'rm -rf "$WORK"' EXIT
+
+printf 'hello\n' > "$WORK/hello.txt"
+printf 'nested\n' > "$WORK/nested.txt"
+: > "$WORK/empty"
+
+{
+  echo "/dev/null"
+  echo "0 0"
+  echo "d--755 0 0"
+  echo "hello.txt ---644 0 0 $WORK/hello.txt"
+  echo "empty_file ---644 0 0 $WORK/empty"
+  echo "subdir d--755 0 0"
+  echo "nested.txt ---644 0 0 $WORK/nested.txt"
+  for i in $(seq 1 200); do
+    echo "file_$i ---644 0 0 $WORK/empty"
+  done
+  echo "\$"
+  echo "\$"
+} > "$WORK/proto"
+
+LONG_TARGET="$(printf 'segment_%03d/' $(seq 1 80))"
+{
+  echo "/dev/null"
+  echo "0 0"
+  echo "d--755 0 0"
+  echo "short l--777 0 0 hello.txt"
+  echo "long l--777 0 0 $LONG_TARGET"
+  echo "\$"
+  echo "\$"
+} > "$WORK/symlinkproto"
+
+{
+  echo "/dev/null"
+  echo "0 0"
+  echo "d--755 0 0"
+  echo "big d--755 0 0"
+  for i in $(seq 0 999999); do
+    echo "entry_$i ---644 0 0 $WORK/empty"
+  done
+  echo "\$"
+  echo "\$"
+} > "$WORK/bigproto"
+
+make_image() {
+  local name=$1 size=$2 proto=$3
+  shift 3
+  rm -f "$OUT/$name"
+  truncate -s "$size" "$OUT/$name"
+  mkfs.xfs -q -f -p "$proto" "$@" "$OUT/$name"
+  echo "built $name"
+}
+
+make_image test_v5.xfs 64M "$WORK/proto"
+make_image test_v5_dirblk8k.xfs 64M "$WORK/proto" -n size=8192
+# 2 KiB inodes (a 64-inode chunk spans 32 blocks) with 64 KiB directory
+# blocks, and 64 KiB filesystem blocks, where one block holds two chunks of
+# 512-byte inodes.
+make_image test_v5_inode2k_dirblk64k.xfs 64M "$WORK/proto" -i size=2048 -n size=65536
+make_image test_v5_bs64k.xfs 64M "$WORK/proto" -b size=65536
+make_image test_v4.xfs 64M "$WORK/proto" -m crc=0 -n ftype=1
+make_image test_v4_noftype.xfs 64M "$WORK/proto" -m crc=0 -n ftype=0
+make_image test_v4_dirblk8k.xfs 64M "$WORK/proto" -m crc=0 -n size=8192
+make_image test_rmap.xfs 64M "$WORK/proto" -m rmapbt=1,reflink=1
+make_image test_symlinks.xfs 64M "$WORK/symlinkproto"
+make_image test_symlinks_v4.xfs 64M "$WORK/symlinkproto" -m crc=0
+
+# Directory blocks of 1 and 4 filesystem blocks.
+make_image test_bigdir.xfs 2G "$WORK/bigproto"
+make_image test_bigdir_16k.xfs 2G "$WORK/bigproto" -n size=16384
+
+# A regular file with 40000 one-block extents, enough for a bmbt with a
+# node level under the inode root, and a huge sparse file. Sparse writes
+# can't be expressed in a protofile, so this image is written through a
+# loop mount.
+if [ "$(id -u)" -ne 0 ]; then
+  echo "skipping test_bmbt.xfs (needs root for a loop mount)"
+  exit 0
+fi
+rm -f "$OUT/test_bmbt.xfs"
+truncate -s 1G "$OUT/test_bmbt.xfs"
+mkfs.xfs -q -f "$OUT/test_bmbt.xfs"
+mkdir "$WORK/mnt"
+mount -o loop "$OUT/test_bmbt.xfs" "$WORK/mnt"
+seq 0 39999 | awk '{ printf "-c\npwrite -q -S 0x5a %d 4096\n", $1 * 8192 }' \
+  | tr '\n' '\0' | xargs -0 -n 2000 xfs_io -f "$WORK/mnt/fragmented"
+# Three blocks at 0, 1 PiB and 4 EiB: logical offsets near the top of the
+# 54-bit extent field.
+xfs_io -f -c "pwrite -q -S 0x5a 0 4096" -c "pwrite -q -S 0x5a $((1 << 50)) 4096" \
+  -c "pwrite -q -S 0x5a $((1 << 62)) 4096" "$WORK/mnt/sparse"
+umount "$WORK/mnt"
+echo "built test_bmbt.xfs"
+
+rm -f "$OUT/test_xattrs.xfs"
+truncate -s 64M "$OUT/test_xattrs.xfs"
+mkfs.xfs -q -f "$OUT/test_xattrs.xfs"
+mount -o loop "$OUT/test_xattrs.xfs" "$WORK/mnt"
+touch "$WORK/mnt/tagged"
+setfattr -n user.color -v blue "$WORK/mnt/tagged"
+setfattr -n trusted.origin -v fixture "$WORK/mnt/tagged"
+touch "$WORK/mnt/heavy"
+for i in $(seq -f '%03g' 0 99); do
+  setfattr -n "user.attr_$i" -v "value_$i" "$WORK/mnt/heavy"
+done
+setfattr -n user.big -v "$(head -c 8000 /dev/zero | tr '\0' x)" "$WORK/mnt/heavy"
+umount "$WORK/mnt"
+echo "built test_xattrs.xfs"
+
+# A 64 KiB file and two reflinked copies: 16 blocks with a refcount of 3.
+rm -f "$OUT/test_reflink.xfs"
+truncate -s 64M "$OUT/test_reflink.xfs"
+mkfs.xfs -q -f -m reflink=1 "$OUT/test_reflink.xfs"
+mount -o loop "$OUT/test_reflink.xfs" "$WORK/mnt"
+xfs_io -f -c "pwrite -q -S 0x5a 0 65536" -c fsync "$WORK/mnt/original"
+cp --reflink=always "$WORK/mnt/original" "$WORK/mnt/copy1"
+cp --reflink=always "$WORK/mnt/original" "$WORK/mnt/copy2"
+umount "$WORK/mnt"
+echo "built test_reflink.xfs"
@@ -1,16 +1,54 @@
+use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
-use std::ops::ControlFlow;
+use std::ops::{ControlFlow, Range};
 use std::os::unix::fs::FileExt;
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::Arc;
 
 use fxfsp::{
-    Extent, FsContext, IoEngine, MaybeInstrumented, parse_superblock,
-    InodeInfo, FileExtentsInfo, DirEntryInfo,
+    BlockKind, CallbackReader, CleanupCollector, CleanupCriteria, Clock, CrcVerification, CrossCheck, DeadlineStop,
+    DecodedBlock, DirEntryInfo, DirStats, DuplicateNames, Extent, FileExtentsInfo, FileJoin, FileRecord, FixedClock,
+    FreeSpaceExtent, FreeSpaceOrder, FsContext, FxfspError, InodeFlags, InodeFlags2, InodeInfo,
+    InstrumentationConfig, InternalInode, IoEngine, IoPhase, IoReader, KindCounts, LogItem, MaybeInstrumented,
+    NamePolicy, OrphanCollector, PackedExtents, Predicate, Provenance, ReplayReader, ReplayReport, RmapOwner,
+    RmapRecord, Rule, RuleEngine, RuleMatch, ScanOptions, ScanWarning, SecurityAudit, SecurityFinding,
+    SecurityIssue, Session, SharedExtent, SliceReader, SpaceAccounting, SymlinkTargetInfo, UsageBucket,
+    UsageCollector, WarningCode, XattrInfo, XattrNamespace, decode_block, escape_name, parse_superblock,
+    parse_superblock_with_options, unescape_name,
 };
+use fxfsp::io::reader::InstrumentedReader;
+use fxfsp::xfs::ag::AgiInfo;
+use fxfsp::xfs::alloc::{collect_free_space_records, parse_alloc_block};
+use fxfsp::xfs::attr::parse_attr_fork_blocks;
+use fxfsp::xfs::bmbt::{BmbtDirInput, parse_bmbt_block, walk_bmbt_extents};
+use fxfsp::xfs::btree::{InobtBlock, find_inobt_record, parse_inobt_block};
+use fxfsp::xfs::crc::{metadata_uuid, update_metadata_crc, verify_metadata_crc};
+use fxfsp::xfs::inode::{
+    S_IFDIR, S_IFREG, XFS_DINODE_FMT_BTREE, XFS_DINODE_FMT_DEV, XFS_DINODE_FMT_EXTENTS, XFS_DINODE_FMT_LOCAL,
+    decode_rdev, parse_inode_core,
+};
+use fxfsp::xfs::refcount::collect_refcount_records;
+use fxfsp::xfs::rmap::collect_rmap_records;
+use fxfsp::xfs::superblock::{FormatVersion, XFS_SB_FEAT_RO_COMPAT_REFLINK};
+use fxfsp::xfs::symlink::{XfsDsymlinkHdr, parse_remote_symlink_block};
+use zerocopy::FromBytes;
 
 const FIXTURE_PATH: &str = "tests/fixtures/test_v5.xfs";
 
+/// Every image built by `tests/fixtures/make_fixtures.sh`, all holding the
+/// same tree: (path, format version, has ftype).
+const FORMAT_MATRIX: &[(&str, u8, bool)] = &[
+    ("tests/fixtures/test_v5.xfs", 5, true),
+    ("tests/fixtures/test_v5_dirblk8k.xfs", 5, true),
+    ("tests/fixtures/test_v5_inode2k_dirblk64k.xfs", 5, true),
+    ("tests/fixtures/test_v5_bs64k.xfs", 5, true),
+    ("tests/fixtures/test_v4.xfs", 4, true),
+    ("tests/fixtures/test_v4_noftype.xfs", 4, false),
+    ("tests/fixtures/test_v4_dirblk8k.xfs", 4, true),
+];
+
 /// Collect all scan events into structured data for assertions.
 struct ScanResult {
     block_size: u32,
@@ -34,6 +72,7 @@ struct InodeRecord {
     uid: u32,
     gid: u32,
     nblocks: u64,
+    internal: Option<InternalInode>,
 }
 
 #[derive(Clone)]
@@ -46,6 +85,10 @@ struct DirEntryRecord {
 
 impl ScanResult {
     fn collect() -> Self {
+        Self::collect_from(FIXTURE_PATH)
+    }
+
+    fn collect_from(path: &str) -> Self {
         let mut result = ScanResult {
             block_size: 0,
             ag_count: 0,
@@ -57,8 +100,8 @@ impl ScanResult {
             file_extents: HashMap::new(),
         };
 
-        let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
-        let reader = MaybeInstrumented::from_env(engine).expect("failed to create reader");
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let reader = MaybeInstrumented::new(engine, &InstrumentationConfig::from_env()).expect("failed to create reader");
 
         let (sb, mut scanner) = parse_superblock(reader).expect("failed to parse superblock");
 
@@ -82,6 +125,7 @@ impl ScanResult {
                         uid: inode.uid,
                         gid: inode.gid,
                         nblocks: inode.nblocks,
+                        internal: inode.internal,
                     },
                 );
                 if let Some(exts) = &inode.extents {
@@ -126,42 +170,2386 @@ impl ScanResult {
             .find(|e| e.parent_ino == parent_ino && e.name == name)
     }
 
-    /// Get "." and ".." entries for a given parent.
-    fn dot_entries_of(&self, parent_ino: u64) -> (Option<&DirEntryRecord>, Option<&DirEntryRecord>) {
-        let dot = self.dir_entries.iter().find(|e| e.parent_ino == parent_ino && e.name == ".");
-        let dotdot = self.dir_entries.iter().find(|e| e.parent_ino == parent_ino && e.name == "..");
-        (dot, dotdot)
-    }
+    /// Get "." and ".." entries for a given parent.
+    fn dot_entries_of(&self, parent_ino: u64) -> (Option<&DirEntryRecord>, Option<&DirEntryRecord>) {
+        let dot = self.dir_entries.iter().find(|e| e.parent_ino == parent_ino && e.name == ".");
+        let dotdot = self.dir_entries.iter().find(|e| e.parent_ino == parent_ino && e.name == "..");
+        (dot, dotdot)
+    }
+}
+
+fn skip_if_missing() -> bool {
+    if !Path::new(FIXTURE_PATH).exists() {
+        eprintln!("Skipping: fixture not found at {FIXTURE_PATH}");
+        return true;
+    }
+    false
+}
+
+/// The entries of [`FORMAT_MATRIX`] whose image exists.
+fn matrix_fixtures() -> impl Iterator<Item = (&'static str, u8, bool)> {
+    FORMAT_MATRIX.iter().copied().filter(|&(path, _, _)| {
+        let exists = Path::new(path).exists();
+        if !exists {
+            eprintln!("Skipping: fixture not found at {path}");
+        }
+        exists
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Format matrix (V4/V5, ftype, directory block size)
+// ---------------------------------------------------------------------------
+
+#[test]
+fn format_matrix_reports_version_and_ftype() {
+    for (path, version, has_ftype) in matrix_fixtures() {
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (_sb, scanner) = parse_superblock(engine).expect("failed to parse superblock");
+        let report = scanner.context().feature_report();
+        assert_eq!(report.version, version, "{path}");
+        assert_eq!(report.ftype, has_ftype, "{path}");
+        let ftype_absent = scanner.warnings().iter().any(|w| w.code == WarningCode::FtypeAbsent);
+        assert_eq!(ftype_absent, !has_ftype, "{path}");
+    }
+}
+
+#[test]
+fn format_matrix_tree_is_identical() {
+    for (path, _, has_ftype) in matrix_fixtures() {
+        let r = ScanResult::collect_from(path);
+
+        let mut root: Vec<&str> = r.children_of(r.root_ino).iter().map(|e| e.name.as_str()).collect();
+        root.sort_unstable();
+        assert_eq!(root, ["empty_file", "hello.txt", "subdir"], "{path}");
+
+        let hello = r.find_entry(r.root_ino, "hello.txt").unwrap();
+        let subdir = r.find_entry(r.root_ino, "subdir").unwrap();
+        assert_eq!(r.inodes[&hello.child_ino].size, 6, "{path}");
+        let (hello_ft, subdir_ft) = if has_ftype { (1, 2) } else { (0, 0) };
+        assert_eq!((hello.file_type, subdir.file_type), (hello_ft, subdir_ft), "{path}");
+
+        let (dot, dotdot) = r.dot_entries_of(subdir.child_ino);
+        assert_eq!(dot.unwrap().child_ino, subdir.child_ino, "{path}");
+        assert_eq!(dotdot.unwrap().child_ino, r.root_ino, "{path}");
+
+        let children = r.children_of(subdir.child_ino);
+        assert_eq!(children.len(), 201, "{path}");
+        let names: HashSet<&str> = children.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names.len(), 201, "{path}: duplicate entries");
+        let nested = r.find_entry(subdir.child_ino, "nested.txt").unwrap();
+        assert_eq!(r.inodes[&nested.child_ino].size, 7, "{path}");
+        assert!(r.file_extents.contains_key(&nested.child_ino), "{path}");
+    }
+}
+
+#[test]
+fn format_matrix_scans_without_other_warnings() {
+    for (path, _, _) in matrix_fixtures() {
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+        while let Some(ag) = scanner.next_ag() {
+            ag.expect("failed to get AG")
+                .scan_inodes(|_inode: &InodeInfo| ControlFlow::Continue(()))
+                .expect("failed to scan inodes")
+                .skip_extents()
+                .scan_dir_entries(|_de: &DirEntryInfo| ControlFlow::Continue(()))
+                .expect("failed to scan dirs");
+        }
+        let unexpected: Vec<_> =
+            scanner.warnings().iter().filter(|w| w.code != WarningCode::FtypeAbsent).collect();
+        assert!(unexpected.is_empty(), "{path}: {unexpected:?}");
+    }
+}
+
+#[test]
+fn format_matrix_events_are_all_clean() {
+    for (path, _, _) in matrix_fixtures() {
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+        let mut seen = HashSet::new();
+        while let Some(ag) = scanner.next_ag() {
+            ag.expect("failed to get AG")
+                .scan_inodes(|inode: &InodeInfo| {
+                    seen.insert(inode.provenance);
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan inodes")
+                .scan_file_extents(|fe: &FileExtentsInfo| {
+                    seen.insert(fe.provenance);
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan extents")
+                .scan_dir_entries(|de: &DirEntryInfo| {
+                    seen.insert(de.provenance);
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan dirs");
+        }
+        assert_eq!(seen, HashSet::from([Provenance::Clean]), "{path}");
+    }
+}
+
+/// Scan every phase of `reader`'s image with `options` and return the
+/// warnings.
+fn scan_checked<R: IoReader>(reader: R, options: ScanOptions) -> Result<Vec<ScanWarning>, FxfspError> {
+    let (_sb, mut scanner) = parse_superblock_with_options(reader, options)?;
+    while let Some(ag) = scanner.next_ag() {
+        let mut dirs = ag?
+            .scan_inodes(|_: &InodeInfo| ControlFlow::Continue(()))?
+            .scan_file_extents(|_: &FileExtentsInfo| ControlFlow::Continue(()))?;
+        dirs.scan_symlink_targets(|_: &SymlinkTargetInfo| ControlFlow::Continue(()))?;
+        dirs.scan_xattrs(|_: &XattrInfo| ControlFlow::Continue(()))?;
+        dirs.scan_dir_entries(|_: &DirEntryInfo| ControlFlow::Continue(()))?;
+    }
+    Ok(scanner.take_warnings())
+}
+
+fn crc_options(verify_crcs: CrcVerification) -> ScanOptions {
+    ScanOptions { verify_crcs, ..Default::default() }
+}
+
+/// A reader over the fixture that flips the byte at `target` as it is read.
+fn corrupting_reader(file: &File, target: u64) -> impl IoReader {
+    let file = file.try_clone().unwrap();
+    CallbackReader::new(move |offset, buf: &mut [u8]| {
+        let n = file.read_at(buf, offset)?;
+        if (offset..offset + n as u64).contains(&target) {
+            buf[(target - offset) as usize] ^= 0xff;
+        }
+        Ok(n)
+    })
+}
+
+#[test]
+fn metadata_checks_pass_clean_images() {
+    for (path, _, _) in matrix_fixtures() {
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let options = ScanOptions { verify_uuids: true, ..crc_options(CrcVerification::Strict) };
+        let warnings = scan_checked(engine, options).unwrap_or_else(|e| panic!("{path}: {e}"));
+        assert!(
+            warnings.iter().all(|w| w.code != WarningCode::CrcMismatch && w.code != WarningCode::UuidMismatch),
+            "{path}: {warnings:?}"
+        );
+    }
+}
+
+#[test]
+fn crc_verification_flags_a_corrupt_inode() {
+    if skip_if_missing() { return; }
+    let file = File::open(FIXTURE_PATH).unwrap();
+    let mut sb_buf = vec![0u8; 4096];
+    file.read_at(&mut sb_buf, 0).unwrap();
+    let ctx = FsContext::from_superblock(&sb_buf).unwrap();
+    // The root inode's atime.
+    let target = ctx.locate_inode(ctx.root_ino).unwrap().byte_offset + 32;
+
+    let warnings = scan_checked(corrupting_reader(&file, target), crc_options(CrcVerification::Lenient))
+        .expect("lenient scan failed");
+    let mismatches: Vec<_> = warnings.iter().filter(|w| w.code == WarningCode::CrcMismatch).collect();
+    assert!(!mismatches.is_empty(), "corrupt inode not reported");
+    assert!(mismatches.iter().all(|w| w.ino == Some(ctx.root_ino) && w.ag_number == Some(0)), "{mismatches:?}");
+    assert!(matches!(
+        scan_checked(corrupting_reader(&file, target), crc_options(CrcVerification::Strict)),
+        Err(FxfspError::CrcMismatch("inode"))
+    ));
+    assert!(scan_checked(corrupting_reader(&file, target), crc_options(CrcVerification::Off)).is_ok());
+}
+
+#[test]
+fn uuid_verification_flags_a_foreign_inode() {
+    if skip_if_missing() { return; }
+    let file = File::open(FIXTURE_PATH).unwrap();
+    let mut sb_buf = vec![0u8; 4096];
+    file.read_at(&mut sb_buf, 0).unwrap();
+    let ctx = FsContext::from_superblock(&sb_buf).unwrap();
+    // The first byte of the root inode's di_uuid.
+    let target = ctx.locate_inode(ctx.root_ino).unwrap().byte_offset + 160;
+
+    let options = ScanOptions { verify_uuids: true, ..Default::default() };
+    let warnings = scan_checked(corrupting_reader(&file, target), options).expect("scan failed");
+    let mismatches: Vec<_> = warnings.iter().filter(|w| w.code == WarningCode::UuidMismatch).collect();
+    assert!(!mismatches.is_empty(), "foreign inode not reported");
+    assert!(mismatches.iter().all(|w| w.ino == Some(ctx.root_ino)), "{mismatches:?}");
+    let unchecked = scan_checked(corrupting_reader(&file, target), ScanOptions::default()).expect("scan failed");
+    assert!(unchecked.iter().all(|w| w.code != WarningCode::UuidMismatch));
+}
+
+fn coverage_options() -> ScanOptions {
+    ScanOptions { verify_coverage: true, ..Default::default() }
+}
+
+fn is_coverage_warning(w: &ScanWarning) -> bool {
+    matches!(w.code, WarningCode::InodeCountMismatch | WarningCode::DirectoryNotScanned)
+}
+
+#[test]
+fn coverage_checks_pass_clean_images() {
+    for (path, _, _) in matrix_fixtures() {
+        for dir_work_budget in [0, 1] {
+            let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+            let options = ScanOptions { dir_work_budget, ..coverage_options() };
+            let warnings = scan_checked(engine, options).unwrap_or_else(|e| panic!("{path}: {e}"));
+            assert!(!warnings.iter().any(is_coverage_warning), "{path}: {warnings:?}");
+        }
+    }
+}
+
+#[test]
+fn coverage_check_flags_a_directory_with_only_unwritten_extents() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+    let subdir = r.find_entry(r.root_ino, "subdir").unwrap().child_ino;
+
+    let file = File::open(FIXTURE_PATH).unwrap();
+    let mut sb_buf = vec![0u8; 4096];
+    file.read_at(&mut sb_buf, 0).unwrap();
+    let ctx = FsContext::from_superblock(&sb_buf).unwrap();
+    let inode_at = ctx.locate_inode(subdir).unwrap().byte_offset;
+    let mut inode = vec![0u8; ctx.inode_size as usize];
+    file.read_at(&mut inode, inode_at).unwrap();
+    let info = parse_inode_core(&inode, subdir, true, ctx.has_nrext64, ctx.inode_size).unwrap();
+    // The unwritten flag is the top bit of each extent record.
+    let flags: Vec<u64> =
+        (0..info.nextents as u64).map(|i| inode_at + info.data_fork_offset as u64 + 16 * i).collect();
+    let reader_file = file.try_clone().unwrap();
+    let reader = CallbackReader::new(move |offset, buf: &mut [u8]| {
+        let n = reader_file.read_at(buf, offset)?;
+        for &at in flags.iter().filter(|&&at| (offset..offset + n as u64).contains(&at)) {
+            buf[(at - offset) as usize] |= 0x80;
+        }
+        Ok(n)
+    });
+
+    let warnings = scan_checked(reader, coverage_options()).expect("scan failed");
+    let flagged: Vec<_> = warnings.iter().filter(|w| is_coverage_warning(w)).collect();
+    assert_eq!(flagged.len(), 1, "{warnings:?}");
+    assert_eq!((flagged[0].code, flagged[0].ino), (WarningCode::DirectoryNotScanned, Some(subdir)));
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let unchecked = scan_checked(engine, ScanOptions::default()).expect("scan failed");
+    assert!(!unchecked.iter().any(is_coverage_warning));
+}
+
+#[test]
+fn coverage_check_flags_shortfalls_on_a_synthetic_image() {
+    // / holds the block-format directory /d, whose one data block at fs
+    // block 20 names the file /d/f.
+    let sf = [&[1, 0][..], &64u32.to_be_bytes(), &[1, 0, 0x30], b"d", &65u32.to_be_bytes()].concat();
+    let mut root = v4_inode(512, 0o040755, XFS_DINODE_FMT_LOCAL, &sf);
+    root[56..64].copy_from_slice(&(sf.len() as u64).to_be_bytes());
+    let mut dir = v4_inode(512, 0o040755, XFS_DINODE_FMT_EXTENTS, &bmbt_rec(0, 20, 1));
+    dir[56..64].copy_from_slice(&4096u64.to_be_bytes());
+    dir[76..80].copy_from_slice(&1u32.to_be_bytes());
+    let file = v4_inode(512, 0o100644, XFS_DINODE_FMT_EXTENTS, &[]);
+    let mut image = synthetic_image(&sane_superblock(), 64, &[root, dir, file]);
+    let block = &mut image[20 * 4096..21 * 4096];
+    block[0..4].copy_from_slice(b"XD2D");
+    block[16..24].copy_from_slice(&66u64.to_be_bytes());
+    block[24..26].copy_from_slice(&[1, b'f']);
+    block[32..36].copy_from_slice(&[0xff, 0xff, 0x0f, 0xe0]); // the rest is free
+
+    let coverage = |image: &[u8]| -> Vec<(WarningCode, Option<u64>)> {
+        let warnings = scan_checked(SliceReader::new(image), coverage_options()).expect("scan failed");
+        warnings.iter().filter(|w| is_coverage_warning(w)).map(|w| (w.code, w.ino)).collect()
+    };
+    assert_eq!(coverage(&image), []);
+
+    // /d's only extent unwritten: nothing of it is parsed.
+    let mut unwritten = image.clone();
+    unwritten[65 * 512 + 100] |= 0x80;
+    assert_eq!(coverage(&unwritten), [(WarningCode::DirectoryNotScanned, Some(65))]);
+
+    // The AGI counts one more inode in use than the inobt marks.
+    let mut short = image.clone();
+    short[2 * 512 + 28..][..4].copy_from_slice(&60u32.to_be_bytes());
+    assert_eq!(coverage(&short), [(WarningCode::InodeCountMismatch, None)]);
+}
+
+#[test]
+fn inode_chunks_inside_64k_blocks_are_read_in_place() {
+    // 512-byte inodes in 64 KiB blocks: 128 inodes per block, so a chunk
+    // covers half a block and may start in its middle.
+    let mut sb = sane_superblock();
+    sb[4..8].copy_from_slice(&65536u32.to_be_bytes());
+    sb[106..108].copy_from_slice(&128u16.to_be_bytes()); // inopblock
+    sb[120] = 16; // blocklog
+    sb[123] = 7; // inopblog
+    for first_agino in [256u32, 320] {
+        let root = first_agino as u64;
+        let sf = [&[1, 0][..], &first_agino.to_be_bytes(), &[1, 0, 0x30], b"f", &(first_agino + 1).to_be_bytes()];
+        let sf = sf.concat();
+        let mut dir = v4_inode(512, 0o040755, XFS_DINODE_FMT_LOCAL, &sf);
+        dir[56..64].copy_from_slice(&(sf.len() as u64).to_be_bytes());
+        let file = v4_inode(512, 0o100644, XFS_DINODE_FMT_EXTENTS, &[]);
+        let image = synthetic_image(&sb, first_agino, &[dir, file]);
+
+        let (_, mut scanner) = parse_superblock(SliceReader::new(&image)).expect("failed to parse superblock");
+        let mut inodes = Vec::new();
+        let mut entries = Vec::new();
+        while let Some(ag) = scanner.next_ag() {
+            ag.expect("failed to get AG")
+                .scan_inodes(|inode: &InodeInfo| {
+                    inodes.push((inode.ino, inode.mode));
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan inodes")
+                .skip_extents()
+                .scan_dir_entries(|de: &DirEntryInfo| {
+                    entries.push((de.parent_ino, de.name.to_vec(), de.child_ino));
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan dirs");
+        }
+        assert_eq!(inodes, [(root, 0o040755), (root + 1, 0o100644)], "chunk at {first_agino}");
+        assert!(entries.contains(&(root, b"f".to_vec(), root + 1)), "chunk at {first_agino}");
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Symlinks
+// ---------------------------------------------------------------------------
+
+/// Images holding `/short -> hello.txt` (inline) and `/long` (remote).
+const SYMLINK_FIXTURES: &[&str] = &["tests/fixtures/test_symlinks.xfs", "tests/fixtures/test_symlinks_v4.xfs"];
+
+#[test]
+fn symlink_targets_are_delivered_inline_and_remote() {
+    let long_target: String = (1..=80).map(|i| format!("segment_{i:03}/")).collect();
+    for &path in SYMLINK_FIXTURES {
+        if !Path::new(path).exists() {
+            eprintln!("Skipping: fixture not found at {path}");
+            continue;
+        }
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+        let mut targets = HashMap::new();
+        let mut names = HashMap::new();
+        while let Some(ag) = scanner.next_ag() {
+            let mut dirs = ag
+                .expect("failed to get AG")
+                .scan_inodes(|_: &InodeInfo| ControlFlow::Continue(()))
+                .expect("failed to scan inodes")
+                .skip_extents();
+            dirs.scan_symlink_targets(|link: &SymlinkTargetInfo| {
+                targets.insert(link.ino, link.target.to_vec());
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan symlinks");
+            dirs.scan_dir_entries(|de: &DirEntryInfo| {
+                if de.parent_ino == sb.root_ino {
+                    names.insert(de.name.to_vec(), de.child_ino);
+                }
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+        }
+
+        assert_eq!(targets.len(), 2, "{path}");
+        assert_eq!(targets[&names[&b"short"[..]]], b"hello.txt", "{path}");
+        assert_eq!(targets[&names[&b"long"[..]]], long_target.as_bytes(), "{path}");
+        assert!(scanner.warnings().iter().all(|w| w.code != WarningCode::BadSymlink), "{path}");
+    }
+}
+
+#[test]
+fn symlink_with_a_bad_remote_block_is_not_delivered_truncated() {
+    // V5 link 65 has two remote blocks: block 20 is not a symlink block,
+    // block 22 claims to start the target.
+    let v5_inode = |mode: u16, format: u8, size: u64, nextents: u32, fork: &[u8]| {
+        let mut inode = vec![0u8; 512];
+        inode[0..2].copy_from_slice(b"IN");
+        inode[2..4].copy_from_slice(&mode.to_be_bytes());
+        inode[4..6].copy_from_slice(&[3, format]);
+        inode[16..20].copy_from_slice(&1u32.to_be_bytes()); // nlink
+        inode[56..64].copy_from_slice(&size.to_be_bytes());
+        inode[76..80].copy_from_slice(&nextents.to_be_bytes());
+        inode[96..100].copy_from_slice(&u32::MAX.to_be_bytes()); // next_unlinked
+        inode[176..176 + fork.len()].copy_from_slice(fork);
+        inode
+    };
+    let root = v5_inode(0o040755, XFS_DINODE_FMT_LOCAL, 6, 0, &[0, 0, 0, 0, 0, 64]);
+    let map = [bmbt_rec(0, 20, 1), bmbt_rec(1, 22, 1)].concat();
+    let link = v5_inode(0o120777, XFS_DINODE_FMT_EXTENTS, 4, 2, &map);
+    let mut image = synthetic_image(&v5_superblock(), 64, &[root, link]);
+    let block = &mut image[22 * 4096..][..4096];
+    block[0..12].copy_from_slice(&be_words(&[0x58534c4d, 0, 4])); // XSLM, offset, bytes
+    block[56..60].copy_from_slice(b"tail");
+
+    let (_, mut scanner) = parse_superblock(SliceReader::new(&image)).expect("failed to parse superblock");
+    let mut targets = Vec::new();
+    scanner
+        .next_ag()
+        .expect("no AG")
+        .expect("failed to get AG")
+        .scan_inodes(|_: &InodeInfo| ControlFlow::Continue(()))
+        .expect("failed to scan inodes")
+        .skip_extents()
+        .scan_symlink_targets(|link: &SymlinkTargetInfo| {
+            targets.push(link.target.to_vec());
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan symlinks");
+    assert!(targets.is_empty(), "{targets:?}");
+    let bad: Vec<_> =
+        scanner.warnings().iter().filter(|w| w.code == WarningCode::BadSymlink).map(|w| w.ino).collect();
+    assert_eq!(bad, [Some(65)]);
+}
+
+#[test]
+fn xattrs_are_delivered_from_shortform_and_leaf_forks() {
+    let path = "tests/fixtures/test_xattrs.xfs";
+    if !Path::new(path).exists() {
+        eprintln!("Skipping: fixture not found at {path}");
+        return;
+    }
+    let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut attrs = Vec::new();
+    let mut tagged = None;
+    let mut heavy = None;
+    while let Some(ag) = scanner.next_ag() {
+        let mut dirs = ag
+            .expect("failed to get AG")
+            .scan_inodes(|_: &InodeInfo| ControlFlow::Continue(()))
+            .expect("failed to scan inodes")
+            .skip_extents();
+        dirs.scan_xattrs(|attr: &XattrInfo| {
+            attrs.push((attr.ino, attr.namespace, attr.name.to_vec(), attr.value.to_vec()));
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan xattrs");
+        dirs.scan_dir_entries(|de: &DirEntryInfo| {
+            if de.parent_ino == sb.root_ino && de.name == b"tagged" {
+                tagged = Some(de.child_ino);
+            }
+            if de.parent_ino == sb.root_ino && de.name == b"heavy" {
+                heavy = Some(de.child_ino);
+            }
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan dirs");
+    }
+
+    // Leaf-format fork with a remote value.
+    let heavy = heavy.expect("/heavy not found");
+    let heavy_attrs: HashMap<_, _> =
+        attrs.iter().filter(|a| a.0 == heavy).map(|(_, _, n, v)| (n.clone(), v.clone())).collect();
+    assert_eq!(heavy_attrs.len(), 101);
+    assert_eq!(heavy_attrs[&b"attr_042"[..]], b"value_042");
+    assert_eq!(heavy_attrs[&b"big"[..]], vec![b'x'; 8000]);
+
+    let tagged = tagged.expect("/tagged not found");
+    let mut ours: Vec<_> = attrs.into_iter().filter(|a| a.0 == tagged).map(|(_, ns, n, v)| (ns, n, v)).collect();
+    ours.sort_by(|a, b| a.1.cmp(&b.1));
+    assert_eq!(
+        ours,
+        [
+            (XattrNamespace::User, b"color".to_vec(), b"blue".to_vec()),
+            (XattrNamespace::Trusted, b"origin".to_vec(), b"fixture".to_vec()),
+        ]
+    );
+    assert!(scanner.warnings().iter().all(|w| w.code != WarningCode::BadAttrFork));
+}
+
+#[test]
+fn attr_fork_blocks_follow_the_dabtree_and_leaf_chain() {
+    let ctx = FsContext::from_superblock(&sane_superblock()).expect("failed to parse superblock");
+    // V4 leaf: forw at 0, magic at 8, count at 12, entries (hash, nameidx,
+    // flags) from 32, names and values from `nameidx`.
+    let leaf = |forw: u32, entries: &[(u8, &[u8])]| {
+        let mut block = vec![0u8; 4096];
+        block[0..4].copy_from_slice(&forw.to_be_bytes());
+        block[8..10].copy_from_slice(&0xfbee_u16.to_be_bytes());
+        block[12..14].copy_from_slice(&(entries.len() as u16).to_be_bytes());
+        let mut nameidx = 4096;
+        for (i, (flags, name)) in entries.iter().enumerate() {
+            nameidx -= 64;
+            block[32 + i * 8 + 4..][..2].copy_from_slice(&(nameidx as u16).to_be_bytes());
+            block[32 + i * 8 + 6] = *flags;
+            block[nameidx..nameidx + name.len()].copy_from_slice(name);
+        }
+        block
+    };
+    const LOCAL: u8 = 1;
+    const ROOT: u8 = 1 << 1;
+    const INCOMPLETE: u8 = 1 << 7;
+    let local = |name: &[u8], value: &[u8]| {
+        [&(value.len() as u16).to_be_bytes()[..], &[name.len() as u8], name, value].concat()
+    };
+    let remote = [&be_words(&[5, 6000])[..], &[3], b"big"].concat();
+
+    let mut blocks = vec![vec![0u8; 4096]; 7];
+    // Node at logical block 0 -> leaf 2 -> leaf 3; the value of "big" in 5 and 6.
+    blocks[0][8..10].copy_from_slice(&0xfebe_u16.to_be_bytes());
+    blocks[0][12..16].copy_from_slice(&[0, 1, 0, 1]);
+    blocks[0][20..24].copy_from_slice(&2u32.to_be_bytes());
+    blocks[2] = leaf(3, &[(LOCAL, &local(b"a", b"1")), (0, &remote)]);
+    blocks[3] = leaf(0, &[(LOCAL | ROOT, &local(b"t", b"2")), (LOCAL | INCOMPLETE, &local(b"half", b"x"))]);
+    blocks[5].fill(b'x');
+    blocks[6].fill(b'y');
+
+    let mut attrs = Vec::new();
+    parse_attr_fork_blocks(&ctx, |lblk| blocks.get(lblk as usize).map(Vec::as_slice), 16, &mut |attr| {
+        attrs.push((attr.namespace, attr.name.to_vec(), attr.value.to_vec()));
+        ControlFlow::Continue(())
+    })
+    .expect("failed to walk attr fork");
+    let big = [vec![b'x'; 4096], vec![b'y'; 6000 - 4096]].concat();
+    assert_eq!(attrs, [
+        (XattrNamespace::User, b"a".to_vec(), b"1".to_vec()),
+        (XattrNamespace::User, b"big".to_vec(), big),
+        (XattrNamespace::Trusted, b"t".to_vec(), b"2".to_vec()),
+    ]);
+
+    // A leaf chain that loops is cut off at `max_blocks`.
+    blocks[3][0..4].copy_from_slice(&2u32.to_be_bytes());
+    let looped = parse_attr_fork_blocks(&ctx, |lblk| blocks.get(lblk as usize).map(Vec::as_slice), 16, &mut |_| {
+        ControlFlow::Continue(())
+    });
+    assert!(matches!(looped, Err(FxfspError::Parse(_))));
+}
+
+#[test]
+fn xattrs_of_block_and_shortform_forks_are_delivered_in_inode_order() {
+    // Files 65 and 67 have shortform attr forks, 66 one leaf block at
+    // filesystem block 20. V4 attr forks start `forkoff` * 8 after the
+    // data fork at 100.
+    let with_attrs = |aformat: u8, attrs: &[u8], anextents: u16| {
+        let mut inode = v4_inode(512, 0o100644, XFS_DINODE_FMT_EXTENTS, &[]);
+        inode[80..82].copy_from_slice(&anextents.to_be_bytes());
+        inode[82..84].copy_from_slice(&[2, aformat]);
+        inode[116..116 + attrs.len()].copy_from_slice(attrs);
+        inode
+    };
+    let shortform = |name: &[u8], value: &[u8]| {
+        let entry = [&[name.len() as u8, value.len() as u8, 0][..], name, value].concat();
+        [&(4 + entry.len() as u16).to_be_bytes()[..], &[1, 0], &entry].concat()
+    };
+    let root = v4_inode(512, 0o040755, XFS_DINODE_FMT_LOCAL, &[0, 0, 0, 0, 0, 64]);
+    let files = [
+        with_attrs(XFS_DINODE_FMT_LOCAL, &shortform(b"s1", b"1"), 0),
+        with_attrs(XFS_DINODE_FMT_EXTENTS, &bmbt_rec(0, 20, 1), 1),
+        with_attrs(XFS_DINODE_FMT_LOCAL, &shortform(b"s2", b"2"), 0),
+    ];
+    let mut image = synthetic_image(&sane_superblock(), 64, &[&[root][..], &files].concat());
+    // A V4 leaf holding one local attribute, "a" = "b".
+    let leaf = &mut image[20 * 4096..][..4096];
+    leaf[8..10].copy_from_slice(&0xfbee_u16.to_be_bytes());
+    leaf[12..14].copy_from_slice(&1u16.to_be_bytes());
+    leaf[36..39].copy_from_slice(&[0x0f, 0xc0, 1]); // nameidx 4032, LOCAL
+    leaf[4032..4037].copy_from_slice(&[0, 1, 1, b'a', b'b']);
+
+    let (_, mut scanner) = parse_superblock(SliceReader::new(&image)).expect("failed to parse superblock");
+    let mut attrs = Vec::new();
+    scanner
+        .next_ag()
+        .expect("no AG")
+        .expect("failed to get AG")
+        .scan_inodes(|_: &InodeInfo| ControlFlow::Continue(()))
+        .expect("failed to scan inodes")
+        .skip_extents()
+        .scan_xattrs(|attr: &XattrInfo| {
+            attrs.push((attr.ino, attr.name.to_vec(), attr.value.to_vec()));
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan xattrs");
+    assert_eq!(attrs, [
+        (65, b"s1".to_vec(), b"1".to_vec()),
+        (66, b"a".to_vec(), b"b".to_vec()),
+        (67, b"s2".to_vec(), b"2".to_vec()),
+    ]);
+    assert!(scanner.warnings().iter().all(|w| w.code != WarningCode::BadAttrFork));
+}
+
+// ---------------------------------------------------------------------------
+// Large (node-format) directories
+// ---------------------------------------------------------------------------
+
+/// Images holding `/big` with `entry_0 .. entry_{BIGDIR_ENTRIES - 1}`.
+const BIGDIR_FIXTURES: &[&str] = &["tests/fixtures/test_bigdir.xfs", "tests/fixtures/test_bigdir_16k.xfs"];
+const BIGDIR_ENTRIES: usize = 1_000_000;
+
+#[test]
+fn large_directory_lists_every_entry_exactly_once() {
+    for &path in BIGDIR_FIXTURES {
+        if !Path::new(path).exists() {
+            eprintln!("Skipping: fixture not found at {path}");
+            continue;
+        }
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+
+        let mut big_ino = None;
+        let mut entries: Vec<(u64, Vec<u8>)> = Vec::new();
+        while let Some(ag) = scanner.next_ag() {
+            ag.expect("failed to get AG")
+                .scan_inodes(|_inode: &InodeInfo| ControlFlow::Continue(()))
+                .expect("failed to scan inodes")
+                .skip_extents()
+                .scan_dir_entries(|de: &DirEntryInfo| {
+                    if de.parent_ino == sb.root_ino && de.name == b"big" {
+                        big_ino = Some(de.child_ino);
+                    }
+                    if de.name != b"." && de.name != b".." {
+                        entries.push((de.parent_ino, de.name.to_vec()));
+                    }
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan dirs");
+        }
+        assert!(scanner.warnings().is_empty(), "{path}: {:?}", scanner.warnings());
+
+        let big_ino = big_ino.expect("/big not found");
+        let names: Vec<&[u8]> =
+            entries.iter().filter(|(parent, _)| *parent == big_ino).map(|(_, name)| name.as_slice()).collect();
+        assert_eq!(names.len(), BIGDIR_ENTRIES, "{path}: entry count");
+        let unique: HashSet<&[u8]> = names.iter().copied().collect();
+        assert_eq!(unique.len(), BIGDIR_ENTRIES, "{path}: duplicate entries");
+        for i in [0, BIGDIR_ENTRIES / 2, BIGDIR_ENTRIES - 1] {
+            assert!(unique.contains(format!("entry_{i}").as_bytes()), "{path}: entry_{i} missing");
+        }
+    }
+}
+
+/// Every `(parent, child, name)` entry of the image, sorted.
+#[test]
+fn directory_block_split_across_extents_is_stitched() {
+    // 8 KiB directory blocks in 4 KiB fs blocks, the only one mapped by
+    // two extents that are not adjacent on disk.
+    let mut sb = sane_superblock();
+    sb[192] = 1; // dirblklog
+    sb[200..204].copy_from_slice(&0x200u32.to_be_bytes()); // ftype
+    let mut dir = v4_inode(512, 0o040755, XFS_DINODE_FMT_EXTENTS, &[bmbt_rec(0, 20, 1), bmbt_rec(1, 24, 1)].concat());
+    dir[56..64].copy_from_slice(&8192u64.to_be_bytes());
+    dir[76..80].copy_from_slice(&2u32.to_be_bytes());
+    let file = v4_inode(512, 0o100644, XFS_DINODE_FMT_EXTENTS, &[]);
+    let mut image = synthetic_image(&sb, 64, &[dir, file.clone(), file]);
+
+    // "a" in the first fs block, "b" in the second, free space around them.
+    let mut block = vec![0u8; 8192];
+    block[0..4].copy_from_slice(b"XD2D");
+    for (at, ino, name) in [(16, 65u64, b"a"), (4112, 66, b"b")] {
+        block[at..at + 8].copy_from_slice(&ino.to_be_bytes());
+        block[at + 8..at + 11].copy_from_slice(&[1, name[0], 1]);
+    }
+    for (at, len) in [(32u16, 4080u16), (4128, 4064)] {
+        block[at as usize..at as usize + 4].copy_from_slice(&[0xff, 0xff, (len >> 8) as u8, len as u8]);
+    }
+    image[20 * 4096..21 * 4096].copy_from_slice(&block[..4096]);
+    image[24 * 4096..25 * 4096].copy_from_slice(&block[4096..]);
+
+    assert_eq!(synthetic_dir_entries(&image), [(64, b"a".to_vec(), 65), (64, b"b".to_vec(), 66)]);
+}
+
+fn sorted_entries(path: &str, options: ScanOptions) -> Vec<(u64, u64, Vec<u8>)> {
+    let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock_with_options(engine, options).expect("failed to parse superblock");
+    let mut entries = Vec::new();
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|_inode: &InodeInfo| ControlFlow::Continue(()))
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| {
+                entries.push((de.parent_ino, de.child_ino, de.name.to_vec()));
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+    }
+    assert!(scanner.warnings().is_empty(), "{path}: {:?}", scanner.warnings());
+    entries.sort_unstable();
+    entries
+}
+
+#[test]
+fn dir_work_budget_defers_directories_without_losing_entries() {
+    let paths = matrix_fixtures().map(|(path, _, _)| path).chain(BIGDIR_FIXTURES.iter().copied());
+    for path in paths {
+        if !Path::new(path).exists() {
+            continue;
+        }
+        let unbounded = sorted_entries(path, ScanOptions::default());
+        // A one-byte budget holds one directory at a time.
+        let bounded = sorted_entries(path, ScanOptions { dir_work_budget: 1, ..Default::default() });
+        assert_eq!(bounded.len(), unbounded.len(), "{path}");
+        assert!(bounded == unbounded, "{path}: entries differ");
+    }
+}
+
+#[test]
+fn skip_dot_entries_drops_exactly_the_dot_entries() {
+    for (path, _, _) in matrix_fixtures() {
+        let mut all = sorted_entries(path, ScanOptions::default());
+        let named = sorted_entries(path, ScanOptions { skip_dot_entries: true, ..Default::default() });
+        let dots = all.iter().filter(|(_, _, name)| name == b"." || name == b"..").count();
+        assert!(dots > 0, "{path}: no dot entries to skip");
+        all.retain(|(_, _, name)| name != b"." && name != b"..");
+        assert!(named == all, "{path}: entries differ beyond the dot entries");
+    }
+}
+
+#[test]
+fn entry_locations_are_unique_and_names_are_never_duplicated() {
+    let paths = matrix_fixtures().map(|(path, _, _)| path).chain(BIGDIR_FIXTURES.iter().copied());
+    for path in paths {
+        if !Path::new(path).exists() {
+            continue;
+        }
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+        let mut dups = DuplicateNames::new();
+        let mut locations = HashSet::new();
+        while let Some(ag) = scanner.next_ag() {
+            ag.expect("failed to get AG")
+                .scan_inodes(|_inode: &InodeInfo| ControlFlow::Continue(()))
+                .expect("failed to scan inodes")
+                .skip_extents()
+                .scan_dir_entries(|de: &DirEntryInfo| {
+                    if let Some(loc) = de.location {
+                        assert!(locations.insert((de.parent_ino, loc)), "{path}: {loc:?} delivered twice");
+                    }
+                    dups.add_dir_entry(de);
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan dirs");
+            dups.end_ag();
+        }
+        assert!(!locations.is_empty(), "{path}: no entry locations");
+        assert_eq!(dups.finish(), Vec::new(), "{path}");
+    }
+}
+
+#[test]
+fn prefetched_ag_headers_skip_empty_ags_without_losing_events() {
+    for (path, _, _) in matrix_fixtures() {
+        let options = ScanOptions { prefetch_ag_headers: true, ..Default::default() };
+        assert!(sorted_entries(path, options.clone()) == sorted_entries(path, ScanOptions::default()), "{path}");
+
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (sb, mut scanner) = parse_superblock_with_options(engine, options).expect("failed to parse superblock");
+        let mut inodes = 0;
+        let mut opened = 0;
+        while let Some(ag) = scanner.next_ag() {
+            let ag = ag.expect("failed to get AG");
+            assert!(ag.agi().count > 0, "{path}: AG {} has no inodes", ag.ag_number());
+            opened += 1;
+            ag.scan_inodes(|_inode: &InodeInfo| {
+                inodes += 1;
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .skip_dirs()
+            .expect("failed to skip dirs");
+        }
+        assert!(opened <= sb.ag_count, "{path}");
+        assert_eq!(inodes, ScanResult::collect_from(path).inodes.len(), "{path}");
+    }
+}
+
+/// A reader over `image` that records the requests of every directory
+/// batch handed to it.
+struct BatchLog {
+    image: Vec<u8>,
+    phase: IoPhase,
+    batches: Rc<RefCell<Vec<Vec<u64>>>>,
+}
+
+impl IoReader for BatchLog {
+    fn read_at(&mut self, offset: u64, len: usize, _phase: IoPhase) -> Result<&[u8], FxfspError> {
+        self.image.get(offset as usize..offset as usize + len).ok_or(FxfspError::Parse("read past the image"))
+    }
+
+    fn coalesced_read_batch<T: Copy, F>(
+        &mut self,
+        requests: &[(u64, usize, T)],
+        mut on_complete: F,
+        phase: IoPhase,
+    ) -> Result<(), FxfspError>
+    where
+        F: FnMut(&[u8], T) -> Result<(), FxfspError>,
+    {
+        if phase == self.phase {
+            self.batches.borrow_mut().push(requests.iter().map(|r| r.0).collect());
+        }
+        for &(offset, len, tag) in requests {
+            on_complete(&self.image[offset as usize..offset as usize + len], tag)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn reads_are_not_coalesced_into_an_empty_ag() {
+    // Two AGs of 64 blocks; AG 1 holds no inodes. Directory 65 has a data
+    // block near the end of AG 0 and one at the start of AG 1.
+    let mut sb = sane_superblock();
+    sb[8..16].copy_from_slice(&128u64.to_be_bytes()); // dblocks
+    sb[84..92].copy_from_slice(&be_words(&[64, 2])); // agblocks, agcount
+    sb[124] = 6; // agblklog
+    let sf = [&[1, 0][..], &64u32.to_be_bytes(), &[1, 0, 0x30], b"d", &65u32.to_be_bytes()].concat();
+    let mut root = v4_inode(512, 0o040755, XFS_DINODE_FMT_LOCAL, &sf);
+    root[56..64].copy_from_slice(&(sf.len() as u64).to_be_bytes());
+    let mut dir = v4_inode(512, 0o040755, XFS_DINODE_FMT_EXTENTS, &[bmbt_rec(0, 60, 1), bmbt_rec(1, 66, 1)].concat());
+    dir[56..64].copy_from_slice(&8192u64.to_be_bytes());
+    dir[76..80].copy_from_slice(&2u32.to_be_bytes());
+    let mut image = synthetic_image(&sb, 64, &[root, dir]);
+    image.resize(128 * 4096, 0);
+    copy_first_ag(&mut image, 1, false);
+    for (fsblock, name) in [(60, b'a'), (66, b'b')] {
+        let block = &mut image[fsblock * 4096..][..4096];
+        block[0..4].copy_from_slice(b"XD2D");
+        block[16..24].copy_from_slice(&64u64.to_be_bytes());
+        block[24..26].copy_from_slice(&[1, name]);
+        block[32..36].copy_from_slice(&[0xff, 0xff, 0x0f, 0xe0]);
+    }
+
+    let dir_batches = Rc::default();
+    let reader = BatchLog { image, phase: IoPhase::DirExtents, batches: Rc::clone(&dir_batches) };
+    let options = ScanOptions { prefetch_ag_headers: true, ..Default::default() };
+    let (_, mut scanner) = parse_superblock_with_options(reader, options).expect("failed to parse superblock");
+    let mut names = Vec::new();
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|_: &InodeInfo| ControlFlow::Continue(()))
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| {
+                names.push((de.parent_ino, de.name.to_vec()));
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+    }
+    assert!(names.contains(&(65, b"a".to_vec())) && names.contains(&(65, b"b".to_vec())), "{names:?}");
+    assert_eq!(*dir_batches.borrow(), [vec![60 * 4096], vec![66 * 4096]]);
+}
+
+/// A one-AG image whose directory 65 has three data blocks, at blocks 20,
+/// 22 and 24, naming `a`, `b` and `c`.
+fn three_block_dir_image() -> Vec<u8> {
+    let sf = [&[1, 0][..], &64u32.to_be_bytes(), &[1, 0, 0x30], b"d", &65u32.to_be_bytes()].concat();
+    let mut root = v4_inode(512, 0o040755, XFS_DINODE_FMT_LOCAL, &sf);
+    root[56..64].copy_from_slice(&(sf.len() as u64).to_be_bytes());
+    let map = [bmbt_rec(0, 20, 1), bmbt_rec(1, 22, 1), bmbt_rec(2, 24, 1)].concat();
+    let mut dir = v4_inode(512, 0o040755, XFS_DINODE_FMT_EXTENTS, &map);
+    dir[56..64].copy_from_slice(&(3 * 4096u64).to_be_bytes());
+    dir[76..80].copy_from_slice(&3u32.to_be_bytes());
+    let mut image = synthetic_image(&sane_superblock(), 64, &[root, dir]);
+    for (fsblock, name) in [(20, b'a'), (22, b'b'), (24, b'c')] {
+        let block = &mut image[fsblock * 4096..][..4096];
+        block[0..4].copy_from_slice(b"XD2D");
+        block[16..24].copy_from_slice(&64u64.to_be_bytes());
+        block[24..26].copy_from_slice(&[1, name]);
+        block[32..36].copy_from_slice(&[0xff, 0xff, 0x0f, 0xe0]);
+    }
+    image
+}
+
+#[test]
+fn event_limit_splits_directory_reads_into_slices() {
+    let image = three_block_dir_image();
+    let dir_batches = |limit: usize| {
+        let batches = Rc::default();
+        let reader = BatchLog { image: image.clone(), phase: IoPhase::DirExtents, batches: Rc::clone(&batches) };
+        let options = ScanOptions { max_unconsumed_events: limit, ..Default::default() };
+        let (_, mut scanner) = parse_superblock_with_options(reader, options).expect("failed to parse superblock");
+        let handle = scanner.scan_handle();
+        let mut names = Vec::new();
+        scanner
+            .next_ag()
+            .expect("no AG")
+            .expect("failed to get AG")
+            .scan_inodes(|_: &InodeInfo| {
+                handle.consumed(1);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| {
+                if de.parent_ino == 65 && !de.is_dot() {
+                    names.push(de.name.to_vec());
+                }
+                handle.consumed(1);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+        assert_eq!(names, [&b"a"[..], b"b", b"c"]);
+        batches.take()
+    };
+    let blocks = [20 * 4096, 22 * 4096, 24 * 4096];
+    assert_eq!(dir_batches(0), [blocks.to_vec()]);
+    assert_eq!(dir_batches(1), blocks.map(|offset| vec![offset]));
+}
+
+#[test]
+fn deadline_stops_a_phase_between_read_slices() {
+    // The event limit reads each directory block in its own slice; the
+    // budget runs out while the first is delivered.
+    let deadline = std::time::Duration::from_millis(500);
+    let batches = Rc::default();
+    let image = three_block_dir_image();
+    let reader = BatchLog { image, phase: IoPhase::DirExtents, batches: Rc::clone(&batches) };
+    let options = ScanOptions { max_unconsumed_events: 1, deadline: Some(deadline), ..Default::default() };
+    let (_, mut scanner) = parse_superblock_with_options(reader, options).expect("failed to parse superblock");
+    let handle = scanner.scan_handle();
+    let mut names = Vec::new();
+    scanner
+        .next_ag()
+        .expect("no AG")
+        .expect("failed to get AG")
+        .scan_inodes(|_: &InodeInfo| {
+            handle.consumed(1);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes")
+        .skip_extents()
+        .scan_dir_entries(|de: &DirEntryInfo| {
+            if de.parent_ino == 65 && !de.is_dot() {
+                names.push(de.name.to_vec());
+                std::thread::sleep(deadline);
+            }
+            handle.consumed(1);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan dirs");
+    assert_eq!(names, [b"a"]);
+    assert_eq!(*batches.borrow(), [vec![20 * 4096]]);
+    assert_eq!(handle.deadline_stops(), [DeadlineStop { ag_number: 0, phase: IoPhase::DirExtents }]);
+}
+
+#[test]
+fn shortform_entries_delivered_with_inodes_match_dir_phase() {
+    for (path, _, _) in matrix_fixtures() {
+        let deferred = sorted_entries(path, ScanOptions::default());
+
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+        let mut inline = Vec::new();
+        let mut later = Vec::new();
+        while let Some(ag) = scanner.next_ag() {
+            ag.expect("failed to get AG")
+                .scan_inodes_with_shortform_dirs(
+                    |_inode: &InodeInfo| ControlFlow::Continue(()),
+                    |de: &DirEntryInfo| {
+                        inline.push((de.parent_ino, de.child_ino, de.name.to_vec()));
+                        ControlFlow::Continue(())
+                    },
+                )
+                .expect("failed to scan inodes")
+                .skip_extents()
+                .scan_dir_entries(|de: &DirEntryInfo| {
+                    later.push((de.parent_ino, de.child_ino, de.name.to_vec()));
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan dirs");
+        }
+
+        // The fixture tree has shortform directories, so some entries move.
+        assert!(!inline.is_empty(), "{path}: no shortform entries delivered early");
+        let inline_dirs: HashSet<u64> = inline.iter().map(|e| e.0).collect();
+        assert!(later.iter().all(|e| !inline_dirs.contains(&e.0)), "{path}: directory delivered twice");
+
+        let mut all = [inline, later].concat();
+        all.sort_unstable();
+        assert!(all == deferred, "{path}: entries differ");
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Btree-format regular files
+// ---------------------------------------------------------------------------
+
+/// Image holding `/fragmented`: one 4 KiB block written every 8 KiB.
+const BMBT_FIXTURE: &str = "tests/fixtures/test_bmbt.xfs";
+const BMBT_EXTENTS: u64 = 40_000;
+
+#[test]
+fn multi_level_bmbt_file_has_complete_ordered_extent_map() {
+    if !Path::new(BMBT_FIXTURE).exists() {
+        eprintln!("Skipping: fixture not found at {BMBT_FIXTURE}");
+        return;
+    }
+    let r = ScanResult::collect_from(BMBT_FIXTURE);
+    let entry = r.find_entry(r.root_ino, "fragmented").expect("/fragmented not found");
+    let inode = &r.inodes[&entry.child_ino];
+    let extents = r.file_extents.get(&entry.child_ino).expect("no extents reported");
+
+    assert_eq!(extents.len() as u64, BMBT_EXTENTS);
+    for (i, ext) in extents.iter().enumerate() {
+        assert_eq!(ext.logical_offset, 2 * i as u64 * 4096 / r.block_size as u64, "extent {i} out of place");
+        assert_eq!(ext.block_count * r.block_size as u64, 4096, "extent {i} length");
+    }
+
+    // di_nblocks also counts the bmbt blocks: at least one node and the
+    // leaves below it, and far fewer than the data blocks.
+    let data_blocks: u64 = extents.iter().map(|e| e.block_count).sum();
+    let bmbt_blocks = inode.nblocks - data_blocks;
+    assert!(bmbt_blocks >= 2 && bmbt_blocks < data_blocks / 100, "bmbt blocks: {bmbt_blocks}");
+}
+
+#[test]
+fn btree_format_file_on_synthetic_image_has_its_whole_map() {
+    // /file: a bmbt root in the inode pointing at two leaves of 3 and 2
+    // one-block extents, every other block of the file written.
+    let mut root = vec![0u8; 412];
+    root[0..4].copy_from_slice(&[0, 1, 0, 2]);
+    // (412 - 4) / 16 key slots: pointers from byte 204.
+    root[204..220].copy_from_slice(&[24u64.to_be_bytes(), 20u64.to_be_bytes()].concat());
+    let mut file = v4_inode(512, 0o100644, XFS_DINODE_FMT_BTREE, &root);
+    file[76..80].copy_from_slice(&5u32.to_be_bytes());
+    let sf_root = [&[1, 0][..], &64u32.to_be_bytes(), &[4, 0, 0x60], b"file", &65u32.to_be_bytes()].concat();
+    let mut dir = v4_inode(512, 0o040755, XFS_DINODE_FMT_LOCAL, &sf_root);
+    dir[56..64].copy_from_slice(&(sf_root.len() as u64).to_be_bytes());
+    let mut image = synthetic_image(&sane_superblock(), 64, &[dir, file]);
+    for (block, logicals) in [(24u64, &[0u64, 2, 4][..]), (20, &[6, 8])] {
+        let leaf = &mut image[block as usize * 4096..][..4096];
+        leaf[0..4].copy_from_slice(b"BMAP");
+        leaf[6..8].copy_from_slice(&(logicals.len() as u16).to_be_bytes());
+        for (i, &logical) in logicals.iter().enumerate() {
+            leaf[24 + i * 16..][..16].copy_from_slice(&bmbt_rec(logical, 30 + logical, 1));
+        }
+    }
+
+    let (_, mut scanner) = parse_superblock(SliceReader::new(&image)).expect("failed to parse superblock");
+    let mut maps: HashMap<u64, Vec<(u64, u64)>> = HashMap::new();
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|_: &InodeInfo| ControlFlow::Continue(()))
+            .expect("failed to scan inodes")
+            .scan_file_extents(|info: &FileExtentsInfo| {
+                let extents = info.extents.iter().map(|e| (e.logical_offset, e.block_count));
+                maps.entry(info.ino).or_default().extend(extents);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan extents")
+            .skip_dirs()
+            .expect("failed to skip dirs");
+    }
+    assert_eq!(maps.keys().collect::<Vec<_>>(), [&65]);
+    assert_eq!(maps[&65], [(0, 1), (2, 1), (4, 1), (6, 1), (8, 1)]);
+}
+
+#[test]
+fn bmbt_walk_emits_leaves_in_key_order() {
+    let ctx = FsContext::from_superblock(&sane_superblock()).expect("failed to parse superblock");
+    // A V4 bmbt block: 24-byte header, then keys or records; a node's
+    // pointers follow (4096 - 24) / 16 key slots.
+    let block = |level: u16, body: &[u8], ptrs: &[u64]| {
+        let mut block = vec![0u8; 4096];
+        block[0..4].copy_from_slice(b"BMAP");
+        block[4..6].copy_from_slice(&level.to_be_bytes());
+        let numrecs = if level == 0 { body.len() / 16 } else { ptrs.len() };
+        block[6..8].copy_from_slice(&(numrecs as u16).to_be_bytes());
+        block[24..24 + body.len()].copy_from_slice(body);
+        let ptrs: Vec<u8> = ptrs.iter().flat_map(|p| p.to_be_bytes()).collect();
+        block[2056..2056 + ptrs.len()].copy_from_slice(&ptrs);
+        block
+    };
+    // Root (level 2, in the fork) -> nodes at 5 and 3 -> leaves 9, 2 and 4:
+    // key order runs against disk order at every level.
+    let mut image = vec![0u8; 10 * 4096];
+    for (at, buf) in [
+        (5, block(1, &[], &[9, 2])),
+        (3, block(1, &[], &[4])),
+        (9, block(0, &bmbt_rec(0, 100, 1), &[])),
+        (2, block(0, &bmbt_rec(1, 200, 1), &[])),
+        (4, block(0, &[bmbt_rec(2, 300, 1), bmbt_rec(3, 50, 2)].concat(), &[])),
+    ] {
+        image[at * 4096..][..4096].copy_from_slice(&buf);
+    }
+    // Two key slots in a 36-byte fork: pointers from byte 20.
+    let mut fork = vec![0u8; 36];
+    fork[0..4].copy_from_slice(&[0, 2, 0, 2]);
+    fork[20..36].copy_from_slice(&[5u64.to_be_bytes(), 3u64.to_be_bytes()].concat());
+    let inputs = [BmbtDirInput { ino: 131, fork_data: &fork, data_fork_size: 36 }];
+
+    let mut leaves = Vec::new();
+    walk_bmbt_extents(&mut SliceReader::new(&image), &ctx, &inputs, 1, |ino, extents| {
+        leaves.push((ino, extents.iter().map(|e| (e.logical_offset, e.ag_block)).collect::<Vec<_>>()));
+        Ok(())
+    })
+    .expect("walk failed");
+    assert_eq!(leaves, [(131, vec![(0, 100)]), (131, vec![(1, 200)]), (131, vec![(2, 300), (3, 50)])]);
+
+    // A leaf where the parent expects a node.
+    image[5 * 4096..][..4096].copy_from_slice(&block(0, &[], &[]));
+    let walked = walk_bmbt_extents(&mut SliceReader::new(&image), &ctx, &inputs, 0, |_, _| Ok(()));
+    assert!(walked.is_err(), "level mismatch accepted");
+}
+
+#[test]
+fn large_extent_maps_stream_in_bounded_events() {
+    if !Path::new(BMBT_FIXTURE).exists() {
+        eprintln!("Skipping: fixture not found at {BMBT_FIXTURE}");
+        return;
+    }
+    let whole = ScanResult::collect_from(BMBT_FIXTURE);
+    let ino = whole.find_entry(whole.root_ino, "fragmented").expect("/fragmented not found").child_ino;
+
+    const MAX: usize = 1000;
+    let engine = IoEngine::open(BMBT_FIXTURE, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let options = ScanOptions { max_extents_per_event: MAX, ..Default::default() };
+    let (_sb, mut scanner) = parse_superblock_with_options(engine, options).expect("failed to parse superblock");
+    let mut events: Vec<(u64, usize, bool)> = Vec::new();
+    let mut streamed: Vec<Extent> = Vec::new();
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|_inode: &InodeInfo| ControlFlow::Continue(()))
+            .expect("failed to scan inodes")
+            .scan_file_extents(|fe: &FileExtentsInfo| {
+                events.push((fe.ino, fe.extents.len(), fe.more));
+                if fe.ino == ino {
+                    streamed.extend(fe.extents.iter().cloned());
+                }
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan extents")
+            .skip_dirs()
+            .expect("failed to skip dirs");
+    }
+
+    let ours: Vec<_> = events.iter().filter(|e| e.0 == ino).collect();
+    assert!(ours.len() as u64 >= BMBT_EXTENTS / MAX as u64, "{} events", ours.len());
+    assert!(ours.iter().all(|e| e.1 < 2 * MAX), "oversized event");
+    assert!(ours[..ours.len() - 1].iter().all(|e| e.2) && !ours[ours.len() - 1].2, "more flags");
+    let first = events.iter().position(|e| e.0 == ino).unwrap();
+    assert!(events[first..first + ours.len()].iter().all(|e| e.0 == ino), "events not consecutive");
+
+    let offsets = |exts: &[Extent]| exts.iter().map(|e| (e.logical_offset, e.block_count)).collect::<Vec<_>>();
+    assert_eq!(offsets(&streamed), offsets(&whole.file_extents[&ino]));
+}
+
+#[test]
+fn packed_extents_round_trip_and_find() {
+    if !Path::new(BMBT_FIXTURE).exists() {
+        eprintln!("Skipping: fixture not found at {BMBT_FIXTURE}");
+        return;
+    }
+    let r = ScanResult::collect_from(BMBT_FIXTURE);
+    let mut sb_buf = vec![0u8; 4096];
+    File::open(BMBT_FIXTURE).unwrap().read_at(&mut sb_buf, 0).unwrap();
+    let ctx = FsContext::from_superblock(&sb_buf).unwrap();
+
+    for (ino, extents) in &r.file_extents {
+        let packed = PackedExtents::from_extents(&ctx, extents);
+        assert_eq!(packed.len(), extents.len(), "ino {ino}");
+        for (a, b) in packed.iter().zip(extents) {
+            assert_eq!(
+                (a.logical_offset, a.ag_number, a.ag_block, a.block_count, a.is_unwritten),
+                (b.logical_offset, b.ag_number, b.ag_block, b.block_count, b.is_unwritten),
+                "ino {ino}"
+            );
+        }
+    }
+
+    let entry = r.find_entry(r.root_ino, "fragmented").expect("/fragmented not found");
+    let extents = &r.file_extents[&entry.child_ino];
+    let packed = PackedExtents::from_extents(&ctx, extents);
+    assert!(packed.heap_bytes() * 4 < extents.len() * size_of::<Extent>(), "{} bytes", packed.heap_bytes());
+    for ext in [&extents[0], &extents[12_345], &extents[extents.len() - 1]] {
+        let found = packed.find(ext.logical_offset).expect("mapped block not found");
+        assert_eq!((found.ag_number, found.ag_block), (ext.ag_number, ext.ag_block));
+        // The next block is a hole.
+        assert!(packed.find(ext.logical_end()).is_none());
+    }
+}
+
+/// `/sparse` in the same image: one 4 KiB block at each of these offsets.
+const SPARSE_OFFSETS: [u64; 3] = [0, 1 << 50, 1 << 62];
+
+#[test]
+fn huge_sparse_file_keeps_extreme_offsets() {
+    if !Path::new(BMBT_FIXTURE).exists() {
+        eprintln!("Skipping: fixture not found at {BMBT_FIXTURE}");
+        return;
+    }
+    let r = ScanResult::collect_from(BMBT_FIXTURE);
+    let entry = r.find_entry(r.root_ino, "sparse").expect("/sparse not found");
+    let inode = &r.inodes[&entry.child_ino];
+    let extents = r.file_extents.get(&entry.child_ino).expect("no extents reported");
+    let block_size = r.block_size as u64;
+
+    assert_eq!(inode.size, SPARSE_OFFSETS[2] + 4096);
+    assert_eq!(inode.nblocks * block_size, 3 * 4096);
+    let starts: Vec<u64> = extents.iter().map(|e| e.logical_offset * block_size).collect();
+    assert_eq!(starts, SPARSE_OFFSETS);
+
+    // Windows straddling each block: zeros from the hole, then the data.
+    for offset in SPARSE_OFFSETS {
+        let window = offset.saturating_sub(4096)..offset + 4096;
+        let data = read_file_range(BMBT_FIXTURE, extents, inode.size, window.clone());
+        let hole = (offset - window.start) as usize;
+        assert!(data[..hole].iter().all(|&b| b == 0), "hole before {offset:#x}");
+        assert!(data[hole..].iter().all(|&b| b == 0x5a), "data at {offset:#x}");
+    }
+}
+
+#[test]
+fn extent_byte_range_rejects_offsets_past_u64() {
+    // Only the block size matters; 4K blocks put the overflow at 2^52.
+    let ctx = FsContext::from_superblock(&sane_superblock()).unwrap();
+    let extent = |logical_offset, block_count| Extent {
+        logical_offset,
+        ag_number: 0,
+        ag_block: 0,
+        block_count,
+        is_unwritten: false,
+    };
+
+    let max_offset = (1u64 << 54) - 1;
+    let last = extent(max_offset, 0x1F_FFFF);
+    assert_eq!(last.logical_end(), max_offset + 0x1F_FFFF);
+    assert_eq!(last.logical_byte_range(&ctx), None);
+
+    let fits = (u64::MAX >> ctx.block_log) - 1;
+    let range = extent(fits, 1).logical_byte_range(&ctx).unwrap();
+    assert_eq!(range, fits << ctx.block_log..(fits + 1) << ctx.block_log);
+}
+
+#[test]
+fn inode_core_fast_path_fields() {
+    // No fixture needed: nrext64 and device inodes aren't in the images.
+    let mut buf = vec![0u8; 512];
+    buf[0..2].copy_from_slice(&0x494e_u16.to_be_bytes());
+    buf[2..4].copy_from_slice(&(0o020644_u16).to_be_bytes());
+    buf[4] = 3;
+    buf[5] = XFS_DINODE_FMT_DEV;
+    buf[24..32].copy_from_slice(&0x0000_0000_0012_3456_u64.to_be_bytes());
+    buf[76..80].copy_from_slice(&7_u32.to_be_bytes());
+    buf[96..100].copy_from_slice(&42_u32.to_be_bytes());
+    buf[20..22].copy_from_slice(&0x0002_u16.to_be_bytes()); // di_projid
+    buf[22..24].copy_from_slice(&0x0001_u16.to_be_bytes()); // di_projid_hi
+    buf[90..92].copy_from_slice(&0x0088_u16.to_be_bytes()); // immutable, nodump
+    buf[120..128].copy_from_slice(&0x22_u64.to_be_bytes()); // reflink and an unnamed bit
+    buf[176..180].copy_from_slice(&0x0800_0001_u32.to_be_bytes());
+
+    let info = parse_inode_core(&buf, 1234, true, true, 512).expect("failed to parse inode");
+    assert_eq!((info.nextents, info.anextents), (0x12_3456, 7));
+    assert_eq!(info.next_unlinked, 42);
+    assert_eq!(info.rdev, Some(0x0800_0001));
+    assert_eq!(info.projid, 0x1_0002);
+    assert_eq!(InodeFlags::from_bits_retain(info.flags), InodeFlags::IMMUTABLE | InodeFlags::NODUMP);
+    let flags2 = InodeFlags2::from_bits_retain(info.flags2);
+    assert!(flags2.contains(InodeFlags2::REFLINK) && !flags2.contains(InodeFlags2::DAX));
+    assert_eq!(flags2.bits(), 0x22, "unnamed bits were dropped");
+    // sda1 (8:1) and a minor past 8 bits (4:300).
+    assert_eq!(decode_rdev(0x0020_0001), (8, 1));
+    assert_eq!(decode_rdev((4 << 18) | 300), (4, 300));
+
+    let info = parse_inode_core(&buf, 1234, true, false, 512).expect("failed to parse inode");
+    assert_eq!(info.nextents, 7);
+
+    buf[0] = 0;
+    assert!(parse_inode_core(&buf, 1234, true, true, 512).is_err());
+}
+
+#[test]
+fn v4_data_fork_follows_next_unlinked() {
+    // A v2 inode: di_next_unlinked at 96, the data fork from 100.
+    let mut buf = vec![0u8; 256];
+    buf[0..2].copy_from_slice(&0x494e_u16.to_be_bytes());
+    buf[2..4].copy_from_slice(&(0o060644_u16).to_be_bytes());
+    buf[4] = 2;
+    buf[5] = XFS_DINODE_FMT_DEV;
+    buf[96..100].copy_from_slice(&0xffff_ffff_u32.to_be_bytes());
+    buf[100..104].copy_from_slice(&0x0020_0001_u32.to_be_bytes());
+
+    let info = parse_inode_core(&buf, 128, false, false, 256).expect("failed to parse inode");
+    assert_eq!(info.data_fork_offset, 100);
+    assert_eq!(info.data_fork_size, 156);
+    assert_eq!(info.next_unlinked, 0xffff_ffff);
+    assert_eq!(info.rdev.map(decode_rdev), Some((8, 1)));
+}
+
+#[test]
+fn single_btree_block_decodes_without_a_scan() {
+    if skip_if_missing() { return; }
+    let file = File::open(FIXTURE_PATH).unwrap();
+    let mut sb_buf = vec![0u8; 4096];
+    file.read_at(&mut sb_buf, 0).unwrap();
+    let ctx = FsContext::from_superblock(&sb_buf).unwrap();
+    let mut agi_buf = vec![0u8; ctx.sect_size as usize];
+    file.read_at(&mut agi_buf, ctx.agi_byte_offset(0)).unwrap();
+    let agi = AgiInfo::from_buf(&agi_buf, 0, ctx.version).unwrap();
+
+    let mut block = vec![0u8; ctx.block_size as usize];
+    file.read_at(&mut block, ctx.ag_block_to_byte(0, agi.inobt_root)).unwrap();
+    match parse_inobt_block(&block, &ctx).expect("failed to parse inobt root") {
+        InobtBlock::Leaf(records) => {
+            let in_use: u32 = records.iter().map(|r| r.allocated_mask().count_ones()).sum();
+            assert_eq!(in_use, agi.count - agi.free_count);
+        }
+        InobtBlock::Node { level, children } => {
+            assert_eq!(level as u32, agi.inobt_level - 1);
+            assert!(!children.is_empty());
+        }
+    }
+    assert!(parse_bmbt_block(&block, &ctx).is_err(), "an inobt block is not a bmbt block");
+}
+
+#[test]
+fn inode_locations_match_their_inobt_records() {
+    if skip_if_missing() { return; }
+    let file = File::open(FIXTURE_PATH).unwrap();
+    let mut sb_buf = vec![0u8; 4096];
+    file.read_at(&mut sb_buf, 0).unwrap();
+    let ctx = FsContext::from_superblock(&sb_buf).unwrap();
+    let mut agi_buf = vec![0u8; ctx.sect_size as usize];
+    file.read_at(&mut agi_buf, ctx.agi_byte_offset(0)).unwrap();
+    let agi = AgiInfo::from_buf(&agi_buf, 0, ctx.version).unwrap();
+    let mut block = vec![0u8; ctx.block_size as usize];
+    file.read_at(&mut block, ctx.ag_block_to_byte(0, agi.inobt_root)).unwrap();
+    let Ok(InobtBlock::Leaf(records)) = parse_inobt_block(&block, &ctx) else { return };
+
+    let loc = ctx.locate_inode(ctx.root_ino).expect("root inode has no location");
+    assert_eq!((loc.agno, loc.agino), (0, ctx.ino_to_agino(ctx.root_ino)));
+    let rec = find_inobt_record(&records, loc.agino).expect("no inobt record covers the root");
+    assert_eq!(rec.start_ino(), loc.chunk_start_agino);
+    assert!(rec.is_present(loc.agino));
+    assert_ne!(rec.allocated_mask() & (1 << loc.chunk_index), 0);
+
+    let mut inode = vec![0u8; ctx.inode_size as usize];
+    file.read_at(&mut inode, loc.byte_offset).unwrap();
+    let info = parse_inode_core(&inode, ctx.root_ino, true, ctx.has_nrext64, ctx.inode_size).unwrap();
+    assert!(info.is_dir());
+
+    assert!(ctx.locate_inode(ctx.agino_to_ino(ctx.ag_count, 0)).is_err(), "beyond the last AG");
+    assert!(ctx.locate_inode(ctx.agino_to_ino(0, 0)).is_err(), "inside the AG headers");
+}
+
+#[test]
+fn metadata_blocks_decode_by_magic() {
+    if skip_if_missing() { return; }
+    let file = File::open(FIXTURE_PATH).unwrap();
+    let mut block = vec![0u8; 4096];
+    file.read_at(&mut block, 0).unwrap();
+    let ctx = FsContext::from_superblock(&block).unwrap();
+    let decode = |offset: u64| {
+        let mut buf = vec![0u8; ctx.block_size as usize];
+        file.read_at(&mut buf, offset).unwrap();
+        (BlockKind::detect(&buf), decode_block(&buf, &ctx, None).expect("failed to decode"))
+    };
+
+    let (kind, sb) = decode(0);
+    assert_eq!(kind, Some(BlockKind::Superblock));
+    assert!(matches!(sb, DecodedBlock::Superblock(c) if c.root_ino == ctx.root_ino));
+    let (kind, agi) = decode(ctx.agi_byte_offset(0));
+    assert_eq!(kind, Some(BlockKind::Agi));
+    let DecodedBlock::Agi(agi) = agi else { panic!("expected an AGI, got {agi:?}") };
+    assert_eq!(decode(ctx.agf_byte_offset(0)).0, Some(BlockKind::Agf));
+    assert_eq!(decode(ctx.agfl_byte_offset(0)).0, Some(BlockKind::Agfl));
+    assert_eq!(decode(ctx.ag_block_to_byte(0, agi.inobt_root)).0, Some(BlockKind::Inobt));
+
+    let root_block = ctx.ag_block_to_byte(0, ctx.ino_to_agino(ctx.root_ino) >> ctx.inop_blog);
+    let (kind, inodes) = decode(root_block);
+    assert_eq!(kind, Some(BlockKind::Inode));
+    let DecodedBlock::Inodes(inodes) = inodes else { panic!("expected inodes, got {inodes:?}") };
+    assert!(inodes.iter().flatten().any(|i| i.ino == ctx.root_ino && i.is_dir()));
+
+    // Forcing the wrong kind is a parse error, not a misread.
+    let mut buf = vec![0u8; ctx.block_size as usize];
+    file.read_at(&mut buf, 0).unwrap();
+    assert!(decode_block(&buf, &ctx, Some(BlockKind::Inobt)).is_err());
+    assert!(matches!(decode_block(&[0; 512], &ctx, None), Ok(DecodedBlock::Unknown { magic: 0 })));
+}
+
+// ---------------------------------------------------------------------------
+// Superblock
+// ---------------------------------------------------------------------------
+
+#[test]
+fn ag_lengths_match_superblock_geometry() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+
+    while let Some(ag_result) = scanner.next_ag() {
+        let ag = ag_result.expect("failed to get AG");
+        assert!(ag.ag_length() > 0 && ag.ag_length() <= sb.ag_blocks, "AG length out of range");
+        assert_eq!(ag.geometry_mismatch(), None, "AG {} disagrees with superblock", ag.ag_number());
+    }
+}
+
+#[test]
+fn agf_headers_are_consistent_with_agi() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+
+    while let Some(ag_result) = scanner.next_ag() {
+        let ag = ag_result.expect("failed to get AG");
+        let agf = ag.agf();
+        assert_eq!(agf.length, ag.ag_length(), "AGF and AGI lengths differ in AG {}", ag.ag_number());
+        assert!(agf.free_blocks <= agf.length);
+        assert!(agf.longest_free <= agf.free_blocks);
+        assert_eq!(ag.free_list().len(), agf.fl_count as usize);
+        assert!(ag.free_list().iter().all(|&b| b < agf.length), "AGFL block beyond end of AG");
+
+        let summary = ag.summary();
+        assert_eq!((summary.ag_number, summary.length), (ag.ag_number(), agf.length));
+        assert_eq!((summary.free_blocks, summary.longest_free), (agf.free_blocks, agf.longest_free));
+        assert_eq!(summary.free_list_blocks, agf.fl_count);
+        assert_eq!((summary.inodes, summary.free_inodes), (ag.agi().count, ag.agi().free_count));
+        assert!(summary.bnobt_level >= 1 && summary.cntbt_level >= 1 && summary.inobt_level >= 1);
+        // Both counts include the root.
+        assert_eq!(summary.rmapbt_level > 0, summary.rmap_blocks > 0);
+        assert_eq!(summary.refcountbt_level > 0, summary.refcount_blocks > 0);
+    }
+}
+
+#[test]
+fn free_space_btrees_agree_with_agf() {
+    for (path, _, _) in matrix_fixtures() {
+        let engine = IoEngine::open(path, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+        while let Some(ag_result) = scanner.next_ag() {
+            let mut ag = ag_result.expect("failed to get AG");
+            let agf = ag.agf().clone();
+            let mut walk = |order| {
+                let mut extents = Vec::new();
+                ag.scan_free_space(order, |e: &FreeSpaceExtent| {
+                    extents.push((e.start_block, e.block_count));
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to walk free space btree");
+                extents
+            };
+            let by_block = walk(FreeSpaceOrder::ByBlock);
+            let by_size = walk(FreeSpaceOrder::BySize);
+
+            let ctx = format!("{path} AG {}", agf.ag_number);
+            assert!(by_block.is_sorted(), "{ctx}: bnobt out of order");
+            assert!(by_size.is_sorted_by_key(|&(start, count)| (count, start)), "{ctx}: cntbt out of order");
+            assert!(by_block.windows(2).all(|w| w[0].0 + w[0].1 < w[1].0), "{ctx}: free extents overlap or touch");
+            assert!(by_block.iter().all(|&(start, count)| start + count <= agf.length), "{ctx}: beyond end of AG");
+            let mut sorted = by_size.clone();
+            sorted.sort_unstable();
+            assert_eq!(sorted, by_block, "{ctx}: btrees disagree");
+            assert_eq!(by_block.iter().map(|&(_, count)| count).sum::<u32>(), agf.free_blocks, "{ctx}");
+            assert_eq!(by_size.last().map_or(0, |&(_, count)| count), agf.longest_free, "{ctx}");
+        }
+    }
+}
+
+/// The main tree on a filesystem with the rmapbt and reflink features.
+const RMAP_FIXTURE: &str = "tests/fixtures/test_rmap.xfs";
+
+#[test]
+fn rmap_accounts_for_every_used_block() {
+    if !Path::new(RMAP_FIXTURE).exists() {
+        eprintln!("Skipping: fixture not found at {RMAP_FIXTURE}");
+        return;
+    }
+    let engine = IoEngine::open(RMAP_FIXTURE, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    assert!(scanner.context().feature_report().rmapbt);
+    let ag_blk_log = scanner.context().ag_blk_log;
+    // (ino, filesystem block of its first extent)
+    let mut first_blocks = Vec::new();
+    while let Some(ag_result) = scanner.next_ag() {
+        let mut ag = ag_result.expect("failed to get AG");
+        let agf = ag.agf().clone();
+        let ctx = format!("AG {}", agf.ag_number);
+        let mut extents = Vec::new();
+        ag.scan_rmap(|rec: &RmapRecord| {
+            extents.push((rec.start_block, rec.block_count));
+            ControlFlow::Continue(())
+        })
+        .expect("failed to walk rmapbt");
+        assert!(extents.is_sorted(), "{ctx}: rmapbt out of order");
+        ag.scan_free_space(FreeSpaceOrder::ByBlock, |e: &FreeSpaceExtent| {
+            extents.push((e.start_block, e.block_count));
+            ControlFlow::Continue(())
+        })
+        .expect("failed to walk free space btree");
+
+        // Nothing is shared, so mapped and free extents tile the AG.
+        extents.sort_unstable();
+        let mut next = 0;
+        for (start, count) in extents {
+            assert_eq!(start, next, "{ctx}: gap or overlap at block {next}");
+            next = start + count;
+        }
+        assert_eq!(next, agf.length, "{ctx}");
+
+        ag.scan_inodes(|inode: &InodeInfo| {
+            if let Some(e) = inode.extents.as_ref().and_then(|extents| extents.first()) {
+                first_blocks.push((inode.ino, ((e.ag_number as u64) << ag_blk_log) | e.ag_block as u64));
+            }
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes")
+        .skip_extents()
+        .skip_dirs()
+        .expect("failed to skip dirs");
+    }
+
+    assert!(!first_blocks.is_empty());
+    for (ino, fsblock) in first_blocks {
+        let owners = scanner.owners_of(fsblock..fsblock + 1).expect("owner lookup failed");
+        assert!(owners.iter().any(|rec| rec.owner == RmapOwner::Inode(ino)), "inode {ino}: {owners:?}");
+    }
+}
+
+const REFLINK_FIXTURE: &str = "tests/fixtures/test_reflink.xfs";
+/// Blocks of the 64 KiB file shared with its two reflinked copies.
+const REFLINK_BLOCKS: u32 = 16;
+
+#[test]
+fn shared_extents_cover_reflinked_copies() {
+    if !Path::new(REFLINK_FIXTURE).exists() {
+        eprintln!("Skipping: fixture not found at {REFLINK_FIXTURE}");
+        return;
+    }
+    let engine = IoEngine::open(REFLINK_FIXTURE, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut shared = Vec::new();
+    let mut copies = Vec::new();
+    while let Some(ag_result) = scanner.next_ag() {
+        let mut ag = ag_result.expect("failed to get AG");
+        ag.scan_shared_extents(|e: &SharedExtent| {
+            shared.push(*e);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to walk refcountbt");
+        ag.scan_inodes(|inode: &InodeInfo| {
+            if inode.size == 65536 {
+                copies.push(inode.extents.clone().expect("copy has no extent list"));
+            }
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes")
+        .skip_extents()
+        .skip_dirs()
+        .expect("failed to skip dirs");
+    }
+
+    assert_eq!(shared.iter().map(|e| e.block_count).sum::<u32>(), REFLINK_BLOCKS);
+    assert!(shared.iter().all(|e| e.refcount == 3), "{shared:?}");
+    assert_eq!(copies.len(), 3);
+    for extent in copies.iter().flatten() {
+        let covered = shared.iter().any(|e| {
+            e.ag_number == extent.ag_number
+                && e.start_block <= extent.ag_block
+                && extent.ag_block as u64 + extent.block_count <= e.start_block as u64 + e.block_count as u64
+        });
+        assert!(covered, "{extent:?} not shared: {shared:?}");
+    }
+}
+
+#[test]
+fn superblock_has_valid_parameters() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    assert_eq!(r.block_size, 4096, "expected 4K block size");
+    assert_eq!(r.ag_count, 4, "expected 4 AGs");
+    assert_eq!(r.inode_size, 512, "expected 512-byte inodes");
+    assert!(r.root_ino > 0, "root inode should be nonzero");
+    assert!(r.ag_blocks > 0, "ag_blocks should be nonzero");
+}
+
+/// A sane V4 superblock sector: 4 KiB blocks, 512-byte sectors and inodes,
+/// one AG of 16384 blocks.
+fn sane_superblock() -> Vec<u8> {
+    let mut sb = vec![0u8; 512];
+    sb[0..4].copy_from_slice(b"XFSB");
+    sb[4..8].copy_from_slice(&4096u32.to_be_bytes());
+    sb[8..16].copy_from_slice(&16384u64.to_be_bytes()); // dblocks
+    sb[84..88].copy_from_slice(&16384u32.to_be_bytes()); // agblocks
+    sb[88..92].copy_from_slice(&1u32.to_be_bytes()); // agcount
+    sb[100..102].copy_from_slice(&0xa0a4u16.to_be_bytes()); // V4
+    sb[102..104].copy_from_slice(&512u16.to_be_bytes());
+    sb[104..106].copy_from_slice(&512u16.to_be_bytes());
+    sb[106..108].copy_from_slice(&8u16.to_be_bytes()); // inopblock
+    sb[120..125].copy_from_slice(&[12, 9, 9, 3, 14]); // block, sect, inode, inopb, agblk logs
+    sb
+}
+
+#[test]
+fn internal_inodes_are_classified() {
+    let mut sb = sane_superblock();
+    sb[64..72].copy_from_slice(&129u64.to_be_bytes()); // rbmino
+    sb[72..80].copy_from_slice(&130u64.to_be_bytes()); // rsumino
+    sb[160..168].copy_from_slice(&131u64.to_be_bytes()); // uquotino
+    sb[168..176].copy_from_slice(&u64::MAX.to_be_bytes()); // gquotino, unset
+    let ctx = FsContext::from_superblock(&sb).expect("failed to parse superblock");
+    assert_eq!(
+        ctx.internal_inodes,
+        [(129, InternalInode::RealtimeBitmap), (130, InternalInode::RealtimeSummary), (131, InternalInode::UserQuota)]
+    );
+
+    let mut buf = vec![0u8; 512];
+    buf[0..2].copy_from_slice(&0x494e_u16.to_be_bytes());
+    buf[2..4].copy_from_slice(&(0o100600_u16).to_be_bytes());
+    buf[4] = 3;
+    buf[5] = XFS_DINODE_FMT_EXTENTS;
+    let inode = |ino| parse_inode_core(&buf, ino, true, false, 512).expect("failed to parse inode");
+    assert_eq!(ctx.internal_inode(&inode(131)), Some(InternalInode::UserQuota));
+    assert_eq!(ctx.internal_inode(&inode(1234)), None);
+    buf[120..128].copy_from_slice(&InodeFlags2::METADATA.bits().to_be_bytes());
+    let inode = parse_inode_core(&buf, 1234, true, false, 512).expect("failed to parse inode");
+    assert_eq!(ctx.internal_inode(&inode), Some(InternalInode::Metadata));
+}
+
+/// A log record at log block `block`: a header sector, then `ops` as
+/// `(tid, flags, payload)`, each data sector stamped with `cycle` and its
+/// first word saved in the header.
+fn log_record(cycle: u32, block: u32, tail_lsn: u64, ops: &[(u32, u8, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (tid, flags, payload) in ops {
+        data.extend_from_slice(&tid.to_be_bytes());
+        data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        data.extend_from_slice(&[0x69, *flags, 0, 0]);
+        data.extend_from_slice(payload);
+    }
+    let len = data.len() as u32;
+    data.resize(data.len().div_ceil(512) * 512, 0);
+    let mut record = vec![0u8; 512];
+    record[0..4].copy_from_slice(&0xfeed_babe_u32.to_be_bytes());
+    record[4..8].copy_from_slice(&cycle.to_be_bytes());
+    record[8..12].copy_from_slice(&2u32.to_be_bytes());
+    record[12..16].copy_from_slice(&len.to_be_bytes());
+    record[16..24].copy_from_slice(&((cycle as u64) << 32 | block as u64).to_be_bytes());
+    record[24..32].copy_from_slice(&tail_lsn.to_be_bytes());
+    record[40..44].copy_from_slice(&(ops.len() as u32).to_be_bytes());
+    record[320..324].copy_from_slice(&32768u32.to_be_bytes());
+    for (i, sector) in data.chunks_exact_mut(512).enumerate() {
+        record[44 + i * 4..48 + i * 4].copy_from_slice(&sector[..4]);
+        sector[..4].copy_from_slice(&cycle.to_be_bytes());
+    }
+    record.extend(data);
+    record
+}
+
+/// A log item format region, little-endian as from an x86 host: type,
+/// region count, then `(offset, value)` words.
+fn log_item(len: usize, kind: u16, regions: u16, fields: &[(usize, u64)]) -> Vec<u8> {
+    let mut item = vec![0u8; len];
+    item[0..2].copy_from_slice(&kind.to_le_bytes());
+    item[2..4].copy_from_slice(&regions.to_le_bytes());
+    for &(at, value) in fields {
+        item[at..at + 8].copy_from_slice(&value.to_le_bytes());
+    }
+    item
+}
+
+#[test]
+fn log_inspection_lists_pending_transactions() {
+    const START: u8 = 0x01;
+    const COMMIT: u8 = 0x02;
+    const CONTINUE: u8 = 0x04;
+    const WAS_CONT: u8 = 0x08;
+    const UNMOUNT: u8 = 0x20;
+    let lsn = |block: u64| 1u64 << 32 | block;
+
+    let mut image = sane_superblock();
+    image[48..56].copy_from_slice(&8u64.to_be_bytes()); // logstart
+    image[96..100].copy_from_slice(&4u32.to_be_bytes()); // logblocks: 32 log blocks
+    image.resize(8 * 4096 + 4 * 4096, 0);
+    let log = 8 * 4096;
+
+    let mut trans_header = 0x5452_414e_u32.to_le_bytes().to_vec();
+    trans_header.extend_from_slice(&40u32.to_le_bytes());
+    let inode = log_item(56, 0x123b, 2, &[(16, 131)]);
+    let mut buf = log_item(28, 0x123c, 2, &[(8, 64)]);
+    buf[6..8].copy_from_slice(&8u16.to_le_bytes());
+    let open_inode = log_item(56, 0x123b, 2, &[(16, 200)]);
+    let core = [0u8; 176];
+    // Transaction 7 commits; 9 is cut off by the end of the log, its inode
+    // split across two records.
+    let first = log_record(1, 0, lsn(0), &[
+        (7, START, &[]),
+        (7, 0, &trans_header),
+        (7, 0, &inode),
+        (7, 0, &core),
+        (7, 0, &buf),
+        (7, 0, &[0xaa; 4096]),
+        (7, COMMIT, &[]),
+        (9, START, &[]),
+        (9, 0, &trans_header),
+        (9, CONTINUE, &open_inode[..20]),
+    ]);
+    let second_block = first.len() / 512;
+    let second = log_record(1, second_block as u32, lsn(0), &[(9, WAS_CONT, &open_inode[20..]), (9, 0, &core)]);
+    let third_block = second_block + second.len() / 512;
+    image[log..log + first.len()].copy_from_slice(&first);
+    image[log + second_block * 512..][..second.len()].copy_from_slice(&second);
+    // A record torn mid-write: its data sector never got the new cycle.
+    let mut torn = log_record(1, third_block as u32, lsn(0), &[(11, START, &[])]);
+    torn[512..516].fill(0);
+    image[log + third_block * 512..][..torn.len()].copy_from_slice(&torn);
+
+    let (_, mut scanner) = parse_superblock(SliceReader::new(&image)).expect("failed to parse superblock");
+    let summary = scanner.inspect_log().expect("failed to inspect log").expect("log is internal");
+    assert!(!summary.clean);
+    assert_eq!((summary.tail_lsn, summary.head_lsn), (lsn(0), lsn(second_block as u64)));
+    assert_eq!(summary.records.len(), 2);
+    let transactions: Vec<_> =
+        summary.transactions.iter().map(|tx| (tx.tid, tx.kind, tx.commit_lsn, tx.items.clone())).collect();
+    assert_eq!(transactions, [
+        (7, 40, Some(lsn(0)), vec![LogItem::Inode { ino: 131 }, LogItem::Buffer { daddr: 64, len: 8 }]),
+        (9, 40, None, vec![LogItem::Inode { ino: 200 }]),
+    ]);
+    assert_eq!(summary.pending_inodes(), [131]);
+    assert_eq!(summary.pending_buffers(), [(64, 8)]);
+
+    // An unmount record at the head leaves nothing to replay.
+    let unmount = log_record(1, third_block as u32, lsn(third_block as u64), &[(11, UNMOUNT, &[0; 8])]);
+    image[log + third_block * 512..][..unmount.len()].copy_from_slice(&unmount);
+    let (_, mut scanner) = parse_superblock(SliceReader::new(&image)).expect("failed to parse superblock");
+    let summary = scanner.inspect_log().expect("failed to inspect log").expect("log is internal");
+    assert!(summary.clean);
+    assert_eq!((summary.tail_lsn, summary.head_lsn), (lsn(third_block as u64), lsn(third_block as u64)));
+    assert!(summary.transactions.is_empty());
+}
+
+#[test]
+fn log_records_with_impossible_lengths_count_as_torn() {
+    const START: u8 = 0x01;
+    const COMMIT: u8 = 0x02;
+    let lsn = |block: u64| 1u64 << 32 | block;
+
+    let mut image = sane_superblock();
+    image[48..56].copy_from_slice(&8u64.to_be_bytes()); // logstart
+    image[96..100].copy_from_slice(&4u32.to_be_bytes()); // logblocks: 32 log blocks
+    image.resize(8 * 4096 + 4 * 4096, 0);
+    let log = 8 * 4096;
+    let mut trans_header = 0x5452_414e_u32.to_le_bytes().to_vec();
+    trans_header.extend_from_slice(&40u32.to_le_bytes());
+    // Three records of one committed transaction each, two blocks apiece.
+    for (i, tid) in [7, 8, 9].into_iter().enumerate() {
+        let ops: [(u32, u8, &[u8]); 3] = [(tid, START, &[]), (tid, 0, &trans_header), (tid, COMMIT, &[])];
+        let record = log_record(1, 2 * i as u32, lsn(0), &ops);
+        image[log + i * 1024..][..record.len()].copy_from_slice(&record);
+    }
+    let h_len = |record: usize| log + record * 1024 + 12..log + record * 1024 + 16;
+    let committed = |image: &[u8]| {
+        let (_, mut scanner) = parse_superblock(SliceReader::new(image)).expect("failed to parse superblock");
+        let summary = scanner.inspect_log().expect("failed to inspect log").expect("log is internal");
+        let tids: Vec<u32> = summary.transactions.iter().map(|tx| tx.tid).collect();
+        (summary.head_lsn, tids)
+    };
+    assert_eq!(committed(&image), (lsn(4), vec![7, 8, 9]));
+
+    // The newest record claims more than the log: the head is the one before.
+    let len = image[h_len(2)].to_vec();
+    image[h_len(2)].copy_from_slice(&u32::MAX.to_be_bytes());
+    assert_eq!(committed(&image), (lsn(2), vec![7, 8]));
+    // Behind the head, it ends the walk.
+    image[h_len(2)].copy_from_slice(&len);
+    image[h_len(1)].copy_from_slice(&(64 * 512u32).to_be_bytes());
+    assert_eq!(committed(&image), (lsn(4), vec![7]));
+}
+
+#[test]
+fn replay_reader_applies_committed_transactions() {
+    const START: u8 = 0x01;
+    const COMMIT: u8 = 0x02;
+    const XFS_BLF_CANCEL: u64 = 0x4;
+    let mut image = sane_superblock();
+    image[48..56].copy_from_slice(&8u64.to_be_bytes()); // logstart
+    image[96..100].copy_from_slice(&4u32.to_be_bytes()); // logblocks
+    image.resize(20 * 4096, 0);
+    let log = 8 * 4096;
+    // The inode at daddr 128, as written before the log's changes.
+    let inode_at = 128 * 512;
+    image[inode_at..inode_at + 2].copy_from_slice(b"IN");
+    image[inode_at + 2..inode_at + 4].copy_from_slice(&0o100644u16.to_be_bytes());
+    image[inode_at + 4..inode_at + 6].copy_from_slice(&[2, XFS_DINODE_FMT_EXTENTS]);
+    image[inode_at + 96..inode_at + 100].copy_from_slice(&u32::MAX.to_be_bytes());
+
+    let mut trans_header = 0x5452_414e_u32.to_le_bytes().to_vec();
+    trans_header.extend_from_slice(&40u32.to_le_bytes());
+    // Core and data fork extents; the log dinode is in host byte order.
+    let inode = log_item(56, 0x123b, 3, &[(4, 0x1 | 0x4), (16, 128), (40, 128)]);
+    let mut core = [0u8; 96];
+    core[0..2].copy_from_slice(&0x494e_u16.to_le_bytes());
+    core[2..4].copy_from_slice(&0o100600u16.to_le_bytes());
+    core[4..6].copy_from_slice(&[2, XFS_DINODE_FMT_EXTENTS]);
+    core[56..64].copy_from_slice(&1234u64.to_le_bytes());
+    core[76..80].copy_from_slice(&1u32.to_le_bytes());
+    let extent = [0x5a; 16];
+    // Chunks 0 and 2 of the buffer at daddr 136.
+    let buffer = |daddr: u64, flags: u64, map: u64, regions: u16| {
+        let mut item = log_item(28, 0x123c, regions, &[(8, daddr), (16, 1 | map << 32)]);
+        item[4..8].copy_from_slice(&((flags as u32) | 8 << 16).to_le_bytes());
+        item
+    };
+    let dirty = buffer(136, 0, 0b101, 3);
+    // Transaction 8's change to daddr 144 is cancelled by 10; 9 never commits.
+    let freed = buffer(144, 0, 0b1, 2);
+    let cancel = buffer(144, XFS_BLF_CANCEL, 0, 1);
+    let uncommitted = buffer(152, 0, 0b1, 2);
+    let record = log_record(1, 0, 1 << 32, &[
+        (7, START, &[]),
+        (7, 0, &trans_header),
+        (7, 0, &inode),
+        (7, 0, &core),
+        (7, 0, &extent),
+        (7, 0, &dirty),
+        (7, 0, &[0xaa; 128]),
+        (7, 0, &[0xbb; 128]),
+        (7, COMMIT, &[]),
+        (8, START, &[]),
+        (8, 0, &trans_header),
+        (8, 0, &freed),
+        (8, 0, &[0xcc; 128]),
+        (8, COMMIT, &[]),
+        (9, START, &[]),
+        (9, 0, &trans_header),
+        (9, 0, &uncommitted),
+        (9, 0, &[0xdd; 128]),
+        (10, START, &[]),
+        (10, 0, &trans_header),
+        (10, 0, &cancel),
+        (10, COMMIT, &[]),
+    ]);
+    image[log..log + record.len()].copy_from_slice(&record);
+
+    let mut reader = ReplayReader::new(SliceReader::new(&image)).expect("failed to replay log");
+    assert_eq!(reader.log().expect("log is internal").transactions.len(), 4);
+    assert_eq!(reader.report(), ReplayReport {
+        transactions: 3,
+        buffers: 1,
+        inodes: 1,
+        cancelled: 1,
+        already_written: 0,
+        unsupported: 0,
+    });
+
+    let check = |bytes: &[u8]| {
+        let (inode, buf) = bytes.split_at(4096);
+        assert_eq!(inode[..2], *b"IN");
+        assert_eq!(inode[2..4], 0o100600u16.to_be_bytes());
+        assert_eq!(inode[56..64], 1234u64.to_be_bytes());
+        assert_eq!(inode[76..80], 1u32.to_be_bytes());
+        // The unlinked pointer is logged with the buffer, not the inode.
+        assert_eq!(inode[96..100], u32::MAX.to_be_bytes());
+        assert_eq!(inode[100..116], extent);
+        assert!(buf[..128].iter().all(|&b| b == 0xaa));
+        assert!(buf[128..256].iter().all(|&b| b == 0));
+        assert!(buf[256..384].iter().all(|&b| b == 0xbb));
+        // daddr 144 and 152, left as they were.
+        assert!(buf[4096..8192].iter().all(|&b| b == 0));
+    };
+    check(reader.read_at(inode_at as u64, 3 * 4096, IoPhase::InodeChunks).expect("failed to read"));
+    let mut batched = vec![0u8; 3 * 4096];
+    let requests: Vec<(u64, usize, usize)> = (0..3).map(|i| ((inode_at + i * 4096) as u64, 4096, i)).collect();
+    reader
+        .coalesced_read_batch(
+            &requests,
+            |bytes, i| {
+                batched[i * 4096..][..4096].copy_from_slice(bytes);
+                Ok(())
+            },
+            IoPhase::InodeChunks,
+        )
+        .expect("failed to read");
+    check(&batched);
+    // The device itself is untouched.
+    let mut device = reader.into_inner();
+    let untouched = device.read_at(inode_at as u64 + 4096, 128, IoPhase::InodeChunks).expect("failed to read");
+    assert!(untouched.iter().all(|&b| b == 0));
+}
+
+#[test]
+fn replay_reader_finds_inode_from_64bit_format() {
+    const START: u8 = 0x01;
+    const COMMIT: u8 = 0x02;
+    let mut image = sane_superblock();
+    image[104..106].copy_from_slice(&256u16.to_be_bytes()); // inodesize
+    image[106..108].copy_from_slice(&16u16.to_be_bytes()); // inopblock
+    image[122..124].copy_from_slice(&[8, 4]); // inode, inopb logs
+    image[48..56].copy_from_slice(&8u64.to_be_bytes()); // logstart
+    image[96..100].copy_from_slice(&4u32.to_be_bytes()); // logblocks
+    image.resize(20 * 4096, 0);
+    let log = 8 * 4096;
+    // Two 256-byte inodes in the sector at daddr 128; the item logs the
+    // second.
+    let sector = 128 * 512;
+    for inode_at in [sector, sector + 256] {
+        image[inode_at..inode_at + 2].copy_from_slice(b"IN");
+        image[inode_at + 2..inode_at + 4].copy_from_slice(&0o100644u16.to_be_bytes());
+        image[inode_at + 4..inode_at + 6].copy_from_slice(&[2, XFS_DINODE_FMT_EXTENTS]);
+    }
+
+    let mut trans_header = 0x5452_414e_u32.to_le_bytes().to_vec();
+    trans_header.extend_from_slice(&40u32.to_le_bytes());
+    // 64-bit xfs_inode_log_format: ilf_blkno at 40, ilf_boffset at 52, the
+    // ilf_u padding before them left as garbage.
+    let mut inode = log_item(56, 0x123b, 2, &[(4, 0x1), (16, 129), (40, 128)]);
+    inode[24..40].fill(0xee);
+    inode[52..56].copy_from_slice(&256u32.to_le_bytes());
+    let mut core = [0u8; 96];
+    core[0..2].copy_from_slice(&0x494e_u16.to_le_bytes());
+    core[2..4].copy_from_slice(&0o100600u16.to_le_bytes());
+    core[4..6].copy_from_slice(&[2, XFS_DINODE_FMT_EXTENTS]);
+    core[56..64].copy_from_slice(&4321u64.to_le_bytes());
+    let record = log_record(1, 0, 1 << 32, &[
+        (7, START, &[]),
+        (7, 0, &trans_header),
+        (7, 0, &inode),
+        (7, 0, &core),
+        (7, COMMIT, &[]),
+    ]);
+    image[log..log + record.len()].copy_from_slice(&record);
+
+    // Like an O_DIRECT device, refuse reads that are not whole sectors.
+    let device = CallbackReader::new(|offset: u64, buf: &mut [u8]| {
+        let aligned = offset.is_multiple_of(512) && buf.len().is_multiple_of(512);
+        assert!(aligned, "unaligned read of {} at {offset}", buf.len());
+        let n = buf.len().min(image.len().saturating_sub(offset as usize));
+        buf[..n].copy_from_slice(&image[offset as usize..offset as usize + n]);
+        Ok(n)
+    });
+    let mut reader = ReplayReader::new(device).expect("failed to replay log");
+    assert_eq!(reader.report().inodes, 1);
+    let bytes = reader.read_at(sector as u64, 512, IoPhase::InodeChunks).expect("failed to read");
+    let (first, second) = bytes.split_at(256);
+    assert_eq!(first[2..4], 0o100644u16.to_be_bytes());
+    assert_eq!(second[2..4], 0o100600u16.to_be_bytes());
+    assert_eq!(second[56..64], 4321u64.to_be_bytes());
+}
+
+fn v5_superblock() -> Vec<u8> {
+    let mut sb = sane_superblock();
+    sb[100..102].copy_from_slice(&0xb4a5u16.to_be_bytes());
+    sb
+}
+
+/// A 4K block of a V5 short-form btree: the header, `keys` (or records)
+/// after it and `ptrs` at byte `ptr_at`.
+fn short_btree_block(magic: &[u8; 4], level: u16, numrecs: u16, keys: &[u8], ptr_at: usize, ptrs: &[u32]) -> Vec<u8> {
+    let mut block = vec![0u8; 4096];
+    block[0..4].copy_from_slice(magic);
+    block[4..6].copy_from_slice(&level.to_be_bytes());
+    block[6..8].copy_from_slice(&numrecs.to_be_bytes());
+    block[56..56 + keys.len()].copy_from_slice(keys);
+    let ptrs = be_words(ptrs);
+    block[ptr_at..ptr_at + ptrs.len()].copy_from_slice(&ptrs);
+    block
+}
+
+fn be_words(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|w| w.to_be_bytes()).collect()
+}
+
+/// A one-AG image with the superblock `sb`, an inobt leaf at AG block 1
+/// and one inode chunk at AG inode `first_agino`, the root. `inodes` are
+/// written from there on and are the chunk's only allocated inodes; the
+/// image leaves 16 free blocks after the chunk for file data.
+fn synthetic_image(sb: &[u8], first_agino: u32, inodes: &[Vec<u8>]) -> Vec<u8> {
+    let ctx = FsContext::from_superblock(sb).expect("failed to parse superblock");
+    let block_size = ctx.block_size as usize;
+    let (sect_size, inode_size) = (ctx.sect_size as usize, ctx.inode_size as usize);
+    let chunk_end = (first_agino as usize + 64) * inode_size;
+    let mut image = vec![0u8; chunk_end.next_multiple_of(block_size) + 16 * block_size];
+    image[..sb.len()].copy_from_slice(sb);
+    image[56..64].copy_from_slice(&(first_agino as u64).to_be_bytes()); // rootino
+    let is_v5 = ctx.version == FormatVersion::V5;
+    if is_v5 {
+        assert!(update_metadata_crc(&mut image[..sect_size]));
+    }
+
+    let agf = sect_size;
+    image[agf..agf + 8].copy_from_slice(&be_words(&[0x5841_4746, 1])); // XAGF
+    image[agf + 12..agf + 16].copy_from_slice(&ctx.ag_blocks.to_be_bytes());
+    let agi = 2 * sect_size;
+    image[agi..agi + 40].copy_from_slice(&be_words(&[
+        0x5841_4749, // XAGI
+        1,
+        0,
+        ctx.ag_blocks,
+        64, // count: the whole chunk
+        1, // inobt root
+        1, // inobt level
+        64 - inodes.len() as u32,
+        first_agino + 63,
+        u32::MAX, // dirino
+    ]));
+    image[agi + 40..agi + 40 + 64 * 4].fill(0xff); // unlinked buckets
+    if is_v5 {
+        image[3 * sect_size..3 * sect_size + 4].copy_from_slice(b"XAFL");
+    }
+
+    let leaf = block_size;
+    image[leaf..leaf + 4].copy_from_slice(if is_v5 { b"IAB3" } else { b"IABT" });
+    image[leaf + 6..leaf + 8].copy_from_slice(&1u16.to_be_bytes());
+    image[leaf + 8..leaf + 16].fill(0xff); // no siblings
+    let free = u64::MAX.checked_shl(inodes.len() as u32).unwrap_or(0);
+    let rec = leaf + if is_v5 { 56 } else { 16 };
+    image[rec..rec + 4].copy_from_slice(&first_agino.to_be_bytes());
+    image[rec + 6] = 64; // count
+    image[rec + 7] = free.count_ones() as u8;
+    image[rec + 8..rec + 16].copy_from_slice(&free.to_be_bytes());
+
+    for (i, inode) in inodes.iter().enumerate() {
+        let at = (first_agino as usize + i) * inode_size;
+        image[at..at + inode.len()].copy_from_slice(inode);
+    }
+    image
+}
+
+/// Fill AG `agno` of `image`, a [`synthetic_image`] grown to AGs of 64
+/// blocks, from AG 0: its headers and inobt, and with `chunk` its inode
+/// chunk too. Without the chunk the AG holds no inodes.
+fn copy_first_ag(image: &mut [u8], agno: usize, chunk: bool) {
+    let (ag0, rest) = image.split_at_mut(64 * 4096);
+    let ag = &mut rest[(agno - 1) * 64 * 4096..][..64 * 4096];
+    let len = if chunk { 16 * 4096 } else { 2 * 4096 };
+    ag[..len].copy_from_slice(&ag0[..len]);
+    ag[512 + 8..][..4].copy_from_slice(&(agno as u32).to_be_bytes()); // AGF seqno
+    ag[1024 + 8..][..4].copy_from_slice(&(agno as u32).to_be_bytes()); // AGI seqno
+    if !chunk {
+        ag[1024 + 16..][..4].fill(0); // count
+        ag[1024 + 28..][..8].copy_from_slice(&be_words(&[0, u32::MAX])); // freecount, newino
+        ag[4096 + 6..][..2].fill(0); // numrecs
+    }
+}
+
+/// A v2 inode of `size` bytes with `mode`, data fork `format` and `fork`
+/// written at the start of the data fork.
+fn v4_inode(size: usize, mode: u16, format: u8, fork: &[u8]) -> Vec<u8> {
+    let mut inode = vec![0u8; size];
+    inode[0..2].copy_from_slice(b"IN");
+    inode[2..4].copy_from_slice(&mode.to_be_bytes());
+    inode[4..6].copy_from_slice(&[2, format]);
+    inode[16..20].copy_from_slice(&1u32.to_be_bytes()); // nlink
+    inode[96..100].copy_from_slice(&u32::MAX.to_be_bytes()); // next_unlinked
+    inode[100..100 + fork.len()].copy_from_slice(fork);
+    inode
+}
+
+/// A data fork extent record mapping `count` blocks at `logical` to
+/// filesystem block `fsblock`.
+fn bmbt_rec(logical: u64, fsblock: u64, count: u32) -> Vec<u8> {
+    let l0 = (logical << 9) | (fsblock >> 43);
+    let l1 = (fsblock << 21) | count as u64;
+    [l0.to_be_bytes(), l1.to_be_bytes()].concat()
+}
+
+/// Every directory entry of a scan of `image`, without `.` and `..`, as
+/// `(parent, name, child)`.
+fn synthetic_dir_entries(image: &[u8]) -> Vec<(u64, Vec<u8>, u64)> {
+    let (_, mut scanner) = parse_superblock(SliceReader::new(image)).expect("failed to parse superblock");
+    let mut entries = Vec::new();
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|_: &InodeInfo| ControlFlow::Continue(()))
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| {
+                if de.name != b"." && de.name != b".." {
+                    entries.push((de.parent_ino, de.name.to_vec(), de.child_ino));
+                }
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+    }
+    entries
+}
+
+#[test]
+fn free_space_walk_returns_cntbt_records_in_size_order() {
+    let ctx = FsContext::from_superblock(&v5_superblock()).expect("failed to parse superblock");
+    let mut image = vec![0u8; 4 * 4096];
+    // Keys are (start, length) records; the root's children are out of disk
+    // order. 8-byte keys and 4-byte pointers: (4096 - 56) / 12 slots.
+    let root = short_btree_block(b"AB3C", 1, 2, &be_words(&[900, 1, 40, 8]), 56 + 336 * 8, &[3, 2]);
+    let short = short_btree_block(b"AB3C", 0, 2, &be_words(&[900, 1, 100, 2]), 0, &[]);
+    let long = short_btree_block(b"AB3C", 0, 2, &be_words(&[40, 8, 10, 30]), 0, &[]);
+    for (block, buf) in [(1, root), (2, long), (3, short)] {
+        image[block * 4096..][..4096].copy_from_slice(&buf);
+    }
+
+    let walk = |order, level| collect_free_space_records(&mut SliceReader::new(&image), &ctx, 0, order, 1, level);
+    let records = walk(FreeSpaceOrder::BySize, 2).expect("walk failed");
+    let records: Vec<_> = records.iter().map(|rec| (rec.ar_startblock.get(), rec.ar_blockcount.get())).collect();
+    assert_eq!(records, [(900, 1), (100, 2), (40, 8), (10, 30)]);
+
+    // A cntbt is not a bnobt, and the AGF's level must match the root's.
+    assert!(walk(FreeSpaceOrder::ByBlock, 2).is_err());
+    assert!(walk(FreeSpaceOrder::BySize, 1).is_err());
+    assert!(parse_alloc_block(&image[4096..8192], &ctx, FreeSpaceOrder::ByBlock).is_err());
+}
+
+#[test]
+fn refcount_walk_returns_records_in_key_order() {
+    let ctx = FsContext::from_superblock(&v5_superblock()).expect("failed to parse superblock");
+    let mut image = vec![0u8; 4 * 4096];
+    // The root's children are out of disk order: keys 10 at block 3, 20 at
+    // block 2. 4-byte keys and pointers: (4096 - 56) / 8 slots.
+    let root = short_btree_block(b"R3FC", 1, 2, &be_words(&[10, 20]), 56 + 505 * 4, &[3, 2]);
+    let low = short_btree_block(b"R3FC", 0, 1, &be_words(&[10, 5, 2]), 0, &[]);
+    let high = short_btree_block(b"R3FC", 0, 2, &be_words(&[20, 1, 3, 1 << 31 | 30, 2, 1]), 0, &[]);
+    for (block, buf) in [(1, root), (2, high), (3, low)] {
+        image[block * 4096..][..4096].copy_from_slice(&buf);
+    }
+
+    let records = collect_refcount_records(&mut SliceReader::new(&image), &ctx, 0, 1, 2).expect("walk failed");
+    let records: Vec<_> = records
+        .iter()
+        .map(|rec| (rec.start_block(), rec.rc_blockcount.get(), rec.rc_refcount.get(), rec.is_cow_staging()))
+        .collect();
+    assert_eq!(records, [(10, 5, 2, false), (20, 1, 3, false), (30, 2, 1, true)]);
+
+    // The AGF claims one level more than the root block has.
+    assert!(collect_refcount_records(&mut SliceReader::new(&image), &ctx, 0, 1, 3).is_err());
+}
+
+#[test]
+fn rmap_walk_follows_only_overlapping_children() {
+    let ctx = FsContext::from_superblock(&v5_superblock()).expect("failed to parse superblock");
+    let rec = |start: u32, count: u32, owner: u64, offset: u64| {
+        [&be_words(&[start, count])[..], &owner.to_be_bytes(), &offset.to_be_bytes()].concat()
+    };
+    let key = |start: u32| [&start.to_be_bytes()[..], &[0; 16]].concat();
+    // Each child has a low and a high key: 40 bytes and a 4-byte pointer,
+    // (4096 - 56) / 44 slots. Keys 10..=59 at block 3, 100..=141 at block 2.
+    let keys = [key(10), key(59), key(100), key(141)].concat();
+    let root = short_btree_block(b"RMB3", 1, 2, &keys, 56 + 91 * 40, &[3, 2]);
+    let own_ag = -5i64 as u64;
+    let low = short_btree_block(b"RMB3", 0, 2, &[rec(10, 5, 131, 0), rec(50, 10, own_ag, 0)].concat(), 0, &[]);
+    let high = [rec(100, 4, 132, 1 << 61 | 7), rec(140, 2, 131, 1 << 63)].concat();
+    let high = short_btree_block(b"RMB3", 0, 2, &high, 0, &[]);
+    let mut image = vec![0u8; 4 * 4096];
+    for (block, buf) in [(1, root), (2, high), (3, low)] {
+        image[block * 4096..][..4096].copy_from_slice(&buf);
+    }
+
+    let walk = |image: &[u8], blocks: Range<u32>| {
+        let records = collect_rmap_records(&mut SliceReader::new(image), &ctx, 0, 1, 2, blocks).expect("walk failed");
+        records.iter().map(|rec| RmapRecord::new(0, rec)).map(|r| (r.start_block, r.owner)).collect::<Vec<_>>()
+    };
+    let all = walk(&image, 0..u32::MAX);
+    assert_eq!(all, [
+        (10, RmapOwner::Inode(131)),
+        (50, RmapOwner::AgBtrees),
+        (100, RmapOwner::Inode(132)),
+        (140, RmapOwner::Inode(131)),
+    ]);
+    let records = collect_rmap_records(&mut SliceReader::new(&image), &ctx, 0, 1, 2, 100..101).expect("walk failed");
+    let unwritten = RmapRecord::new(0, &records[0]);
+    assert_eq!((unwritten.offset, unwritten.unwritten, unwritten.attr_fork), (7, true, false));
+
+    // With the other leaf unreadable, a query inside one child's key range
+    // still succeeds: it never descends into the other.
+    let mut no_high = image.clone();
+    no_high[2 * 4096..3 * 4096].fill(0);
+    assert_eq!(walk(&no_high, 55..56), [(50, RmapOwner::AgBtrees)]);
+    let mut no_low = image.clone();
+    no_low[3 * 4096..4 * 4096].fill(0);
+    assert_eq!(walk(&no_low, 141..200), [(140, RmapOwner::Inode(131))]);
+    assert_eq!(walk(&no_low, 60..100), []);
+}
+
+#[test]
+fn remote_symlink_header_fields_are_in_disk_order() {
+    let ctx = FsContext::from_superblock(&v5_superblock()).expect("failed to parse superblock");
+
+    // magic, offset, bytes, crc, uuid, owner, blkno, lsn, then the target.
+    let mut block = vec![0u8; 4096];
+    block[0..4].copy_from_slice(b"XSLM");
+    block[8..12].copy_from_slice(&6u32.to_be_bytes());
+    block[16..32].fill(0xee);
+    block[32..40].copy_from_slice(&133u64.to_be_bytes());
+    block[40..48].copy_from_slice(&64u64.to_be_bytes());
+    block[56..62].copy_from_slice(b"target");
+    let (hdr, _) = XfsDsymlinkHdr::ref_from_prefix(&block).expect("block holds a header");
+    assert_eq!((hdr.sl_uuid, hdr.sl_owner.get(), hdr.sl_blkno.get()), ([0xee; 16], 133, 64));
+    assert_eq!(metadata_uuid(&block), Some(&[0xee; 16][..]));
+
+    let mut target = Vec::new();
+    parse_remote_symlink_block(&block, &ctx, &mut target).expect("failed to parse symlink block");
+    assert_eq!(target, b"target");
+}
+
+#[test]
+fn da3_header_crc_and_uuid_are_located() {
+    let uuid = *b"0123456789abcdef";
+    for magic in [0x3df1u16, 0x3dff, 0x3ebe, 0x3bee] {
+        // xfs_da3_blkinfo: forw, back, magic, pad, crc, blkno, lsn, uuid,
+        // owner.
+        let mut block = vec![0u8; 4096];
+        block[8..10].copy_from_slice(&magic.to_be_bytes());
+        block[16..24].copy_from_slice(&64u64.to_be_bytes());
+        block[32..48].copy_from_slice(&uuid);
+        block[48..56].copy_from_slice(&128u64.to_be_bytes());
+        assert!(update_metadata_crc(&mut block));
+        assert_ne!(block[12..16], [0; 4], "{magic:#x}: crc not stored at 12");
+        assert!(verify_metadata_crc(&block).expect("stamped block verifies"));
+        assert_eq!(metadata_uuid(&block), Some(&uuid[..]), "{magic:#x}");
+
+        block[40] ^= 1;
+        assert!(verify_metadata_crc(&block).is_err());
+    }
+}
+
+#[test]
+fn hostile_superblock_geometry_is_rejected() {
+    FsContext::from_superblock(&sane_superblock()).expect("sane superblock rejected");
+
+    // (what, byte offset, bytes written there)
+    let hostile: [(&str, usize, &[u8]); 12] = [
+        ("zero block size", 4, &[0; 4]),
+        ("block size not a power of two", 4, &4097u32.to_be_bytes()),
+        ("block log disagrees", 120, &[13]),
+        ("block log past the shift width", 120, &[200]),
+        ("zero sector size", 102, &[0; 2]),
+        ("zero inode size", 104, &[0; 2]),
+        ("inode log disagrees", 122, &[8]),
+        ("inopblog disagrees", 123, &[0]),
+        ("zero AG blocks", 84, &[0; 4]),
+        ("zero AG count", 88, &[0; 4]),
+        ("AG blocks beyond agblklog", 124, &[2]),
+        ("huge directory blocks", 192, &[8]),
+    ];
+    for (what, at, bytes) in hostile {
+        let mut sb = sane_superblock();
+        sb[at..at + bytes.len()].copy_from_slice(bytes);
+        assert!(
+            matches!(FsContext::from_superblock(&sb), Err(FxfspError::Parse(_))),
+            "{what}: not rejected as a parse error"
+        );
+        // The scan entry point must fail the same way, not panic.
+        assert!(parse_superblock(SliceReader::new(&sb)).is_err(), "{what}: scan started");
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Root directory
+// ---------------------------------------------------------------------------
+
+#[test]
+fn feature_report_describes_a_v5_filesystem() {
+    if skip_if_missing() { return; }
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, scanner) = parse_superblock(engine).expect("failed to parse superblock");
+
+    let report = scanner.context().feature_report();
+    assert_eq!(report.version, 5);
+    assert!(report.crc && report.ftype && report.lazy_sb_counters && report.attr2);
+    assert!(!report.needs_repair);
+    assert_eq!(report.features_incompat, sb.features_incompat);
+    assert_eq!(report.reflink, sb.features_ro_compat & XFS_SB_FEAT_RO_COMPAT_REFLINK != 0);
+}
+
+#[test]
+fn small_superblock_probe_reads_the_same_geometry() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let options = ScanOptions { superblock_read_size: 512, ..Default::default() };
+    let (sb, _scanner) = parse_superblock_with_options(engine, options).expect("failed to parse superblock");
+
+    assert_eq!(sb.block_size, r.block_size);
+    assert_eq!(sb.ag_count, r.ag_count);
+    assert_ne!(sb.uuid, [0; 16], "V5 fixture should have a UUID");
+    assert_eq!(sb.meta_uuid, sb.uuid, "fixture UUID was never changed");
+}
+
+#[test]
+fn scan_meta_describes_the_scan() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let meta = scanner.scan_meta();
+
+    let image_len = std::fs::metadata(FIXTURE_PATH).expect("failed to stat fixture").len();
+    assert!(meta.device_size <= image_len, "device size beyond end of image");
+    assert_eq!(meta.fs_uuid, sb.uuid);
+    assert_eq!(meta.event_schema_version, fxfsp::EVENT_SCHEMA_VERSION);
+    assert!(!meta.fxfsp_version.is_empty());
 }
 
-fn skip_if_missing() -> bool {
-    if !Path::new(FIXTURE_PATH).exists() {
-        eprintln!("Skipping: fixture not found at {FIXTURE_PATH}");
-        return true;
+#[test]
+fn expected_inode_total_matches_the_inodes_scanned() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    assert_eq!(scanner.scan_meta().expected_inodes_total, None);
+    let expected = scanner.expected_inodes_total().expect("failed to sweep AG headers");
+    assert_eq!(expected, r.inodes.len() as u64);
+    assert_eq!(scanner.scan_meta().expected_inodes_total, Some(expected));
+
+    // The scan reuses the swept headers and still finds every inode.
+    let mut scanned = 0u64;
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to open AG")
+            .scan_inodes(|_: &InodeInfo| {
+                scanned += 1;
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .skip_dirs()
+            .expect("failed to skip dirs");
     }
-    false
+    assert_eq!(scanned, expected);
 }
 
-// ---------------------------------------------------------------------------
-// Superblock
-// ---------------------------------------------------------------------------
-
 #[test]
-fn superblock_has_valid_parameters() {
+fn file_join_matches_the_collect_then_join_result() {
     if skip_if_missing() { return; }
     let r = ScanResult::collect();
 
-    assert_eq!(r.block_size, 4096, "expected 4K block size");
-    assert_eq!(r.ag_count, 4, "expected 4 AGs");
-    assert_eq!(r.inode_size, 512, "expected 512-byte inodes");
-    assert!(r.root_ino > 0, "root inode should be nonzero");
-    assert!(r.ag_blocks > 0, "ag_blocks should be nonzero");
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut join = FileJoin::new(&sb);
+    let mut records: HashMap<u64, FileRecord> = HashMap::new();
+    while let Some(ag) = scanner.next_ag() {
+        let ag = ag.expect("failed to open AG");
+        let agno = ag.ag_number();
+        ag.scan_inodes(|inode: &InodeInfo| {
+            join.add_inode(inode);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes")
+        .scan_file_extents(|fe: &FileExtentsInfo| {
+            join.add_file_extents(fe);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan extents")
+        .scan_dir_entries(|de: &DirEntryInfo| {
+            join.add_dir_entry(de);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan dirs");
+        join.end_ag(agno);
+        for record in join.drain_ready() {
+            assert!(records.insert(record.stat.ino, record).is_none(), "record joined twice");
+        }
+    }
+    assert!(join.finish().is_empty(), "records left unmatched on a clean image");
+
+    let linked = r.inodes.values().filter(|i| i.nlink > 0).count();
+    assert_eq!(records.len(), linked);
+    assert_eq!(records[&r.root_ino].paths, vec![Vec::<Vec<u8>>::new()]);
+    for (ino, record) in &records {
+        let starts =
+            |exts: &[Extent]| exts.iter().map(|e| (e.logical_offset, e.ag_number, e.ag_block)).collect::<Vec<_>>();
+        let expected = r.file_extents.get(ino).map(Vec::as_slice).unwrap_or_default();
+        assert_eq!(starts(&record.extents), starts(expected), "extents of {ino}");
+        let mut names: Vec<&[u8]> = r
+            .dir_entries
+            .iter()
+            .filter(|de| de.child_ino == *ino && de.name != "." && de.name != "..")
+            .map(|de| de.name.as_bytes())
+            .collect();
+        let mut leaves: Vec<&[u8]> = record.paths.iter().filter_map(|p| p.last()).map(Vec::as_slice).collect();
+        names.sort_unstable();
+        leaves.sort_unstable();
+        assert_eq!(leaves, names, "paths of {ino}");
+    }
 }
 
-// ---------------------------------------------------------------------------
-// Root directory
-// ---------------------------------------------------------------------------
-
 #[test]
 fn root_inode_is_a_directory() {
     if skip_if_missing() { return; }
@@ -299,6 +2687,102 @@ fn subdir_contains_nested_txt() {
     assert_eq!(inode.size, 7, "nested.txt should have size 7 (\"nested\\n\")");
 }
 
+#[test]
+fn path_of_resolves_files_and_directories() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+    let subdir_ino = r.find_entry(r.root_ino, "subdir").unwrap().child_ino;
+    let nested_ino = r.find_entry(subdir_ino, "nested.txt").unwrap().child_ino;
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+
+    assert_eq!(scanner.path_of(r.root_ino).unwrap().as_deref(), Some(&b"/"[..]));
+    assert_eq!(scanner.path_of(subdir_ino).unwrap().as_deref(), Some(&b"/subdir"[..]));
+    assert_eq!(scanner.path_of(nested_ino).unwrap().as_deref(), Some(&b"/subdir/nested.txt"[..]));
+}
+
+#[test]
+fn path_of_reads_parent_pointers() {
+    let mut image = v5_superblock();
+    image[56..64].copy_from_slice(&64u64.to_be_bytes()); // rootino
+    image[216..220].copy_from_slice(&(1u32 << 7).to_be_bytes()); // parent pointers
+    assert!(update_metadata_crc(&mut image[..512]));
+    image.resize(16 * 4096, 0);
+    // V5 inodes: the data fork at 176, the attr fork `forkoff` * 8 after it.
+    let mut put_inode = |ino: usize, mode: u16, forkoff: u8, attrs: &[u8]| {
+        let inode = &mut image[ino * 512..][..512];
+        inode[0..2].copy_from_slice(b"IN");
+        inode[2..4].copy_from_slice(&mode.to_be_bytes());
+        inode[4..6].copy_from_slice(&[3, XFS_DINODE_FMT_EXTENTS]);
+        inode[82..84].copy_from_slice(&[forkoff, XFS_DINODE_FMT_LOCAL]);
+        let at = 176 + forkoff as usize * 8;
+        inode[at..at + attrs.len()].copy_from_slice(attrs);
+    };
+    // Shortform attrs (namelen, valuelen, flags, name, value): a user
+    // attribute, then a parent pointer naming /f, its value the parent's
+    // inode number and generation.
+    let pptr = [&be_words(&[0, 64, 7])[..]].concat();
+    let entries = [&[1, 1, 0][..], b"ux", &[1, 12, 1 << 3], b"f", &pptr].concat();
+    let attrs = [&(4 + entries.len() as u16).to_be_bytes()[..], &[2, 0], &entries].concat();
+    put_inode(65, 0o100644, 2, &attrs);
+    put_inode(66, 0o100644, 2, &[0, 4, 0, 0]);
+
+    let (_, mut scanner) = parse_superblock(SliceReader::new(&image)).expect("failed to parse superblock");
+    assert_eq!(scanner.path_of(65).expect("lookup failed").as_deref(), Some(&b"/f"[..]));
+    // No parent pointer: no name, and no directory scan (the image has no
+    // AG headers to scan).
+    assert_eq!(scanner.path_of(66).expect("lookup failed"), None);
+}
+
+/// `ag_count` AGs of 64 blocks, file 576 (AG 1) named `/f` only by the
+/// root directory, in AG 0: the last AG searched from AG 1.
+fn file_named_from_ag0(ag_count: u32) -> Vec<u8> {
+    let mut sb = sane_superblock();
+    sb[8..16].copy_from_slice(&(64 * ag_count as u64).to_be_bytes()); // dblocks
+    sb[84..92].copy_from_slice(&be_words(&[64, ag_count])); // agblocks, agcount
+    sb[124] = 6; // agblklog
+    let sf = [&[1, 0][..], &64u32.to_be_bytes(), &[1, 0, 0x30], b"f", &576u32.to_be_bytes()].concat();
+    let mut root = v4_inode(512, 0o040755, XFS_DINODE_FMT_LOCAL, &sf);
+    root[56..64].copy_from_slice(&(sf.len() as u64).to_be_bytes());
+    let mut image = synthetic_image(&sb, 64, &[root]);
+    image.resize(ag_count as usize * 64 * 4096, 0);
+    copy_first_ag(&mut image, 1, true);
+    let file = v4_inode(512, 0o100644, XFS_DINODE_FMT_EXTENTS, &[]);
+    image[(64 + 8) * 4096..][..512].copy_from_slice(&file);
+    for agno in 2..ag_count as usize {
+        copy_first_ag(&mut image, agno, false);
+    }
+    image
+}
+
+#[test]
+fn path_of_without_parent_pointers_searches_a_bounded_number_of_ags() {
+    let path_of = |image: &[u8]| {
+        let (_, mut scanner) = parse_superblock(SliceReader::new(image)).expect("failed to parse superblock");
+        scanner.path_of(576).expect("lookup failed")
+    };
+    assert_eq!(path_of(&file_named_from_ag0(8)).as_deref(), Some(&b"/f"[..]));
+    assert_eq!(path_of(&file_named_from_ag0(9)), None);
+}
+
+#[test]
+fn path_of_ignores_the_scan_limits() {
+    // The link search's events never reach the caller, so they must not
+    // count against the event limit, nor be cut short by the deadline.
+    let image = file_named_from_ag0(2);
+    let options = ScanOptions {
+        max_unconsumed_events: 1,
+        deadline: Some(std::time::Duration::ZERO),
+        skip_dot_entries: true,
+        ..Default::default()
+    };
+    let (_, mut scanner) =
+        parse_superblock_with_options(SliceReader::new(&image), options).expect("failed to parse superblock");
+    assert_eq!(scanner.path_of(576).expect("lookup failed").as_deref(), Some(&b"/f"[..]));
+    assert_eq!(scanner.scan_handle().unconsumed(), 0);
+}
+
 #[test]
 fn subdir_contains_all_200_numbered_files() {
     if skip_if_missing() { return; }
@@ -337,6 +2821,432 @@ fn subdir_has_exactly_201_children() {
     );
 }
 
+#[test]
+fn usage_report_groups_by_extension_and_top_dir() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut usage = UsageCollector::new(&sb);
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|inode: &InodeInfo| {
+                usage.add_inode(inode);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| {
+                usage.add_dir_entry(de);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+    }
+    let report = usage.finish();
+
+    let bucket = |key: &str, files, bytes| UsageBucket { key: key.into(), files, bytes };
+    // hello.txt (6) + nested.txt (7); empty_file + file_1..file_200.
+    assert_eq!(report.by_extension, vec![bucket("txt", 2, 13), bucket("", 201, 0)]);
+    // subdir: nested.txt + 200 empty files; root: hello.txt + empty_file.
+    assert_eq!(report.by_top_dir, vec![bucket("subdir", 201, 7), bucket("/", 2, 6)]);
+    // Modes 644 and 755: nothing setuid, setgid or world-writable.
+    let kinds = report.kinds;
+    assert!(kinds.internal >= 2, "realtime bitmap and summary not counted as internal");
+    assert_eq!(kinds, KindCounts { files: 203, dirs: 2, internal: kinds.internal, ..Default::default() });
+}
+
+fn security_findings<R: IoReader>(reader: R) -> Vec<SecurityFinding> {
+    let (sb, mut scanner) = parse_superblock(reader).expect("failed to parse superblock");
+    let mut audit = SecurityAudit::new(&sb);
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|inode: &InodeInfo| {
+                audit.add_inode(inode);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| {
+                audit.add_dir_entry(de);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+    }
+    audit.finish()
+}
+
+#[test]
+fn security_audit_flags_setuid_files_and_open_directories() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    assert_eq!(security_findings(engine), vec![], "the fixture is clean");
+
+    // Rewrite di_mode: hello.txt setuid, subdir world-writable without the
+    // sticky bit.
+    let hello = r.find_entry(r.root_ino, "hello.txt").unwrap().child_ino;
+    let subdir = r.find_entry(r.root_ino, "subdir").unwrap().child_ino;
+    let file = File::open(FIXTURE_PATH).unwrap();
+    let mut sb_buf = vec![0u8; 4096];
+    file.read_at(&mut sb_buf, 0).unwrap();
+    let ctx = FsContext::from_superblock(&sb_buf).unwrap();
+    let modes = [(hello, 0o104755_u16), (subdir, 0o040777)]
+        .map(|(ino, mode)| (ctx.locate_inode(ino).unwrap().byte_offset + 2, mode.to_be_bytes()));
+    let reader = CallbackReader::new(move |offset, buf: &mut [u8]| {
+        let n = file.read_at(buf, offset)?;
+        for (at, mode) in modes.iter().filter(|&&(at, _)| at >= offset && at + 2 <= offset + n as u64) {
+            let at = (at - offset) as usize;
+            buf[at..at + 2].copy_from_slice(mode);
+        }
+        Ok(n)
+    });
+
+    let finding = |issue, ino, path: &str| SecurityFinding { issue, ino, path: Some(path.into()) };
+    assert_eq!(
+        security_findings(reader),
+        vec![
+            finding(SecurityIssue::Setuid { uid: 0 }, hello, "/hello.txt"),
+            finding(SecurityIssue::WorldWritableDir, subdir, "/subdir"),
+        ]
+    );
+}
+
+#[test]
+fn rule_engine_reports_each_matching_link_once() {
+    if skip_if_missing() { return; }
+    let rules = vec![
+        Rule::new("greeting", Predicate::NameEquals(b"hello.txt".to_vec())),
+        Rule::new("nested-files", Predicate::All(vec![
+            Predicate::FileType(S_IFREG),
+            Predicate::Under(b"/subdir".to_vec()),
+        ])),
+        Rule::new("non-empty-elsewhere", Predicate::All(vec![
+            Predicate::SizeAtLeast(1),
+            Predicate::Not(Box::new(Predicate::Under(b"/subdir".to_vec()))),
+        ])),
+        Rule::new("dirs", Predicate::FileType(S_IFDIR)),
+    ];
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut matches: Vec<(String, String)> = Vec::new();
+    let sink = |m: &RuleMatch<'_>| matches.push((m.rule.to_owned(), escape_name(m.path.unwrap()).into_owned()));
+    let mut rules = RuleEngine::new(&sb, rules, sink);
+    while let Some(ag) = scanner.next_ag() {
+        let ag = ag.expect("failed to get AG");
+        let ag_number = ag.ag_number();
+        let dirs = ag
+            .scan_inodes(|inode: &InodeInfo| {
+                rules.add_inode(inode);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan inodes")
+            .skip_extents();
+        rules.end_ag(ag_number);
+        dirs.scan_dir_entries(|de: &DirEntryInfo| {
+            rules.add_dir_entry(de);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan dirs");
+    }
+    let _ = rules.finish();
+
+    matches.sort();
+    let count = |rule: &str| matches.iter().filter(|(r, _)| r == rule).count();
+    let paths = |rule: &str| matches.iter().filter(|(r, _)| r == rule).map(|(_, p)| p.as_str()).collect::<Vec<_>>();
+    assert_eq!(paths("greeting"), ["/hello.txt"]);
+    assert_eq!(count("nested-files"), 201, "nested.txt and file_1..file_200");
+    assert!(paths("nested-files").contains(&"/subdir/nested.txt"));
+    assert_eq!(paths("non-empty-elsewhere"), ["/hello.txt"]);
+    assert_eq!(paths("dirs"), ["/subdir"]);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_export_holds_every_event() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let conn = rusqlite::Connection::open_in_memory().expect("failed to open database");
+    // A small batch size commits mid-scan.
+    let mut export = fxfsp::SqliteExporter::new(conn).expect("failed to create tables").with_batch_size(64);
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|inode: &InodeInfo| export.add_inode(inode))
+            .expect("failed to scan inodes")
+            .scan_file_extents(|fe: &FileExtentsInfo| export.add_file_extents(fe))
+            .expect("failed to scan extents")
+            .scan_dir_entries(|de: &DirEntryInfo| export.add_dir_entry(de))
+            .expect("failed to scan dirs");
+    }
+    let conn = export.finish().expect("export failed");
+
+    let count = |table: &str| -> usize {
+        conn.query_row(&format!("SELECT count(*) FROM {table}"), [], |row| row.get(0)).expect("count failed")
+    };
+    assert_eq!(count("inodes"), r.inodes.len());
+    assert_eq!(count("dirents"), r.dir_entries.len());
+    assert_eq!(count("extents"), r.file_extents.values().map(Vec::len).sum::<usize>());
+    let hello: u64 = conn
+        .query_row(
+            "SELECT i.size FROM dirents d JOIN inodes i ON i.ino = d.child WHERE d.parent = ? AND d.name = ?",
+            rusqlite::params![r.root_ino as i64, b"hello.txt".as_slice()],
+            |row| row.get(0),
+        )
+        .expect("hello.txt missing");
+    assert_eq!(hello, 6);
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn parquet_export_holds_every_event() {
+    use arrow_array::{BinaryArray, RecordBatch, UInt64Array};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    // A small batch size writes batches mid-scan.
+    let mut export = fxfsp::ParquetExporter::create(dir.path()).expect("failed to create files").with_batch_size(64);
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|inode: &InodeInfo| export.add_inode(inode))
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| export.add_dir_entry(de))
+            .expect("failed to scan dirs");
+    }
+    export.finish().expect("export failed");
+
+    let read = |name: &str| -> Vec<RecordBatch> {
+        let file = File::open(dir.path().join(name)).expect("failed to open export");
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).expect("bad parquet file");
+        reader.build().expect("bad parquet file").map(|batch| batch.expect("bad record batch")).collect()
+    };
+    let inodes = read("inodes.parquet");
+    let dirents = read("dirents.parquet");
+    assert_eq!(inodes.iter().map(RecordBatch::num_rows).sum::<usize>(), r.inodes.len());
+    assert_eq!(dirents.iter().map(RecordBatch::num_rows).sum::<usize>(), r.dir_entries.len());
+    let column = |batch: &RecordBatch, name: &str| batch.column_by_name(name).expect("missing column").clone();
+    let mut inos = HashSet::new();
+    for batch in &inodes {
+        let column = column(batch, "ino");
+        inos.extend(column.as_any().downcast_ref::<UInt64Array>().expect("ino not u64").values());
+    }
+    assert_eq!(inos, r.inodes.keys().copied().collect());
+    let has_hello = dirents.iter().any(|batch| {
+        let column = column(batch, "name");
+        let names = column.as_any().downcast_ref::<BinaryArray>().expect("name not binary");
+        names.iter().any(|name| name == Some(b"hello.txt".as_slice()))
+    });
+    assert!(has_hello);
+}
+
+#[cfg(feature = "kafka")]
+#[test]
+fn kafka_sink_publishes_every_event() {
+    use rdkafka::Message;
+    use rdkafka::config::ClientConfig;
+    use rdkafka::consumer::{BaseConsumer, Consumer};
+    use rdkafka::message::Headers;
+    use rdkafka::mocking::MockCluster;
+    use std::time::{Duration, Instant};
+
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let cluster = MockCluster::new(1).expect("failed to start mock cluster");
+    cluster.create_topic("scans", 1, 1).expect("failed to create topic");
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    // A small batch size publishes mid-scan.
+    let mut sink = fxfsp::KafkaSink::new(&cluster.bootstrap_servers(), "scans", "fixture")
+        .expect("failed to create producer")
+        .with_batch_size(64);
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|inode: &InodeInfo| sink.add_inode(inode))
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| sink.add_dir_entry(de))
+            .expect("failed to scan dirs");
+    }
+    sink.finish().expect("publish failed");
+
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", cluster.bootstrap_servers())
+        .set("group.id", "test")
+        .set("auto.offset.reset", "earliest")
+        .create()
+        .expect("failed to create consumer");
+    consumer.subscribe(&["scans"]).expect("failed to subscribe");
+    let mut inos = HashSet::new();
+    let mut dirents = 0;
+    let mut has_hello = false;
+    let started = Instant::now();
+    while inos.len() + dirents < r.inodes.len() + r.dir_entries.len() {
+        // Polls come back empty while the consumer joins its group.
+        let Some(message) = consumer.poll(Duration::from_millis(200)) else {
+            assert!(started.elapsed() < Duration::from_secs(30), "timed out waiting for messages");
+            continue;
+        };
+        let message = message.expect("consume failed");
+        assert_eq!(message.key(), Some(b"fixture".as_slice()));
+        let schema = message.headers().expect("no headers").get(0);
+        assert_eq!(schema.value, Some(fxfsp::EVENT_SCHEMA_VERSION.to_string().as_bytes()));
+        for line in message.payload().expect("empty message").split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+            let event: serde_json::Value = serde_json::from_slice(line).expect("bad JSON line");
+            match event["type"].as_str() {
+                Some("inode") => assert!(inos.insert(event["ino"].as_u64().expect("no ino"))),
+                Some("dirent") => {
+                    dirents += 1;
+                    has_hello |= event["name"] == "hello.txt";
+                }
+                other => panic!("unexpected event type {other:?}"),
+            }
+        }
+    }
+    assert_eq!(inos, r.inodes.keys().copied().collect());
+    assert_eq!(dirents, r.dir_entries.len());
+    assert!(has_hello);
+}
+
+#[test]
+fn cleanup_candidates_match_age_size_and_path() {
+    if skip_if_missing() { return; }
+
+    let collect = |criteria: CleanupCriteria| {
+        let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+        let mut cleanup = CleanupCollector::new(&sb, criteria);
+        while let Some(ag) = scanner.next_ag() {
+            ag.expect("failed to get AG")
+                .scan_inodes(|inode: &InodeInfo| {
+                    cleanup.add_inode(inode);
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan inodes")
+                .skip_extents()
+                .scan_dir_entries(|de: &DirEntryInfo| {
+                    cleanup.add_dir_entry(de);
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan dirs");
+        }
+        cleanup.finish()
+    };
+
+    // The fixture was built in the past: every regular file is older than now.
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let all = collect(CleanupCriteria { mtime_before: Some(now), ..Default::default() });
+    assert_eq!(all.len(), 203, "empty_file, hello.txt, nested.txt, file_1..file_200");
+    assert!(collect(CleanupCriteria { mtime_before: Some(0), ..Default::default() }).is_empty());
+
+    let nested = collect(CleanupCriteria { min_size: 1, under: vec![b"/subdir/".to_vec()], ..Default::default() });
+    let paths: Vec<&str> = nested.iter().map(|c| c.path.as_str()).collect();
+    assert_eq!(paths, ["/subdir/nested.txt"]);
+    assert_eq!(nested[0].size, 7);
+}
+
+#[test]
+fn clean_fixture_has_no_orphans() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut orphans = OrphanCollector::new(&sb);
+    while let Some(ag) = scanner.next_ag() {
+        let mut ag = ag.expect("failed to get AG");
+        orphans.add_unlinked(&ag.unlinked_inodes().expect("failed to walk unlinked chains"));
+        ag.scan_inodes(|inode: &InodeInfo| {
+            orphans.add_inode(inode);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes");
+    }
+    assert!(scanner.warnings().is_empty(), "{:?}", scanner.warnings());
+
+    let report = orphans.finish();
+    assert!(report.orphans.is_empty(), "{:?}", report.orphans);
+    assert_eq!(report.held_bytes, 0);
+    assert!(report.linked_on_list.is_empty());
+}
+
+#[test]
+fn superblock_counters_reconcile_on_clean_fixture() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut accounting = SpaceAccounting::new(&sb);
+    while let Some(ag) = scanner.next_ag() {
+        let ag = ag.expect("failed to get AG");
+        accounting.add_ag(ag.agi(), ag.agf());
+        ag.scan_inodes(|inode: &InodeInfo| {
+            accounting.add_inode(inode);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes");
+    }
+
+    // The fixture image was cleanly unmounted, so the counters are current.
+    let report = accounting.finish();
+    assert!(report.discrepancies.is_empty(), "{:?}", report.discrepancies);
+    assert!(report.file_blocks <= report.used_blocks);
+}
+
+#[test]
+fn escaped_names_round_trip() {
+    // No fixture needed: non-UTF-8 names are awkward to put in the image.
+    for name in [&b"plain"[..], b"back\\slash", b"caf\xc3\xa9", b"bad\xff\xfe", b"\\xff"] {
+        let escaped = escape_name(name);
+        assert_eq!(unescape_name(&escaped).as_deref(), Some(name), "{escaped}");
+    }
+    assert_eq!(escape_name(b"bad\xff"), "bad\\xff");
+    assert_eq!(NamePolicy::Utf8Lossy.encode(b"bad\xff").as_ref(), "bad\u{fffd}".as_bytes());
+    assert_eq!(NamePolicy::Raw.encode(b"bad\xff").as_ref(), b"bad\xff");
+    assert_eq!(unescape_name("trailing\\"), None);
+}
+
+#[test]
+fn dir_stats_rank_subdir_first() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+    let subdir_ino = r.find_entry(r.root_ino, "subdir").unwrap().child_ino;
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut stats = DirStats::new();
+    while let Some(ag) = scanner.next_ag() {
+        ag.expect("failed to get AG")
+            .scan_inodes(|inode: &InodeInfo| {
+                stats.add_inode(inode);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|de: &DirEntryInfo| {
+                stats.add_dir_entry(de);
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+    }
+
+    let top = stats.top(2);
+    assert_eq!(top.len(), 2);
+    assert_eq!(top[0].ino, subdir_ino);
+    assert_eq!(top[0].entries, 201);
+    // nested.txt is the only non-empty child: "nested\n".
+    assert_eq!(top[0].child_bytes, 7);
+    assert_eq!(top[1].ino, r.root_ino);
+    assert_eq!(top[1].entries, 3);
+}
+
 #[test]
 fn all_numbered_files_are_empty_regular_files() {
     if skip_if_missing() { return; }
@@ -430,33 +3340,38 @@ fn directories_have_no_file_extents() {
 // ---------------------------------------------------------------------------
 
 /// Read file content from the raw fixture image using extent information.
-///
-/// Opens the fixture file, parses the superblock to get an FsContext,
-/// then reads data at the byte offsets computed from the extent records.
 fn read_file_from_extents(extents: &[Extent], file_size: u64) -> Vec<u8> {
-    let f = File::open(FIXTURE_PATH).expect("failed to open fixture for extent read");
+    read_file_range(FIXTURE_PATH, extents, file_size, 0..file_size)
+}
+
+/// Read bytes `range` of a file (clamped to `file_size`) from a raw image.
+///
+/// Parses the superblock to get an FsContext, then reads each extent's
+/// overlap with the range at the disk offset computed from the record.
+/// Holes and unwritten extents read as zeros; only the range is allocated,
+/// so windows into huge sparse files are cheap.
+fn read_file_range(image: &str, extents: &[Extent], file_size: u64, range: Range<u64>) -> Vec<u8> {
+    let f = File::open(image).expect("failed to open fixture for extent read");
 
     // Parse the superblock to get FsContext (needed for computing byte offset).
     let mut sb_buf = vec![0u8; 4096];
     f.read_at(&mut sb_buf, 0).expect("failed to read superblock");
     let ctx = FsContext::from_superblock(&sb_buf).expect("failed to parse superblock");
 
-    let block_size = ctx.block_size as u64;
-    let mut data = Vec::new();
-    let mut remaining = file_size;
+    let end = range.end.min(file_size);
+    let start = range.start.min(end);
+    let mut data = vec![0u8; (end - start) as usize];
 
-    for ext in extents {
-        if remaining == 0 {
-            break;
+    for ext in extents.iter().filter(|e| !e.is_unwritten) {
+        let file_bytes = ext.logical_byte_range(&ctx).expect("extent beyond u64 bytes");
+        let lo = file_bytes.start.max(start);
+        let hi = file_bytes.end.min(end);
+        if lo >= hi {
+            continue;
         }
-        let byte_offset = ext.start_byte(&ctx);
-        let extent_bytes = ext.block_count * block_size;
-        let to_read = remaining.min(extent_bytes) as usize;
-
-        let mut buf = vec![0u8; to_read];
-        f.read_at(&mut buf, byte_offset).expect("failed to read extent data");
-        data.extend_from_slice(&buf);
-        remaining = remaining.saturating_sub(extent_bytes);
+        let disk_offset = ext.start_byte(&ctx) + (lo - file_bytes.start);
+        let buf = &mut data[(lo - start) as usize..(hi - start) as usize];
+        f.read_at(buf, disk_offset).expect("failed to read extent data");
     }
 
     data
@@ -568,15 +3483,12 @@ fn total_inode_count_is_plausible() {
     if skip_if_missing() { return; }
     let r = ScanResult::collect();
 
-    // We created: root dir, 2 internal inodes (typically ino 129, 130),
-    // empty_file, hello.txt, subdir, nested.txt, and file_1..file_200.
-    // That's 3 + 3 + 1 + 200 = 207, but internal inodes may vary.
-    // At minimum we need 204 user-visible inodes.
-    assert!(
-        r.inodes.len() >= 204,
-        "expected at least 204 inodes, got {}",
-        r.inodes.len()
-    );
+    // We created: root dir, empty_file, hello.txt, subdir, nested.txt, and
+    // file_1..file_200. mkfs adds internal inodes: at least the realtime
+    // bitmap and summary, more with quotas or a metadata directory.
+    let user = r.inodes.values().filter(|i| i.internal.is_none()).count();
+    assert_eq!(user, 205, "user-visible inodes");
+    assert!(r.inodes.len() - user >= 2, "expected the realtime bitmap and summary as internal inodes");
 }
 
 #[test]
@@ -614,3 +3526,366 @@ fn extent_has_ag_number_and_ag_block() {
     // ag_block should be non-zero (not at the start of the AG)
     assert!(ext.ag_block > 0, "hello.txt ag_block should be > 0");
 }
+
+// ---------------------------------------------------------------------------
+// Sparse chunk reads
+// ---------------------------------------------------------------------------
+
+#[test]
+fn sparse_chunk_reads_find_the_same_inodes() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let options = ScanOptions { sparse_chunk_threshold: 64, ..Default::default() };
+    let (_sb, mut scanner) = parse_superblock_with_options(engine, options).expect("failed to parse superblock");
+
+    let mut inodes = HashSet::new();
+    while let Some(ag_result) = scanner.next_ag() {
+        let ag = ag_result.expect("failed to get AG");
+        ag.scan_inodes(|inode: &InodeInfo| {
+            inodes.insert(inode.ino);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes")
+        .skip_extents()
+        .skip_dirs()
+        .expect("failed to skip dirs");
+    }
+
+    let expected: HashSet<u64> = r.inodes.keys().copied().collect();
+    assert_eq!(inodes, expected, "block-granular chunk reads should find the same inodes");
+}
+
+// ---------------------------------------------------------------------------
+// Parallel AG scanning
+// ---------------------------------------------------------------------------
+
+#[test]
+fn parallel_scan_matches_sequential_inode_count() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let parallel = scanner.into_parallel(|_agno| IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024));
+
+    let total: usize = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..parallel.ag_count())
+            .map(|agno| {
+                let parallel = &parallel;
+                s.spawn(move || {
+                    parallel.scan_ag(agno, |ag| {
+                        let mut count = 0usize;
+                        ag.scan_inodes(|_inode: &InodeInfo| {
+                            count += 1;
+                            ControlFlow::Continue(())
+                        })?
+                        .skip_extents()
+                        .skip_dirs()?;
+                        Ok(count)
+                    })
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("AG thread panicked").expect("AG scan failed"))
+            .sum()
+    });
+
+    assert_eq!(total, r.inodes.len(), "parallel scan should find the same inodes as a sequential scan");
+}
+
+#[test]
+fn concurrent_sessions_with_different_tunings_agree() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let small = Session { merge_gap: 0, max_merged: 64 * 1024, buffer_size: 64 * 1024, ..Default::default() };
+    let large = Session { stripe_width: Some(0), ..Default::default() };
+
+    let counts: Vec<usize> = std::thread::scope(|s| {
+        [&small, &large]
+            .map(|session| {
+                s.spawn(move || {
+                    let (_sb, mut scanner) = session.open(FIXTURE_PATH).expect("failed to open session");
+                    let mut count = 0usize;
+                    while let Some(ag_result) = scanner.next_ag() {
+                        ag_result
+                            .expect("failed to get AG")
+                            .scan_inodes(|_inode: &InodeInfo| {
+                                count += 1;
+                                ControlFlow::Continue(())
+                            })
+                            .expect("failed to scan inodes")
+                            .skip_extents()
+                            .skip_dirs()
+                            .expect("failed to skip dirs");
+                    }
+                    count
+                })
+            })
+            .map(|h| h.join().expect("session thread panicked"))
+            .to_vec()
+    });
+
+    assert_eq!(counts, vec![r.inodes.len(); 2]);
+}
+
+// ---------------------------------------------------------------------------
+// In-memory readers
+// ---------------------------------------------------------------------------
+
+#[test]
+fn slice_reader_scan_matches_io_engine() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let image = std::fs::read(FIXTURE_PATH).expect("failed to read fixture");
+    let (_sb, mut scanner) = parse_superblock(SliceReader::new(image)).expect("failed to parse superblock");
+
+    let mut inodes = 0usize;
+    let mut entries = 0usize;
+    while let Some(ag_result) = scanner.next_ag() {
+        let ag = ag_result.expect("failed to get AG");
+        ag.scan_inodes(|_inode: &InodeInfo| {
+            inodes += 1;
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes")
+        .skip_extents()
+        .scan_dir_entries(|_de: &DirEntryInfo| {
+            entries += 1;
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan dirs");
+    }
+
+    assert_eq!(inodes, r.inodes.len(), "in-memory scan should find the same inodes");
+    assert_eq!(entries, r.dir_entries.len(), "in-memory scan should find the same dir entries");
+}
+
+// ---------------------------------------------------------------------------
+// Warnings
+// ---------------------------------------------------------------------------
+
+#[test]
+fn clean_fixture_scan_produces_no_warnings() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+
+    while let Some(ag_result) = scanner.next_ag() {
+        let ag = ag_result.expect("failed to get AG");
+        ag.scan_inodes(|_inode: &InodeInfo| ControlFlow::Continue(()))
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|_de: &DirEntryInfo| ControlFlow::Continue(()))
+            .expect("failed to scan dirs");
+    }
+
+    let warnings = scanner.take_warnings();
+    assert!(warnings.is_empty(), "unexpected warnings: {warnings:?}");
+}
+
+#[test]
+fn clean_fixture_has_no_cross_check_conflicts() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let mut check = CrossCheck::new(&sb);
+
+    while let Some(ag_result) = scanner.next_ag() {
+        let ag = ag_result.expect("failed to get AG");
+        ag.scan_inodes(|inode: &InodeInfo| {
+            check.add_inode(inode);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan inodes")
+        .scan_file_extents(|fe: &FileExtentsInfo| {
+            check.add_file_extents(fe);
+            ControlFlow::Continue(())
+        })
+        .expect("failed to scan extents")
+        .skip_dirs()
+        .expect("failed to skip dirs");
+    }
+
+    let conflicts = check.finish();
+    assert!(conflicts.is_empty(), "unexpected conflicts: {conflicts:?}");
+}
+
+// ---------------------------------------------------------------------------
+// Deterministic clocks
+// ---------------------------------------------------------------------------
+
+#[test]
+fn fixed_clock_makes_scan_output_reproducible() {
+    if skip_if_missing() { return; }
+
+    let wall = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    let run = |log: &Path| {
+        let clock: Arc<dyn Clock> = Arc::new(FixedClock::new(wall, std::time::Duration::from_micros(10)));
+        let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+        let reader = InstrumentedReader::with_clock(engine, log.to_str().unwrap(), usize::MAX, clock.clone())
+            .expect("failed to create reader");
+        let options = ScanOptions { clock: Some(clock), ..Default::default() };
+        let (_sb, mut scanner) = parse_superblock_with_options(reader, options).expect("failed to parse superblock");
+        let meta = scanner.scan_meta();
+        while let Some(ag_result) = scanner.next_ag() {
+            let ag = ag_result.expect("failed to get AG");
+            ag.scan_inodes(|_inode: &InodeInfo| ControlFlow::Continue(()))
+                .expect("failed to scan inodes")
+                .skip_extents()
+                .skip_dirs()
+                .expect("failed to skip dirs");
+        }
+        drop(scanner);
+        (meta.started_at, std::fs::read(log).expect("failed to read log"))
+    };
+
+    let dir = std::env::temp_dir();
+    let a = run(&dir.join(format!("fxfsp-clock-a-{}.csv", std::process::id())));
+    let b = run(&dir.join(format!("fxfsp-clock-b-{}.csv", std::process::id())));
+    assert_eq!(a.0, wall);
+    assert_eq!(a, b, "runs with the same fixed clock should produce identical output");
+}
+
+// ---------------------------------------------------------------------------
+// Flow control
+// ---------------------------------------------------------------------------
+
+#[test]
+fn paused_scan_waits_until_resumed() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let (_sb, mut scanner) = parse_superblock(engine).expect("failed to parse superblock");
+    let handle = scanner.scan_handle();
+    handle.pause();
+
+    let count = std::sync::atomic::AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        let worker = s.spawn(|| {
+            while let Some(ag_result) = scanner.next_ag() {
+                ag_result
+                    .expect("failed to get AG")
+                    .scan_inodes(|_inode: &InodeInfo| {
+                        count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                        ControlFlow::Continue(())
+                    })
+                    .expect("failed to scan inodes")
+                    .skip_extents()
+                    .skip_dirs()
+                    .expect("failed to skip dirs");
+            }
+        });
+
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(count.load(std::sync::atomic::Ordering::Relaxed), 0, "paused scan made progress");
+        assert!(!worker.is_finished());
+
+        handle.resume();
+        worker.join().expect("scan thread panicked");
+    });
+
+    assert_eq!(count.into_inner(), r.inodes.len());
+}
+
+#[test]
+fn backpressured_scan_delivers_every_event() {
+    if skip_if_missing() { return; }
+    let r = ScanResult::collect();
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let options = ScanOptions { max_unconsumed_events: 16, ..Default::default() };
+    let (_sb, mut scanner) = parse_superblock_with_options(engine, options).expect("failed to parse superblock");
+    let handle = scanner.scan_handle();
+    let (tx, rx) = std::sync::mpsc::channel::<u64>();
+
+    let received = std::thread::scope(|s| {
+        let consumer = s.spawn(|| {
+            let mut received = 0usize;
+            for _ino in rx {
+                received += 1;
+                handle.consumed(1);
+            }
+            received
+        });
+
+        while let Some(ag_result) = scanner.next_ag() {
+            ag_result
+                .expect("failed to get AG")
+                .scan_inodes(|inode: &InodeInfo| {
+                    tx.send(inode.ino).expect("consumer hung up");
+                    ControlFlow::Continue(())
+                })
+                .expect("failed to scan inodes")
+                .skip_extents()
+                .skip_dirs()
+                .expect("failed to skip dirs");
+        }
+        drop(tx);
+        consumer.join().expect("consumer panicked")
+    });
+
+    assert_eq!(received, r.inodes.len());
+}
+
+#[test]
+fn spent_deadline_stops_the_scan_before_the_first_ag() {
+    if skip_if_missing() { return; }
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let options = ScanOptions { deadline: Some(std::time::Duration::ZERO), ..Default::default() };
+    let (_sb, mut scanner) = parse_superblock_with_options(engine, options).expect("failed to parse superblock");
+    assert!(scanner.next_ag().is_none(), "an AG was opened past the deadline");
+    let stops = scanner.scan_handle().deadline_stops();
+    assert_eq!(stops, [DeadlineStop { ag_number: 0, phase: IoPhase::Agi }]);
+
+    let parallel = scanner.into_parallel(|_agno| IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024));
+    let result = parallel.scan_ag(1, |ag| Ok(ag.ag_number()));
+    assert!(matches!(result, Err(FxfspError::DeadlineExceeded)));
+}
+
+#[test]
+fn deadline_stops_the_scan_at_the_next_phase_boundary() {
+    if skip_if_missing() { return; }
+    let deadline = std::time::Duration::from_secs(1);
+
+    let engine = IoEngine::open(FIXTURE_PATH, 256 * 1024, 2 * 1024 * 1024).expect("failed to open fixture");
+    let options = ScanOptions { deadline: Some(deadline), ..Default::default() };
+    let (_sb, mut scanner) = parse_superblock_with_options(engine, options).expect("failed to parse superblock");
+    let mut ags = 0;
+    let mut entries = 0;
+    while let Some(ag) = scanner.next_ag() {
+        ags += 1;
+        let mut slept = false;
+        ag.expect("failed to open AG")
+            .scan_inodes(|_: &InodeInfo| {
+                // Spend the budget inside the first AG's inode batch.
+                if !slept {
+                    std::thread::sleep(deadline);
+                    slept = true;
+                }
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan inodes")
+            .skip_extents()
+            .scan_dir_entries(|_: &DirEntryInfo| {
+                entries += 1;
+                ControlFlow::Continue(())
+            })
+            .expect("failed to scan dirs");
+    }
+
+    assert_eq!(ags, 1, "the scan went on past the deadline");
+    assert_eq!(entries, 0, "directories were scanned past the deadline");
+    let handle = scanner.scan_handle();
+    assert!(handle.deadline_passed());
+    assert_eq!(handle.deadline_stops(), [DeadlineStop { ag_number: 0, phase: IoPhase::BmbtWalk }]);
+}
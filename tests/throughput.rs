@@ -0,0 +1,262 @@
+//! Throughput and allocation regression gates for the parsing hot path.
+//!
+//! Scans a V4 image generated in memory (no fixtures, no root) through
+//! [`SliceReader`], so the numbers measure parsing and event delivery only.
+//! Allocations are counted per thread by a wrapping global allocator; the
+//! libtest harness runs each test on its own thread.
+//!
+//! The floors are loose enough for an unoptimised build on a busy machine;
+//! a regression that trips them is an order of magnitude, not noise.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use fxfsp::prelude::*;
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn allocations() -> u64 {
+    ALLOCATIONS.with(Cell::get)
+}
+
+const BLOCK_SIZE: usize = 4096;
+const SECT_SIZE: usize = 512;
+const INODE_SIZE: usize = 512;
+const INODES_PER_BLOCK: usize = BLOCK_SIZE / INODE_SIZE;
+const AG_BLOCKS: u32 = 16384;
+/// AG block of the inobt root (a single leaf).
+const INOBT_BLOCK: u32 = 4;
+/// AG block of the first inode chunk.
+const FIRST_CHUNK_BLOCK: u32 = 64;
+/// Inode chunks; one inobt leaf holds up to 255 V4 records.
+const CHUNKS: u32 = 128;
+/// First block handed out as file data. Never read by the scan, so the
+/// image ends here.
+const DATA_BLOCK: u64 = 1200;
+const NULLAGINO: u32 = u32::MAX;
+
+/// A single-AG V4 filesystem with `CHUNKS` full inode chunks. The first
+/// inode of every block is a shortform directory naming the other seven,
+/// which are regular files with one extent each; the very first directory
+/// is the root and the parent of all the others.
+fn generate_image() -> Vec<u8> {
+    let mut image = vec![0u8; DATA_BLOCK as usize * BLOCK_SIZE];
+    let put16 = |image: &mut [u8], at: usize, v: u16| image[at..at + 2].copy_from_slice(&v.to_be_bytes());
+    let put32 = |image: &mut [u8], at: usize, v: u32| image[at..at + 4].copy_from_slice(&v.to_be_bytes());
+    let put64 = |image: &mut [u8], at: usize, v: u64| image[at..at + 8].copy_from_slice(&v.to_be_bytes());
+
+    let inodes = CHUNKS as u64 * 64;
+    let first_agino = FIRST_CHUNK_BLOCK * INODES_PER_BLOCK as u32;
+    let root_ino = first_agino as u64;
+
+    // Superblock.
+    put32(&mut image, 0, 0x5846_5342); // XFSB
+    put32(&mut image, 4, BLOCK_SIZE as u32);
+    put64(&mut image, 8, AG_BLOCKS as u64);
+    put64(&mut image, 56, root_ino);
+    put32(&mut image, 84, AG_BLOCKS);
+    put32(&mut image, 88, 1); // agcount
+    put16(&mut image, 100, 0xa0a4); // V4 + nlink, align, dirv2, morebits
+    put16(&mut image, 102, SECT_SIZE as u16);
+    put16(&mut image, 104, INODE_SIZE as u16);
+    put16(&mut image, 106, INODES_PER_BLOCK as u16);
+    image[120] = 12; // blocklog
+    image[121] = 9; // sectlog
+    image[122] = 9; // inodelog
+    image[123] = 3; // inopblog
+    image[124] = 14; // agblklog
+    put64(&mut image, 128, inodes); // icount
+    put64(&mut image, 144, AG_BLOCKS as u64 - DATA_BLOCK - inodes); // fdblocks
+    put32(&mut image, 200, 0x200); // features2: ftype
+
+    // AGF.
+    let agf = SECT_SIZE;
+    put32(&mut image, agf, 0x5841_4746); // XAGF
+    put32(&mut image, agf + 4, 1);
+    put32(&mut image, agf + 12, AG_BLOCKS);
+
+    // AGI.
+    let agi = 2 * SECT_SIZE;
+    put32(&mut image, agi, 0x5841_4749); // XAGI
+    put32(&mut image, agi + 4, 1);
+    put32(&mut image, agi + 12, AG_BLOCKS);
+    put32(&mut image, agi + 16, inodes as u32);
+    put32(&mut image, agi + 20, INOBT_BLOCK);
+    put32(&mut image, agi + 24, 1); // level
+    put32(&mut image, agi + 36, NULLAGINO); // dirino
+    for bucket in 0..64 {
+        put32(&mut image, agi + 40 + bucket * 4, NULLAGINO);
+    }
+
+    // Inobt leaf.
+    let leaf = INOBT_BLOCK as usize * BLOCK_SIZE;
+    put32(&mut image, leaf, 0x4941_4254); // IABT
+    put16(&mut image, leaf + 6, CHUNKS as u16);
+    put32(&mut image, leaf + 8, NULLAGINO);
+    put32(&mut image, leaf + 12, NULLAGINO);
+    for chunk in 0..CHUNKS {
+        let rec = leaf + 16 + chunk as usize * 16;
+        put32(&mut image, rec, first_agino + chunk * 64);
+        image[rec + 6] = 64; // count; no holes, nothing free
+    }
+
+    // Inodes.
+    let chunk_start = FIRST_CHUNK_BLOCK as usize * BLOCK_SIZE;
+    for i in 0..inodes as usize {
+        let at = chunk_start + i * INODE_SIZE;
+        let ino = root_ino + i as u64;
+        put16(&mut image, at, 0x494e); // IN
+        image[at + 4] = 2; // version
+        put32(&mut image, at + 96, NULLAGINO); // next_unlinked
+        put32(&mut image, at + 92, i as u32); // generation
+        let fork = at + 100;
+        if i % INODES_PER_BLOCK == 0 {
+            put16(&mut image, at + 2, 0o040755);
+            image[at + 5] = 1; // local
+            put32(&mut image, at + 16, 2);
+            image[fork] = (INODES_PER_BLOCK - 1) as u8;
+            put32(&mut image, fork + 2, root_ino as u32);
+            let mut entry = fork + 6;
+            for child in 1..INODES_PER_BLOCK {
+                let name = format!("f{:06}", i + child);
+                image[entry] = name.len() as u8;
+                put16(&mut image, entry + 1, (entry - fork) as u16);
+                image[entry + 3..entry + 3 + name.len()].copy_from_slice(name.as_bytes());
+                entry += 3 + name.len();
+                image[entry] = 1; // XFS_DIR3_FT_REG_FILE
+                put32(&mut image, entry + 1, (ino + child as u64) as u32);
+                entry += 5;
+            }
+            put64(&mut image, at + 56, (entry - fork) as u64);
+        } else {
+            put16(&mut image, at + 2, 0o100644);
+            image[at + 5] = 2; // extents
+            put32(&mut image, at + 16, 1);
+            put64(&mut image, at + 56, BLOCK_SIZE as u64);
+            put64(&mut image, at + 64, 1); // nblocks
+            put32(&mut image, at + 76, 1); // nextents
+            let fsblock = DATA_BLOCK + i as u64;
+            put64(&mut image, fork, fsblock >> 43);
+            put64(&mut image, fork + 8, (fsblock << 21) | 1);
+        }
+    }
+
+    image
+}
+
+#[derive(Default)]
+struct ScanCost {
+    events: u64,
+    allocations: u64,
+    elapsed: Duration,
+}
+
+/// Scan every phase, counting events. With `inline_shortform`, shortform
+/// directory entries are delivered during the inode phase.
+fn scan(image: &[u8], inline_shortform: bool) -> ScanCost {
+    let allocations_before = allocations();
+    let started = Instant::now();
+    let events = Cell::new(0u64);
+    let count = || {
+        events.set(events.get() + 1);
+        ControlFlow::Continue(())
+    };
+
+    let (_, mut scanner) = parse_superblock(SliceReader::new(image)).expect("failed to parse superblock");
+    while let Some(ag) = scanner.next_ag() {
+        let ag = ag.expect("failed to open AG");
+        let extent_phase = if inline_shortform {
+            ag.scan_inodes_with_shortform_dirs(|_: &InodeInfo| count(), |_: &DirEntryInfo| count())
+        } else {
+            ag.scan_inodes(|_: &InodeInfo| count())
+        };
+        extent_phase
+            .expect("failed to scan inodes")
+            .scan_file_extents(|_: &FileExtentsInfo| count())
+            .expect("failed to scan extents")
+            .scan_dir_entries(|_: &DirEntryInfo| count())
+            .expect("failed to scan dirs");
+    }
+
+    ScanCost {
+        events: events.get(),
+        allocations: allocations() - allocations_before,
+        elapsed: started.elapsed(),
+    }
+}
+
+/// Every inode, plus `.`, `..` and seven names per directory.
+fn expected_events() -> u64 {
+    let inodes = CHUNKS as u64 * 64;
+    let dirs = inodes / INODES_PER_BLOCK as u64;
+    inodes + dirs * (2 + INODES_PER_BLOCK as u64 - 1)
+}
+
+#[test]
+fn generated_image_scans_completely() {
+    let image = generate_image();
+    assert_eq!(scan(&image, false).events, expected_events());
+    assert_eq!(scan(&image, true).events, expected_events());
+}
+
+#[test]
+fn scan_throughput_floor() {
+    // Debug builds are roughly 20x slower than release.
+    let min_events_per_sec = if cfg!(debug_assertions) { 200_000.0 } else { 4_000_000.0 };
+
+    let image = generate_image();
+    // Best of several runs, so one preempted run doesn't fail the gate.
+    let best = (0..5)
+        .map(|_| scan(&image, false))
+        .min_by_key(|cost| cost.elapsed)
+        .unwrap_or_default();
+    let rate = best.events as f64 / best.elapsed.as_secs_f64();
+    assert!(
+        rate >= min_events_per_sec,
+        "{} events in {:?}: {rate:.0} events/s, floor {min_events_per_sec:.0}",
+        best.events,
+        best.elapsed,
+    );
+}
+
+#[test]
+fn scan_allocations_per_event_ceiling() {
+    // One extent list per regular file is inherent, about 0.41 per event on
+    // this image; directory forks share an arena and the rest is per AG,
+    // not per event.
+    const MAX_ALLOCATIONS_PER_EVENT: f64 = 0.45;
+
+    let image = generate_image();
+    let cost = scan(&image, false);
+    let per_event = cost.allocations as f64 / cost.events as f64;
+    assert!(
+        per_event <= MAX_ALLOCATIONS_PER_EVENT,
+        "{} allocations for {} events: {per_event:.3} per event, ceiling {MAX_ALLOCATIONS_PER_EVENT}",
+        cost.allocations,
+        cost.events,
+    );
+}